@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// User annotations (tags like "verified-safe" or "false-positive", and free
+// form notes), keyed by mangled name so that they survive reloading the graph
+// or moving to a newer one. Stored as JSON in a side file, which is rewritten
+// after every change.
+
+#[derive(Default, Clone, Debug)]
+pub struct Annotation {
+    pub tags : Vec<String>,
+    pub notes : Vec<String>,
+}
+
+impl Annotation {
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.notes.is_empty()
+    }
+}
+
+#[derive(Default)]
+pub struct Annotations {
+    path : Option<PathBuf>,
+    by_name : BTreeMap<String, Annotation>,
+}
+
+fn strings(value : &json::JsonValue) -> Vec<String> {
+    value.members().filter_map(|v| v.as_str()).map(|s| s.to_string()).collect()
+}
+
+impl Annotations {
+    // A missing file is fine; it will be created on the first change.
+    pub fn load(path : &Path) -> io::Result<Annotations> {
+        let mut annotations = Annotations { path: Some(path.to_path_buf()), by_name: BTreeMap::new() };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(annotations),
+            Err(e) => return Err(e),
+        };
+        let data = json::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        for (name, entry) in data.entries() {
+            annotations.by_name.insert(name.to_string(), Annotation {
+                tags: strings(&entry["tags"]),
+                notes: strings(&entry["notes"]),
+            });
+        }
+        Ok(annotations)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut data = json::JsonValue::new_object();
+        for (name, annotation) in &self.by_name {
+            data[name.as_str()] = json::object!{
+                "tags" => annotation.tags.clone(),
+                "notes" => annotation.notes.clone(),
+            };
+        }
+        fs::write(path, data.pretty(2))
+    }
+
+    pub fn get(&self, mangled : &str) -> Option<&Annotation> {
+        self.by_name.get(mangled)
+    }
+
+    pub fn has_tag(&self, mangled : &str, tag : &str) -> bool {
+        self.get(mangled).is_some_and(|a| a.tags.iter().any(|t| t == tag))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.by_name.keys().map(|s| s.as_str())
+    }
+
+    pub fn add_tag(&mut self, mangled : &str, tag : &str) -> io::Result<()> {
+        let entry = self.by_name.entry(mangled.to_string()).or_default();
+        if !entry.tags.iter().any(|t| t == tag) {
+            entry.tags.push(tag.to_string());
+        }
+        self.save()
+    }
+
+    pub fn remove_tag(&mut self, mangled : &str, tag : &str) -> io::Result<()> {
+        if let Some(entry) = self.by_name.get_mut(mangled) {
+            entry.tags.retain(|t| t != tag);
+            if entry.is_empty() {
+                self.by_name.remove(mangled);
+            }
+        }
+        self.save()
+    }
+
+    pub fn add_note(&mut self, mangled : &str, note : &str) -> io::Result<()> {
+        self.by_name.entry(mangled.to_string()).or_default().notes.push(note.to_string());
+        self.save()
+    }
+
+    pub fn clear(&mut self, mangled : &str) -> io::Result<()> {
+        self.by_name.remove(mangled);
+        self.save()
+    }
+}
@@ -0,0 +1,231 @@
+use crate::callgraph::{Callgraph, Direction};
+use crate::hazard::{load_graph_with_options, LoadOptions};
+use petgraph::graph::NodeIndex;
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Duration, Instant};
+
+// Timings of a fixed set of operations on the loaded graph, for spotting
+// performance regressions between versions. Results can be saved to a file
+// and compared against on a later run (with the same graph, or the numbers
+// don't mean much).
+
+const SAMPLES : usize = 100;
+
+pub struct BenchResult {
+    pub name : &'static str,
+    pub runs : usize,
+    pub total : Duration,
+}
+
+impl BenchResult {
+    pub fn per_op(&self) -> Duration {
+        self.total / self.runs.max(1) as u32
+    }
+}
+
+// Deterministic spread of functions across the graph, so that runs are
+// comparable. A different spread for each graph seed, if one is set.
+fn sample_functions(cg : &Callgraph, count : usize) -> Vec<NodeIndex> {
+    let n = cg.graph.node_count();
+    if n == 0 {
+        return vec![];
+    }
+    let mut state : u64 = cg.seed().map_or(0x2545_f491_4f6c_dd1d, |seed| seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    (0..count).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        NodeIndex::new((state % n as u64) as usize)
+    }).collect()
+}
+
+fn query_name(cg : &Callgraph, idx : NodeIndex) -> String {
+    let name = cg.names(idx).pop().unwrap_or("");
+    name.split('(').next().unwrap_or(name).to_string()
+}
+
+fn time<F : FnMut()>(name : &'static str, runs : usize, mut f : F) -> BenchResult {
+    let start = Instant::now();
+    f();
+    BenchResult { name, runs, total: start.elapsed() }
+}
+
+pub fn run(cg : &Callgraph, filename : &str) -> Vec<BenchResult> {
+    let mut results = vec![];
+    let samples = sample_functions(cg, SAMPLES);
+    let names : Vec<String> = samples.iter().map(|&idx| query_name(cg, idx)).collect();
+    let none = HashSet::new();
+
+    // Build the lazily-created lookup tables up front, so they aren't
+    // charged to whichever query happens to come first.
+    if let Some(name) = names.first() {
+        cg.resolve(name);
+    }
+
+    let options = LoadOptions { quiet: true, ..Default::default() };
+    results.push(time("load", 1, || {
+        let _ = load_graph_with_options(filename, &options, None);
+    }));
+
+    results.push(time("resolve exact", names.len(), || {
+        for name in &names {
+            cg.resolve(name);
+        }
+    }));
+
+    // Chop off the end of each name, so that each prefix matches a handful of
+    // functions rather than the whole namespace.
+    let prefixes : Vec<String> = names.iter().map(|name| {
+        let len = name.chars().count().saturating_sub(2);
+        name.chars().take(len).collect::<String>() + "*"
+    }).collect();
+    results.push(time("resolve prefix", prefixes.len(), || {
+        for prefix in &prefixes {
+            cg.resolve(prefix);
+        }
+    }));
+
+    let regexes : Vec<String> = names.iter().take(5).map(|name| {
+        let word : String = name.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        format!("/{}/", &word[word.len().saturating_sub(6)..])
+    }).collect();
+    results.push(time("resolve regex", regexes.len(), || {
+        for regex in &regexes {
+            cg.resolve(regex);
+        }
+    }));
+
+    // Short routes go to something a couple of calls away; long routes are
+    // between unrelated functions, and are mostly searches that fail.
+    let short : Vec<(NodeIndex, HashSet<NodeIndex>)> = samples.iter().filter_map(|&src| {
+        let dst = cg.callees(src).into_iter().flat_map(|callee| cg.callees(callee)).last()?;
+        Some((src, [dst].iter().cloned().collect()))
+    }).collect();
+    results.push(time("short route", short.len(), || {
+        for (src, dst) in &short {
+            cg.any_route(*src, dst, &none, 0);
+        }
+    }));
+
+    let long : Vec<(NodeIndex, HashSet<NodeIndex>)> = samples.iter().zip(samples.iter().rev()).map(|(&src, &dst)| {
+        (src, [dst].iter().cloned().collect())
+    }).collect();
+    results.push(time("long route", long.len(), || {
+        for (src, dst) in &long {
+            cg.any_route(*src, dst, &none, 0);
+        }
+    }));
+
+    let origins = &samples[..samples.len().min(10)];
+    results.push(time("reachable callees", origins.len(), || {
+        for &origin in origins {
+            cg.reachable(&[origin], Direction::Callees, None, &none, 0);
+        }
+    }));
+    results.push(time("reachable callers", origins.len(), || {
+        for &origin in origins {
+            cg.reachable(&[origin], Direction::Callers, None, &none, 0);
+        }
+    }));
+
+    results
+}
+
+fn format_duration(d : Duration) -> String {
+    let nanos = d.as_nanos();
+    if nanos >= 1_000_000_000 {
+        format!("{:.2}s", d.as_secs_f64())
+    } else if nanos >= 1_000_000 {
+        format!("{:.2}ms", nanos as f64 / 1e6)
+    } else if nanos >= 1_000 {
+        format!("{:.2}us", nanos as f64 / 1e3)
+    } else {
+        format!("{}ns", nanos)
+    }
+}
+
+// Per-operation times in nanoseconds, keyed by benchmark name.
+pub fn save(results : &[BenchResult], filename : &str) -> std::io::Result<()> {
+    let mut obj = json::JsonValue::new_object();
+    for result in results {
+        obj[result.name] = (result.per_op().as_nanos() as u64).into();
+    }
+    fs::write(filename, obj.pretty(2))
+}
+
+pub fn load_baseline(filename : &str) -> Result<json::JsonValue, String> {
+    let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+    json::parse(&text).map_err(|e| e.to_string())
+}
+
+pub fn print_table(results : &[BenchResult], baseline : Option<&json::JsonValue>) {
+    print!("{:<20} {:>6} {:>10} {:>10}", "operation", "runs", "total", "per op");
+    if baseline.is_some() {
+        print!(" {:>10} {:>8}", "baseline", "change");
+    }
+    println!();
+    for result in results {
+        print!("{:<20} {:>6} {:>10} {:>10}", result.name, result.runs,
+               format_duration(result.total), format_duration(result.per_op()));
+        if let Some(baseline) = baseline {
+            match baseline[result.name].as_u64() {
+                Some(before) if before > 0 => {
+                    let now = result.per_op().as_nanos() as f64;
+                    print!(" {:>10} {:>+7.1}%", format_duration(Duration::from_nanos(before)),
+                           (now / before as f64 - 1.0) * 100.0);
+                },
+                _ => print!(" {:>10} {:>8}", "-", "-"),
+            }
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testgraph::{self, Params};
+    use std::path::PathBuf;
+
+    fn generated(name : &str, params : &Params) -> (Callgraph, PathBuf) {
+        let path = std::env::temp_dir().join(format!("bench-{}-{}.txt", name, std::process::id()));
+        testgraph::write_file(params, path.to_str().unwrap()).unwrap();
+        let options = LoadOptions { quiet: true, ..Default::default() };
+        let cg = load_graph_with_options(path.to_str().unwrap(), &options, None).unwrap();
+        (cg, path)
+    }
+
+    #[test]
+    fn runs_every_operation() {
+        let (cg, path) = generated("small", &Params { functions: 200, ..Default::default() });
+        let results = run(&cg, path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        let names : Vec<&str> = results.iter().map(|result| result.name).collect();
+        assert_eq!(names, vec!["load", "resolve exact", "resolve prefix", "resolve regex",
+                               "short route", "long route", "reachable callees", "reachable callers"]);
+        assert!(results.iter().all(|result| result.runs > 0));
+    }
+
+    // The same timings as the bench command, on a generated graph of about
+    // the size of a real one, so that they can be compared between versions
+    // without a browser build to hand:
+    //
+    //   cargo test --release --bin cgtraverse -- --ignored --nocapture timings
+    //
+    // Set BENCH_BASELINE to a file to compare against, and BENCH_SAVE to a
+    // file to save the results to.
+    #[test]
+    #[ignore]
+    fn timings() {
+        let params = Params { functions: 200_000, ..Default::default() };
+        let (cg, path) = generated("timings", &params);
+        let results = run(&cg, path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        let baseline = std::env::var("BENCH_BASELINE").ok().map(|file| load_baseline(&file).unwrap());
+        print_table(&results, baseline.as_ref());
+        if let Ok(file) = std::env::var("BENCH_SAVE") {
+            save(&results, &file).unwrap();
+        }
+    }
+}
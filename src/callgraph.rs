@@ -1,13 +1,33 @@
 pub use petgraph::graph::{
+    Edge,
     Graph,
+    Node,
     NodeIndex,
     EdgeIndex,
-    EdgeReference
 };
 
+use petgraph::Direction::Incoming;
+use petgraph::algo::tarjan_scc;
 use petgraph::visit::{EdgeRef, IntoNodeReferences};
 use regex::Regex;
+use crate::annotations::Annotations;
+use crate::csr::Csr;
+use crate::query::{self, Expr};
+use crate::rules;
+use crate::signature;
+use crate::namefmt::{self, Field, NameFormat};
+use crate::reach::ReachIndex;
+use crate::trie::Trie;
+use fixedbitset::FixedBitSet;
+use memchr::memchr_iter;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::collections::{
+    BTreeMap,
+    BinaryHeap,
     HashMap,
     HashSet,
     VecDeque
@@ -19,57 +39,459 @@ pub struct PropertySet {
     pub any : u32
 }
 
+// An edge stands for every call from one function to another, so a property
+// can hold for all of those calls or only some. Routes avoiding a property
+// normally skip an edge only when it holds for `All` of them (eg only when
+// every call is GC-suppressed); with `Any`, a single matching call is enough.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PropertyMatch {
+    All,
+    Any,
+}
+
+impl PropertyMatch {
+    pub fn blocks(self, props : PropertySet, avoid_props : u32) -> bool {
+        let bits = match self {
+            PropertyMatch::All => props.all,
+            PropertyMatch::Any => props.any,
+        };
+        bits & avoid_props != 0
+    }
+}
+
+// What stands behind a node. Calls through function pointers and virtual
+// method fields are loaded from I and V lines as nodes named after the
+// variable or field ("VARIABLE fnptr", "CLASS Foo FIELD bar"), with an edge
+// from each caller; everything else is a real function.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NodeKind {
+    Direct,
+    Indirect,
+    Virtual,
+}
+
+impl NodeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            NodeKind::Direct => "direct",
+            NodeKind::Indirect => "indirect",
+            NodeKind::Virtual => "virtual",
+        }
+    }
+}
+
+// How traversals treat calls through virtual methods (V lines), since
+// different investigations need different assumptions: every method of that
+// name might be called (Conservative), only those in the called class and
+// its subclasses as given by the type info (Refined), or none (Ignore).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VirtualMode {
+    Conservative,
+    Refined,
+    Ignore,
+}
+
+impl VirtualMode {
+    const ALL : [VirtualMode; 3] = [VirtualMode::Conservative, VirtualMode::Refined, VirtualMode::Ignore];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VirtualMode::Conservative => "conservative",
+            VirtualMode::Refined => "refined",
+            VirtualMode::Ignore => "ignore",
+        }
+    }
+
+    pub fn parse(name : &str) -> Option<VirtualMode> {
+        VirtualMode::ALL.iter().cloned().find(|mode| mode.name() == name)
+    }
+}
+
+// "CLASS Foo FIELD bar" to ("Foo", "bar").
+fn virtual_method(name : &str) -> Option<(&str, &str)> {
+    let (class, field) = name.strip_prefix("CLASS ")?.split_once(" FIELD ")?;
+    Some((class.trim(), field.trim()))
+}
+
+// There is one of these per edge, in both the graph and the CSR, so keep an
+// eye on the layout: 8 bytes of properties plus petgraph's u32 links, with no
+// padding anywhere.
+const _ : () = assert!(size_of::<PropertySet>() == 8);
+const _ : () = assert!(size_of::<Edge<PropertySet>>() == 24);
+const _ : () = assert!(size_of::<NodeIndex>() == 4);
+
+// Queries all take &self, with any caching behind OnceLock or RwLock, so one
+// graph can be shared between server threads or Python threads with the GIL
+// released. Keep it that way.
+fn assert_send_sync<T : Send + Sync>() {}
+const _ : fn() = assert_send_sync::<Callgraph>;
+
+fn query_string(expr : &Expr) -> Result<&str, String> {
+    match expr {
+        Expr::Word(s) | Expr::Str(s) => Ok(s),
+        _ => Err(format!("expected a name or string, got {:?}", expr)),
+    }
+}
+
 pub struct Callgraph {
     // Graph of mangled function names associated with their "limits" bit
     // vectors. NodeIndexes in this graph are also used as IDs.
     pub graph : Graph<String, PropertySet>,
 
-    pub roots : Option<HashSet<NodeIndex>>,
-    pub sinks : Option<HashSet<NodeIndex>>,
+    // Functions with no callers (or callees), plus one function from each
+    // cycle that nothing outside it calls (or is called by). Computed on
+    // first use.
+    roots : OnceLock<HashSet<NodeIndex>>,
+    sinks : OnceLock<HashSet<NodeIndex>>,
 
-    root : NodeIndex,
-    sink : NodeIndex,
-
-    // Graph of the reverse relation (function callers).
-    pub caller_graph : Graph<NodeIndex, PropertySet>,
+    // Pick roots the way older versions did, which may include cycle members
+    // that something outside the cycle calls, and depends on load order. See
+    // compute_roots().
+    legacy_roots : bool,
 
     // Table mapping from stems (simple function names) to all functions with
     // that name.
     pub stem_table : HashMap<String, Vec<NodeIndex>>,
 
+    // Stems and qualified names, for prefix searches and completion.
+    // Built on first use, since most sessions never need it.
+    name_trie : OnceLock<Trie>,
+
+    // Function ids sorted by mangled name, for exact lookups. Also built on
+    // first use.
+    mangled_order : OnceLock<Vec<u32>>,
+
     // Map from IDs to all the known unmangled names of a function.
     pub alt_names : Vec<Vec<String>>,
 
     // Bits to descriptions of properties.
     pub property_names : HashMap<u32, String>,
+
+    // Other names for properties, eg "suppressed" for GC_SUPPRESSED, to the
+    // name they stand for. Accepted wherever a property name is, but output
+    // always uses the property's own name.
+    pub property_aliases : HashMap<String, String>,
+
+    // Traversal-friendly copy of the graph, built on first query and thrown
+    // away whenever the graph changes.
+    frozen : OnceLock<Csr>,
+
+    // Optional reachability index, see build_reach_index(). Also discarded
+    // whenever the graph changes.
+    reach_index : Option<ReachIndex>,
+
+    // Source locations ("file.cpp:123") of calls, where known.
+    pub call_sites : HashMap<EdgeIndex, String>,
+
+    // How many times a call appeared in the input, for calls that appeared
+    // more than once (one D line per call site).
+    pub call_counts : HashMap<EdgeIndex, u32>,
+
+    // Let resolve() find the synthetic nodes, for debugging.
+    pub show_synthetic : bool,
+
+    // Functions known to GC directly. Anything that can reach one of these
+    // without going through a GC-suppressed call can GC too.
+    pub gc_functions : HashSet<NodeIndex>,
+
+    // Distance from each function to the nearest GC function, for guiding
+    // gc_route, for each virtual mode (which changes what calls what).
+    gc_distances : [OnceLock<Vec<u32>>; 3],
+
+    // Sets of functions materialized by rules (see rules.rs), resolvable as
+    // @name. Not recomputed when the graph changes.
+    named_sets : RwLock<HashMap<String, Vec<NodeIndex>>>,
+
+    // User tags and notes, keyed by mangled name.
+    annotations : RwLock<Annotations>,
+
+    // How Normal descriptions name a function, if not the default. See
+    // namefmt.rs.
+    name_format : RwLock<Option<NameFormat>>,
+
+    // Calls from virtual method nodes to the methods they might dispatch to,
+    // true for those the class hierarchy allows. See VirtualMode.
+    pub dispatch : HashMap<EdgeIndex, bool>,
+
+    // Direct subclasses of each class, from the type info.
+    pub subclasses : HashMap<String, Vec<String>>,
+
+    // The session's virtual mode, for queries that don't ask for one of
+    // their own. Route queries take theirs as a parameter instead of
+    // switching this, since other threads may be using the graph.
+    virtual_mode : AtomicU8,
+
+    // If set, neighbors are visited in id order rather than the order the
+    // calls were added, so that ties (which of two equally short routes,
+    // which function of a cycle is its root) come out the same whatever the
+    // load order, and the seed drives anything random. See set_seed().
+    seed : Option<u64>,
+}
+
+// A step of a route as displayed, with runs of calls inside one recursion
+// cycle folded together.
+pub enum RouteStep {
+    Call(EdgeIndex),
+    // Calls that stay within a cycle of `functions` functions, ending where
+    // the route leaves it.
+    Cycle { functions : usize, calls : Vec<EdgeIndex> },
+}
+
+// One piece of two routes lined up against each other: functions they
+// share, in order, or a stretch where they go different ways, between the
+// shared functions on either side (if any).
+pub enum RouteDiff {
+    Same(Vec<NodeIndex>),
+    Diverged { first : Vec<NodeIndex>, second : Vec<NodeIndex> },
+}
+
+// Functions from an entry points file (see load_entry_points), by kind, and
+// the patterns that matched nothing.
+pub struct EntryPoints {
+    pub kinds : Vec<(String, Vec<NodeIndex>)>,
+    pub unmatched : Vec<String>,
+}
+
+// The roots by which kinds of entry point they are, and those that are none,
+// which tend to be static initializers and the like.
+pub struct RootClasses {
+    pub kinds : Vec<(String, Vec<NodeIndex>)>,
+    pub unclassified : Vec<NodeIndex>,
+}
+
+pub struct Impact {
+    pub callers : Vec<NodeIndex>,
+    pub namespaces : Vec<(String, usize)>,
+    pub roots : Vec<NodeIndex>,
+}
+
+// A weakly connected piece of the graph: functions joined by calls in either
+// direction. The representative is the one with the most calls in and out.
+pub struct Component {
+    pub functions : Vec<NodeIndex>,
+    pub representative : NodeIndex,
+}
+
+// Functions bucketed by top-level namespace, largest bucket first, with how
+// many functions are in each, and how many calls go from each bucket to
+// each other one (or within itself), keyed by bucket positions. "" is the
+// bucket of functions outside any namespace.
+pub struct NamespaceMatrix {
+    pub namespaces : Vec<(String, usize)>,
+    pub calls : BTreeMap<(usize, usize), usize>,
+}
+
+// Where a search was stopped by avoidances, most often first: each avoided
+// function by how many calls to it were not followed, and likewise each
+// avoided property (as a single bit).
+pub struct Blockers {
+    pub reached : usize,
+    pub functions : Vec<(NodeIndex, usize)>,
+    pub properties : Vec<(u32, usize)>,
+}
+
+// Avoided functions and properties, as given to reachable() and the route
+// searches.
+#[derive(Default)]
+pub struct AvoidConfig {
+    pub functions : HashSet<NodeIndex>,
+    pub props : u32,
 }
 
+// Everything route_with_options() can be asked for, so that a caller (eg
+// Python) can set any combination rather than picking among the route
+// functions.
+pub struct RouteOptions {
+    pub avoid : AvoidConfig,
+    pub matching : PropertyMatch,
+    // Calls with any of these at any call site are not followed at all.
+    pub clean : u32,
+    // The most calls a route may have.
+    pub max_depth : Option<usize>,
+    // What a call through a function pointer or virtual method costs, where
+    // a direct call costs 1. Raising it prefers routes of direct calls.
+    pub indirect_cost : u64,
+    // Give up after visiting this many functions.
+    pub budget : Option<usize>,
+    // Which virtual calls to follow, if not the graph's current mode.
+    pub virtual_mode : Option<VirtualMode>,
+}
+
+impl Default for RouteOptions {
+    fn default() -> RouteOptions {
+        RouteOptions {
+            avoid: AvoidConfig::default(),
+            matching: PropertyMatch::All,
+            clean: 0,
+            max_depth: None,
+            indirect_cost: 1,
+            budget: None,
+            virtual_mode: None,
+        }
+    }
+}
+
+// What changes between two avoid configurations: the functions reachable
+// under the first but not the second, and the other way around.
+pub struct ReachDiff {
+    pub reached_a : usize,
+    pub reached_b : usize,
+    pub lost : Vec<NodeIndex>,
+    pub gained : Vec<NodeIndex>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Callees,
+    Callers,
+}
+
+// What a traversal visitor wants to happen after seeing a node.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VisitAction {
+    Continue,
+    Skip, // Do not expand this node's neighbors.
+    Ignore, // As if this call weren't there; the node may be reached another way.
+    Stop,
+}
+
+#[derive(Copy, Clone)]
 pub enum DescriptionBrevity {
     _Brief,
     Normal,
     Verbose,
 }
 
-lazy_static! {
-    static ref STEM_RE : Regex = Regex::new(r"([\w_]+)\(").unwrap();
+const NO_EDGE : u32 = u32::MAX;
+
+// Below this, a route search is quick enough that starting threads for it
+// would cost more than it saves.
+const PARALLEL_ROUTE_MIN_NODES : usize = 100_000;
+
+// Per-node working storage for traversals, kept per thread and reused across
+// queries so that back-to-back queries on a large graph don't each allocate
+// and zero several node-sized tables. Only the bits touched by the previous
+// query are cleared, so the cost is proportional to the nodes visited.
+#[derive(Default)]
+struct TraversalScratch {
+    marked : FixedBitSet,
+    is_origin : FixedBitSet,
+    closed : FixedBitSet,
+    touched : Vec<u32>,
+    // The edge that led to each node, and how far it is from the origin.
+    // Only meaningful for marked nodes, so never need clearing.
+    pred : Vec<u32>,
+    depth : Vec<u32>,
+    work : VecDeque<(NodeIndex, usize)>,
+}
+
+impl TraversalScratch {
+    fn prepare(&mut self, node_count : usize) {
+        for idx in self.touched.iter().map(|&idx| idx as usize) {
+            self.marked.set(idx, false);
+            self.is_origin.set(idx, false);
+            self.closed.set(idx, false);
+        }
+        self.touched.clear();
+        self.work.clear();
+        if self.marked.len() < node_count {
+            self.marked.grow(node_count);
+            self.is_origin.grow(node_count);
+            self.closed.grow(node_count);
+        }
+        if self.pred.len() < node_count {
+            self.pred.resize(node_count, NO_EDGE);
+            self.depth.resize(node_count, 0);
+        }
+    }
+
+    // Returns false if already marked.
+    fn mark(&mut self, idx : usize) -> bool {
+        if self.marked.contains(idx) {
+            return false;
+        }
+        self.marked.insert(idx);
+        self.touched.push(idx as u32);
+        true
+    }
+}
+
+thread_local! {
+    static SCRATCH : RefCell<TraversalScratch> = RefCell::new(TraversalScratch::default());
+}
+
+fn with_scratch<R>(node_count : usize, f : impl FnOnce(&mut TraversalScratch) -> R) -> R {
+    SCRATCH.with(|cell| match cell.try_borrow_mut() {
+        Ok(mut scratch) => {
+            scratch.prepare(node_count);
+            f(&mut scratch)
+        },
+        // Already in use further up the stack.
+        Err(_) => {
+            let mut scratch = TraversalScratch::default();
+            scratch.prepare(node_count);
+            f(&mut scratch)
+        }
+    })
+}
+
+// The run of identifier characters (plus any of `extra`) just before the
+// first `(` that has one, eg `foo` in `int foo(int)`. This is called for every
+// unmangled name during loading, so it avoids going through a regex. Anything
+// non-ASCII counts as an identifier character, as it can in C++, which also
+// keeps the result on a character boundary.
+fn name_before_paren<'a>(raw : &'a str, extra : &[u8]) -> Option<&'a str> {
+    let bytes = raw.as_bytes();
+    for paren in memchr_iter(b'(', bytes) {
+        let start = bytes[..paren].iter().rposition(|&c| {
+            !(c.is_ascii_alphanumeric() || c == b'_' || !c.is_ascii() || extra.contains(&c))
+        }).map_or(0, |i| i + 1);
+        if start < paren {
+            return Some(&raw[start..paren]);
+        }
+    }
+    None
 }
 
 fn stem(raw : &str) -> &str {
-    match STEM_RE.captures(raw) {
-        Some(m) => m.get(1).unwrap().as_str(),
-        None => raw
+    name_before_paren(raw, b"").unwrap_or(raw)
+}
+
+// The names a function can be looked up by: its stem plus every qualified
+// suffix of its name, eg `collect`, `GC::collect`, `js::GC::collect`.
+pub fn lookup_keys(raw : &str) -> Vec<&str> {
+    let qualified = match name_before_paren(raw, b":~") {
+        Some(name) if !name.trim_start_matches(':').is_empty() => name.trim_start_matches(':'),
+        _ => return vec![stem(raw)],
+    };
+    let mut keys = vec![qualified];
+    let mut rest = qualified;
+    while let Some(pos) = rest.find("::") {
+        rest = &rest[pos+2..];
+        if !rest.is_empty() {
+            keys.push(rest);
+        }
     }
+    keys
 }
 
 pub enum Matcher<'a> {
     Substring(&'a str),
     Pattern(Regex),
+    Tag(&'a str),
 }
 
 impl<'a> Matcher<'a> {
     pub fn new(pattern : &str) -> Option<Matcher> {
-        if &pattern[0..1] == "/" && &pattern[pattern.len()-1..] == "/" {
-            let pattern = &pattern[1..pattern.len()-1];
+        // No C++ name starts with '#', and demangled names only have one in
+        // eg "{lambda()#1}", so this cannot shadow a substring search.
+        if let Some(tag) = pattern.strip_prefix("#tag:") {
+            return Some(Matcher::Tag(tag));
+        }
+        if pattern.is_empty() {
+            return None;
+        }
+        if let Some(pattern) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
             if let Ok(matcher) = Regex::new(pattern) {
                 Some(Matcher::Pattern(matcher))
             } else {
@@ -80,7 +502,14 @@ impl<'a> Matcher<'a> {
         }
     }
 
+    pub fn regex(pattern : &str) -> Option<Matcher<'a>> {
+        Regex::new(pattern).ok().map(Matcher::Pattern)
+    }
+
     pub fn is_match(&self, cg : &Callgraph, idx : NodeIndex) -> bool {
+        if let Matcher::Tag(tag) = self {
+            return cg.annotations().has_tag(&cg.graph[idx], tag);
+        }
         for name in cg.names(idx) {
             match self {
                 Matcher::Substring(sub) => {
@@ -92,94 +521,460 @@ impl<'a> Matcher<'a> {
                     if re.is_match(name) {
                         return true;
                     }
-                }
+                },
+                Matcher::Tag(_) => {}
             }
         }
         false
     }
 }
 
+// Incremental version of Callgraph::reachable(), producing functions in the
+// same order as they are reached, so that a caller can start printing (or
+// give up) long before a traversal from a hub completes. It does not borrow
+// the graph between steps, so it can be resumed later, eg from a Python
+// iterator.
+pub struct ReachableWalk {
+    direction : Direction,
+    max_depth : Option<usize>,
+    avoid : HashSet<NodeIndex>,
+    avoid_props : u32,
+    seen : FixedBitSet,
+    work : VecDeque<(NodeIndex, usize)>,
+    ready : VecDeque<NodeIndex>,
+}
+
+impl ReachableWalk {
+    pub fn new(
+        origins : &[NodeIndex],
+        direction : Direction,
+        max_depth : Option<usize>,
+        avoid : HashSet<NodeIndex>,
+        avoid_props : u32
+    ) -> ReachableWalk
+    {
+        ReachableWalk {
+            direction,
+            max_depth,
+            avoid,
+            avoid_props,
+            seen: FixedBitSet::with_capacity(0),
+            work: origins.iter().map(|&idx| (idx, 0)).collect(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    pub fn next(&mut self, cg : &Callgraph) -> Option<NodeIndex> {
+        if self.seen.len() < cg.graph.node_count() {
+            self.seen.grow(cg.graph.node_count());
+        }
+        loop {
+            if let Some(idx) = self.ready.pop_front() {
+                return Some(idx);
+            }
+            let (src, depth) = self.work.pop_front()?;
+            if self.max_depth.is_some_and(|max| depth >= max) { continue; }
+            for (dst, _, props) in cg.neighbor_edges(src, self.direction) {
+                if self.avoid.contains(&dst) { continue; }
+                if (self.avoid_props & props.all) != 0 { continue; }
+                if self.seen.put(dst.index()) { continue; }
+                self.ready.push_back(dst);
+                self.work.push_back((dst, depth + 1));
+            }
+        }
+    }
+}
+
 impl Callgraph {
     pub fn new() -> Callgraph {
         let mut cg = Callgraph {
             graph: Graph::new(),
-	    roots: None,
-	    sinks: None,
-            root: NodeIndex::new(0),
-            sink: NodeIndex::new(0),
-            caller_graph: Graph::new(),
+            roots: OnceLock::new(),
+            sinks: OnceLock::new(),
+            legacy_roots: false,
             stem_table: HashMap::new(),
+            name_trie: OnceLock::new(),
+            mangled_order: OnceLock::new(),
             alt_names: Vec::new(),
             property_names: HashMap::new(),
+            property_aliases: HashMap::new(),
+            frozen: OnceLock::new(),
+            reach_index: None,
+            call_sites: HashMap::new(),
+            call_counts: HashMap::new(),
+            show_synthetic: false,
+            gc_functions: HashSet::new(),
+            gc_distances: Default::default(),
+            named_sets: RwLock::new(HashMap::new()),
+            annotations: RwLock::new(Annotations::default()),
+            name_format: RwLock::new(None),
+            dispatch: HashMap::new(),
+            subclasses: HashMap::new(),
+            virtual_mode: AtomicU8::new(VirtualMode::Conservative as u8),
+            seed: None,
         };
-        let idx = cg.graph.add_node(String::from("(dummy node zero)"));
-        cg.caller_graph.add_node(idx);
+        cg.graph.add_node(String::from("(dummy node zero)"));
         cg.alt_names.push(Vec::new());
         cg
     }
 
     pub fn add_function(&mut self, name : &str) -> NodeIndex {
+        self.invalidate_caches();
         let idx = self.graph.add_node(String::from(name));
-        self.caller_graph.add_node(idx);
-        self.alt_names.push(Vec::new());
+        self.mangled_order.take();
+        // Names may have been given before the function itself.
+        if self.alt_names.len() < self.graph.node_count() {
+            self.alt_names.resize(self.graph.node_count(), Vec::new());
+        }
         idx
     }
 
-    pub fn add_unmangled_name(&mut self, id : usize, unmangled : &str) {
+    // Drop everything derived from the graph's structure.
+    fn invalidate_caches(&mut self) {
+        self.frozen.take();
+        self.reach_index = None;
+        self.gc_distances.iter_mut().for_each(|dist| { dist.take(); });
+        self.roots.take();
+        self.sinks.take();
+    }
+
+    pub fn add_unmangled_name(&mut self, idx : NodeIndex, unmangled : &str) {
         let func_stem = stem(unmangled);
-        self.stem_table.entry(String::from(func_stem)).or_default().push(NodeIndex::new(id));
-        self.alt_names[id].push(unmangled.to_string());
+        self.stem_table.entry(String::from(func_stem)).or_default().push(idx);
+        self.name_trie.take();
+        if idx.index() >= self.alt_names.len() {
+            self.alt_names.resize(idx.index() + 1, Vec::new());
+        }
+        self.alt_names[idx.index()].push(unmangled.to_string());
+    }
+
+    pub fn unmangled_names(&self, idx : NodeIndex) -> &[String] {
+        self.alt_names.get(idx.index()).map_or(&[], |names| names.as_slice())
+    }
+
+    pub fn add_edge(&mut self, src : NodeIndex, dst : NodeIndex, limit : PropertySet) -> EdgeIndex {
+        self.invalidate_caches();
+        self.graph.add_edge(src, dst, limit)
+    }
+
+    // One more call along `edge`, for a call that is repeated in the input.
+    pub fn count_call(&mut self, edge : EdgeIndex) {
+        *self.call_counts.entry(edge).or_insert(1) += 1;
+    }
+
+    pub fn call_count(&self, edge : EdgeIndex) -> u32 {
+        self.call_counts.get(&edge).cloned().unwrap_or(1)
+    }
+
+    // Read call site locations from lines of "src dst file:line", using the
+    // function ids of the graph file. Returns how many calls were found.
+    pub fn load_call_sites(&mut self, filename : &str) -> Result<usize, String> {
+        let text = std::fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        let mut found = 0;
+        for (lineno, line) in text.lines().enumerate() {
+            let mut fields = line.splitn(3, ' ');
+            let (src, dst, location) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(src), Some(dst), Some(location)) => (src, dst, location.trim()),
+                _ if line.trim().is_empty() => continue,
+                _ => return Err(format!("{}:{}: expected 'src dst location'", filename, lineno + 1)),
+            };
+            let id = |s : &str| s.parse::<usize>().ok().filter(|&id| id < self.graph.node_count()).map(NodeIndex::new);
+            match (id(src), id(dst)) {
+                (Some(src), Some(dst)) => {
+                    if let Some(edge) = self.graph.find_edge(src, dst) {
+                        self.call_sites.insert(edge, location.to_string());
+                        found += 1;
+                    }
+                },
+                _ => return Err(format!("{}:{}: bad function id", filename, lineno + 1)),
+            }
+        }
+        Ok(found)
+    }
+
+    // Connect each virtual method node ("CLASS Foo FIELD bar", from V lines)
+    // to every method named bar, noting which of them the class hierarchy
+    // allows. Returns how many calls were added.
+    pub fn resolve_virtual_calls(&mut self, nodes : &[NodeIndex]) -> usize {
+        let mut added = 0;
+        for &node in nodes {
+            let method = match virtual_method(&self.graph[node]) {
+                Some((_, method)) => method.to_string(),
+                None => continue,
+            };
+            for func in self.stem_table.get(&method).cloned().unwrap_or_default() {
+                if self.scope(func).is_some() && self.graph.find_edge(node, func).is_none() {
+                    let edge = self.add_edge(node, func, PropertySet { all: 0, any: 0 });
+                    self.dispatch.insert(edge, false);
+                    added += 1;
+                }
+            }
+        }
+        self.classify_dispatch();
+        added
+    }
+
+    // Read the class hierarchy from a JSON file with a "Subclasses" table of
+    // class names to their direct subclasses. Returns how many classes have
+    // subclasses.
+    pub fn load_type_info(&mut self, filename : &str) -> Result<usize, String> {
+        let text = std::fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        let parsed = json::parse(&text).map_err(|e| format!("{}: {}", filename, e))?;
+        let table = match &parsed["Subclasses"] {
+            json::JsonValue::Object(table) => table,
+            _ => return Err(format!("{}: 'Subclasses' table required", filename)),
+        };
+        for (class, subclasses) in table.iter() {
+            let names = subclasses.members().filter_map(|name| name.as_str()).map(String::from);
+            self.subclasses.entry(class.to_string()).or_default().extend(names);
+        }
+        self.classify_dispatch();
+        Ok(self.subclasses.len())
+    }
+
+    // The class and everything derived from it.
+    fn class_and_subclasses(&self, class : &str) -> HashSet<String> {
+        let mut found = HashSet::from([class.to_string()]);
+        let mut work = vec![class];
+        while let Some(class) = work.pop() {
+            for sub in self.subclasses.get(class).into_iter().flatten() {
+                if found.insert(sub.clone()) {
+                    work.push(sub);
+                }
+            }
+        }
+        found
+    }
+
+    fn classify_dispatch(&mut self) {
+        let mut related = HashMap::<String, HashSet<String>>::new();
+        let edges : Vec<EdgeIndex> = self.dispatch.keys().cloned().collect();
+        for edge in edges {
+            let (node, func) = self.graph.edge_endpoints(edge).unwrap();
+            let class = match virtual_method(&self.graph[node]) {
+                Some((class, _)) => class,
+                None => continue,
+            };
+            let classes = related.entry(class.to_string()).or_insert_with(|| self.class_and_subclasses(class));
+            let allowed = self.scope(func).is_some_and(|scope| classes.contains(scope));
+            self.dispatch.insert(edge, allowed);
+        }
+        self.gc_distances[VirtualMode::Refined as usize].take();
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    // Switch to (or, with None, out of) deterministic mode.
+    pub fn set_seed(&mut self, seed : Option<u64>) {
+        self.seed = seed;
+        self.invalidate_caches();
+    }
+
+    pub fn set_legacy_roots(&mut self, legacy : bool) {
+        self.legacy_roots = legacy;
+        self.roots.take();
+        self.sinks.take();
+    }
+
+    pub fn virtual_mode(&self) -> VirtualMode {
+        VirtualMode::ALL[self.virtual_mode.load(Ordering::Relaxed) as usize]
+    }
+
+    pub fn set_virtual_mode(&self, mode : VirtualMode) {
+        self.virtual_mode.store(mode as u8, Ordering::Relaxed);
+    }
+
+    // The dummy node zero, or a function dropped at load time (see
+    // filter_out), neither of which is a real function.
+    pub fn is_synthetic(&self, idx : NodeIndex) -> bool {
+        idx.index() == 0 || self.graph[idx].is_empty()
+    }
+
+    // Blank out a function rejected by LoadOptions::node_filter. It stays as
+    // a nameless placeholder so that everything else keeps its id from the
+    // file. It must not have any calls yet.
+    pub fn filter_out(&mut self, idx : NodeIndex) {
+        for name in std::mem::take(&mut self.alt_names[idx.index()]) {
+            if let Some(funcs) = self.stem_table.get_mut(stem(&name)) {
+                funcs.retain(|&f| f != idx);
+                if funcs.is_empty() {
+                    self.stem_table.remove(stem(&name));
+                }
+            }
+        }
+        self.graph[idx] = String::new();
+        self.name_trie.take();
+        self.mangled_order.take();
+    }
+
+    // Give a placeholder for a function used before it was declared its
+    // real name.
+    pub fn rename_function(&mut self, idx : NodeIndex, name : &str) {
+        self.graph[idx] = name.to_string();
+        self.mangled_order.take();
+    }
+
+    fn is_hidden(&self, idx : NodeIndex) -> bool {
+        !self.show_synthetic && self.is_synthetic(idx)
+    }
+
+    pub fn node_kind(&self, idx : NodeIndex) -> NodeKind {
+        let name = &self.graph[idx];
+        if name.starts_with("VARIABLE ") {
+            NodeKind::Indirect
+        } else if name.starts_with("CLASS ") || name.starts_with("FIELD ") {
+            NodeKind::Virtual
+        } else {
+            NodeKind::Direct
+        }
+    }
+
+    // Indirect and virtual call targets, with how many functions call each,
+    // most called first.
+    pub fn indirect_targets(&self, filter : Option<&Matcher>) -> Vec<(NodeIndex, usize)> {
+        let mut targets : Vec<(NodeIndex, usize)> = self.node_ids()
+            .filter(|&idx| self.node_kind(idx) != NodeKind::Direct)
+            .filter(|&idx| filter.is_none_or(|m| m.is_match(self, idx)))
+            .map(|idx| {
+                let mut callers : Vec<NodeIndex> = self.graph.neighbors_directed(idx, Incoming).collect();
+                callers.sort();
+                callers.dedup();
+                (idx, callers.len())
+            })
+            .collect();
+        targets.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        targets
+    }
+
+    // All real function ids.
+    pub fn node_ids<'a>(&'a self) -> impl Iterator<Item = NodeIndex> + 'a {
+        self.graph.node_indices().filter(move |&idx| !self.is_synthetic(idx))
+    }
+
+    // All calls. Node zero never has any, so these are all between real
+    // functions.
+    pub fn edge_ids(&self) -> impl Iterator<Item = EdgeIndex> + '_ {
+        self.graph.edge_indices()
     }
 
-    pub fn add_edge(&mut self, src : NodeIndex, dst : NodeIndex, limit : PropertySet) {
-        self.graph.add_edge(src, dst, limit);
-        self.caller_graph.add_edge(dst, src, limit);
+    // Find a function with exactly the given mangled or unmangled name.
+    pub fn lookup_name(&self, name : &str) -> Option<NodeIndex> {
+        let order = self.mangled_order();
+        let pos = order.partition_point(|&id| self.graph[NodeIndex::new(id as usize)].as_str() < name);
+        let mangled = order.get(pos).map(|&id| NodeIndex::new(id as usize)).filter(|&idx| self.graph[idx] == name);
+        let unmangled = self.stem_table.get(stem(name)).and_then(|funcs| {
+            funcs.iter().cloned().filter(|&idx| self.unmangled_names(idx).iter().any(|n| n == name)).min()
+        });
+        // The lowest id, whichever way it matched.
+        match (mangled, unmangled) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
     }
 
     pub fn names(&self, idx : NodeIndex) -> Vec<&str> {
         let mut result = Vec::<&str>::new();
         result.push(&self.graph[idx]);
-        for name in &self.alt_names[idx.index()] {
+        for name in self.unmangled_names(idx) {
             result.push(name);
         }
         result
     }
 
+    fn name_field(&self, idx : NodeIndex, field : Field) -> String {
+        let unmangled = self.unmangled_names(idx).first().unwrap_or(&self.graph[idx]);
+        let sig = signature::parse(unmangled);
+        match field {
+            Field::Id => idx.index().to_string(),
+            Field::Mangled => self.graph[idx].clone(),
+            Field::Unmangled => unmangled.clone(),
+            Field::Stem => stem(unmangled).to_string(),
+            Field::Qualified => sig.map_or_else(|| stem(unmangled).to_string(), |sig| sig.name.to_string()),
+            Field::Scope => self.scope(idx).unwrap_or("").to_string(),
+            Field::Params => sig.map_or(String::new(), |sig| format!("({})", sig.params.join(", "))),
+            Field::Kind => self.node_kind(idx).name().to_string(),
+        }
+    }
+
+    // Set the template for naming functions (see namefmt.rs), or go back to
+    // the default with None.
+    pub fn set_name_format(&self, template : Option<&str>) -> Result<(), String> {
+        *self.name_format.write().unwrap() = match template {
+            Some(template) => Some(NameFormat::parse(template)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    pub fn name_format(&self) -> String {
+        self.name_format.read().unwrap().as_ref().map_or(namefmt::DEFAULT.to_string(), |format| format.template.clone())
+    }
+
     pub fn name(&self, idx : NodeIndex, brevity : DescriptionBrevity) -> String {
         match brevity {
             DescriptionBrevity::_Brief => self.graph[idx].to_string(),
 
             DescriptionBrevity::Normal => {
-                let alt = &self.alt_names[idx.index()];
-                if alt.is_empty() {
-                    format!("#{} = {}", idx.index(), self.graph[idx])
-                } else {
-                    format!("#{} = {}", idx.index(), alt[0])
+                let alt = self.unmangled_names(idx);
+                let mut s = match &*self.name_format.read().unwrap() {
+                    Some(format) => format.render(|field| self.name_field(idx, field)),
+                    None if alt.is_empty() => format!("#{} = {}", idx.index(), self.graph[idx]),
+                    None => format!("#{} = {}", idx.index(), alt[0]),
+                };
+                if let Some(annotation) = self.annotations().get(&self.graph[idx]) {
+                    let mut parts = annotation.tags.clone();
+                    match annotation.notes.len() {
+                        0 => {},
+                        1 => parts.push("1 note".to_string()),
+                        n => parts.push(format!("{} notes", n)),
+                    }
+                    s += &format!(" {{{}}}", parts.join(", "));
                 }
+                s
             },
 
             DescriptionBrevity::Verbose => {
-                let mut s = format!("#{} = {}", idx.index(), self.graph[idx]);
-                for unmangled in &self.alt_names[idx.index()] {
+                let mut s = match &*self.name_format.read().unwrap() {
+                    Some(format) => format.render(|field| self.name_field(idx, field)),
+                    None => format!("#{} = {}", idx.index(), self.graph[idx]),
+                };
+                for unmangled in self.unmangled_names(idx) {
                     s += &("\n  ".to_owned() + unmangled);
                 }
+                if let Some(annotation) = self.annotations().get(&self.graph[idx]) {
+                    if !annotation.tags.is_empty() {
+                        s += &format!("\n  tags: {}", annotation.tags.join(", "));
+                    }
+                    for note in &annotation.notes {
+                        s += &format!("\n  note: {}", note);
+                    }
+                }
                 s
             },
         }
     }
 
+    // Names of the properties in `propset`, in bit order, and the bits that
+    // have no name (eg from a newer analysis than the property table).
+    pub fn split_property_set(&self, propset : u32) -> (Vec<&str>, u32) {
+        let mut known : Vec<(u32, &str)> = self.property_names.iter()
+            .filter(|(bit, _)| propset & **bit != 0)
+            .map(|(bit, name)| (*bit, name.as_str()))
+            .collect();
+        known.sort();
+        let unknown = known.iter().fold(propset, |rest, (bit, _)| rest & !bit);
+        (known.into_iter().map(|(_, name)| name).collect(), unknown)
+    }
+
+    // Unnamed bits are shown by their index, eg "bit3" for 0x8, which
+    // resolve_property accepts.
     pub fn describe_property_set(&self, propset : u32) -> String {
-        // Someday I'll find somebody to teach me Rust.
-        let mut s = self.property_names.iter().map(
-            |(bit, desc)| if (propset & bit) != 0 { Some(desc) } else { None }
-        ).into_iter().flatten().fold(String::new(), |mut a, b| {
-            a.reserve(b.len() + 1);
-            a.push_str(b);
-            a.push_str(",");
-            a
-        }).to_string();
-        s.pop();
-        s
+        let (known, unknown) = self.split_property_set(propset);
+        let mut names : Vec<String> = known.into_iter().map(String::from).collect();
+        names.extend((0..32).filter(|i| unknown & (1 << i) != 0).map(|i| format!("bit{}", i)));
+        names.join(",")
     }
 
     pub fn resolve_property(&self, query : &str) -> Option<u32> {
@@ -188,18 +983,65 @@ impl Callgraph {
                 return Some(*prop)
             }
         }
-        None
+        if let Some(name) = self.property_aliases.get(query) {
+            return self.property_names.iter().find(|(_, n)| *n == name).map(|(prop, _)| *prop);
+        }
+        query.strip_prefix("bit").and_then(|n| n.parse::<u32>().ok()).filter(|&n| n < 32).map(|n| 1 << n)
     }
     
     pub fn describe_edge(&self, idx : EdgeIndex, brevity : DescriptionBrevity) -> String {
         let target = self.graph.edge_endpoints(idx).unwrap().1;
-        let node_str = self.name(target, brevity);
+        let detail = self.describe_call(idx);
+        if detail.is_empty() {
+            self.name(target, brevity)
+        } else {
+            self.name(target, brevity) + " " + &detail
+        }
+    }
+
+    // The properties and location of a call, or "" if it has neither.
+    fn describe_call(&self, idx : EdgeIndex) -> String {
         let (any, all) = (self.graph[idx].any, self.graph[idx].all);
-        match any {
-            0 => node_str,
-            x if x == all => node_str + " [" + &self.describe_property_set(any) + "]",
-            _ => node_str + " [" + &self.describe_property_set(any) + ":" + &self.describe_property_set(all) + "]",
+        let props = match any {
+            0 => String::new(),
+            x if x == all => format!("[{}]", self.describe_property_set(any)),
+            _ => format!("[{}:{}]", self.describe_property_set(any), self.describe_property_set(all)),
+        };
+        let desc = match self.call_sites.get(&idx) {
+            Some(location) if props.is_empty() => format!("at {}", location),
+            Some(location) => format!("{} at {}", props, location),
+            None => props,
+        };
+        match self.call_count(idx) {
+            1 => desc,
+            n if desc.is_empty() => format!("x{}", n),
+            n => format!("{} x{}", desc, n),
+        }
+    }
+
+    // Several calls to the same function: the callee once, then each call's
+    // properties and location. A single call is described as by
+    // describe_edge.
+    pub fn describe_calls(&self, edges : &[EdgeIndex], brevity : DescriptionBrevity) -> String {
+        let (src, dst) = self.graph.edge_endpoints(edges[0]).unwrap();
+        let mut desc = if edges.len() == 1 {
+            self.describe_edge(edges[0], brevity)
+        } else {
+            let details : Vec<String> = edges.iter().map(|&edge| {
+                let detail = self.describe_call(edge);
+                if detail.is_empty() { "plain".to_string() } else { detail }
+            }).collect();
+            format!("{} ({} calls: {})", self.name(dst, brevity), edges.len(), details.join(", "))
+        };
+        if src == dst {
+            desc += " (self call)";
         }
+        desc
+    }
+
+    // The function with id `n`, if there is one.
+    pub fn resolve_id(&self, n : usize) -> Option<NodeIndex> {
+        if n < self.graph.node_count() && !self.is_hidden(NodeIndex::new(n)) { Some(NodeIndex::new(n)) } else { None }
     }
 
     pub fn resolve(&self, pattern : &str) -> Option<Vec<NodeIndex>> {
@@ -207,22 +1049,57 @@ impl Callgraph {
             return None;
         }
 
+        if let Some(name) = pattern.strip_prefix('@') {
+            if let Some(funcs) = self.named_sets.read().unwrap().get(name) {
+                return Some(funcs.clone());
+            }
+            // The GC functions are always a goal set, unless redefined.
+            if name == "GC" && !self.gc_functions.is_empty() {
+                let mut funcs : Vec<NodeIndex> = self.gc_functions.iter().cloned().collect();
+                funcs.sort();
+                return Some(funcs);
+            }
+            // As are the indirect call targets, so "avoid @indirect" keeps
+            // routes to direct calls.
+            if name == "indirect" {
+                return Some(self.node_ids().filter(|&idx| self.node_kind(idx) != NodeKind::Direct).collect());
+            }
+            return None;
+        }
+
+        // Narrowing overloads by parameters, eg Foo(JSContext
+        if !pattern.starts_with('/') {
+            if let Some(matches) = self.resolve_signature(pattern) {
+                return Some(matches);
+            }
+        }
+
         // Look for exact match with stem.
         if let Some(matches) = self.stem_table.get(pattern) {
             return Some(matches.to_vec());
         }
 
+        // Or with a qualified name, eg Class::method.
+        if let Some(matches) = self.trie().get(pattern) {
+            return Some(matches.to_vec());
+        }
+
+        // Prefix match if pattern is foo*
+        if let Some(prefix) = pattern.strip_suffix('*') {
+            let matches = self.trie().functions_with_prefix(prefix);
+            return if matches.is_empty() { None } else { Some(matches) };
+        }
+
         // Regex match if pattern is /.../
         let mut results = Vec::<NodeIndex>::new();
-        if &pattern[0..1] == "/" && &pattern[pattern.len()-1..] == "/" {
-            let pattern = &pattern[1..pattern.len()-1];
+        if let Some(pattern) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
             if let Ok(matcher) = Regex::new(pattern) {
                 for (idx, mangled) in self.graph.node_references() {
-                    if idx.index() == 0 { continue };
+                    if self.is_hidden(idx) { continue };
                     if matcher.is_match(mangled) {
                         results.push(idx);
                     } else {
-                        for unmangled in &self.alt_names[idx.index()] {
+                        for unmangled in self.unmangled_names(idx) {
                             if matcher.is_match(unmangled) {
                                 results.push(idx);
                                 break;
@@ -238,23 +1115,13 @@ impl Callgraph {
         }
 
         // #id match
-        if &pattern[0..1] == "#" {
-            return match &pattern[1..].parse::<usize>() {
-                Ok(n) => {
-                    if *n < self.graph.node_count() {
-                        Some(vec!(NodeIndex::new(*n)))
-                    } else {
-                        None
-                    }
-
-                },
-                Err(_) => None
-            };
+        if let Some(id) = pattern.strip_prefix('#') {
+            return id.parse::<usize>().ok().and_then(|n| self.resolve_id(n)).map(|idx| vec![idx]);
         }
 
         // Exact match against mangled name.
         for (idx, func) in self.graph.node_references() {
-            if pattern == func {
+            if pattern == func && !self.is_hidden(idx) {
                 results.push(idx);
             }
         }
@@ -277,118 +1144,1716 @@ impl Callgraph {
         None
     }
 
-    pub fn callees(&self, idx : NodeIndex) -> Vec<NodeIndex> {
-        self.graph.neighbors(idx).filter(|n| *n != self.sink).collect()
-    }
-
-    pub fn callee_edges(&self, idx : NodeIndex) -> Vec<EdgeIndex> {
-        self.graph.edges(idx).
-            filter(|e| e.target() != self.sink).
-            map(|e| e.id()).
-            collect()
-    }
-
-    pub fn callers(&self, idx : NodeIndex) -> Vec<NodeIndex> {
-        self.caller_graph.neighbors(idx).filter(|n| *n != self.root).collect()
+    // Functions matching a signature pattern (see signature.rs), or None if
+    // it isn't one or nothing matches.
+    fn resolve_signature(&self, pattern : &str) -> Option<Vec<NodeIndex>> {
+        let sig = signature::parse_pattern(pattern)?;
+        let candidates : Vec<NodeIndex> = if sig.name.is_empty() {
+            self.node_ids().collect()
+        } else {
+            self.resolve(&sig.name)?
+        };
+        let matches : Vec<NodeIndex> = candidates.into_iter().filter(|&idx| {
+            self.unmangled_names(idx).iter().any(|name| signature::parse(name).is_some_and(|s| sig.matches(&s)))
+        }).collect();
+        if matches.is_empty() { None } else { Some(matches) }
     }
 
-    pub fn caller_edges(&self, idx : NodeIndex) -> Vec<EdgeIndex> {
-        self.caller_graph.edges(idx).
-            filter(|e| e.target() != self.root).
-            map(|e| e.id()).
-            collect()
-    }
+    // What resolve(pattern) matches, each with the rule that matched it, for
+    // working out why a pattern catches more than intended. Follows the same
+    // order as resolve; unmangled names are numbered from 1.
+    pub fn explain_resolve(&self, pattern : &str) -> Option<Vec<(NodeIndex, String)>> {
+        let matches = self.resolve(pattern)?;
+        let alt_name = |idx : NodeIndex, test : &dyn Fn(&str) -> bool| -> Option<usize> {
+            self.unmangled_names(idx).iter().position(|name| test(name)).map(|i| i + 1)
+        };
+        let same = |reason : String| matches.iter().map(|&idx| (idx, reason.clone())).collect();
 
-    // FIXME: If there are many origins (eg AddRef), then this could do a large
-    // traversal N times. Sample: `route from AddRef to (GC) avoiding #2`.
-    pub fn any_route_from_one_of(
+        if let Some(name) = pattern.strip_prefix('@') {
+            return Some(same(if self.named_sets.read().unwrap().contains_key(name) {
+                format!("named set @{}", name)
+            } else if name == "GC" {
+                "GC functions".to_string()
+            } else {
+                "indirect call target".to_string()
+            }));
+        }
+        if !pattern.starts_with('/') {
+            if let Some(sig) = signature::parse_pattern(pattern) {
+                if self.resolve_signature(pattern).is_some() {
+                    return Some(matches.iter().map(|&idx| {
+                        let n = alt_name(idx, &|name| signature::parse(name).is_some_and(|s| sig.matches(&s)));
+                        (idx, format!("signature of unmangled name #{}", n.unwrap_or(0)))
+                    }).collect());
+                }
+            }
+        }
+        if self.stem_table.contains_key(pattern) {
+            return Some(same("stem exact".to_string()));
+        }
+        if self.trie().get(pattern).is_some() {
+            return Some(same("qualified name exact".to_string()));
+        }
+        if pattern.ends_with('*') {
+            return Some(same("qualified name prefix".to_string()));
+        }
+        if let Some(re) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            let re = Regex::new(re).ok()?;
+            return Some(matches.iter().map(|&idx| {
+                (idx, if re.is_match(&self.graph[idx]) {
+                    "regex on mangled name".to_string()
+                } else {
+                    format!("regex on unmangled name #{}", alt_name(idx, &|name| re.is_match(name)).unwrap_or(0))
+                })
+            }).collect());
+        }
+        if pattern.starts_with('#') {
+            return Some(same("id".to_string()));
+        }
+        Some(matches.iter().map(|&idx| {
+            (idx, if self.graph[idx] == pattern {
+                "mangled exact".to_string()
+            } else {
+                format!("substring of unmangled name #{}", alt_name(idx, &|name| name.contains(pattern)).unwrap_or(0))
+            })
+        }).collect())
+    }
+
+    // The class or namespace a function belongs to, from its first unmangled
+    // name, eg "js::GC" for js::GC::collect().
+    pub fn scope(&self, idx : NodeIndex) -> Option<&str> {
+        self.unmangled_names(idx).first()
+            .and_then(|name| signature::parse(name))
+            .and_then(|sig| signature::scope(sig.name))
+    }
+
+    // Evaluate a query expression (see query.rs) to a sorted list of
+    // functions.
+    pub fn query(&self, text : &str) -> Result<Vec<NodeIndex>, String> {
+        let mut result : Vec<NodeIndex> = self.eval_query(&query::parse(text)?)?.into_iter().filter(|&idx| {
+            !self.is_synthetic(idx)
+        }).collect();
+        result.sort();
+        Ok(result)
+    }
+
+    fn eval_query(&self, expr : &Expr) -> Result<HashSet<NodeIndex>, String> {
+        let none = HashSet::new();
+        Ok(match expr {
+            Expr::Union(a, b) => &self.eval_query(a)? | &self.eval_query(b)?,
+            Expr::Intersect(a, b) => &self.eval_query(a)? & &self.eval_query(b)?,
+            Expr::Difference(a, b) => &self.eval_query(a)? - &self.eval_query(b)?,
+            Expr::Word(pattern) | Expr::Str(pattern) => {
+                self.resolve(pattern).unwrap_or_default().into_iter().collect()
+            },
+            Expr::Id(id) => {
+                match self.resolve_id(*id as usize) {
+                    Some(idx) => [idx].iter().cloned().collect(),
+                    None => return Err(format!("no function #{}", id)),
+                }
+            },
+            Expr::Num(n) => return Err(format!("expected functions, got the number {}", n)),
+            Expr::Call(name, args) => match (name.as_str(), args.as_slice()) {
+                ("resolve", [pattern]) => {
+                    self.resolve(query_string(pattern)?).unwrap_or_default().into_iter().collect()
+                },
+                ("match", [pattern]) => {
+                    let matcher = Matcher::regex(query_string(pattern)?).ok_or("invalid regex")?;
+                    self.node_ids().filter(|&idx| matcher.is_match(self, idx)).collect()
+                },
+                ("tagged", [tag]) => {
+                    let tag = query_string(tag)?;
+                    let annotations = self.annotations();
+                    self.node_ids().filter(|&idx| annotations.has_tag(&self.graph[idx], tag)).collect()
+                },
+                ("all", []) => self.node_ids().collect(),
+                ("roots", []) => self.roots().clone(),
+                ("sinks", []) => self.sinks().clone(),
+                ("gc", []) => self.gc_functions.clone(),
+                ("indirect", []) => self.node_ids().filter(|&idx| self.node_kind(idx) != NodeKind::Direct).collect(),
+                // Functions that call themselves directly.
+                ("selfcalls", []) => self.edge_ids().filter_map(|edge| {
+                    let (src, dst) = self.graph.edge_endpoints(edge).unwrap();
+                    if src == dst { Some(src) } else { None }
+                }).collect(),
+                // Functions with a route to a GC function that doesn't go
+                // through a GC-suppressed call.
+                ("cangc", []) => {
+                    let gc : Vec<NodeIndex> = self.gc_functions.iter().cloned().collect();
+                    let mut result : HashSet<NodeIndex> =
+                        self.reachable(&gc, Direction::Callers, None, &none, self.gc_suppressed_bit()).into_iter().collect();
+                    result.extend(gc);
+                    result
+                },
+                // Functions only reachable from the roots through a call with
+                // the given property.
+                ("onlyvia", [prop]) => {
+                    let prop = query_string(prop)?;
+                    let bit = self.resolve_property(prop).ok_or(format!("unknown property {}", prop))?;
+                    self.only_reachable_via(bit).into_iter().collect()
+                },
+                // Functions making any call with the given property.
+                ("prop", [prop]) => {
+                    let prop = query_string(prop)?;
+                    let bit = self.resolve_property(prop).ok_or(format!("unknown property {}", prop))?;
+                    self.edge_ids().filter(|&edge| self.graph[edge].any & bit != 0)
+                        .map(|edge| self.graph.edge_endpoints(edge).unwrap().0).collect()
+                },
+                ("callees", [set]) | ("callers", [set]) => {
+                    let direction = if name == "callees" { Direction::Callees } else { Direction::Callers };
+                    let mut result = HashSet::new();
+                    for idx in self.eval_query(set)? {
+                        result.extend(self.neighbor_edges(idx, direction).map(|(other, _, _)| other));
+                    }
+                    result
+                },
+                // Transitive callees (reachable) or callers (reaches), not
+                // including the starting functions unless they're reachable
+                // from one of the others. Optionally limited to a depth.
+                ("reachable", [set, rest @ ..]) | ("reaches", [set, rest @ ..]) if rest.len() <= 1 => {
+                    let direction = if name == "reachable" { Direction::Callees } else { Direction::Callers };
+                    let max_depth = match rest {
+                        [Expr::Num(n)] => Some(*n as usize),
+                        [_] => return Err(format!("{}: depth must be a number", name)),
+                        _ => None,
+                    };
+                    let origins : Vec<NodeIndex> = self.eval_query(set)?.into_iter().collect();
+                    self.reachable(&origins, direction, max_depth, &none, 0).into_iter().collect()
+                },
+                _ => return Err(format!("unknown function {}() with {} arguments", name, args.len())),
+            },
+        })
+    }
+
+    // Evaluate rules (see rules.rs) and remember each derived predicate as a
+    // named set, replacing any earlier set of the same name.
+    pub fn apply_rules(&self, text : &str) -> Result<Vec<(String, Vec<NodeIndex>)>, String> {
+        let derived = rules::evaluate(self, text)?;
+        for (name, funcs) in &derived {
+            self.define_set(name, funcs.clone());
+        }
+        Ok(derived)
+    }
+
+    // Make `funcs` resolvable as @name.
+    pub fn define_set(&self, name : &str, funcs : Vec<NodeIndex>) {
+        self.named_sets.write().unwrap().insert(name.to_string(), funcs);
+    }
+
+    // Name the functions matched by a query (see query.rs) as a goal set, so
+    // that eg `route from X to @Alloc` can use it.
+    pub fn define_goal_set(&self, name : &str, spec : &str) -> Result<Vec<NodeIndex>, String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("invalid goal set name '{}'", name));
+        }
+        let funcs = self.query(spec)?;
+        self.define_set(name, funcs.clone());
+        Ok(funcs)
+    }
+
+    // Goal sets from "name = query" lines. `%` starts a comment.
+    pub fn load_goal_sets(&self, filename : &str) -> Result<Vec<(String, Vec<NodeIndex>)>, String> {
+        let text = std::fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        let mut defined = vec![];
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.split('%').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, spec) = line.split_once('=').ok_or(format!("{}:{}: expected name = query", filename, lineno + 1))?;
+            let name = name.trim();
+            let funcs = self.define_goal_set(name, spec.trim()).map_err(|e| format!("{}:{}: {}", filename, lineno + 1, e))?;
+            defined.push((name.to_string(), funcs));
+        }
+        Ok(defined)
+    }
+
+    // The patterns of an avoid list (eg a runbook's known-safe functions),
+    // one per line and resolved as usual, with `#` starting a comment. Each
+    // with what it matched, which is nothing for those not in this graph.
+    pub fn avoid_list(&self, text : &str) -> Vec<(String, Vec<NodeIndex>)> {
+        text.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|pattern| (pattern.to_string(), self.resolve(pattern).unwrap_or_default()))
+            .collect()
+    }
+
+    // Entry points (IPC handlers, event handlers, JSAPI functions...) from a
+    // file of "[kind]" headers, each followed by function names or /regexes/,
+    // one per line, resolved as usual. `#` starts a comment. Each kind becomes
+    // @<kind>-entrypoints, and all of them together @entrypoints.
+    pub fn load_entry_points(&self, filename : &str) -> Result<EntryPoints, String> {
+        let text = std::fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        let mut kinds : Vec<(String, Vec<NodeIndex>)> = vec![];
+        let mut unmatched = vec![];
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(kind) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let kind = kind.trim();
+                if kind.is_empty() || kind.contains(char::is_whitespace) {
+                    return Err(format!("{}:{}: invalid entry point kind '{}'", filename, lineno + 1, kind));
+                }
+                kinds.push((kind.to_string(), vec![]));
+                continue;
+            }
+            let funcs = match kinds.last_mut() {
+                Some((_, funcs)) => funcs,
+                None => return Err(format!("{}:{}: expected a [kind] header first", filename, lineno + 1)),
+            };
+            match self.resolve(line) {
+                Some(found) if !found.is_empty() => funcs.extend(found),
+                _ => unmatched.push(line.to_string()),
+            }
+        }
+
+        let mut all = vec![];
+        for (kind, funcs) in &mut kinds {
+            funcs.sort();
+            funcs.dedup();
+            all.extend(funcs.iter().cloned());
+            self.define_set(&format!("{}-entrypoints", kind), funcs.clone());
+        }
+        all.sort();
+        all.dedup();
+        self.define_set("entrypoints", all);
+        Ok(EntryPoints { kinds, unmatched })
+    }
+
+    // Sort the roots into the kinds of entry point loaded so far. A root can
+    // be more than one kind.
+    pub fn classify_roots(&self) -> RootClasses {
+        let sets = self.named_sets.read().unwrap();
+        let mut kinds : Vec<(String, HashSet<NodeIndex>)> = sets.iter()
+            .filter_map(|(name, funcs)| name.strip_suffix("-entrypoints").map(|kind| (kind.to_string(), funcs.iter().cloned().collect())))
+            .collect();
+        kinds.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut roots : Vec<NodeIndex> = self.roots().iter().cloned().filter(|&idx| !self.is_hidden(idx)).collect();
+        roots.sort();
+        let mut classes : Vec<(String, Vec<NodeIndex>)> = kinds.iter().map(|(kind, _)| (kind.clone(), vec![])).collect();
+        let mut unclassified = vec![];
+        for idx in roots {
+            let mut found = false;
+            for (i, (_, funcs)) in kinds.iter().enumerate() {
+                if funcs.contains(&idx) {
+                    classes[i].1.push(idx);
+                    found = true;
+                }
+            }
+            if !found {
+                unclassified.push(idx);
+            }
+        }
+        RootClasses { kinds: classes, unclassified }
+    }
+
+    pub fn annotations(&self) -> RwLockReadGuard<'_, Annotations> {
+        self.annotations.read().unwrap()
+    }
+
+    pub fn annotations_mut(&self) -> RwLockWriteGuard<'_, Annotations> {
+        self.annotations.write().unwrap()
+    }
+
+    // Replace the current annotations with those in `path`, which later
+    // changes will be written back to.
+    pub fn load_annotations(&self, path : &std::path::Path) -> std::io::Result<()> {
+        *self.annotations_mut() = Annotations::load(path)?;
+        Ok(())
+    }
+
+    pub fn named_set_names(&self) -> Vec<String> {
+        let mut names : Vec<String> = self.named_sets.read().unwrap().keys().cloned().collect();
+        if !self.gc_functions.is_empty() && !names.iter().any(|name| name == "GC") {
+            names.push("GC".to_string());
+        }
+        if !names.iter().any(|name| name == "indirect") {
+            names.push("indirect".to_string());
+        }
+        names.sort();
+        names
+    }
+
+    // Precompute which functions can possibly reach which others, so that
+    // route queries (including can_gc) with no possible answer return without
+    // searching. Memory use is quadratic in the number of cycles-or-functions,
+    // so this is opt-in.
+    pub fn build_reach_index(&mut self) {
+        self.reach_index = Some(ReachIndex::build(&self.graph));
+    }
+
+    fn csr(&self) -> &Csr {
+        self.frozen.get_or_init(|| Csr::build(&self.graph, self.seed.is_some()))
+    }
+
+    // Calls out of or into `idx`, less the virtual dispatches ruled out by
+    // the virtual mode.
+    pub fn neighbor_edges(&self, idx : NodeIndex, direction : Direction) -> impl Iterator<Item = (NodeIndex, EdgeIndex, PropertySet)> + '_ {
+        self.neighbor_edges_in(idx, direction, self.virtual_mode())
+    }
+
+    // As neighbor_edges, for a query that asked for its own virtual mode.
+    pub fn neighbor_edges_in(&self, idx : NodeIndex, direction : Direction, mode : VirtualMode) -> impl Iterator<Item = (NodeIndex, EdgeIndex, PropertySet)> + '_ {
+        self.csr().neighbors(idx, direction).filter(move |(_, edge, _)| {
+            mode == VirtualMode::Conservative || match self.dispatch.get(edge) {
+                None => true,
+                Some(&allowed) => allowed && mode == VirtualMode::Refined,
+            }
+        })
+    }
+
+    fn mangled_order(&self) -> &[u32] {
+        self.mangled_order.get_or_init(|| {
+            let mut order : Vec<u32> = self.node_ids().map(|idx| idx.index() as u32).collect();
+            // Stable, so equal names stay in id order.
+            order.sort_by(|&a, &b| self.graph[NodeIndex::new(a as usize)].cmp(&self.graph[NodeIndex::new(b as usize)]));
+            order
+        })
+    }
+
+    fn trie(&self) -> &Trie {
+        self.name_trie.get_or_init(|| {
+            let mut trie = Trie::new();
+            for (id, names) in self.alt_names.iter().enumerate() {
+                for name in names {
+                    for key in lookup_keys(name) {
+                        trie.insert(key, NodeIndex::new(id));
+                    }
+                }
+            }
+            trie
+        })
+    }
+
+    // Hash of everything that makes up the graph: function ids and names,
+    // calls with their properties and counts, and the property names. Two
+    // graphs with the same fingerprint answer every query the same way, and
+    // ids from one mean the same functions in the other. FNV-1a, rather than
+    // std's hasher, so that it stays the same across Rust versions.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint_renumbered(|idx| idx.index() as u32)
+    }
+
+    // As the graph would be with every function's id changed, eg by a
+    // snapshot closing up the gaps.
+    pub fn fingerprint_renumbered(&self, id : impl Fn(NodeIndex) -> u32) -> u64 {
+        let mut hash : u64 = 0xcbf29ce484222325;
+        let mut feed = |bytes : &[u8]| {
+            for &b in (bytes.len() as u32).to_le_bytes().iter().chain(bytes) {
+                hash = (hash ^ b as u64).wrapping_mul(0x100000001b3);
+            }
+        };
+
+        let mut properties : Vec<(&u32, &String)> = self.property_names.iter().collect();
+        properties.sort();
+        for (bit, name) in properties {
+            feed(&bit.to_le_bytes());
+            feed(name.as_bytes());
+        }
+
+        for idx in self.node_ids() {
+            feed(&id(idx).to_le_bytes());
+            feed(self.graph[idx].as_bytes());
+            for name in self.unmangled_names(idx) {
+                feed(name.as_bytes());
+            }
+        }
+
+        // Edge ids depend on the order calls were added, so leave them out.
+        let mut calls : Vec<[u32; 5]> = self.edge_ids().map(|edge| {
+            let (src, dst) = self.graph.edge_endpoints(edge).unwrap();
+            [id(src), id(dst), self.graph[edge].all, self.graph[edge].any, self.call_count(edge)]
+        }).collect();
+        calls.sort();
+        for call in calls {
+            for n in call {
+                feed(&n.to_le_bytes());
+            }
+        }
+        hash
+    }
+
+    // Approximate heap usage of each part of the graph, in bytes. Caches that
+    // have not been built yet show up as zero.
+    pub fn memory_report(&self) -> Vec<(&'static str, usize)> {
+        let (node_capacity, edge_capacity) = self.graph.capacity();
+        let nodes = node_capacity * size_of::<Node<String>>();
+        let edges = edge_capacity * size_of::<Edge<PropertySet>>();
+        let names = self.graph.raw_nodes().iter().map(|n| n.weight.capacity()).sum();
+        let alt_names = self.alt_names.capacity() * size_of::<Vec<String>>() +
+            self.alt_names.iter().map(|names| {
+                names.capacity() * size_of::<String>() + names.iter().map(|s| s.capacity()).sum::<usize>()
+            }).sum::<usize>();
+        let stem_table = self.stem_table.capacity() * size_of::<(String, Vec<NodeIndex>)>() +
+            self.stem_table.iter().map(|(stem, funcs)| {
+                stem.capacity() + funcs.capacity() * size_of::<NodeIndex>()
+            }).sum::<usize>();
+        vec![
+            ("nodes", nodes),
+            ("edges", edges),
+            ("names", names),
+            ("alt_names", alt_names),
+            ("stem_table", stem_table),
+            ("call_sites", self.call_sites.capacity() * size_of::<(EdgeIndex, String)>() +
+                           self.call_sites.values().map(|s| s.capacity()).sum::<usize>()),
+            ("call_counts", self.call_counts.capacity() * size_of::<(EdgeIndex, u32)>()),
+            ("csr", self.frozen.get().map_or(0, |csr| csr.heap_size())),
+            ("reach index", self.reach_index.as_ref().map_or(0, |index| index.heap_size())),
+            ("name trie", self.name_trie.get().map_or(0, |trie| trie.heap_size())),
+            ("mangled name order", self.mangled_order.get().map_or(0, |order| order.capacity() * size_of::<u32>())),
+        ]
+    }
+
+    // Up to `limit` stems or qualified names starting with `prefix`.
+    pub fn complete(&self, prefix : &str, limit : usize) -> Vec<String> {
+        self.trie().keys_with_prefix(prefix, limit)
+    }
+
+    pub fn callees(&self, idx : NodeIndex) -> Vec<NodeIndex> {
+        self.neighbor_edges(idx, Direction::Callees).map(|(n, _, _)| n).collect()
+    }
+
+    pub fn callee_edges(&self, idx : NodeIndex) -> Vec<EdgeIndex> {
+        self.neighbor_edges(idx, Direction::Callees).map(|(_, e, _)| e).collect()
+    }
+
+    // Calls out of `idx` grouped by callee, in the order each callee is first
+    // seen, so a function called from several places is listed once.
+    pub fn grouped_callee_edges(&self, idx : NodeIndex) -> Vec<(NodeIndex, Vec<EdgeIndex>)> {
+        let mut groups : Vec<(NodeIndex, Vec<EdgeIndex>)> = vec![];
+        let mut position = HashMap::new();
+        for (callee, edge, _) in self.neighbor_edges(idx, Direction::Callees) {
+            let i = *position.entry(callee).or_insert_with(|| {
+                groups.push((callee, vec![]));
+                groups.len() - 1
+            });
+            groups[i].1.push(edge);
+        }
+        groups
+    }
+
+    pub fn callers(&self, idx : NodeIndex) -> Vec<NodeIndex> {
+        self.neighbor_edges(idx, Direction::Callers).map(|(n, _, _)| n).collect()
+    }
+
+    pub fn caller_edges(&self, idx : NodeIndex) -> Vec<EdgeIndex> {
+        self.neighbor_edges(idx, Direction::Callers).map(|(_, e, _)| e).collect()
+    }
+
+    // Compare what `origins` can reach under two sets of avoidances, eg
+    // before and after a proposed annotation.
+    pub fn reach_diff(&self, origins : &[NodeIndex], a : &AvoidConfig, b : &AvoidConfig) -> ReachDiff {
+        let reached_a : HashSet<NodeIndex> = self.reachable(origins, Direction::Callees, None, &a.functions, a.props).into_iter().collect();
+        let reached_b : HashSet<NodeIndex> = self.reachable(origins, Direction::Callees, None, &b.functions, b.props).into_iter().collect();
+        let mut lost : Vec<NodeIndex> = reached_a.difference(&reached_b).cloned().collect();
+        let mut gained : Vec<NodeIndex> = reached_b.difference(&reached_a).cloned().collect();
+        lost.sort();
+        gained.sort();
+        ReachDiff { reached_a: reached_a.len(), reached_b: reached_b.len(), lost, gained }
+    }
+
+    // Functions that the roots can only reach through a call with one of
+    // `props` at every call site, eg those only ever called while GC is
+    // suppressed: reachable from the roots, but not once those calls are
+    // avoided. Sorted.
+    pub fn only_reachable_via(&self, props : u32) -> Vec<NodeIndex> {
+        let none = HashSet::new();
+        let roots : Vec<NodeIndex> = self.roots().iter().cloned().collect();
+        let mut without : HashSet<NodeIndex> = self.reachable(&roots, Direction::Callees, None, &none, props).into_iter().collect();
+        without.extend(&roots);
+        let mut only : Vec<NodeIndex> = self.reachable(&roots, Direction::Callees, None, &none, 0).into_iter()
+            .filter(|idx| !without.contains(idx) && !self.is_hidden(*idx))
+            .collect();
+        only.sort();
+        only
+    }
+
+    // Breadth-first search from all of `origins`, returning every function
+    // reached within `max_depth` steps (origins themselves are only included
+    // if they are reachable from another origin). Avoided functions and edges
+    // with any of `avoid_props` in their `all` set are not traversed.
+    pub fn reachable(
         &self,
         origins : &[NodeIndex],
-        goal : &HashSet<NodeIndex>,
+        direction : Direction,
+        max_depth : Option<usize>,
         avoid : &HashSet<NodeIndex>,
         avoid_props : u32
-    ) -> Option<Vec<EdgeIndex>>
+    ) -> Vec<NodeIndex>
     {
-        let mut bestpath : Option<Vec<EdgeIndex>> = None;
-        for origin in origins {
-            if avoid.contains(origin) { continue; }
-            if let Some(path) = self.any_route(*origin, goal, avoid, avoid_props) {
-                if let Some(prev) = bestpath.as_ref() {
-                    if prev.len() > path.len() {
-                        bestpath = Some(path);
+        with_scratch(self.graph.node_count(), |scratch| {
+            let mut result = vec![];
+            scratch.work.extend(origins.iter().map(|&idx| (idx, 0)));
+            while let Some((src, depth)) = scratch.work.pop_front() {
+                if max_depth.is_some_and(|max| depth >= max) { continue; }
+                for (dst, _, props) in self.neighbor_edges(src, direction) {
+                    if avoid.contains(&dst) { continue; }
+                    if (avoid_props & props.all) != 0 { continue; }
+                    if !scratch.mark(dst.index()) { continue; }
+                    result.push(dst);
+                    scratch.work.push_back((dst, depth + 1));
+                }
+            }
+            result
+        })
+    }
+
+    // Everything within `depth` calls of `origin`, with the avoidances of
+    // reachable(), as a tree in printing order: each function follows the
+    // one it was first reached from, along with its distance from the origin
+    // (which is 0 for the origin itself). Breadth first, so that distance is
+    // the shortest.
+    pub fn expand(&self, origin : NodeIndex, direction : Direction, depth : usize, avoid : &HashSet<NodeIndex>, avoid_props : u32) -> Vec<(NodeIndex, usize)> {
+        let mut children = HashMap::<NodeIndex, Vec<NodeIndex>>::new();
+        self.bfs(&[origin], direction, |src, dst, props, distance| {
+            if distance > depth {
+                return VisitAction::Stop;
+            }
+            if avoid.contains(&dst) || (avoid_props & props.all) != 0 {
+                return VisitAction::Ignore;
+            }
+            children.entry(src).or_default().push(dst);
+            if distance < depth { VisitAction::Continue } else { VisitAction::Skip }
+        });
+
+        let mut tree = vec![];
+        let mut work = vec![(origin, 0)];
+        while let Some((idx, distance)) = work.pop() {
+            tree.push((idx, distance));
+            if let Some(kids) = children.get(&idx) {
+                work.extend(kids.iter().rev().map(|&kid| (kid, distance + 1)));
+            }
+        }
+        tree
+    }
+
+    // Functions exactly `hops` calls away from `origin` by the shortest
+    // route, sorted, with the same avoidances as reachable(). Ring 0 is the
+    // origin itself.
+    pub fn ring(&self, origin : NodeIndex, direction : Direction, hops : usize, avoid : &HashSet<NodeIndex>, avoid_props : u32) -> Vec<NodeIndex> {
+        let mut ring : Vec<NodeIndex> = self.expand(origin, direction, hops, avoid, avoid_props).into_iter()
+            .filter(|&(_, distance)| distance == hops)
+            .map(|(idx, _)| idx)
+            .collect();
+        ring.sort();
+        ring
+    }
+
+    // Namespace dependencies, a cheap architectural view. Functions in
+    // `exclude` are left out, along with their calls.
+    pub fn namespace_matrix(&self, exclude : &HashSet<NodeIndex>) -> NamespaceMatrix {
+        let mut sizes = HashMap::<&str, usize>::new();
+        for idx in self.node_ids().filter(|idx| !exclude.contains(idx)) {
+            *sizes.entry(self.namespace(idx)).or_insert(0) += 1;
+        }
+        let mut namespaces : Vec<(String, usize)> = sizes.into_iter().map(|(ns, n)| (ns.to_string(), n)).collect();
+        namespaces.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let position : HashMap<&str, usize> = namespaces.iter().enumerate().map(|(i, (ns, _))| (ns.as_str(), i)).collect();
+
+        let mut calls = BTreeMap::new();
+        for edge in self.edge_ids() {
+            let (src, dst) = self.graph.edge_endpoints(edge).unwrap();
+            if exclude.contains(&src) || exclude.contains(&dst) { continue; }
+            let key = (position[self.namespace(src)], position[self.namespace(dst)]);
+            *calls.entry(key).or_insert(0) += 1;
+        }
+        NamespaceMatrix { namespaces, calls }
+    }
+
+    // Weakly connected components, largest first. A partial build tends to
+    // leave many, and nothing routes between them.
+    pub fn components(&self) -> Vec<Component> {
+        let mut seen = FixedBitSet::with_capacity(self.graph.node_count());
+        let mut components = vec![];
+        for start in self.node_ids() {
+            if seen.put(start.index()) { continue; }
+            let mut functions = vec![];
+            let mut work = vec![start];
+            while let Some(idx) = work.pop() {
+                functions.push(idx);
+                for direction in [Direction::Callees, Direction::Callers] {
+                    for (other, _, _) in self.neighbor_edges(idx, direction) {
+                        if !self.is_synthetic(other) && !seen.put(other.index()) {
+                            work.push(other);
+                        }
+                    }
+                }
+            }
+            functions.sort();
+            let degree = |idx : NodeIndex| self.neighbor_edges(idx, Direction::Callees).count() + self.neighbor_edges(idx, Direction::Callers).count();
+            let representative = functions.iter().cloned().max_by_key(|&idx| (degree(idx), std::cmp::Reverse(idx))).unwrap();
+            components.push(Component { functions, representative });
+        }
+        components.sort_by_key(|c| (std::cmp::Reverse(c.functions.len()), c.functions[0]));
+        components
+    }
+
+    // The blast radius of changing `idx`: everything that transitively calls
+    // it, counted by top-level namespace (largest first), and the roots it is
+    // reachable from.
+    pub fn impact(&self, idx : NodeIndex) -> Impact {
+        let callers : Vec<NodeIndex> = self.reachable(&[idx], Direction::Callers, None, &HashSet::new(), 0)
+            .into_iter().filter(|&f| f != idx && !self.is_synthetic(f)).collect();
+        let mut counts : HashMap<&str, usize> = HashMap::new();
+        for &f in &callers {
+            *counts.entry(self.namespace(f)).or_insert(0) += 1;
+        }
+        let mut namespaces : Vec<(String, usize)> = counts.into_iter().map(|(ns, n)| (ns.to_string(), n)).collect();
+        namespaces.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        // A root is a function (or one per cycle) with no callers from
+        // outside its own cycle. The callers are closed under calling, so
+        // a depth-first search of the set in the callee direction finishes
+        // last in a root, and after removing everything that root calls, the
+        // last to finish of the rest is the next root, and so on.
+        let n = self.graph.node_count();
+        let mut in_set = FixedBitSet::with_capacity(n);
+        in_set.extend(callers.iter().chain(std::iter::once(&idx)).map(|f| f.index()));
+        let mut visited = FixedBitSet::with_capacity(n);
+        let mut finished = vec![];
+        for start in callers.iter().chain(std::iter::once(&idx)) {
+            if visited.put(start.index()) {
+                continue;
+            }
+            // An explicit stack, since call chains can be very deep.
+            let mut stack = vec![(*start, self.callees(*start), 0)];
+            while let Some(top) = stack.last_mut() {
+                let next = top.1.get(top.2).cloned();
+                top.2 += 1;
+                match next {
+                    Some(g) => if in_set.contains(g.index()) && !visited.put(g.index()) {
+                        stack.push((g, self.callees(g), 0));
+                    },
+                    None => {
+                        finished.push(top.0);
+                        stack.pop();
+                    },
+                }
+            }
+        }
+        let mut covered = FixedBitSet::with_capacity(n);
+        let mut roots = vec![];
+        for &f in finished.iter().rev() {
+            if covered.put(f.index()) {
+                continue;
+            }
+            roots.push(f);
+            let mut work = vec![f];
+            while let Some(g) = work.pop() {
+                for h in self.callees(g) {
+                    if in_set.contains(h.index()) && !covered.put(h.index()) {
+                        work.push(h);
+                    }
+                }
+            }
+        }
+        roots.sort();
+        Impact { callers, namespaces, roots }
+    }
+
+    // First component of the qualified name, eg "js" for js::GC::collect(),
+    // or "" for a function outside any namespace.
+    pub fn namespace(&self, idx : NodeIndex) -> &str {
+        let raw = self.unmangled_names(idx).last().unwrap_or(&self.graph[idx]);
+        match name_before_paren(raw, b":~").map(|name| name.trim_start_matches(':')) {
+            Some(name) => name.find("::").map_or("", |pos| &name[..pos]),
+            None => "",
+        }
+    }
+
+    // Breadth-first traversal from `origins`, calling `visit(src, dst, props,
+    // depth)` for the edge by which each function is first reached.
+    pub fn bfs<F>(&self, origins : &[NodeIndex], direction : Direction, mut visit : F)
+        where F : FnMut(NodeIndex, NodeIndex, PropertySet, usize) -> VisitAction
+    {
+        let mut seen : HashSet<NodeIndex> = origins.iter().cloned().collect();
+        let mut work : VecDeque<(NodeIndex, usize)> = origins.iter().map(|&idx| (idx, 0)).collect();
+        while let Some((src, depth)) = work.pop_front() {
+            for (dst, _, props) in self.neighbor_edges(src, direction) {
+                if seen.contains(&dst) { continue; }
+                match visit(src, dst, props, depth + 1) {
+                    VisitAction::Continue => work.push_back((dst, depth + 1)),
+                    VisitAction::Skip => (),
+                    VisitAction::Ignore => continue,
+                    VisitAction::Stop => return,
+                }
+                seen.insert(dst);
+            }
+        }
+    }
+
+    // Extract the portion of the graph lying on some path from one of
+    // `sources` to one of `targets`, as a new Callgraph. Functions are
+    // renumbered in the new graph.
+    pub fn subgraph_between(&self, sources : &[NodeIndex], targets : &[NodeIndex]) -> Callgraph {
+        let none = HashSet::new();
+        let mut forward : HashSet<NodeIndex> = self.reachable(sources, Direction::Callees, None, &none, 0).into_iter().collect();
+        forward.extend(sources);
+        let mut backward : HashSet<NodeIndex> = self.reachable(targets, Direction::Callers, None, &none, 0).into_iter().collect();
+        backward.extend(targets);
+
+        let mut keep : Vec<NodeIndex> = forward.intersection(&backward).cloned().collect();
+        keep.sort();
+        self.extract(&keep)
+    }
+
+    // Build a new Callgraph containing only the given functions and the edges
+    // between them.
+    pub fn extract(&self, keep : &[NodeIndex]) -> Callgraph {
+        self.extract_calls(keep, &|_| true)
+    }
+
+    // A copy without the functions rejected by `keep` (or any calls to or from
+    // them), renumbered densely, eg to drop all of std:: before exporting.
+    pub fn retain(&self, keep : impl Fn(NodeIndex) -> bool) -> Callgraph {
+        let kept : Vec<NodeIndex> = self.node_ids().filter(|&idx| keep(idx)).collect();
+        self.extract_calls(&kept, &|_| true)
+    }
+
+    // A copy with every function but only the calls accepted by `keep`.
+    pub fn retain_calls(&self, keep : impl Fn(EdgeIndex) -> bool) -> Callgraph {
+        let all : Vec<NodeIndex> = self.node_ids().collect();
+        self.extract_calls(&all, &keep)
+    }
+
+    // A copy without leaf functions, repeatedly, so that what's left is the
+    // skeleton of functions that call something interesting: `keep` or the GC
+    // functions. A function calling only itself is not a leaf.
+    pub fn trim_leaves(&self, keep : &HashSet<NodeIndex>) -> Callgraph {
+        let kept = |idx : NodeIndex| keep.contains(&idx) || self.gc_functions.contains(&idx);
+        let mut callees : Vec<usize> = vec![0; self.graph.node_count()];
+        let mut work = vec![];
+        for idx in self.node_ids() {
+            callees[idx.index()] = self.neighbor_edges(idx, Direction::Callees).count();
+            if callees[idx.index()] == 0 && !kept(idx) {
+                work.push(idx);
+            }
+        }
+        let mut removed = FixedBitSet::with_capacity(self.graph.node_count());
+        while let Some(idx) = work.pop() {
+            removed.insert(idx.index());
+            for (caller, _, _) in self.neighbor_edges(idx, Direction::Callers) {
+                callees[caller.index()] -= 1;
+                if callees[caller.index()] == 0 && !kept(caller) {
+                    work.push(caller);
+                }
+            }
+        }
+        self.retain(|idx| !removed.contains(idx.index()))
+    }
+
+    // A thinned copy with about `keep_fraction` of the functions, for
+    // visualization tools that can't cope with the whole graph: `must_keep`
+    // and a shortest route between each two of them (every path between them
+    // can be most of the graph, with enough recursion), then small clusters
+    // of callees grown from randomly chosen functions, so that what's kept
+    // still calls each other rather than being a scatter of isolated
+    // functions. The same every time for the same graph and seed.
+    pub fn sample_subgraph(&self, keep_fraction : f64, must_keep : &[NodeIndex]) -> Callgraph {
+        const CLUSTER_SIZE : usize = 8;
+
+        let none = HashSet::new();
+        let mut kept = FixedBitSet::with_capacity(self.graph.node_count());
+        for &src in must_keep {
+            kept.insert(src.index());
+            for &dst in must_keep.iter().filter(|&&dst| dst != src) {
+                let goal : HashSet<NodeIndex> = [dst].iter().cloned().collect();
+                for edge in self.any_route(src, &goal, &none, 0).unwrap_or_default() {
+                    kept.insert(self.graph.edge_endpoints(edge).unwrap().1.index());
+                }
+            }
+        }
+        let mut count = kept.count_ones(..);
+
+        let target = (self.node_ids().count() as f64 * keep_fraction.clamp(0.0, 1.0)).round() as usize;
+        let seed = self.seed.unwrap_or(0x2545_f491_4f6c_dd1d);
+        // splitmix64, for a random but repeatable order.
+        let mix = |x : u64| {
+            let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let mut order : Vec<NodeIndex> = self.node_ids().collect();
+        order.sort_by_key(|idx| mix(seed ^ idx.index() as u64));
+        for origin in order {
+            if count >= target {
+                break;
+            }
+            let mut work = VecDeque::from(vec![origin]);
+            let mut grown = 0;
+            while let Some(idx) = work.pop_front() {
+                if count >= target || grown >= CLUSTER_SIZE {
+                    break;
+                }
+                if kept.contains(idx.index()) {
+                    continue;
+                }
+                kept.insert(idx.index());
+                count += 1;
+                grown += 1;
+                work.extend(self.neighbor_edges(idx, Direction::Callees).map(|(callee, _, _)| callee));
+            }
+        }
+        self.retain(|idx| kept.contains(idx.index()))
+    }
+
+    fn extract_calls(&self, keep : &[NodeIndex], keep_call : &dyn Fn(EdgeIndex) -> bool) -> Callgraph {
+        let mut sub = Callgraph::new();
+        sub.property_names = self.property_names.clone();
+        sub.property_aliases = self.property_aliases.clone();
+
+        let mut renumber = HashMap::new();
+        for &idx in keep {
+            let new_idx = sub.add_function(&self.graph[idx]);
+            for name in self.unmangled_names(idx) {
+                sub.add_unmangled_name(new_idx, name);
+            }
+            renumber.insert(idx, new_idx);
+        }
+        sub.gc_functions = self.gc_functions.iter().filter_map(|idx| renumber.get(idx).cloned()).collect();
+        sub.seed = self.seed;
+        sub.legacy_roots = self.legacy_roots;
+
+        for &idx in keep {
+            for edge in self.graph.edges(idx) {
+                if !keep_call(edge.id()) {
+                    continue;
+                }
+                if let Some(&dst) = renumber.get(&edge.target()) {
+                    let new_edge = sub.add_edge(renumber[&idx], dst, *edge.weight());
+                    if let Some(location) = self.call_sites.get(&edge.id()) {
+                        sub.call_sites.insert(new_edge, location.clone());
+                    }
+                    if let Some(&count) = self.call_counts.get(&edge.id()) {
+                        sub.call_counts.insert(new_edge, count);
+                    }
+                }
+            }
+        }
+
+        sub
+    }
+
+    // Write the graph back out in the callgraph.txt format it was loaded
+    // from, with functions renumbered densely. Function pointer nodes become
+    // ordinary functions named "VARIABLE ...", and GC functions are not
+    // recorded.
+    pub fn write_text(&self, out : &mut impl std::io::Write) -> std::io::Result<()> {
+        if !self.property_names.is_empty() {
+            let mut table = json::JsonValue::new_object();
+            let mut bits : Vec<&u32> = self.property_names.keys().collect();
+            bits.sort();
+            for bit in bits {
+                table[bit.to_string().as_str()] = self.property_names[bit].clone().into();
+            }
+            let mut info = json::object!{ "Properties" => table };
+            if !self.property_aliases.is_empty() {
+                let mut aliases : Vec<(&String, &String)> = self.property_aliases.iter().collect();
+                aliases.sort();
+                info["Aliases"] = json::JsonValue::new_object();
+                for (alias, name) in aliases {
+                    info["Aliases"][alias.as_str()] = name.clone().into();
+                }
+            }
+            writeln!(out, "! {}", info.dump())?;
+        }
+        let mut renumber = HashMap::new();
+        for (i, idx) in self.node_ids().enumerate() {
+            renumber.insert(idx, i + 1);
+            writeln!(out, "#{} {}", i + 1, self.graph[idx])?;
+            for name in self.unmangled_names(idx) {
+                writeln!(out, "= {} {}", i + 1, name)?;
+            }
+        }
+        for edge in self.edge_ids() {
+            let (src, dst) = self.graph.edge_endpoints(edge).unwrap();
+            let PropertySet { all, any } = self.graph[edge];
+            for _ in 0..self.call_count(edge) {
+                match (all, any) {
+                    (0, 0) => write!(out, "D {} {}", renumber[&src], renumber[&dst])?,
+                    (all, any) if all == any => write!(out, "D /{} {} {}", all, renumber[&src], renumber[&dst])?,
+                    (all, any) => write!(out, "D {}:{} {} {}", all, any, renumber[&src], renumber[&dst])?,
+                }
+                match self.call_sites.get(&edge) {
+                    Some(location) => writeln!(out, " {}", location)?,
+                    None => writeln!(out)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn annotate_gc(&mut self, funcs : &[NodeIndex]) {
+        self.gc_functions.extend(funcs);
+        self.gc_distances.iter_mut().for_each(|dist| { dist.take(); });
+    }
+
+    // Edges marked SUPPRESS_GC are loaded with bit 1 set, even if the graph
+    // has no property table naming it.
+    pub fn gc_suppressed_bit(&self) -> u32 {
+        self.resolve_property("GC_SUPPRESSED").unwrap_or(1)
+    }
+
+    // Shortest route from `idx` to a GC function, avoiding GC-suppressed
+    // calls. A GC function itself gets an empty route.
+    pub fn gc_route(&self, idx : NodeIndex) -> Option<Vec<EdgeIndex>> {
+        if self.gc_functions.contains(&idx) {
+            return Some(vec![]);
+        }
+        let mode = self.virtual_mode();
+        let dist = self.gc_distances[mode as usize].get_or_init(|| self.distances_to(&self.gc_functions, mode));
+        self.guided_route(idx, &self.gc_functions, &HashSet::new(), self.gc_suppressed_bit(), mode, dist)
+    }
+
+    pub fn can_gc(&self, idx : NodeIndex) -> bool {
+        self.gc_route(idx).is_some()
+    }
+
+    // The fewest calls that would need to be marked GC-suppressed so that none
+    // of `entries` can reach `goal`: a minimum edge cut, found by pushing one
+    // unit of flow at a time along routes until there are none left. Calls
+    // that are already suppressed are never proposed.
+    pub fn gc_cut(
+        &self,
+        entries : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        interrupted : &dyn Fn() -> bool
+    ) -> Result<Vec<EdgeIndex>, String>
+    {
+        if let Some(&entry) = entries.iter().find(|idx| goal.contains(idx)) {
+            return Err(format!("{} is itself in the goal set", self.name(entry, DescriptionBrevity::Normal)));
+        }
+        let n = self.graph.node_count();
+        let suppressed = self.gc_suppressed_bit();
+        let mut flow = FixedBitSet::with_capacity(self.graph.edge_count());
+        loop {
+            if interrupted() {
+                return Err("interrupted".to_string());
+            }
+            // Search what is left: calls not carrying flow yet, forwards, and
+            // calls carrying flow, backwards (to reroute it).
+            let mut parent : Vec<Option<EdgeIndex>> = vec![None; n];
+            let mut seen = FixedBitSet::with_capacity(n);
+            let mut work = VecDeque::new();
+            for &idx in entries {
+                if !seen.put(idx.index()) {
+                    work.push_back(idx);
+                }
+            }
+            let mut found = None;
+            'search: while let Some(src) = work.pop_front() {
+                for (dst, edge, props) in self.neighbor_edges(src, Direction::Callees) {
+                    if props.all & suppressed != 0 || flow.contains(edge.index()) || seen.put(dst.index()) { continue; }
+                    parent[dst.index()] = Some(edge);
+                    if goal.contains(&dst) {
+                        found = Some(dst);
+                        break 'search;
                     }
+                    work.push_back(dst);
+                }
+                for (dst, edge, _) in self.neighbor_edges(src, Direction::Callers) {
+                    if !flow.contains(edge.index()) || seen.put(dst.index()) { continue; }
+                    parent[dst.index()] = Some(edge);
+                    work.push_back(dst);
+                }
+            }
+
+            let mut idx = match found {
+                Some(idx) => idx,
+                None => {
+                    // Everything still reachable is on one side of the cut.
+                    let mut cut = vec![];
+                    for src in seen.ones().map(NodeIndex::new) {
+                        for (dst, edge, props) in self.neighbor_edges(src, Direction::Callees) {
+                            if props.all & suppressed == 0 && !seen.contains(dst.index()) {
+                                cut.push(edge);
+                            }
+                        }
+                    }
+                    cut.sort();
+                    return Ok(cut);
+                },
+            };
+            while let Some(edge) = parent[idx.index()] {
+                let (src, dst) = self.graph.edge_endpoints(edge).unwrap();
+                if dst == idx {
+                    flow.insert(edge.index());
+                    idx = src;
                 } else {
-                    bestpath = Some(path);
+                    flow.set(edge.index(), false);
+                    idx = dst;
+                }
+            }
+        }
+    }
+
+    // Length of the shortest route from each of `sources` to each of
+    // `goals`, if any: matrix[i][j] is for sources[i] to goals[j]. This is one
+    // BFS per source, or one backwards BFS per goal if there are fewer goals,
+    // each stopping once every pair it can answer is answered.
+    pub fn route_matrix(
+        &self,
+        sources : &[NodeIndex],
+        goals : &[NodeIndex],
+        avoid : &HashSet<NodeIndex>,
+        avoid_props : u32,
+        matching : PropertyMatch
+    ) -> Vec<Vec<Option<u32>>>
+    {
+        let mut matrix = vec![vec![None; goals.len()]; sources.len()];
+        let forward = sources.len() <= goals.len();
+        let (starts, ends, direction) = if forward {
+            (sources, goals, Direction::Callees)
+        } else {
+            (goals, sources, Direction::Callers)
+        };
+        let mut dist = vec![u32::MAX; self.graph.node_count()];
+        let mut visited = vec![];
+        for (i, &start) in starts.iter().enumerate() {
+            if avoid.contains(&start) { continue; }
+            // Positions in `ends` still waiting for an answer.
+            let mut wanted : HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+            for (j, &end) in ends.iter().enumerate() {
+                if avoid.contains(&end) { continue; }
+                if let Some(index) = &self.reach_index {
+                    let (src, dst) = if forward { (start, end) } else { (end, start) };
+                    if !index.may_reach(src, dst) { continue; }
+                }
+                wanted.entry(end).or_default().push(j);
+            }
+
+            let mut work = VecDeque::new();
+            dist[start.index()] = 0;
+            visited.push(start);
+            work.push_back(start);
+            while let Some(node) = work.pop_front() {
+                if let Some(found) = wanted.remove(&node) {
+                    for j in found {
+                        let (row, col) = if forward { (i, j) } else { (j, i) };
+                        matrix[row][col] = Some(dist[node.index()]);
+                    }
+                    if wanted.is_empty() { break; }
+                }
+                for (next, _, props) in self.neighbor_edges(node, direction) {
+                    if dist[next.index()] != u32::MAX || avoid.contains(&next) || matching.blocks(props, avoid_props) { continue; }
+                    dist[next.index()] = dist[node.index()] + 1;
+                    visited.push(next);
+                    work.push_back(next);
                 }
             }
+            for node in visited.drain(..) {
+                dist[node.index()] = u32::MAX;
+            }
         }
+        matrix
+    }
 
-        bestpath
+    // Minimum number of calls needed to get from each function to one of
+    // `goal`, ignoring avoided functions and properties, or u32::MAX if it
+    // never can. One backwards BFS.
+    pub fn distances_to(&self, goal : &HashSet<NodeIndex>, mode : VirtualMode) -> Vec<u32> {
+        let mut dist = vec![u32::MAX; self.graph.node_count()];
+        let mut work = VecDeque::new();
+        for &idx in goal {
+            dist[idx.index()] = 0;
+            work.push_back(idx);
+        }
+        while let Some(dst) = work.pop_front() {
+            for (src, _, _) in self.neighbor_edges_in(dst, Direction::Callers, mode) {
+                if dist[src.index()] == u32::MAX {
+                    dist[src.index()] = dist[dst.index()] + 1;
+                    work.push_back(src);
+                }
+            }
+        }
+        dist
     }
 
-    pub fn any_route(
+    // A* search for a shortest route from `origin` (which must not itself be
+    // in `goal`), using `dist` from distances_to(goal) as the heuristic. It is
+    // admissible since avoidances only make routes longer, so this finds
+    // routes as short as any_route's while expanding far fewer functions when
+    // the goal is far away, and never enters functions that can't reach it.
+    fn guided_route(
         &self,
         origin : NodeIndex,
         goal : &HashSet<NodeIndex>,
         avoid : &HashSet<NodeIndex>,
-        avoid_props : u32
+        avoid_props : u32,
+        mode : VirtualMode,
+        dist : &[u32]
     ) -> Option<Vec<EdgeIndex>>
     {
-        // Map from node to the edge that led to that node.
-        let mut edges : HashMap<NodeIndex, EdgeReference<PropertySet>> = HashMap::new();
-        let mut work = VecDeque::new();
-        work.push_back(origin);
+        if dist[origin.index()] == u32::MAX {
+            return None;
+        }
 
-        let mut found : Option<EdgeReference<PropertySet>> = None;
-        'search: while ! work.is_empty() {
-            let src = work.pop_front().unwrap();
-            for edge in self.graph.edges(src) {
-                let dst = edge.target();
-                if edges.contains_key(&dst) { continue; }
-                if avoid.contains(&dst) { continue; }
-                if (avoid_props & self.graph[edge.id()].all) != 0 { continue; }
-                edges.insert(dst, edge);
-                if goal.contains(&dst) {
-                    found = Some(edge);
-                    break 'search;
+        with_scratch(self.graph.node_count(), |scratch| {
+            // `marked` is the open-or-closed set, with the best known
+            // distance from the origin in `depth`.
+            let mut frontier = BinaryHeap::new();
+            scratch.mark(origin.index());
+            scratch.depth[origin.index()] = 0;
+            frontier.push(Reverse((dist[origin.index()], 0, origin)));
+
+            let mut found : Option<NodeIndex> = None;
+            while let Some(Reverse((_, depth, src))) = frontier.pop() {
+                if scratch.closed.contains(src.index()) { continue; }
+                scratch.closed.insert(src.index());
+                if goal.contains(&src) {
+                    found = Some(src);
+                    break;
+                }
+                for (dst, edge, props) in self.neighbor_edges_in(src, Direction::Callees, mode) {
+                    if dist[dst.index()] == u32::MAX { continue; }
+                    if avoid.contains(&dst) { continue; }
+                    if (avoid_props & props.all) != 0 { continue; }
+                    let d = depth + 1;
+                    if !scratch.mark(dst.index()) && d >= scratch.depth[dst.index()] { continue; }
+                    scratch.depth[dst.index()] = d;
+                    scratch.pred[dst.index()] = edge.index() as u32;
+                    frontier.push(Reverse((d + dist[dst.index()], d, dst)));
                 }
-                work.push_back(dst);
             }
+
+            let mut node = found?;
+            let mut result : Vec<EdgeIndex> = vec![];
+            while node != origin {
+                let edge = EdgeIndex::new(scratch.pred[node.index()] as usize);
+                result.push(edge);
+                node = self.graph.edge_endpoints(edge).unwrap().0;
+            }
+            result.reverse();
+
+            Some(result)
+        })
+    }
+
+    // Shortest route from any of `origins`, found with a single traversal
+    // seeded with all of them, so that eg `route from AddRef to @GC` does not
+    // search once per AddRef. An origin already in `goal` gives an empty
+    // route.
+    pub fn any_route_from_one_of(
+        &self,
+        origins : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        avoid_props : u32,
+        matching : PropertyMatch
+    ) -> Option<Vec<EdgeIndex>>
+    {
+        self.clean_route(origins, goal, avoid, avoid_props, matching, 0, self.virtual_mode())
+    }
+
+    // Search everything reachable from `origins` as a route search would,
+    // counting the calls that avoidances kept it from following. Useful when
+    // a route was not found, to tell whether the avoidances or the graph
+    // itself are the reason.
+    pub fn route_blockers(
+        &self,
+        origins : &[NodeIndex],
+        avoid : &HashSet<NodeIndex>,
+        avoid_props : u32,
+        matching : PropertyMatch,
+        mode : VirtualMode
+    ) -> Blockers
+    {
+        let mut functions : HashMap<NodeIndex, usize> = HashMap::new();
+        let mut properties : HashMap<u32, usize> = HashMap::new();
+        let mut seen = FixedBitSet::with_capacity(self.graph.node_count());
+        let mut work : Vec<NodeIndex> = origins.iter().cloned().filter(|origin| !avoid.contains(origin)).collect();
+        for idx in &work {
+            seen.insert(idx.index());
         }
+        let mut reached = 0;
+        while let Some(src) = work.pop() {
+            reached += 1;
+            for (dst, _, props) in self.neighbor_edges_in(src, Direction::Callees, mode) {
+                if matching.blocks(props, avoid_props) {
+                    let bits = avoid_props & if matching == PropertyMatch::All { props.all } else { props.any };
+                    for bit in (0..32).map(|i| 1u32 << i).filter(|bit| bits & bit != 0) {
+                        *properties.entry(bit).or_insert(0) += 1;
+                    }
+                } else if avoid.contains(&dst) {
+                    *functions.entry(dst).or_insert(0) += 1;
+                } else if !seen.put(dst.index()) {
+                    work.push(dst);
+                }
+            }
+        }
+        let mut functions : Vec<(NodeIndex, usize)> = functions.into_iter().collect();
+        functions.sort_by_key(|&(idx, count)| (Reverse(count), idx));
+        let mut properties : Vec<(u32, usize)> = properties.into_iter().collect();
+        properties.sort_by_key(|&(bit, count)| (Reverse(count), bit));
+        Blockers { reached, functions, properties }
+    }
 
-        if found == None {
+    // As any_route_from_one_of, but also requiring that no call on the route
+    // has any of `clean` at any of its call sites, and following virtual
+    // calls as `mode` says.
+    #[allow(clippy::too_many_arguments)]
+    pub fn clean_route(
+        &self,
+        origins : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        avoid_props : u32,
+        matching : PropertyMatch,
+        clean : u32,
+        mode : VirtualMode
+    ) -> Option<Vec<EdgeIndex>>
+    {
+        let origins : Vec<NodeIndex> = origins.iter().cloned().filter(|origin| !avoid.contains(origin)).collect();
+        if origins.is_empty() {
             return None;
         }
+        if origins.iter().any(|origin| goal.contains(origin)) {
+            return Some(vec![]);
+        }
+        let blocked = |props : PropertySet| matching.blocks(props, avoid_props) || props.any & clean != 0;
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        if origins.len() > 1 && origins.len() <= threads && self.graph.node_count() >= PARALLEL_ROUTE_MIN_NODES {
+            self.route_from_each(&origins, goal, avoid, mode, blocked)
+        } else {
+            self.route_from_any(&origins, goal, avoid, mode, blocked)
+        }
+    }
+
+    // A separate search from each origin, in parallel, sharing the best
+    // length found so far so that a search gives up as soon as it can no
+    // longer win. Ties go to the earliest origin, as if they had run in
+    // order. Only worth it for a few origins on a big graph, where their
+    // searches mostly don't overlap; with many origins, one search from all
+    // of them at once does far less work.
+    fn route_from_each(
+        &self,
+        origins : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        mode : VirtualMode,
+        blocked : impl Fn(PropertySet) -> bool + Sync
+    ) -> Option<Vec<EdgeIndex>>
+    {
+        self.csr(); // Build it once up front rather than racing to.
+
+        let bound = AtomicUsize::new(usize::MAX);
+        let best : Mutex<Option<(usize, usize, Vec<EdgeIndex>)>> = Mutex::new(None);
+        std::thread::scope(|scope| {
+            for (position, origin) in origins.iter().enumerate() {
+                let (bound, best, blocked) = (&bound, &best, &blocked);
+                scope.spawn(move || {
+                    let path = match self.route_within(&[*origin], goal, avoid, mode, blocked, bound) {
+                        Some(path) => path,
+                        None => return,
+                    };
+                    bound.fetch_min(path.len(), Ordering::Relaxed);
+                    let mut best = best.lock().unwrap();
+                    let better = match &*best {
+                        Some((len, pos, _)) => (path.len(), position) < (*len, *pos),
+                        None => true,
+                    };
+                    if better {
+                        *best = Some((path.len(), position, path));
+                    }
+                });
+            }
+        });
+
+        best.into_inner().unwrap().map(|(_, _, path)| path)
+    }
 
-        let mut path = vec![found.unwrap()];
-        while path.last().unwrap().source() != origin {
-            let next = path.last().unwrap();
-            path.push(edges[&next.source()]);
+    // The shortest route and up to `count` others, each found by also
+    // avoiding one function along the shortest, shortest first. Not the k
+    // shortest routes, but cheap, and enough to show the other ways through.
+    pub fn alternative_routes(
+        &self,
+        origins : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        avoid_props : u32,
+        matching : PropertyMatch,
+        count : usize
+    ) -> Vec<Vec<EdgeIndex>>
+    {
+        let best = match self.any_route_from_one_of(origins, goal, avoid, avoid_props, matching) {
+            Some(route) => route,
+            None => return vec![],
+        };
+        let mut others : Vec<Vec<EdgeIndex>> = vec![];
+        for &edge in best.iter().take(best.len().saturating_sub(1)) {
+            let mut avoid = avoid.clone();
+            avoid.insert(self.graph.edge_endpoints(edge).unwrap().1);
+            if let Some(route) = self.any_route_from_one_of(origins, goal, &avoid, avoid_props, matching) {
+                if !others.contains(&route) {
+                    others.push(route);
+                }
+            }
         }
+        others.sort_by_key(|route| route.len());
+        others.truncate(count);
+        others.insert(0, best);
+        others
+    }
 
-        let mut result : Vec<EdgeIndex> = vec![];
-        while let Some(edge) = path.pop() {
-            result.push(edge.id());
+    // Cheapest route from any of `origins` to `goal` under `options`, or
+    // Err(visited) if the budget ran out first.
+    pub fn route_with_options(&self, origins : &[NodeIndex], goal : &HashSet<NodeIndex>, options : &RouteOptions) -> Result<Option<Vec<EdgeIndex>>, usize> {
+        let avoid = &options.avoid.functions;
+        let origins : Vec<NodeIndex> = origins.iter().cloned().filter(|origin| !avoid.contains(origin)).collect();
+        if origins.iter().any(|origin| goal.contains(origin)) {
+            return Ok(Some(vec![]));
         }
+        let cost = |dst : NodeIndex| {
+            if options.indirect_cost != 1 && self.node_kind(dst) != NodeKind::Direct { options.indirect_cost } else { 1 }
+        };
 
-        Some(result)
+        // Dijkstra. With max_depth and uneven costs, a function reached by a
+        // cheaper route may still need to be reached again by a shorter one,
+        // so each state is a function and how many calls it took to get
+        // there. Otherwise the cheapest route is also the shortest, and the
+        // depth is left at zero.
+        let by_depth = options.max_depth.is_some() && options.indirect_cost != 1;
+        let level = |depth : usize| if by_depth { depth } else { 0 };
+        let mut best : HashMap<(NodeIndex, usize), u64> = HashMap::new();
+        let mut pred : HashMap<(NodeIndex, usize), EdgeIndex> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for &origin in &origins {
+            best.insert((origin, 0), 0);
+            heap.push(Reverse((0, 0, origin)));
+        }
+        let mode = options.virtual_mode.unwrap_or_else(|| self.virtual_mode());
+        // With depths, the fewest calls each function has been visited at.
+        // Anything visited later cost at least as much, so is only worth
+        // expanding if it took fewer calls.
+        let mut shallowest : HashMap<NodeIndex, usize> = HashMap::new();
+        let mut visited = 0;
+        while let Some(Reverse((d, depth, node))) = heap.pop() {
+            if best.get(&(node, level(depth))).is_some_and(|&cheapest| d > cheapest) {
+                continue;
+            }
+            if by_depth {
+                if shallowest.get(&node).is_some_and(|&shallower| shallower <= depth) {
+                    continue;
+                }
+                shallowest.insert(node, depth);
+            }
+            if goal.contains(&node) {
+                let mut route = vec![];
+                let mut at = (node, level(depth));
+                while let Some(&edge) = pred.get(&at) {
+                    route.push(edge);
+                    at = (self.graph.edge_endpoints(edge).unwrap().0, level(at.1.saturating_sub(1)));
+                }
+                route.reverse();
+                return Ok(Some(route));
+            }
+            visited += 1;
+            if options.budget.is_some_and(|budget| visited > budget) {
+                return Err(visited - 1);
+            }
+            if options.max_depth.is_some_and(|max| depth >= max) { continue; }
+            for (dst, edge, props) in self.neighbor_edges_in(node, Direction::Callees, mode) {
+                if avoid.contains(&dst) || options.matching.blocks(props, options.avoid.props) || props.any & options.clean != 0 { continue; }
+                let nd = d.saturating_add(cost(dst));
+                let state = (dst, level(depth + 1));
+                if best.get(&state).is_none_or(|&cheapest| nd < cheapest) {
+                    best.insert(state, nd);
+                    pred.insert(state, edge);
+                    heap.push(Reverse((nd, depth + 1, dst)));
+                }
+            }
+        }
+        Ok(None)
     }
 
-    fn compute_roots<T,U>(graph : &Graph<T, U>, root_idx : NodeIndex) -> HashSet<NodeIndex> {
-	let mut roots = HashSet::new();
+    // The properties of a route as a whole: `all` has those that every call
+    // along it has at every call site, `any` those that some call might have.
+    pub fn combined_props(&self, route : &[EdgeIndex]) -> PropertySet {
+        if route.is_empty() {
+            return PropertySet { all: 0, any: 0 };
+        }
+        route.iter().fold(PropertySet { all: !0, any: 0 }, |acc, &edge| {
+            PropertySet { all: acc.all & self.graph[edge].all, any: acc.any | self.graph[edge].any }
+        })
+    }
 
-	let mut gen : usize = 0;
-        let mut seen = HashMap::<NodeIndex, usize>::new();
-	for node in graph.node_indices() {
-            if node == root_idx {
+    // Cheapest route from any of `origins` to `goal` by the given per-call
+    // cost (Dijkstra), eg to prefer hot calls from a profile.
+    #[allow(clippy::too_many_arguments)]
+    pub fn weighted_route(
+        &self,
+        origins : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        avoid_props : u32,
+        matching : PropertyMatch,
+        mode : VirtualMode,
+        cost : impl Fn(EdgeIndex) -> u64
+    ) -> Option<Vec<EdgeIndex>>
+    {
+        let mut dist : HashMap<NodeIndex, u64> = HashMap::new();
+        let mut pred : HashMap<NodeIndex, EdgeIndex> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for &origin in origins.iter().filter(|origin| !avoid.contains(origin)) {
+            dist.insert(origin, 0);
+            heap.push(Reverse((0, origin)));
+        }
+        while let Some(Reverse((d, node))) = heap.pop() {
+            if dist.get(&node).is_some_and(|&best| d > best) { continue; }
+            if goal.contains(&node) {
+                let mut route = vec![];
+                let mut at = node;
+                while let Some(&edge) = pred.get(&at) {
+                    route.push(edge);
+                    at = self.graph.edge_endpoints(edge).unwrap().0;
+                }
+                route.reverse();
+                return Some(route);
+            }
+            for (dst, edge, props) in self.neighbor_edges_in(node, Direction::Callees, mode) {
+                if avoid.contains(&dst) || matching.blocks(props, avoid_props) { continue; }
+                let nd = d.saturating_add(cost(edge));
+                if dist.get(&dst).is_none_or(|&best| nd < best) {
+                    dist.insert(dst, nd);
+                    pred.insert(dst, edge);
+                    heap.push(Reverse((nd, dst)));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn any_route(
+        &self,
+        origin : NodeIndex,
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        avoid_props : u32
+    ) -> Option<Vec<EdgeIndex>>
+    {
+        if goal.contains(&origin) {
+            return Some(vec![]);
+        }
+        self.route_from_any(&[origin], goal, avoid, self.virtual_mode(), |props| PropertyMatch::All.blocks(props, avoid_props))
+    }
+
+    // Shortest route from `origin` through at least one call back to itself,
+    // if it is part of a recursion cycle.
+    pub fn cycle_route(&self, origin : NodeIndex, avoid : &HashSet<NodeIndex>, avoid_props : u32) -> Option<Vec<EdgeIndex>> {
+        let goal : HashSet<NodeIndex> = [origin].iter().cloned().collect();
+        self.route_from_any(&[origin], &goal, avoid, self.virtual_mode(), |props| PropertyMatch::All.blocks(props, avoid_props))
+    }
+
+    // Line up two routes by the longest sequence of functions they visit in
+    // the same order, to show where they part and meet again.
+    pub fn diff_routes(&self, a : &[EdgeIndex], b : &[EdgeIndex]) -> Vec<RouteDiff> {
+        let nodes = |route : &[EdgeIndex]| -> Vec<NodeIndex> {
+            let mut nodes : Vec<NodeIndex> = route.first().map(|&edge| self.graph.edge_endpoints(edge).unwrap().0).into_iter().collect();
+            nodes.extend(route.iter().map(|&edge| self.graph.edge_endpoints(edge).unwrap().1));
+            nodes
+        };
+        let (a, b) = (nodes(a), nodes(b));
+
+        // lcs[i][j] is the length of the longest common subsequence of a[i..]
+        // and b[j..].
+        let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+            }
+        }
+
+        let mut diff = vec![];
+        let (mut same, mut first, mut second) = (vec![], vec![], vec![]);
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                if !first.is_empty() || !second.is_empty() {
+                    diff.push(RouteDiff::Diverged { first: std::mem::take(&mut first), second: std::mem::take(&mut second) });
+                }
+                same.push(a[i]);
+                i += 1;
+                j += 1;
                 continue;
             }
+            if !same.is_empty() {
+                diff.push(RouteDiff::Same(std::mem::take(&mut same)));
+            }
+            if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+                first.push(a[i]);
+                i += 1;
+            } else {
+                second.push(b[j]);
+                j += 1;
+            }
+        }
+        if !same.is_empty() {
+            diff.push(RouteDiff::Same(same));
+        }
+        if !first.is_empty() || !second.is_empty() {
+            diff.push(RouteDiff::Diverged { first, second });
+        }
+        diff
+    }
+
+    // Fold every run of two or more consecutive calls within one strongly
+    // connected component into a RouteStep::Cycle. Routes never revisit a
+    // function, so a function later on the route that can call back to an
+    // earlier one shares its cycle with everything in between.
+    pub fn collapse_route(&self, route : &[EdgeIndex]) -> Vec<RouteStep> {
+        let mut nodes = match route.first() {
+            Some(&edge) => vec![self.graph.edge_endpoints(edge).unwrap().0],
+            None => return vec![],
+        };
+        nodes.extend(route.iter().map(|&edge| self.graph.edge_endpoints(edge).unwrap().1));
+        let (sccs, component) = self.strong_components();
+        let component_of = |idx : NodeIndex| component[idx.index()] as usize;
+        let mut steps = vec![];
+        let mut i = 0;
+        while i < route.len() {
+            let mut last = i;
+            while last + 1 < nodes.len() && component_of(nodes[last + 1]) == component_of(nodes[i]) {
+                last += 1;
+            }
+            if last - i >= 2 {
+                let functions = sccs[component_of(nodes[i])].len();
+                steps.push(RouteStep::Cycle { functions, calls: route[i..last].to_vec() });
+                i = last;
+            } else {
+                steps.push(RouteStep::Call(route[i]));
+                i += 1;
+            }
+        }
+        steps
+    }
+
+    // `blocked` says which calls may not be used, by their properties.
+    fn route_from_any(
+        &self,
+        origins : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        mode : VirtualMode,
+        blocked : impl Fn(PropertySet) -> bool
+    ) -> Option<Vec<EdgeIndex>>
+    {
+        self.route_within(origins, goal, avoid, mode, blocked, &AtomicUsize::new(usize::MAX))
+    }
+
+    // route_from_any, but giving up on routes longer than `bound`, which may
+    // be lowered by another thread while the search is running.
+    fn route_within(
+        &self,
+        origins : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        mode : VirtualMode,
+        blocked : impl Fn(PropertySet) -> bool,
+        bound : &AtomicUsize
+    ) -> Option<Vec<EdgeIndex>>
+    {
+        if let Some(index) = &self.reach_index {
+            if !origins.iter().any(|&src| goal.iter().any(|&dst| index.may_reach(src, dst))) {
+                return None;
+            }
+        }
+
+        // Dense per-node tables rather than hash sets: on a large graph the
+        // hashing and allocation dominate the actual traversal.
+        with_scratch(self.graph.node_count(), |scratch| {
+            for idx in avoid {
+                scratch.mark(idx.index());
+            }
+            for idx in origins {
+                scratch.mark(idx.index());
+                scratch.is_origin.insert(idx.index());
+                scratch.work.push_back((*idx, 0));
+            }
+
+            let mut found : Option<NodeIndex> = None;
+            'search: while let Some((src, depth)) = scratch.work.pop_front() {
+                // Breadth-first, so every remaining route is at least this long.
+                if depth + 1 > bound.load(Ordering::Relaxed) { break; }
+                for (dst, edge, props) in self.neighbor_edges_in(src, Direction::Callees, mode) {
+                    if blocked(props) { continue; }
+                    // An origin can only be reached again as the end of a
+                    // cycle back to itself.
+                    let cycle = scratch.is_origin.contains(dst.index()) && goal.contains(&dst);
+                    if !scratch.mark(dst.index()) && !cycle { continue; }
+                    scratch.pred[dst.index()] = edge.index() as u32;
+                    if goal.contains(&dst) {
+                        found = Some(dst);
+                        break 'search;
+                    }
+                    scratch.work.push_back((dst, depth + 1));
+                }
+            }
+
+            let mut node = found?;
+            let mut result : Vec<EdgeIndex> = vec![];
+            loop {
+                let edge = EdgeIndex::new(scratch.pred[node.index()] as usize);
+                result.push(edge);
+                node = self.graph.edge_endpoints(edge).unwrap().0;
+                if scratch.is_origin.contains(node.index()) { break; }
+            }
+            result.reverse();
+
+            Some(result)
+        })
+    }
+
+    // The strongly connected components, and which one each function is in.
+    fn strong_components(&self) -> (Vec<Vec<NodeIndex>>, Vec<u32>) {
+        let sccs = tarjan_scc(&self.graph);
+        let mut component = vec![0u32; self.graph.node_count()];
+        for (i, scc) in sccs.iter().enumerate() {
+            for idx in scc {
+                component[idx.index()] = i as u32;
+            }
+        }
+        (sccs, component)
+    }
+
+    // One function for each strongly connected component that nothing
+    // outside it calls (or, for sinks, that calls nothing outside it): the
+    // function itself if it isn't part of a cycle, otherwise the lowest
+    // numbered function of the cycle. So the same whatever order the graph
+    // was loaded in.
+    fn compute_roots(&self, direction : Direction) -> HashSet<NodeIndex> {
+        if self.legacy_roots {
+            return self.compute_roots_legacy(direction);
+        }
+        let (sccs, component) = self.strong_components();
+        sccs.iter().enumerate()
+            .filter(|&(i, scc)| {
+                scc.iter().all(|&idx| self.csr().neighbors(idx, direction).all(|(other, _, _)| component[other.index()] as usize == i))
+            })
+            .filter_map(|(_, scc)| scc.iter().cloned().filter(|&idx| !self.is_synthetic(idx)).min())
+            .collect()
+    }
+
+    // Depth-first from each function in turn, taking whichever function of
+    // a cycle the walk happens to come back to first.
+    fn compute_roots_legacy(&self, direction : Direction) -> HashSet<NodeIndex> {
+        let graph = &self.graph;
+	let mut roots = HashSet::new();
+
+	let mut gen : u32 = 0;
+        // Generation in which each node was first seen, or 0 for unseen.
+        let mut seen = vec![0u32; graph.node_count()];
+	for node in self.node_ids() {
             gen += 1;
 	    let mut work = vec![node];
 	    while !work.is_empty() {
                 let id = work.pop().unwrap();
-                if let Some(when) = seen.get(&id) {
-		    if *when == gen {
+                let when = seen[id.index()];
+                if when != 0 {
+		    if when == gen {
                         // Seen in the same generation -- we found a cycle.
                         // Randomly pick this node as the root.
 			roots.insert(id);
@@ -403,9 +2868,9 @@ impl Callgraph {
                     }
                 }
 
- 		seen.insert(id, gen);
+ 		seen[id.index()] = gen;
 		let mut any_callers = false;
-		for caller in graph.neighbors(id) {
+		for (caller, _, _) in self.csr().neighbors(id, direction) {
 		    any_callers = true;
 		    work.push(caller);
                 }
@@ -417,39 +2882,361 @@ impl Callgraph {
 	roots
     }
 
-    pub fn roots(&mut self) -> Vec<NodeIndex> {
-        if let Some(roots) = &self.roots {
-	    return roots.iter().map(|&x| x).collect();
-        }
+    pub fn roots(&self) -> &HashSet<NodeIndex> {
+        self.roots.get_or_init(|| self.compute_roots(Direction::Callers))
+    }
 
-        self.root = self.add_function("<root>");
+    pub fn sinks(&self) -> &HashSet<NodeIndex> {
+        self.sinks.get_or_init(|| self.compute_roots(Direction::Callees))
+    }
+}
 
-        let roots = Callgraph::compute_roots(&self.caller_graph, self.root);
-        let result = roots.iter().map(|&x| x).collect();
-        self.roots = Some(roots);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        for root in self.roots() {
-            self.add_edge(self.root, root, PropertySet { all: 0, any: 0 });
+    // Functions #1.. with the given mangled and unmangled names, and calls
+    // between them by id.
+    fn graph(functions : &[(&str, &str)], calls : &[(usize, usize)]) -> Callgraph {
+        let mut cg = Callgraph::new();
+        for (mangled, unmangled) in functions {
+            let idx = cg.add_function(mangled);
+            cg.add_unmangled_name(idx, unmangled);
         }
+        for &(src, dst) in calls {
+            cg.add_edge(NodeIndex::new(src), NodeIndex::new(dst), PropertySet { all: 0, any: 0 });
+        }
+        cg
+    }
 
-        result
+    fn ids(found : Option<Vec<NodeIndex>>) -> Vec<usize> {
+        let mut ids : Vec<usize> = found.unwrap_or_default().iter().map(|idx| idx.index()).collect();
+        ids.sort();
+        ids
     }
 
-    pub fn sinks(&mut self) -> Vec<NodeIndex> {
-        if let Some(sinks) = &self.sinks {
-	    return sinks.iter().map(|&x| x).collect();
-        }
+    fn unicode_graph() -> Callgraph {
+        graph(&[
+            ("_Z6größev", "größe()"),
+            ("_Z5naïvev", "Ünïcode::naïve()"),
+            ("_Z5plainv", "plain()"),
+        ], &[])
+    }
+
+    #[test]
+    fn resolve_non_ascii() {
+        let cg = unicode_graph();
+        assert_eq!(ids(cg.resolve("größe")), vec![1]);
+        assert_eq!(ids(cg.resolve("Ünïcode::naïve")), vec![2]);
+        assert_eq!(ids(cg.resolve("naïve")), vec![2]);
+        assert_eq!(ids(cg.resolve("Ünï*")), vec![2]);
+        assert_eq!(ids(cg.resolve("/ß/")), vec![1]);
+        assert!(ids(cg.resolve("/ï")).is_empty());
+        assert!(ids(cg.resolve("ï/")).is_empty());
+        assert!(ids(cg.resolve("#é")).is_empty());
+        assert!(ids(cg.resolve("é")).is_empty());
+        assert_eq!(cg.lookup_name("größe()"), Some(NodeIndex::new(1)));
+        assert_eq!(cg.lookup_name("_Z5naïvev"), Some(NodeIndex::new(2)));
+    }
+
+    #[test]
+    fn resolve_short_patterns() {
+        let cg = unicode_graph();
+        assert_eq!(cg.resolve(""), None);
+        assert!(ids(cg.resolve("/")).is_empty());
+        assert!(ids(cg.resolve("#")).is_empty());
+        assert_eq!(ids(cg.resolve("*")), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn resolve_checks_ids() {
+        let mut cg = unicode_graph();
+        assert_eq!(cg.resolve_id(3), Some(NodeIndex::new(3)));
+        assert_eq!(cg.resolve_id(4), None);
+        assert_eq!(cg.resolve_id(0), None);
+        assert_eq!(ids(cg.resolve("#3")), vec![3]);
+        assert!(ids(cg.resolve("#4")).is_empty());
+        assert!(ids(cg.resolve("#0")).is_empty());
+        assert!(ids(cg.resolve("#99999999999999999999")).is_empty());
+        cg.filter_out(NodeIndex::new(2));
+        assert_eq!(cg.resolve_id(2), None);
+        assert!(ids(cg.resolve("#2")).is_empty());
+    }
+
+    #[test]
+    fn synthetic_nodes_are_hidden() {
+        let mut cg = unicode_graph();
+        cg.filter_out(NodeIndex::new(2));
+        assert!(cg.is_synthetic(NodeIndex::new(0)));
+        assert!(cg.is_synthetic(NodeIndex::new(2)));
+        assert!(!cg.is_synthetic(NodeIndex::new(1)));
+        assert_eq!(cg.node_ids().map(|idx| idx.index()).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(ids(cg.resolve("/.*/")), vec![1, 3]);
+        assert_eq!(sorted(cg.roots()), vec![1, 3]);
+        assert!(ids(cg.resolve("#2")).is_empty());
+
+        cg.show_synthetic = true;
+        assert_eq!(cg.resolve_id(0), Some(NodeIndex::new(0)));
+        assert_eq!(ids(cg.resolve("#2")), vec![2]);
+        assert_eq!(ids(cg.resolve("/.*/")), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn matcher_non_ascii() {
+        let cg = unicode_graph();
+        let matches = |pattern : &str| -> Vec<usize> {
+            let matcher = Matcher::new(pattern).unwrap();
+            cg.node_ids().filter(|&idx| matcher.is_match(&cg, idx)).map(|idx| idx.index()).collect()
+        };
+        assert_eq!(matches("ö"), vec![1]);
+        assert_eq!(matches("/^Ü/"), vec![2]);
+        assert!(matches("/ï").is_empty());
+        assert!(matches("é").is_empty());
+        assert!(Matcher::new("").is_none());
+        assert!(Matcher::new("/(/").is_none());
+    }
+
+    #[test]
+    fn matcher_tags() {
+        let cg = graph(&[
+            ("_Z3tagv", "tag::f()"),
+            ("_Z1gv", "g()"),
+        ], &[]);
+        cg.annotations_mut().add_tag("_Z1gv", "f").unwrap();
+        let matches = |pattern : &str| -> Vec<usize> {
+            let matcher = Matcher::new(pattern).unwrap();
+            cg.node_ids().filter(|&idx| matcher.is_match(&cg, idx)).map(|idx| idx.index()).collect()
+        };
+        assert_eq!(matches("tag:"), vec![1]);
+        assert_eq!(matches("tag::f"), vec![1]);
+        assert_eq!(matches("#tag:f"), vec![2]);
+        assert!(matches("#tag:g").is_empty());
+    }
+
+    // #1 calls into the cycle #2 <-> #3, which has a tail #4 leading into
+    // the cycle #7 <-> #8. The cycle #5 <-> #6 calls #2 but nothing calls it.
+    const CYCLIC_CALLS : &[(usize, usize)] = &[
+        (1, 2), (2, 3), (3, 2), (3, 4),
+        (5, 6), (6, 5), (6, 2),
+        (4, 7), (7, 8), (8, 7),
+    ];
+
+    fn cyclic_graph(calls : &[(usize, usize)], relabel : fn(usize) -> usize) -> Callgraph {
+        let names : Vec<(String, String)> = (1..=8).map(|i| (format!("_Z2f{}v", i), format!("f{}()", i))).collect();
+        let functions : Vec<(&str, &str)> = names.iter().map(|(m, u)| (m.as_str(), u.as_str())).collect();
+        let calls : Vec<(usize, usize)> = calls.iter().map(|&(src, dst)| (relabel(src), relabel(dst))).collect();
+        graph(&functions, &calls)
+    }
+
+    fn sorted(set : &HashSet<NodeIndex>) -> Vec<usize> {
+        let mut ids : Vec<usize> = set.iter().map(|idx| idx.index()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn roots_are_lowest_of_source_components() {
+        let cg = cyclic_graph(CYCLIC_CALLS, |i| i);
+        assert_eq!(sorted(cg.roots()), vec![1, 5]);
+        assert_eq!(sorted(cg.sinks()), vec![7]);
+    }
+
+    #[test]
+    fn roots_do_not_depend_on_order() {
+        let mut reversed = CYCLIC_CALLS.to_vec();
+        reversed.reverse();
+        let cg = cyclic_graph(&reversed, |i| i);
+        assert_eq!(sorted(cg.roots()), vec![1, 5]);
+        assert_eq!(sorted(cg.sinks()), vec![7]);
+
+        // Numbering the functions backwards still picks the lowest id of
+        // each cycle, which is now the other member.
+        let cg = cyclic_graph(CYCLIC_CALLS, |i| 9 - i);
+        assert_eq!(sorted(cg.roots()), vec![3, 8]);
+        assert_eq!(sorted(cg.sinks()), vec![1]);
+    }
 
-        self.sink = self.add_function("<sink>");
+    #[test]
+    fn route_from_a_goal() {
+        let cg = cyclic_graph(CYCLIC_CALLS, |i| i);
+        let set = |ids : &[usize]| -> HashSet<NodeIndex> { ids.iter().map(|&i| NodeIndex::new(i)).collect() };
+        let none = HashSet::new();
+        assert_eq!(cg.any_route(NodeIndex::new(1), &set(&[1]), &none, 0), Some(vec![]));
+        assert_eq!(cg.any_route(NodeIndex::new(1), &set(&[1, 3]), &none, 0), Some(vec![]));
+        assert_eq!(cg.any_route_from_one_of(&[NodeIndex::new(5), NodeIndex::new(2)], &set(&[2]), &none, 0, PropertyMatch::All), Some(vec![]));
+        assert_eq!(cg.any_route(NodeIndex::new(1), &set(&[3]), &none, 0).map(|route| route.len()), Some(2));
 
-        let sinks = Callgraph::compute_roots(&self.graph, self.sink);
-        let result = sinks.iter().map(|&x| x).collect();
-        self.sinks = Some(sinks);
+        // Only a real cycle leads back to the origin.
+        let cycle = cg.cycle_route(NodeIndex::new(2), &none, 0).unwrap();
+        let endpoints : Vec<(usize, usize)> = cycle.iter().map(|&edge| {
+            let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+            (src.index(), dst.index())
+        }).collect();
+        assert_eq!(endpoints, vec![(2, 3), (3, 2)]);
+        assert_eq!(cg.cycle_route(NodeIndex::new(1), &none, 0), None);
+        assert_eq!(cg.cycle_route(NodeIndex::new(2), &set(&[3]), 0), None);
+    }
+
+    #[test]
+    fn roots_leave_the_graph_alone() {
+        let mut cg = cyclic_graph(CYCLIC_CALLS, |i| i);
+        let (nodes, edges) = (cg.graph.node_count(), cg.graph.edge_count());
+        assert_eq!(sorted(cg.roots()), vec![1, 5]);
+        assert_eq!(sorted(cg.sinks()), vec![7]);
+        assert_eq!((cg.graph.node_count(), cg.graph.edge_count()), (nodes, edges));
+        assert_eq!(cg.node_ids().count(), 8);
+
+        // Adding a call recomputes them.
+        cg.add_edge(NodeIndex::new(4), NodeIndex::new(1), PropertySet { all: 0, any: 0 });
+        assert_eq!(sorted(cg.roots()), vec![5]);
+        assert_eq!(sorted(cg.sinks()), vec![7]);
+    }
 
-        for sink in self.sinks() {
-            self.add_edge(sink, self.sink, PropertySet { all: 0, any: 0 });
+    #[test]
+    fn route_property_matching() {
+        // Some of the calls from #1 to #2 are suppressed, and all of those
+        // from #1 to #3.
+        let mut cg = graph(&[
+            ("_Z1av", "a()"),
+            ("_Z1bv", "b()"),
+            ("_Z1cv", "c()"),
+            ("_Z1dv", "d()"),
+        ], &[(2, 4), (3, 4)]);
+        cg.add_edge(NodeIndex::new(1), NodeIndex::new(2), PropertySet { all: 0, any: 1 });
+        cg.add_edge(NodeIndex::new(1), NodeIndex::new(3), PropertySet { all: 1, any: 1 });
+        let goal : HashSet<NodeIndex> = [NodeIndex::new(4)].iter().cloned().collect();
+        let none = HashSet::new();
+        let via = |avoid_props : u32, matching : PropertyMatch| -> Option<usize> {
+            let route = cg.any_route_from_one_of(&[NodeIndex::new(1)], &goal, &none, avoid_props, matching)?;
+            Some(cg.graph.edge_endpoints(route[0]).unwrap().1.index())
+        };
+        assert!(via(0, PropertyMatch::All).is_some());
+        assert_eq!(via(1, PropertyMatch::All), Some(2));
+        assert_eq!(via(1, PropertyMatch::Any), None);
+        assert!(via(2, PropertyMatch::Any).is_some());
+        assert_eq!(cg.any_route(NodeIndex::new(1), &goal, &none, 1).map(|route| route.len()), Some(2));
+    }
+
+    #[test]
+    fn collapsed_cycles() {
+        // #2 -> #3 -> #4 -> #2 is a cycle, and so is #5 <-> #6.
+        let cg = graph(&[
+            ("_Z1av", "a()"), ("_Z1bv", "b()"), ("_Z1cv", "c()"),
+            ("_Z1dv", "d()"), ("_Z1ev", "e()"), ("_Z1fv", "f()"),
+        ], &[(1, 2), (2, 3), (3, 4), (4, 2), (4, 5), (5, 6), (6, 5)]);
+        let goal : HashSet<NodeIndex> = [NodeIndex::new(6)].iter().cloned().collect();
+        let route = cg.any_route(NodeIndex::new(1), &goal, &HashSet::new(), 0).unwrap();
+        let steps : Vec<String> = cg.collapse_route(&route).iter().map(|step| match step {
+            RouteStep::Call(edge) => format!("{}", cg.graph.edge_endpoints(*edge).unwrap().1.index()),
+            RouteStep::Cycle { functions, calls } => format!("cycle of {} ({} calls)", functions, calls.len()),
+        }).collect();
+        // A single call within #5 <-> #6 is left alone.
+        assert_eq!(steps, vec!["2", "cycle of 3 (2 calls)", "5", "6"]);
+        assert!(cg.collapse_route(&[]).is_empty());
+    }
+
+    #[test]
+    fn max_depth_with_prefer_direct() {
+        // #1 reaches #4 by three direct calls, or by two through the function
+        // pointer #6, and #4 calls the goal #5.
+        let mut cg = graph(&[
+            ("_Z1av", "a()"), ("_Z1bv", "b()"), ("_Z1cv", "c()"),
+            ("_Z1xv", "x()"), ("_Z1gv", "g()"),
+        ], &[(1, 2), (2, 3), (3, 4), (4, 5)]);
+        let fnptr = cg.add_function("VARIABLE fp");
+        cg.add_edge(NodeIndex::new(1), fnptr, PropertySet { all: 0, any: 0 });
+        cg.add_edge(fnptr, NodeIndex::new(4), PropertySet { all: 0, any: 0 });
+        let goal : HashSet<NodeIndex> = [NodeIndex::new(5)].iter().cloned().collect();
+        let length = |max_depth : Option<usize>| {
+            let options = RouteOptions { max_depth, indirect_cost: 10, ..Default::default() };
+            cg.route_with_options(&[NodeIndex::new(1)], &goal, &options).unwrap().map(|route| route.len())
+        };
+        assert_eq!(length(None), Some(4));
+        assert_eq!(length(Some(4)), Some(4));
+        assert_eq!(length(Some(3)), Some(3));
+        assert_eq!(length(Some(2)), None);
+    }
+
+    #[test]
+    fn legacy_roots() {
+        let mut cg = cyclic_graph(CYCLIC_CALLS, |i| i);
+        assert_eq!(sorted(cg.roots()), vec![1, 5]);
+        cg.set_legacy_roots(true);
+        let roots = sorted(cg.roots());
+        assert!(roots.contains(&1));
+        assert!(roots.contains(&5) || roots.contains(&6));
+        assert!(!roots.contains(&4));
+        cg.set_legacy_roots(false);
+        assert_eq!(sorted(cg.roots()), vec![1, 5]);
+    }
+
+    #[test]
+    fn repeated_and_self_calls() {
+        let mut cg = graph(&[
+            ("_Z1av", "a()"),
+            ("_Z1bv", "b()"),
+            ("_Z1cv", "c()"),
+        ], &[(1, 2), (1, 3), (1, 1)]);
+        cg.add_edge(NodeIndex::new(1), NodeIndex::new(2), PropertySet { all: 1, any: 1 });
+        let mut groups : Vec<(usize, Vec<EdgeIndex>)> = cg.grouped_callee_edges(NodeIndex::new(1)).into_iter()
+            .map(|(callee, edges)| (callee.index(), edges))
+            .collect();
+        groups.sort();
+        assert_eq!(groups.iter().map(|(callee, edges)| (*callee, edges.len())).collect::<Vec<_>>(), vec![(1, 1), (2, 2), (3, 1)]);
+
+        let brevity = DescriptionBrevity::Normal;
+        assert_eq!(cg.describe_calls(&groups[0].1, brevity), "#1 = a() (self call)");
+        let repeated = cg.describe_calls(&groups[1].1, brevity);
+        assert!(repeated.starts_with("#2 = b() (2 calls: "));
+        assert!(repeated.contains("plain") && repeated.contains("[bit0]"));
+        assert_eq!(cg.describe_calls(&groups[2].1, brevity), "#3 = c()");
+
+        assert_eq!(cg.query("selfcalls()").unwrap(), vec![NodeIndex::new(1)]);
+    }
+
+    #[test]
+    fn unnamed_property_bits() {
+        let mut cg = graph(&[], &[]);
+        cg.property_names.insert(1, "GC_SUPPRESSED".to_string());
+        assert_eq!(cg.describe_property_set(0x9), "GC_SUPPRESSED,bit3");
+        assert_eq!(cg.describe_property_set(1 << 31), "bit31");
+        assert_eq!(cg.resolve_property("bit3"), Some(0x8));
+        assert_eq!(cg.resolve_property("bit0"), Some(1));
+        assert_eq!(cg.resolve_property("bit31"), Some(1 << 31));
+        assert_eq!(cg.resolve_property("bit32"), None);
+        assert_eq!(cg.resolve_property("bit"), None);
+        for name in cg.describe_property_set(0xf0).split(',') {
+            assert!(cg.resolve_property(name).is_some());
         }
+    }
 
-        result
+    // main() calls run() virtually on a Base, and only Other::run() can GC.
+    fn virtual_gc_graph() -> Callgraph {
+        let mut cg = graph(&[
+            ("_Z4mainv", "main()"),
+            ("_ZN4Base3runEv", "Base::run()"),
+            ("_ZN5Other3runEv", "Other::run()"),
+            ("_Z2gcv", "gc()"),
+        ], &[(3, 4)]);
+        let method = cg.add_function("CLASS Base FIELD run");
+        cg.add_edge(NodeIndex::new(1), method, PropertySet { all: 0, any: 0 });
+        cg.resolve_virtual_calls(&[method]);
+        cg.annotate_gc(&[NodeIndex::new(4)]);
+        cg
+    }
+
+    #[test]
+    fn can_gc_follows_virtual_mode() {
+        let cg = virtual_gc_graph();
+        let main = NodeIndex::new(1);
+        cg.set_virtual_mode(VirtualMode::Ignore);
+        assert!(!cg.can_gc(main));
+        cg.set_virtual_mode(VirtualMode::Conservative);
+        assert!(cg.can_gc(main));
+        assert_eq!(cg.gc_route(main).map(|route| route.len()), Some(3));
+        cg.set_virtual_mode(VirtualMode::Refined);
+        assert!(!cg.can_gc(main));
+        cg.set_virtual_mode(VirtualMode::Conservative);
+        assert!(cg.can_gc(main));
     }
 }
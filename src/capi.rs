@@ -0,0 +1,251 @@
+#![allow(clippy::missing_safety_doc)]
+
+use crate::callgraph::{Callgraph, PropertyMatch};
+use crate::hazard::{load_graph_with_options, LoadOptions};
+use petgraph::graph::NodeIndex;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+// Plain C interface to the traversal engine, for embedding from C++, Go, Node
+// etc without going through Python. Declarations are in hazgraph.h; keep the
+// two in sync.
+//
+// Functions are identified by their u32 ids. Lists of ids are returned in a
+// cg_ids that the caller frees with cg_free_ids, and strings must be freed
+// with cg_free_string. Failures are reported by a null/negative return, with
+// the reason available from cg_last_error. That includes panics, which must
+// not unwind into the caller.
+
+#[repr(C)]
+pub struct CgIds {
+    pub ids : *mut u32,
+    pub len : usize,
+}
+
+impl CgIds {
+    fn empty() -> CgIds {
+        CgIds { ids: ptr::null_mut(), len: 0 }
+    }
+
+    fn from_nodes(nodes : impl IntoIterator<Item = NodeIndex>) -> CgIds {
+        let ids : Box<[u32]> = nodes.into_iter().map(|idx| idx.index() as u32).collect();
+        let len = ids.len();
+        if len == 0 {
+            return CgIds::empty();
+        }
+        CgIds { ids: Box::into_raw(ids) as *mut u32, len }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR : RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message : String) {
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+unsafe fn graph<'a>(cg : *const Callgraph) -> Option<&'a Callgraph> {
+    let cg = cg.as_ref();
+    if cg.is_none() {
+        set_error("null graph".to_string());
+    }
+    cg
+}
+
+unsafe fn string<'a>(s : *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        set_error("null string".to_string());
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(_) => {
+            set_error("string is not valid UTF-8".to_string());
+            None
+        }
+    }
+}
+
+// Run the body of an entry point, turning a panic into an error return.
+fn guarded<R>(failed : R, f : impl FnOnce() -> R) -> R {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string()).
+            or_else(|| payload.downcast_ref::<String>().cloned()).
+            unwrap_or_else(|| "unknown error".to_string());
+        set_error(format!("internal error: {}", message));
+        failed
+    })
+}
+
+fn node(cg : &Callgraph, id : u32) -> Option<NodeIndex> {
+    let idx = cg.resolve_id(id as usize);
+    if idx.is_none() {
+        set_error(format!("invalid function id {}", id));
+    }
+    idx
+}
+
+// Message describing the last failure on this thread, or null. Owned by the
+// library; valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn cg_last_error() -> *const c_char {
+    guarded(ptr::null(), || {
+        LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cg_load(filename : *const c_char) -> *mut Callgraph {
+    guarded(ptr::null_mut(), || {
+        let filename = match string(filename) {
+            Some(f) => f,
+            None => return ptr::null_mut(),
+        };
+        // The embedder's stdout is not ours to write the load summary to.
+        let options = LoadOptions { quiet: true, ..Default::default() };
+        match load_graph_with_options(filename, &options, None) {
+            Ok(cg) => Box::into_raw(Box::new(cg)),
+            Err(e) => {
+                set_error(e.to_string());
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cg_free(cg : *mut Callgraph) {
+    guarded((), || {
+        if !cg.is_null() {
+            drop(Box::from_raw(cg));
+        }
+    })
+}
+
+// Functions have ids from 1 to this. (Id 0 is a dummy, not a function.)
+#[no_mangle]
+pub unsafe extern "C" fn cg_function_count(cg : *const Callgraph) -> u32 {
+    guarded(0, || {
+        graph(cg).map_or(0, |cg| cg.graph.node_count() as u32 - 1)
+    })
+}
+
+// Same query syntax as the REPL's resolve command. An unresolvable query gives
+// an empty list.
+#[no_mangle]
+pub unsafe extern "C" fn cg_resolve(cg : *const Callgraph, query : *const c_char) -> CgIds {
+    guarded(CgIds::empty(), || {
+        match (graph(cg), string(query)) {
+            (Some(cg), Some(query)) => CgIds::from_nodes(cg.resolve(query).unwrap_or_default()),
+            _ => CgIds::empty(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cg_callees(cg : *const Callgraph, id : u32) -> CgIds {
+    guarded(CgIds::empty(), || {
+        match graph(cg).and_then(|cg| Some((cg, node(cg, id)?))) {
+            Some((cg, idx)) => CgIds::from_nodes(cg.callees(idx)),
+            None => CgIds::empty(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cg_callers(cg : *const Callgraph, id : u32) -> CgIds {
+    guarded(CgIds::empty(), || {
+        match graph(cg).and_then(|cg| Some((cg, node(cg, id)?))) {
+            Some((cg, idx)) => CgIds::from_nodes(cg.callers(idx)),
+            None => CgIds::empty(),
+        }
+    })
+}
+
+// Shortest route from `src` to `dst` that does not pass through any of the
+// `avoid_len` functions in `avoid` (which may be null if avoid_len is 0).
+// Returns 1 and fills in `out` with the functions along the route, starting
+// with src and ending with dst; 0 if there is no route; -1 on error.
+#[no_mangle]
+pub unsafe extern "C" fn cg_route(
+    cg : *const Callgraph,
+    src : u32,
+    dst : u32,
+    avoid : *const u32,
+    avoid_len : usize,
+    out : *mut CgIds
+) -> c_int
+{
+    guarded(-1, || {
+        let cg = match graph(cg) {
+            Some(cg) => cg,
+            None => return -1,
+        };
+        if out.is_null() || (avoid.is_null() && avoid_len > 0) {
+            set_error("null pointer argument".to_string());
+            return -1;
+        }
+        let (src, dst) = match (node(cg, src), node(cg, dst)) {
+            (Some(src), Some(dst)) => (src, dst),
+            _ => return -1,
+        };
+        let mut avoid_set = HashSet::new();
+        if avoid_len > 0 {
+            for &id in std::slice::from_raw_parts(avoid, avoid_len) {
+                match node(cg, id) {
+                    Some(idx) => { avoid_set.insert(idx); },
+                    None => return -1,
+                }
+            }
+        }
+
+        let goal : HashSet<NodeIndex> = [dst].iter().cloned().collect();
+        match cg.any_route_from_one_of(&[src], &goal, &avoid_set, 0, PropertyMatch::All) {
+            Some(route) => {
+                let mut nodes = vec![src];
+                nodes.extend(route.iter().map(|&edge| cg.graph.edge_endpoints(edge).unwrap().1));
+                *out = CgIds::from_nodes(nodes);
+                1
+            },
+            None => {
+                *out = CgIds::empty();
+                0
+            }
+        }
+    })
+}
+
+// Mangled name of a function, or null on error. Free with cg_free_string.
+#[no_mangle]
+pub unsafe extern "C" fn cg_name(cg : *const Callgraph, id : u32) -> *mut c_char {
+    guarded(ptr::null_mut(), || {
+        match graph(cg).and_then(|cg| Some((cg, node(cg, id)?))) {
+            Some((cg, idx)) => CString::new(cg.graph[idx].replace('\0', "")).unwrap().into_raw(),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cg_free_ids(ids : CgIds) {
+    guarded((), || {
+        if !ids.ids.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(ids.ids, ids.len)));
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cg_free_string(s : *mut c_char) {
+    guarded((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
@@ -0,0 +1,52 @@
+use crate::callgraph::Callgraph;
+use crate::profile::NameIndex;
+use petgraph::graph::NodeIndex;
+use std::collections::HashSet;
+use std::fs;
+
+// Function coverage from an lcov tracefile (as written by lcov, or by grcov
+// with -t lcov). Functions that ran become @covered, and everything else in
+// the graph -- including functions the coverage build didn't instrument --
+// becomes @uncovered.
+
+pub struct Coverage {
+    pub covered : Vec<NodeIndex>,
+    pub uncovered : Vec<NodeIndex>,
+    pub unmatched : usize,
+}
+
+pub fn load(cg : &Callgraph, filename : &str) -> Result<Coverage, String> {
+    let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+    let index = NameIndex::new(cg);
+    let mut covered = HashSet::new();
+    let mut unmatched = 0;
+    for line in text.lines() {
+        // FNDA:<count>,<name>, or in lcov 2 FNA:<index>,<count>,<name>.
+        let (count, name) = if let Some(rest) = line.strip_prefix("FNDA:") {
+            match rest.split_once(',') {
+                Some(fields) => fields,
+                None => continue,
+            }
+        } else if let Some(rest) = line.strip_prefix("FNA:") {
+            match rest.split_once(',').and_then(|(_, rest)| rest.split_once(',')) {
+                Some(fields) => fields,
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+        let count = count.trim().parse::<u64>().map_err(|_| format!("bad execution count: {}", line))?;
+        match index.get(name) {
+            Some(idx) => if count > 0 { covered.insert(idx); },
+            None => unmatched += 1,
+        }
+    }
+
+    let mut coverage = Coverage {
+        covered: covered.iter().cloned().collect(),
+        uncovered: cg.node_ids().filter(|idx| !covered.contains(idx)).collect(),
+        unmatched,
+    };
+    coverage.covered.sort();
+    Ok(coverage)
+}
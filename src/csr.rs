@@ -0,0 +1,95 @@
+use crate::callgraph::{Direction, PropertySet};
+use petgraph::graph::{EdgeIndex, Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::{Incoming, Outgoing};
+use std::mem::size_of;
+
+// Compressed sparse row form of the callgraph, built once loading is done and
+// used by the query-time traversals. Walking petgraph's per-node linked edge
+// lists jumps all over memory; here each node's neighbors are contiguous.
+
+// One direction of adjacency: the neighbors of node n are at
+// [offsets[n], offsets[n+1]) in the parallel neighbor/edge/props arrays.
+struct Adjacency {
+    offsets : Vec<u32>,
+    neighbors : Vec<u32>,
+    edges : Vec<u32>,
+    props : Vec<PropertySet>,
+}
+
+impl Adjacency {
+    fn build(graph : &Graph<String, PropertySet>, dir : petgraph::Direction, sorted : bool) -> Adjacency {
+        let mut adj = Adjacency {
+            offsets: Vec::with_capacity(graph.node_count() + 1),
+            neighbors: Vec::with_capacity(graph.edge_count()),
+            edges: Vec::with_capacity(graph.edge_count()),
+            props: Vec::with_capacity(graph.edge_count()),
+        };
+        adj.offsets.push(0);
+        // Same neighbor order as petgraph, so results don't change depending
+        // on which representation answered the query. Unless `sorted`, when
+        // it's by neighbor and then edge id, which doesn't depend on the
+        // order the calls were added in.
+        let mut row : Vec<(u32, u32, PropertySet)> = vec![];
+        for node in graph.node_indices() {
+            row.clear();
+            for edge in graph.edges_directed(node, dir) {
+                let other = if dir == Outgoing { edge.target() } else { edge.source() };
+                row.push((other.index() as u32, edge.id().index() as u32, *edge.weight()));
+            }
+            if sorted {
+                row.sort_by_key(|&(other, edge, _)| (other, edge));
+            }
+            for &(other, edge, props) in &row {
+                adj.neighbors.push(other);
+                adj.edges.push(edge);
+                adj.props.push(props);
+            }
+            adj.offsets.push(adj.neighbors.len() as u32);
+        }
+        adj
+    }
+
+    fn heap_size(&self) -> usize {
+        self.offsets.capacity() * size_of::<u32>() +
+            self.neighbors.capacity() * size_of::<u32>() +
+            self.edges.capacity() * size_of::<u32>() +
+            self.props.capacity() * size_of::<PropertySet>()
+    }
+
+    fn range(&self, idx : NodeIndex) -> std::ops::Range<usize> {
+        match self.offsets.get(idx.index() + 1) {
+            Some(&end) => self.offsets[idx.index()] as usize .. end as usize,
+            None => 0..0,
+        }
+    }
+}
+
+pub struct Csr {
+    callees : Adjacency,
+    callers : Adjacency,
+}
+
+impl Csr {
+    pub fn build(graph : &Graph<String, PropertySet>, sorted : bool) -> Csr {
+        Csr {
+            callees: Adjacency::build(graph, Outgoing, sorted),
+            callers: Adjacency::build(graph, Incoming, sorted),
+        }
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.callees.heap_size() + self.callers.heap_size()
+    }
+
+    // (neighbor, edge, props) for each call out of or into `idx`.
+    pub fn neighbors(&self, idx : NodeIndex, direction : Direction) -> impl Iterator<Item = (NodeIndex, EdgeIndex, PropertySet)> + '_ {
+        let adj = match direction {
+            Direction::Callees => &self.callees,
+            Direction::Callers => &self.callers,
+        };
+        adj.range(idx).map(move |i| {
+            (NodeIndex::new(adj.neighbors[i] as usize), EdgeIndex::new(adj.edges[i] as usize), adj.props[i])
+        })
+    }
+}
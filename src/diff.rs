@@ -0,0 +1,46 @@
+use crate::callgraph::{Callgraph, PropertySet};
+use std::collections::HashSet;
+
+// Differences between two callgraphs, eg from consecutive builds. Node ids are
+// not stable between builds, so everything is keyed by mangled name. An edge
+// whose properties changed shows up as both removed and added.
+pub struct GraphDiff {
+    pub added_functions : Vec<String>,
+    pub removed_functions : Vec<String>,
+    pub added_edges : Vec<(String, String, PropertySet)>,
+    pub removed_edges : Vec<(String, String, PropertySet)>,
+}
+
+fn function_set(cg : &Callgraph) -> HashSet<&str> {
+    cg.node_ids().map(|idx| cg.graph[idx].as_str()).collect()
+}
+
+fn edge_set(cg : &Callgraph) -> HashSet<(&str, &str, PropertySet)> {
+    cg.edge_ids().map(|edge| {
+        let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+        (cg.graph[src].as_str(), cg.graph[dst].as_str(), cg.graph[edge])
+    }).collect()
+}
+
+pub fn diff_graphs(old : &Callgraph, new : &Callgraph) -> GraphDiff {
+    let (old_funcs, new_funcs) = (function_set(old), function_set(new));
+    let (old_edges, new_edges) = (edge_set(old), edge_set(new));
+
+    fn owned(edges : Vec<&(&str, &str, PropertySet)>) -> Vec<(String, String, PropertySet)> {
+        let mut result : Vec<_> = edges.iter().map(|(src, dst, props)| (src.to_string(), dst.to_string(), *props)).collect();
+        result.sort();
+        result
+    }
+
+    let mut added_functions : Vec<String> = new_funcs.difference(&old_funcs).map(|s| s.to_string()).collect();
+    added_functions.sort();
+    let mut removed_functions : Vec<String> = old_funcs.difference(&new_funcs).map(|s| s.to_string()).collect();
+    removed_functions.sort();
+
+    GraphDiff {
+        added_functions,
+        removed_functions,
+        added_edges: owned(new_edges.difference(&old_edges).collect()),
+        removed_edges: owned(old_edges.difference(&new_edges).collect()),
+    }
+}
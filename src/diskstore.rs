@@ -0,0 +1,701 @@
+use crate::callgraph::{lookup_keys, Direction, PropertyMatch, PropertySet};
+use crate::hazard::{parse_edge, parse_id, parse_indirect, parse_properties, parse_property_aliases, split_token, to_str, LoadError};
+use fixedbitset::FixedBitSet;
+use petgraph::graph::NodeIndex;
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::os::unix::io::AsRawFd;
+
+// On-disk form of a callgraph for graphs too big to load: adjacency lists in
+// both directions and all names, laid out so the file can be memory-mapped
+// and used in place. Only the pages a query touches are read, and the
+// kernel's page cache evicts the least recently used ones under memory
+// pressure, so a 100M-edge graph can be queried (slowly, when cold) with a
+// fraction of its size in RAM.
+//
+// The store is written straight from callgraph.txt, without ever building the
+// in-memory Callgraph. That still needs about 16 bytes per edge plus the
+// names while converting, but only once. (Or from a snapshot, which does
+// have to be loaded; see snapshot::build_store.)
+//
+// Layout, all little-endian, each section padded to 8 bytes:
+//
+//   header         magic, version, node count (including the dummy node 0),
+//                  edge count, property JSON length, name blob length
+//   properties     the "Properties" and "Aliases" tables, as JSON
+//   callees        offsets[nodes+1], targets[edges], props[edges] (all, any)
+//   callers        offsets[nodes+1], sources[edges], props[edges]
+//   name offsets   u64[nodes+1] into the name blob
+//   name blob      each node's names, mangled first, '\n'-terminated
+//   name index     (blob offset u64, length u32, node u32) for every name,
+//                  sorted by name
+
+const MAGIC : &[u8; 8] = b"HAZSTORE";
+const VERSION : u32 = 2;
+const HEADER_SIZE : usize = 32;
+
+pub fn is_store(filename : &str) -> bool {
+    let mut magic = [0u8; 8];
+    File::open(filename).and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic)).is_ok() && magic == *MAGIC
+}
+
+fn padding(len : usize) -> usize {
+    (8 - len % 8) % 8
+}
+
+struct Writer {
+    out : BufWriter<File>,
+    written : usize,
+}
+
+impl Writer {
+    fn bytes(&mut self, data : &[u8]) -> Result<(), LoadError> {
+        self.out.write_all(data)?;
+        self.written += data.len();
+        Ok(())
+    }
+
+    fn u32(&mut self, n : u32) -> Result<(), LoadError> {
+        self.bytes(&n.to_le_bytes())
+    }
+
+    fn u64(&mut self, n : u64) -> Result<(), LoadError> {
+        self.bytes(&n.to_le_bytes())
+    }
+
+    fn pad(&mut self) -> Result<(), LoadError> {
+        let zeros = [0u8; 8];
+        self.bytes(&zeros[..padding(self.written)])
+    }
+}
+
+fn write_adjacency(w : &mut Writer, node_count : usize, edges : &[(u32, u32, PropertySet)]) -> Result<(), LoadError> {
+    // `edges` is sorted by its first element.
+    let mut start = 0;
+    w.u32(0)?;
+    for node in 0..node_count as u32 {
+        while start < edges.len() && edges[start].0 == node {
+            start += 1;
+        }
+        w.u32(start as u32)?;
+    }
+    w.pad()?;
+    for (_, other, _) in edges {
+        w.u32(*other)?;
+    }
+    w.pad()?;
+    for (_, _, props) in edges {
+        w.u32(props.all)?;
+        w.u32(props.any)?;
+    }
+    w.pad()
+}
+
+// Convert callgraph.txt into a store at `out_filename`. Returns the number of
+// functions and calls written.
+pub fn build(in_filename : &str, out_filename : &str) -> Result<(usize, usize), LoadError> {
+    let mut reader = BufReader::new(File::open(in_filename)?);
+
+    let mut names : Vec<Vec<String>> = vec![vec!["(dummy node zero)".to_string()]];
+    let mut edges : Vec<(u32, u32, PropertySet)> = vec![];
+    let mut indirects = vec![];
+    let mut properties = properties_json(&HashMap::new(), &HashMap::new());
+
+    let mut lineno = 0;
+    let mut line = Vec::<u8>::with_capacity(4000);
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        lineno += 1;
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let parsed : Result<(), String> = match text.first() {
+            Some(b'#') => {
+                let (id, name) = split_token(&text[1..]);
+                parse_id(id).and_then(|id| {
+                    if id as usize != names.len() {
+                        return Err(format!("function #{} declared out of order", id));
+                    }
+                    names.push(vec![to_str(name)?.to_string()]);
+                    Ok(())
+                })
+            },
+            Some(b'D') | Some(b'R') => parse_edge(text).map(|(src, dst, limit, _)| edges.push((src, dst, limit))),
+            Some(b'=') => {
+                let (id, name) = split_token(text.get(2..).unwrap_or(b""));
+                parse_id(id).and_then(|id| {
+                    let name = to_str(name)?.to_string();
+                    names.get_mut(id as usize).ok_or(format!("unknown function #{}", id))?.push(name);
+                    Ok(())
+                })
+            },
+            Some(b'I') => parse_indirect(text).map(|indirect| indirects.push(indirect)),
+            Some(b'!') => {
+                let json = to_str(text.get(2..).unwrap_or(b"")).map_err(|e| LoadError::FormatError(lineno, e))?;
+                // Validate and normalize to just the tables used here.
+                properties = properties_json(&parse_properties(json, lineno)?, &parse_property_aliases(json, lineno)?);
+                Ok(())
+            },
+            Some(_) | None => Ok(()),
+        };
+        parsed.map_err(|message| LoadError::FormatError(lineno, message))?;
+    }
+
+    // Same numbering of function pointer nodes as the regular loader.
+    let mut seen = HashMap::<(String, PropertySet), u32>::new();
+    for (src, dst_name, limit) in indirects {
+        let next = names.len() as u32;
+        let dst = *seen.entry((dst_name.clone(), limit)).or_insert_with(|| {
+            names.push(vec![dst_name]);
+            next
+        });
+        edges.push((src, dst, limit));
+    }
+
+    write(out_filename, names, edges, &properties)
+}
+
+// The property tables as stored.
+pub fn properties_json(properties : &HashMap<u32, String>, aliases : &HashMap<String, String>) -> String {
+    let mut tables = json::object!{ "Properties": {}, "Aliases": {} };
+    for (bit, name) in properties {
+        tables["Properties"][bit.to_string().as_str()] = name.as_str().into();
+    }
+    for (alias, name) in aliases {
+        tables["Aliases"][alias.as_str()] = name.as_str().into();
+    }
+    tables.dump()
+}
+
+// Write a store of `names` (for every node, including 0; mangled name first)
+// and calls between them. Returns the number of functions and calls written.
+pub fn write(
+    out_filename : &str,
+    names : Vec<Vec<String>>,
+    mut edges : Vec<(u32, u32, PropertySet)>,
+    properties_json : &str
+) -> Result<(usize, usize), LoadError>
+{
+    let node_count = names.len();
+    for &(src, dst, _) in &edges {
+        if src as usize >= node_count || dst as usize >= node_count {
+            return Err(LoadError::FormatError(0, format!("call {} -> {} to an unknown function", src, dst)));
+        }
+    }
+
+    let mut blob = Vec::<u8>::new();
+    let mut name_offsets = vec![0u64];
+    let mut index : Vec<(u64, u32, u32)> = vec![];
+    for (node, node_names) in names.iter().enumerate() {
+        for name in node_names {
+            index.push((blob.len() as u64, name.len() as u32, node as u32));
+            blob.extend_from_slice(name.as_bytes());
+            blob.push(b'\n');
+        }
+        name_offsets.push(blob.len() as u64);
+    }
+    drop(names);
+    index.sort_by(|a, b| {
+        let name = |e : &(u64, u32, u32)| &blob[e.0 as usize..e.0 as usize + e.1 as usize];
+        name(a).cmp(name(b)).then(a.2.cmp(&b.2))
+    });
+
+    let mut w = Writer { out: BufWriter::new(File::create(out_filename)?), written: 0 };
+    w.bytes(MAGIC)?;
+    w.u32(VERSION)?;
+    w.u32(node_count as u32)?;
+    w.u32(edges.len() as u32)?;
+    w.u32(properties_json.len() as u32)?;
+    w.u64(blob.len() as u64)?;
+    w.bytes(properties_json.as_bytes())?;
+    w.pad()?;
+
+    // Stable sorts, so each function's calls stay in file order.
+    edges.sort_by_key(|e| e.0);
+    write_adjacency(&mut w, node_count, &edges)?;
+    let mut reversed : Vec<_> = edges.iter().map(|&(src, dst, props)| (dst, src, props)).collect();
+    drop(edges);
+    reversed.sort_by_key(|e| e.0);
+    write_adjacency(&mut w, node_count, &reversed)?;
+
+    for offset in name_offsets {
+        w.u64(offset)?;
+    }
+    w.bytes(&blob)?;
+    w.pad()?;
+    for (offset, len, node) in &index {
+        w.u64(*offset)?;
+        w.u32(*len)?;
+        w.u32(*node)?;
+    }
+    w.out.flush()?;
+    Ok((node_count - 1, reversed.len()))
+}
+
+// The functions along a route, each with the properties of the call into it
+// (None for the first).
+pub type StoreRoute = Vec<(NodeIndex, Option<PropertySet>)>;
+
+// Byte ranges of one direction's arrays within the mapping.
+struct AdjacencyRange {
+    offsets : usize,
+    neighbors : usize,
+    props : usize,
+}
+
+pub struct DiskStore {
+    map : *const u8,
+    len : usize,
+    node_count : usize,
+    edge_count : usize,
+    callees : AdjacencyRange,
+    callers : AdjacencyRange,
+    name_offsets : usize,
+    blob : usize,
+    index : usize,
+    index_len : usize,
+    pub property_names : HashMap<u32, String>,
+    pub property_aliases : HashMap<String, String>,
+}
+
+// The mapping is read-only and never moves, so one store can be queried
+// from several threads (eg Python threads with the GIL released). Separate
+// processes opening the same file share its pages through the page cache.
+unsafe impl Send for DiskStore {}
+unsafe impl Sync for DiskStore {}
+
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.map as *mut libc::c_void, self.len); }
+    }
+}
+
+fn bad(message : &str) -> LoadError {
+    LoadError::BadSnapshot(message.to_string())
+}
+
+impl DiskStore {
+    pub fn open(filename : &str) -> Result<DiskStore, LoadError> {
+        if cfg!(target_endian = "big") {
+            return Err(bad("graph stores can only be used on little-endian machines"));
+        }
+        let file = File::open(filename)?;
+        let len = file.metadata()?.len() as usize;
+        if len < HEADER_SIZE {
+            return Err(bad("not a graph store"));
+        }
+        let map = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if map == libc::MAP_FAILED {
+            return Err(LoadError::IOError(std::io::Error::last_os_error()));
+        }
+        // Traversals hop all over the file, so readahead is mostly wasted.
+        unsafe { libc::madvise(map, len, libc::MADV_RANDOM); }
+
+        let mut store = DiskStore {
+            map: map as *const u8,
+            len,
+            node_count: 0,
+            edge_count: 0,
+            callees: AdjacencyRange { offsets: 0, neighbors: 0, props: 0 },
+            callers: AdjacencyRange { offsets: 0, neighbors: 0, props: 0 },
+            name_offsets: 0,
+            blob: 0,
+            index: 0,
+            index_len: 0,
+            property_names: HashMap::new(),
+            property_aliases: HashMap::new(),
+        };
+        if store.bytes(0, 8)? != MAGIC {
+            return Err(bad("not a graph store"));
+        }
+        if store.u32_at(8)? != VERSION {
+            return Err(bad(&format!("unsupported graph store version {}", store.u32_at(8)?)));
+        }
+        let node_count = store.u32_at(12)? as usize;
+        let edge_count = store.u32_at(16)? as usize;
+        let props_len = store.u32_at(20)? as usize;
+        let blob_len = store.u64_at(24)? as usize;
+        if node_count == 0 {
+            return Err(bad("graph store is corrupt"));
+        }
+        if blob_len > len {
+            return Err(bad("graph store is truncated"));
+        }
+
+        // Work out where everything is, checking it all fits.
+        let mut pos = HEADER_SIZE;
+        let mut section = |size : usize| -> usize {
+            let start = pos;
+            pos += size + padding(size);
+            start
+        };
+        let props = section(props_len);
+        let adjacency = |section : &mut dyn FnMut(usize) -> usize| AdjacencyRange {
+            offsets: section(4 * (node_count + 1)),
+            neighbors: section(4 * edge_count),
+            props: section(8 * edge_count),
+        };
+        let callees = adjacency(&mut section);
+        let callers = adjacency(&mut section);
+        let name_offsets = section(8 * (node_count + 1));
+        let blob = section(blob_len);
+        let index = pos;
+        if index > len || !(len - index).is_multiple_of(16) {
+            return Err(bad("graph store is truncated"));
+        }
+
+        let properties = std::str::from_utf8(store.bytes(props, props_len)?).map_err(|_| bad("invalid property table"))?;
+        let properties = json::parse(properties).map_err(|_| bad("invalid property table"))?;
+        for (bit, name) in properties["Properties"].entries() {
+            if let (Ok(bit), Some(name)) = (bit.parse::<u32>(), name.as_str()) {
+                store.property_names.insert(bit, name.to_string());
+            }
+        }
+        for (alias, name) in properties["Aliases"].entries() {
+            if let Some(name) = name.as_str() {
+                store.property_aliases.insert(alias.to_string(), name.to_string());
+            }
+        }
+        store.node_count = node_count;
+        store.edge_count = edge_count;
+        store.callees = callees;
+        store.callers = callers;
+        store.name_offsets = name_offsets;
+        store.blob = blob;
+        store.index = index;
+        store.index_len = (len - index) / 16;
+        Ok(store)
+    }
+
+    // Everything read from the store goes through here, so that offsets
+    // from a corrupt or truncated file turn into errors rather than reads
+    // outside the mapping.
+    fn bytes(&self, offset : usize, len : usize) -> Result<&[u8], LoadError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(unsafe { std::slice::from_raw_parts(self.map.add(offset), len) }),
+            _ => Err(bad("graph store is corrupt")),
+        }
+    }
+
+    fn u32_at(&self, offset : usize) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    fn u64_at(&self, offset : usize) -> Result<u64, LoadError> {
+        Ok(u64::from_le_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+
+    fn node(&self, id : u32) -> Result<NodeIndex, LoadError> {
+        if (id as usize) < self.node_count { Ok(NodeIndex::new(id as usize)) } else { Err(bad("graph store is corrupt")) }
+    }
+
+    // All real function ids, skipping the dummy node zero.
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeIndex> {
+        (1..self.node_count).map(NodeIndex::new)
+    }
+
+    pub fn function_count(&self) -> usize {
+        self.node_count - 1
+    }
+
+    pub fn names(&self, idx : NodeIndex) -> Result<Vec<&str>, LoadError> {
+        if idx.index() >= self.node_count {
+            return Ok(vec![]);
+        }
+        let start = self.u64_at(self.name_offsets + 8 * idx.index())? as usize;
+        let end = self.u64_at(self.name_offsets + 8 * (idx.index() + 1))? as usize;
+        let len = end.checked_sub(start).ok_or_else(|| bad("graph store is corrupt"))?;
+        let text = std::str::from_utf8(self.blob_bytes(start, len)?).unwrap_or("");
+        Ok(text.split_terminator('\n').collect())
+    }
+
+    // "#N = name", using the unmangled name when there is one.
+    pub fn name(&self, idx : NodeIndex) -> Result<String, LoadError> {
+        Ok(format!("#{} = {}", idx.index(), self.names(idx)?.last().unwrap_or(&"")))
+    }
+
+    fn blob_bytes(&self, offset : usize, len : usize) -> Result<&[u8], LoadError> {
+        if offset.checked_add(len).is_none_or(|end| end > self.index - self.blob) {
+            return Err(bad("graph store is corrupt"));
+        }
+        self.bytes(self.blob + offset, len)
+    }
+
+    fn index_entry(&self, i : usize) -> Result<(&[u8], NodeIndex), LoadError> {
+        let entry = self.index + 16 * i;
+        let offset = self.u64_at(entry)? as usize;
+        let len = self.u32_at(entry + 8)? as usize;
+        Ok((self.blob_bytes(offset, len)?, self.node(self.u32_at(entry + 12)?)?))
+    }
+
+    // First index entry whose name is not less than `key`.
+    fn lower_bound(&self, key : &[u8]) -> Result<usize, LoadError> {
+        let (mut lo, mut hi) = (0, self.index_len);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.index_entry(mid)?.0 < key { lo = mid + 1; } else { hi = mid; }
+        }
+        Ok(lo)
+    }
+
+    // Up to `limit` functions matching `pattern`, with the syntax of
+    // Callgraph::resolve: #N, /regex/ (matching nothing if it is invalid),
+    // prefix*, or a name. A name matches exactly, or failing that the start
+    // of names (so a bare method name finds "method(args)"), or failing that
+    // anywhere in an unmangled name. Everything but exact and start-of-name
+    // matches reads every name.
+    pub fn resolve(&self, pattern : &str, limit : usize) -> Result<Vec<NodeIndex>, LoadError> {
+        if let Some(id) = pattern.strip_prefix('#').and_then(|id| id.parse::<usize>().ok()) {
+            return Ok(if id > 0 && id < self.node_count { vec![NodeIndex::new(id)] } else { vec![] });
+        }
+        if let Some(re) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            return match Regex::new(re) {
+                Ok(re) => self.scan(limit, |names| names.iter().any(|name| re.is_match(name))),
+                Err(_) => Ok(vec![]),
+            };
+        }
+        if let Some(prefix) = pattern.strip_suffix('*') {
+            // Qualified names and stems, of unmangled names only.
+            return self.scan(limit, |names| names[1..].iter().any(|name| lookup_keys(name).iter().any(|key| key.starts_with(prefix))));
+        }
+        let (exact, prefixed) = self.with_prefix(pattern.as_bytes(), limit)?;
+        if !exact.is_empty() {
+            Ok(exact)
+        } else if !prefixed.is_empty() {
+            Ok(prefixed)
+        } else {
+            self.scan(limit, |names| names[1..].iter().any(|name| name.contains(pattern)))
+        }
+    }
+
+    // Functions named exactly `key`, and those with a name starting with it,
+    // through the name index.
+    fn with_prefix(&self, key : &[u8], limit : usize) -> Result<(Vec<NodeIndex>, Vec<NodeIndex>), LoadError> {
+        let start = self.lower_bound(key)?;
+        let mut exact = vec![];
+        let mut prefixed = vec![];
+        for i in start..self.index_len {
+            let (name, idx) = self.index_entry(i)?;
+            if !name.starts_with(key) || prefixed.len() >= limit {
+                break;
+            }
+            if name == key { exact.push(idx); }
+            prefixed.push(idx);
+        }
+        for found in [&mut exact, &mut prefixed] {
+            found.sort();
+            found.dedup();
+        }
+        Ok((exact, prefixed))
+    }
+
+    // Functions whose names (mangled first) satisfy `matches`, in id order.
+    fn scan(&self, limit : usize, matches : impl Fn(&[&str]) -> bool) -> Result<Vec<NodeIndex>, LoadError> {
+        let mut found = vec![];
+        for idx in self.node_ids() {
+            if found.len() >= limit {
+                break;
+            }
+            if matches(&self.names(idx)?) {
+                found.push(idx);
+            }
+        }
+        Ok(found)
+    }
+
+    pub fn neighbors(&self, idx : NodeIndex, direction : Direction) -> Result<Vec<(NodeIndex, PropertySet)>, LoadError> {
+        let adj = match direction {
+            Direction::Callees => &self.callees,
+            Direction::Callers => &self.callers,
+        };
+        if idx.index() >= self.node_count {
+            return Ok(vec![]);
+        }
+        let start = self.u32_at(adj.offsets + 4 * idx.index())? as usize;
+        let end = self.u32_at(adj.offsets + 4 * (idx.index() + 1))? as usize;
+        if start > end || end > self.edge_count {
+            return Err(bad("graph store is corrupt"));
+        }
+        (start..end).map(|i| {
+            let props = PropertySet { all: self.u32_at(adj.props + 8 * i)?, any: self.u32_at(adj.props + 8 * i + 4)? };
+            Ok((self.node(self.u32_at(adj.neighbors + 4 * i)?)?, props))
+        }).collect()
+    }
+
+    // Everything reachable from `origins`, not crossing calls that have any
+    // of `avoid_props` at every call site, in breadth-first order. Stops
+    // early (with a partial result) if `interrupted` returns true.
+    pub fn reachable(
+        &self,
+        origins : &[NodeIndex],
+        direction : Direction,
+        avoid_props : u32,
+        interrupted : &dyn Fn() -> bool
+    ) -> Result<Vec<NodeIndex>, LoadError>
+    {
+        let mut seen = FixedBitSet::with_capacity(self.node_count);
+        // Origins are only included if reached again through a cycle.
+        let mut work : VecDeque<NodeIndex> = origins.iter().cloned().collect();
+        let mut result = vec![];
+        while let Some(idx) = work.pop_front() {
+            if interrupted() { break; }
+            for (other, props) in self.neighbors(idx, direction)? {
+                if PropertyMatch::All.blocks(props, avoid_props) || seen.contains(other.index()) {
+                    continue;
+                }
+                seen.insert(other.index());
+                result.push(other);
+                work.push_back(other);
+            }
+        }
+        Ok(result)
+    }
+
+    // Shortest call chain from `src` to any of `goal`, as the functions along
+    // it and the properties of the call into each one after the first. The
+    // route never enters `avoid`, and avoids calls with `avoid_props` as
+    // `matching` says (see PropertyMatch). Gives LoadError::Aborted if
+    // `interrupted` returns true before the search is done.
+    pub fn route(
+        &self,
+        src : NodeIndex,
+        goal : &[NodeIndex],
+        avoid : &[NodeIndex],
+        avoid_props : u32,
+        matching : PropertyMatch,
+        interrupted : &dyn Fn() -> bool
+    ) -> Result<Option<StoreRoute>, LoadError>
+    {
+        let set = |funcs : &[NodeIndex]| {
+            let mut set = FixedBitSet::with_capacity(self.node_count);
+            funcs.iter().filter(|idx| idx.index() < self.node_count).for_each(|idx| set.insert(idx.index()));
+            set
+        };
+        let (goal, avoid) = (set(goal), set(avoid));
+        // Zero means unvisited; node 0 is never part of a route.
+        let mut parent : Vec<(u32, PropertySet)> = vec![(0, PropertySet { all: 0, any: 0 }); self.node_count];
+        let mut work = VecDeque::new();
+        work.push_back(src);
+        parent[src.index()].0 = src.index() as u32;
+        let mut found = None;
+        while let Some(idx) = work.pop_front() {
+            if goal.contains(idx.index()) {
+                found = Some(idx);
+                break;
+            }
+            if interrupted() { return Err(LoadError::Aborted); }
+            for (callee, props) in self.neighbors(idx, Direction::Callees)? {
+                if matching.blocks(props, avoid_props) || avoid.contains(callee.index()) || parent[callee.index()].0 != 0 {
+                    continue;
+                }
+                parent[callee.index()] = (idx.index() as u32, props);
+                work.push_back(callee);
+            }
+        }
+        let dst = match found {
+            Some(dst) => dst,
+            None => return Ok(None),
+        };
+
+        let mut route = vec![];
+        let mut idx = dst;
+        while idx != src {
+            let (prev, props) = parent[idx.index()];
+            route.push((idx, Some(props)));
+            idx = NodeIndex::new(prev as usize);
+        }
+        route.push((src, None));
+        route.reverse();
+        Ok(Some(route))
+    }
+
+    // As Callgraph::resolve_property.
+    pub fn resolve_property(&self, query : &str) -> Option<u32> {
+        let named = |name : &str| self.property_names.iter().find(|(_, n)| *n == name).map(|(bit, _)| *bit);
+        if let Some(bit) = named(query) {
+            return Some(bit);
+        }
+        if let Some(name) = self.property_aliases.get(query) {
+            return named(name);
+        }
+        query.strip_prefix("bit").and_then(|n| n.parse::<u32>().ok()).filter(|&n| n < 32).map(|n| 1 << n)
+    }
+
+    pub fn describe_props(&self, props : PropertySet) -> String {
+        let mut names : Vec<String> = self.property_names.iter()
+            .filter(|(bit, _)| props.any & **bit != 0)
+            .map(|(_, name)| name.to_string())
+            .collect();
+        names.sort();
+        let unknown = self.property_names.keys().fold(props.any, |rest, bit| rest & !bit);
+        names.extend((0..32).filter(|i| unknown & (1 << i) != 0).map(|i| format!("bit{}", i)));
+        names.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name : &str, text : &str) -> DiskStore {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("diskstore-{}-{}.txt", name, std::process::id()));
+        let output = dir.join(format!("diskstore-{}-{}.store", name, std::process::id()));
+        std::fs::write(&input, text).unwrap();
+        build(input.to_str().unwrap(), output.to_str().unwrap()).unwrap();
+        let store = DiskStore::open(output.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+        store
+    }
+
+    // #1 reaches #4 directly through a GC_SUPPRESSED call, or the long way
+    // round through #2 and #3.
+    const TEXT : &str = "! {\"Properties\": {\"1\": \"GC_SUPPRESSED\"}}\n\
+                         #1 _Z4mainv\n= 1 main()\n\
+                         #2 _ZN2js2GC7collectEv\n= 2 js::GC::collect()\n\
+                         #3 _ZN2js2GC5sweepEv\n= 3 js::GC::sweep()\n\
+                         #4 _Z3barv\n= 4 bar(int)\n\
+                         D /1 1 4\nD 1 2\nD 2 3\nD 3 4\n";
+
+    fn ids(found : Result<Vec<NodeIndex>, LoadError>) -> Vec<usize> {
+        found.unwrap().iter().map(|idx| idx.index()).collect()
+    }
+
+    fn route(store : &DiskStore, goal : &[usize], avoid : &[usize], avoid_props : u32) -> Vec<usize> {
+        let nodes = |ids : &[usize]| -> Vec<NodeIndex> { ids.iter().map(|&id| NodeIndex::new(id)).collect() };
+        let route = store.route(NodeIndex::new(1), &nodes(goal), &nodes(avoid), avoid_props, PropertyMatch::All, &|| false).unwrap();
+        route.unwrap_or_default().iter().map(|(idx, _)| idx.index()).collect()
+    }
+
+    #[test]
+    fn patterns() {
+        let store = store("resolve", TEXT);
+        assert_eq!(ids(store.resolve("_Z3barv", 10)), vec![4]);
+        assert_eq!(ids(store.resolve("bar", 10)), vec![4]);
+        assert_eq!(ids(store.resolve("GC::*", 10)), vec![2, 3]);
+        assert_eq!(ids(store.resolve("sw*", 10)), vec![3]);
+        assert_eq!(ids(store.resolve("/^js::.*\\(\\)$/", 10)), vec![2, 3]);
+        assert_eq!(ids(store.resolve("GC::coll", 10)), vec![2]);
+        assert_eq!(ids(store.resolve("#3", 10)), vec![3]);
+        assert_eq!(ids(store.resolve("GC::*", 1)), vec![2]);
+        assert!(ids(store.resolve("/(/", 10)).is_empty());
+        assert!(ids(store.resolve("#9", 10)).is_empty());
+    }
+
+    #[test]
+    fn routes() {
+        let store = store("route", TEXT);
+        assert_eq!(route(&store, &[4], &[], 0), vec![1, 4]);
+        assert_eq!(route(&store, &[4], &[], 1), vec![1, 2, 3, 4]);
+        assert_eq!(route(&store, &[3, 4], &[], 1), vec![1, 2, 3]);
+        assert!(route(&store, &[4], &[3], 1).is_empty());
+        assert_eq!(route(&store, &[1], &[], 0), vec![1]);
+    }
+}
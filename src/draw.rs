@@ -0,0 +1,175 @@
+use crate::callgraph::{Callgraph, DescriptionBrevity, Direction};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::collections::HashSet;
+
+// Box-drawing pictures of routes and small neighborhoods, for seeing the
+// shape of something without going through Graphviz. A route is a column of
+// boxes joined by the calls between them; a neighborhood is the function in a
+// box with trees of its callers above it and its callees below.
+
+// Past this many callers or callees of one function, the rest are summarized.
+const MAX_FANOUT : usize = 12;
+
+pub struct Glyphs {
+    horizontal : &'static str,
+    vertical : &'static str,
+    corners : [&'static str; 4],
+    tee_down : &'static str,
+    tee_up : &'static str,
+    branch : &'static str,
+    last : &'static str,
+    // What goes under a branch that has more after it.
+    continued : &'static str,
+}
+
+pub const UNICODE : Glyphs = Glyphs {
+    horizontal: "─",
+    vertical: "│",
+    corners: ["┌", "┐", "└", "┘"],
+    tee_down: "┬",
+    tee_up: "┴",
+    branch: "├── ",
+    last: "└── ",
+    continued: "│   ",
+};
+
+pub const ASCII : Glyphs = Glyphs {
+    horizontal: "-",
+    vertical: "|",
+    corners: ["+", "+", "+", "+"],
+    tee_down: "+",
+    tee_up: "+",
+    branch: "|-- ",
+    last: "`-- ",
+    continued: "|   ",
+};
+
+// The properties, location and count of a call, as describe_edge shows them
+// after the callee's name.
+fn call_label(cg : &Callgraph, edge : EdgeIndex) -> String {
+    let (any, all) = (cg.graph[edge].any, cg.graph[edge].all);
+    let mut parts = vec![];
+    match any {
+        0 => {},
+        x if x == all => parts.push(format!("[{}]", cg.describe_property_set(any))),
+        _ => parts.push(format!("[{}:{}]", cg.describe_property_set(any), cg.describe_property_set(all))),
+    }
+    if let Some(location) = cg.call_sites.get(&edge) {
+        parts.push(format!("at {}", location));
+    }
+    if cg.call_count(edge) > 1 {
+        parts.push(format!("x{}", cg.call_count(edge)));
+    }
+    parts.join(" ")
+}
+
+// A box around `text`, `width` columns inside, with a connector in the
+// middle of the top and/or bottom edge.
+fn boxed(g : &Glyphs, text : &str, width : usize, up : bool, down : bool, out : &mut String) {
+    let mid = width / 2 + 1;
+    let edge = |left : &str, right : &str, tee : Option<&str>| -> String {
+        let mut line = left.to_string();
+        for i in 1..=width + 2 {
+            line += match tee {
+                Some(tee) if i == mid => tee,
+                _ => g.horizontal,
+            };
+        }
+        line + right
+    };
+    *out += &edge(g.corners[0], g.corners[1], if up { Some(g.tee_up) } else { None });
+    *out += "\n";
+    *out += &format!("{} {:w$} {}\n", g.vertical, text, g.vertical, w = width);
+    *out += &edge(g.corners[2], g.corners[3], if down { Some(g.tee_down) } else { None });
+    *out += "\n";
+}
+
+pub fn route(cg : &Callgraph, g : &Glyphs, start : NodeIndex, edges : &[EdgeIndex]) -> String {
+    let mut nodes = vec![start];
+    nodes.extend(edges.iter().map(|&edge| cg.graph.edge_endpoints(edge).unwrap().1));
+    let names : Vec<String> = nodes.iter().map(|&idx| cg.name(idx, DescriptionBrevity::Normal)).collect();
+    let width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+    let pad = " ".repeat(width / 2 + 1);
+
+    let mut out = String::new();
+    for (i, name) in names.iter().enumerate() {
+        boxed(g, name, width, i > 0, i + 1 < names.len(), &mut out);
+        if let Some(&edge) = edges.get(i) {
+            out += format!("{}{} {}", pad, g.vertical, call_label(cg, edge)).trim_end();
+            out += "\n";
+        }
+    }
+    out
+}
+
+// Callers (or callees) as an indented tree. Functions already drawn are
+// named again but not expanded.
+struct TreeWalk<'a> {
+    cg : &'a Callgraph,
+    g : &'a Glyphs,
+    direction : Direction,
+    seen : HashSet<NodeIndex>,
+    lines : Vec<String>,
+}
+
+impl<'a> TreeWalk<'a> {
+    fn walk(&mut self, idx : NodeIndex, depth : usize, prefix : &str) {
+        if depth == 0 {
+            return;
+        }
+        let (cg, g) = (self.cg, self.g);
+        let mut neighbors : Vec<(NodeIndex, EdgeIndex)> = cg.neighbor_edges(idx, self.direction).map(|(other, edge, _)| (other, edge)).collect();
+        neighbors.sort();
+        neighbors.dedup_by_key(|&mut (other, _)| other);
+        let extra = neighbors.len().saturating_sub(MAX_FANOUT);
+        neighbors.truncate(MAX_FANOUT);
+        let count = neighbors.len();
+        for (i, (other, edge)) in neighbors.into_iter().enumerate() {
+            let last = i + 1 == count && extra == 0;
+            let label = call_label(cg, edge);
+            let mut line = format!("{}{}{}", prefix, if last { g.last } else { g.branch }, cg.name(other, DescriptionBrevity::Normal));
+            if !label.is_empty() {
+                line += &format!(" {}", label);
+            }
+            let expand = self.seen.insert(other);
+            if !expand && depth > 1 && cg.neighbor_edges(other, self.direction).next().is_some() {
+                line += " (see above)";
+            }
+            self.lines.push(line);
+            if expand {
+                self.walk(other, depth - 1, &format!("{}{}", prefix, if last { "    " } else { g.continued }));
+            }
+        }
+        if extra > 0 {
+            self.lines.push(format!("{}{}... {} more", prefix, g.last, extra));
+        }
+    }
+}
+
+fn tree(cg : &Callgraph, g : &Glyphs, idx : NodeIndex, direction : Direction, depth : usize) -> Vec<String> {
+    let mut walk = TreeWalk { cg, g, direction, seen: HashSet::from([idx]), lines: vec![] };
+    walk.walk(idx, depth, "");
+    walk.lines
+}
+
+pub fn around(cg : &Callgraph, g : &Glyphs, idx : NodeIndex, depth : usize) -> String {
+    let name = cg.name(idx, DescriptionBrevity::Normal);
+    let mut out = String::new();
+
+    let callers = tree(cg, g, idx, Direction::Callers, depth);
+    if !callers.is_empty() {
+        out += "callers:\n";
+        for line in &callers {
+            out += &format!("  {}\n", line);
+        }
+    }
+    boxed(g, &name, name.chars().count(), false, false, &mut out);
+    let callees = tree(cg, g, idx, Direction::Callees, depth);
+    if !callees.is_empty() {
+        out += "callees:\n";
+        for line in &callees {
+            out += &format!("  {}\n", line);
+        }
+    }
+    out
+}
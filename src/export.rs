@@ -0,0 +1,288 @@
+use crate::callgraph::{Callgraph, NamespaceMatrix};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::collections::{BTreeMap, HashSet};
+
+// Self-contained HTML page showing a (small) piece of the callgraph as a
+// force-directed diagram, for attaching to bug reports. The data is embedded
+// as JSON and laid out by a few dozen lines of plain JS, so the page works
+// offline and from a file: URL.
+
+pub fn subgraph_edges(cg : &Callgraph, nodes : &[NodeIndex]) -> Vec<EdgeIndex> {
+    let members : HashSet<NodeIndex> = nodes.iter().cloned().collect();
+    nodes.iter().flat_map(|&idx| cg.callee_edges(idx)).filter(|&edge| {
+        members.contains(&cg.graph.edge_endpoints(edge).unwrap().1)
+    }).collect()
+}
+
+fn graph_json(cg : &Callgraph, nodes : &[NodeIndex], edges : &[EdgeIndex]) -> json::JsonValue {
+    let mut position = std::collections::HashMap::new();
+    let mut json_nodes = json::JsonValue::new_array();
+    for (i, &idx) in nodes.iter().enumerate() {
+        position.insert(idx, i);
+        let alt = cg.unmangled_names(idx);
+        let _ = json_nodes.push(json::object!{
+            "id" => idx.index(),
+            "name" => alt.first().unwrap_or(&cg.graph[idx]).as_str(),
+            "mangled" => cg.graph[idx].as_str(),
+            "gc" => cg.gc_functions.contains(&idx),
+        });
+    }
+
+    let mut json_edges = json::JsonValue::new_array();
+    for &edge in edges {
+        let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+        if let (Some(&s), Some(&d)) = (position.get(&src), position.get(&dst)) {
+            let props = cg.graph[edge];
+            let _ = json_edges.push(json::object!{
+                "source" => s,
+                "target" => d,
+                "props" => if props.any == 0 { String::new() } else { cg.describe_property_set(props.any) },
+            });
+        }
+    }
+
+    json::object!{ "nodes" => json_nodes, "edges" => json_edges }
+}
+
+// Several routes for one query drawn over each other (see `export routes`),
+// with the avoided functions greyed and calls with properties dashed, so the
+// picture shows why the chosen route goes the long way round.
+pub struct PaintedRoute {
+    pub label : String,
+    pub edges : Vec<EdgeIndex>,
+}
+
+const ROUTE_COLORS : [&str; 6] = ["#2060c0", "#c03020", "#20a040", "#a040c0", "#c08000", "#008080"];
+
+// Each call on any of the routes, with the indexes of the routes using it.
+fn painted_calls(routes : &[PaintedRoute]) -> BTreeMap<EdgeIndex, Vec<usize>> {
+    let mut calls : BTreeMap<EdgeIndex, Vec<usize>> = BTreeMap::new();
+    for (i, route) in routes.iter().enumerate() {
+        for &edge in &route.edges {
+            calls.entry(edge).or_default().push(i);
+        }
+    }
+    calls
+}
+
+fn painted_nodes(cg : &Callgraph, calls : &BTreeMap<EdgeIndex, Vec<usize>>) -> Vec<NodeIndex> {
+    let mut nodes : Vec<NodeIndex> = calls.keys().flat_map(|&edge| {
+        let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+        [src, dst]
+    }).collect();
+    nodes.sort();
+    nodes.dedup();
+    nodes
+}
+
+fn dot_string(s : &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn routes_dot(cg : &Callgraph, routes : &[PaintedRoute], avoid : &HashSet<NodeIndex>, avoid_props : u32) -> String {
+    let calls = painted_calls(routes);
+    let mut out = String::from("digraph routes {\n  node [shape=box, fontname=\"monospace\"];\n");
+    for idx in painted_nodes(cg, &calls) {
+        let name = cg.unmangled_names(idx).first().unwrap_or(&cg.graph[idx]).clone();
+        let style = if avoid.contains(&idx) {
+            ", style=filled, fillcolor=\"#dddddd\", fontcolor=\"#888888\", color=\"#aaaaaa\""
+        } else if cg.gc_functions.contains(&idx) {
+            ", style=filled, fillcolor=\"#f0c0c0\""
+        } else {
+            ""
+        };
+        out += &format!("  n{} [label={}{}];\n", idx.index(), dot_string(&format!("#{} {}", idx.index(), name)), style);
+    }
+    for (edge, users) in &calls {
+        let (src, dst) = cg.graph.edge_endpoints(*edge).unwrap();
+        let props = cg.graph[*edge];
+        let color = users.iter().map(|&i| ROUTE_COLORS[i % ROUTE_COLORS.len()]).collect::<Vec<_>>().join(":");
+        let mut attrs = format!("color={}", dot_string(&color));
+        if props.any != 0 {
+            attrs += &format!(", label={}, style={}", dot_string(&cg.describe_property_set(props.any)),
+                              if props.any & avoid_props != 0 { "dotted" } else { "dashed" });
+        }
+        out += &format!("  n{} -> n{} [{}];\n", src.index(), dst.index(), attrs);
+    }
+    // A key to the colors.
+    out += "  subgraph cluster_key {\n    label=\"routes\";\n";
+    for (i, route) in routes.iter().enumerate() {
+        out += &format!("    key{} [shape=plaintext, label={}, fontcolor={}];\n", i, dot_string(&format!("{} ({} calls)", route.label, route.edges.len())),
+                        dot_string(ROUTE_COLORS[i % ROUTE_COLORS.len()]));
+    }
+    out += "  }\n}\n";
+    out
+}
+
+pub fn routes_json(cg : &Callgraph, routes : &[PaintedRoute], avoid : &HashSet<NodeIndex>, avoid_props : u32) -> json::JsonValue {
+    let calls = painted_calls(routes);
+    let mut json_nodes = json::JsonValue::new_array();
+    for idx in painted_nodes(cg, &calls) {
+        let _ = json_nodes.push(json::object!{
+            "id" => idx.index(),
+            "name" => cg.unmangled_names(idx).first().unwrap_or(&cg.graph[idx]).as_str(),
+            "mangled" => cg.graph[idx].as_str(),
+            "gc" => cg.gc_functions.contains(&idx),
+            "avoided" => avoid.contains(&idx),
+        });
+    }
+    let mut json_edges = json::JsonValue::new_array();
+    for (edge, users) in &calls {
+        let (src, dst) = cg.graph.edge_endpoints(*edge).unwrap();
+        let props = cg.graph[*edge];
+        let _ = json_edges.push(json::object!{
+            "id" => edge.index(),
+            "source" => src.index(),
+            "target" => dst.index(),
+            "props" => if props.any == 0 { String::new() } else { cg.describe_property_set(props.any) },
+            "avoided" => props.any & avoid_props != 0,
+            "routes" => users.clone(),
+        });
+    }
+    let mut json_routes = json::JsonValue::new_array();
+    for (i, route) in routes.iter().enumerate() {
+        let _ = json_routes.push(json::object!{
+            "label" => route.label.as_str(),
+            "color" => ROUTE_COLORS[i % ROUTE_COLORS.len()],
+            "edges" => route.edges.iter().map(|e| e.index()).collect::<Vec<_>>(),
+        });
+    }
+    json::object!{ "routes" => json_routes, "nodes" => json_nodes, "edges" => json_edges }
+}
+
+// The namespace dependency matrix (see `export namespaces`) as CSV, rows
+// calling columns, or as a DOT graph of the calls between namespaces.
+fn namespace_label(ns : &str) -> &str {
+    if ns.is_empty() { "(none)" } else { ns }
+}
+
+pub fn namespaces_csv(matrix : &NamespaceMatrix) -> String {
+    let csv_field = |s : &str| if s.contains(',') || s.contains('"') { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() };
+    let mut out = String::from("caller \\ callee");
+    for (ns, _) in &matrix.namespaces {
+        out += &format!(",{}", csv_field(namespace_label(ns)));
+    }
+    out += "\n";
+    for (i, (ns, _)) in matrix.namespaces.iter().enumerate() {
+        out += &csv_field(namespace_label(ns));
+        for j in 0..matrix.namespaces.len() {
+            out += &format!(",{}", matrix.calls.get(&(i, j)).unwrap_or(&0));
+        }
+        out += "\n";
+    }
+    out
+}
+
+pub fn namespaces_dot(matrix : &NamespaceMatrix) -> String {
+    let mut out = String::from("digraph namespaces {\n  node [shape=box, fontname=\"monospace\"];\n");
+    for (i, (ns, functions)) in matrix.namespaces.iter().enumerate() {
+        let inside = matrix.calls.get(&(i, i)).unwrap_or(&0);
+        let label = format!("{}\n{} functions, {} internal calls", namespace_label(ns), functions, inside);
+        out += &format!("  ns{} [label={}];\n", i, dot_string(&label).replace('\n', "\\n"));
+    }
+    // Line weights go by the log of the call count, or the big namespaces
+    // would drown out everything else.
+    for (&(i, j), &count) in matrix.calls.iter().filter(|((i, j), _)| i != j) {
+        let width = 1.0 + (count as f64).log10();
+        out += &format!("  ns{} -> ns{} [label=\"{}\", penwidth={:.1}];\n", i, j, count, width);
+    }
+    out += "}\n";
+    out
+}
+
+pub fn html(cg : &Callgraph, title : &str, nodes : &[NodeIndex], edges : &[EdgeIndex]) -> String {
+    // "</script>" inside a string would end the script element early.
+    let data = graph_json(cg, nodes, edges).dump().replace("</", "<\\/");
+    let title = title.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    TEMPLATE.replace("@TITLE@", &title).replace("@DATA@", &data)
+}
+
+const TEMPLATE : &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>@TITLE@</title>
+<style>
+  body { margin: 0; font: 12px sans-serif; }
+  #info { position: fixed; top: 0; left: 0; right: 0; padding: 4px 8px; background: #eee; min-height: 1.2em; }
+  svg { width: 100vw; height: 100vh; display: block; }
+  line { stroke: #999; stroke-width: 1.5; }
+  line.props { stroke: #c60; }
+  circle { fill: #48c; stroke: #fff; stroke-width: 1.5; cursor: move; }
+  circle.gc { fill: #c33; }
+  text { pointer-events: none; }
+</style>
+</head>
+<body>
+<div id="info">@TITLE@ &mdash; drag to move, hover for details</div>
+<svg id="view">
+  <defs><marker id="arrow" viewBox="0 0 10 10" refX="17" refY="5" markerWidth="6" markerHeight="6" orient="auto">
+    <path d="M0,0 L10,5 L0,10 z" fill="#999"/></marker></defs>
+</svg>
+<script>
+var graph = @DATA@;
+var svg = document.getElementById("view"), info = document.getElementById("info");
+var NS = "http://www.w3.org/2000/svg";
+var W = window.innerWidth, H = window.innerHeight;
+function el(tag, attrs, parent) {
+  var e = document.createElementNS(NS, tag);
+  for (var k in attrs) e.setAttribute(k, attrs[k]);
+  parent.appendChild(e);
+  return e;
+}
+graph.nodes.forEach(function(n, i) {
+  var a = 2 * Math.PI * i / graph.nodes.length;
+  n.x = W / 2 + Math.cos(a) * W / 4; n.y = H / 2 + Math.sin(a) * H / 4; n.vx = n.vy = 0;
+});
+graph.edges.forEach(function(e) {
+  e.line = el("line", { "class": e.props ? "props" : "", "marker-end": "url(#arrow)" }, svg);
+  e.line.onmouseover = function() {
+    info.textContent = graph.nodes[e.source].name + " -> " + graph.nodes[e.target].name + (e.props ? " [" + e.props + "]" : "");
+  };
+});
+graph.nodes.forEach(function(n) {
+  n.circle = el("circle", { r: 7, "class": n.gc ? "gc" : "" }, svg);
+  n.label = el("text", { dx: 10, dy: 4 }, svg);
+  n.label.textContent = "#" + n.id + " " + n.name.split("(")[0];
+  n.circle.onmouseover = function() { info.textContent = "#" + n.id + " " + n.name + "  (" + n.mangled + ")"; };
+  n.circle.onmousedown = function(ev) { dragging = n; ev.preventDefault(); };
+});
+var dragging = null;
+svg.onmousemove = function(ev) { if (dragging) { dragging.x = ev.clientX; dragging.y = ev.clientY; heat = 1; } };
+window.onmouseup = function() { dragging = null; };
+var heat = 1;
+function step() {
+  var nodes = graph.nodes;
+  for (var i = 0; i < nodes.length; i++) {
+    for (var j = i + 1; j < nodes.length; j++) {
+      var dx = nodes[j].x - nodes[i].x, dy = nodes[j].y - nodes[i].y, d2 = dx * dx + dy * dy + 0.01;
+      var f = 2000 / d2, d = Math.sqrt(d2);
+      nodes[i].vx -= f * dx / d; nodes[i].vy -= f * dy / d;
+      nodes[j].vx += f * dx / d; nodes[j].vy += f * dy / d;
+    }
+  }
+  graph.edges.forEach(function(e) {
+    var s = nodes[e.source], t = nodes[e.target];
+    var dx = t.x - s.x, dy = t.y - s.y, d = Math.sqrt(dx * dx + dy * dy) + 0.01, f = (d - 80) * 0.05;
+    s.vx += f * dx / d; s.vy += f * dy / d; t.vx -= f * dx / d; t.vy -= f * dy / d;
+  });
+  nodes.forEach(function(n) {
+    n.vx += (W / 2 - n.x) * 0.002; n.vy += (H / 2 - n.y) * 0.002;
+    if (n !== dragging) { n.x += n.vx * heat; n.y += n.vy * heat; }
+    n.vx *= 0.5; n.vy *= 0.5;
+    n.circle.setAttribute("cx", n.x); n.circle.setAttribute("cy", n.y);
+    n.label.setAttribute("x", n.x); n.label.setAttribute("y", n.y);
+  });
+  graph.edges.forEach(function(e) {
+    var s = nodes[e.source], t = nodes[e.target];
+    e.line.setAttribute("x1", s.x); e.line.setAttribute("y1", s.y);
+    e.line.setAttribute("x2", t.x); e.line.setAttribute("y2", t.y);
+  });
+  heat = Math.max(heat * 0.99, 0.02);
+  requestAnimationFrame(step);
+}
+step();
+</script>
+</body>
+</html>
+"##;
@@ -0,0 +1,157 @@
+use crate::callgraph::{Callgraph, Direction};
+use petgraph::graph::NodeIndex;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::fs;
+
+// Flamegraph view of everything reachable from a function. The callgraph is
+// not a tree, so each function appears once, under the caller through which
+// a breadth-first search first reached it. By default every function weighs
+// 1, making each frame's width the number of functions reachable through it;
+// alternatively the weights come from profile samples in collapsed-stack
+// format ("a;b;c 123", self time going to the last frame).
+
+pub struct CallTree {
+    nodes : Vec<NodeIndex>,
+    children : Vec<Vec<usize>>,
+    // Total weight of each subtree.
+    weight : Vec<u64>,
+}
+
+pub fn load_samples(path : &str) -> Result<HashMap<String, u64>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut samples = HashMap::new();
+    for line in text.lines() {
+        let (stack, count) = match line.rsplit_once(' ') {
+            Some((stack, count)) => (stack, count.trim().parse::<u64>().map_err(|_| format!("bad sample line: {}", line))?),
+            None => continue,
+        };
+        if let Some(frame) = stack.rsplit(';').next() {
+            *samples.entry(frame.to_string()).or_insert(0) += count;
+        }
+    }
+    Ok(samples)
+}
+
+fn self_weight(cg : &Callgraph, idx : NodeIndex, samples : Option<&HashMap<String, u64>>) -> u64 {
+    let samples = match samples {
+        Some(samples) => samples,
+        None => return 1,
+    };
+    // Profilers differ on whether they include parameter lists.
+    let mut keys : Vec<&str> = cg.names(idx).iter().flat_map(|name| vec![*name, name.split('(').next().unwrap()]).collect();
+    keys.sort();
+    keys.dedup();
+    keys.iter().map(|key| samples.get(*key).cloned().unwrap_or(0)).sum()
+}
+
+pub fn build(cg : &Callgraph, root : NodeIndex, samples : Option<&HashMap<String, u64>>) -> CallTree {
+    let mut tree = CallTree { nodes: vec![root], children: vec![vec![]], weight: vec![] };
+    let mut seen : HashMap<NodeIndex, usize> = HashMap::new();
+    seen.insert(root, 0);
+    let mut work = VecDeque::new();
+    work.push_back(0);
+    while let Some(i) = work.pop_front() {
+        for (callee, _, _) in cg.neighbor_edges(tree.nodes[i], Direction::Callees) {
+            if seen.contains_key(&callee) { continue; }
+            let j = tree.nodes.len();
+            seen.insert(callee, j);
+            tree.nodes.push(callee);
+            tree.children.push(vec![]);
+            tree.children[i].push(j);
+            work.push_back(j);
+        }
+    }
+
+    // Children always come after their parents, so summing in reverse order
+    // sees every subtree complete.
+    tree.weight = tree.nodes.iter().map(|&idx| self_weight(cg, idx, samples)).collect();
+    for i in (0..tree.nodes.len()).rev() {
+        let total : u64 = tree.children[i].iter().map(|&c| tree.weight[c]).sum();
+        tree.weight[i] += total;
+    }
+    tree
+}
+
+fn frame_name(cg : &Callgraph, idx : NodeIndex) -> String {
+    // Collapsed stacks use ';' as the separator.
+    let names = cg.names(idx);
+    names.last().unwrap_or(&"").replace(';', ":")
+}
+
+// One line per function: its stack and its own weight.
+pub fn collapsed(cg : &Callgraph, tree : &CallTree) -> String {
+    let mut out = String::new();
+    let mut work = vec![(0, frame_name(cg, tree.nodes[0]))];
+    while let Some((i, stack)) = work.pop() {
+        let own = tree.weight[i] - tree.children[i].iter().map(|&c| tree.weight[c]).sum::<u64>();
+        if own > 0 {
+            let _ = writeln!(out, "{} {}", stack, own);
+        }
+        for &c in tree.children[i].iter().rev() {
+            work.push((c, format!("{};{}", stack, frame_name(cg, tree.nodes[c]))));
+        }
+    }
+    out
+}
+
+const WIDTH : f64 = 1200.0;
+const FRAME_HEIGHT : f64 = 16.0;
+// Frames narrower than this many pixels are left out.
+const MIN_WIDTH : f64 = 0.1;
+
+fn escape(s : &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn svg(cg : &Callgraph, tree : &CallTree) -> String {
+    let total = tree.weight[0].max(1) as f64;
+    let scale = WIDTH / total;
+
+    // (node, x, depth)
+    let mut frames = vec![];
+    let mut work = vec![(0, 0.0, 0)];
+    let mut max_depth = 0;
+    while let Some((i, x, depth)) = work.pop() {
+        if tree.weight[i] as f64 * scale < MIN_WIDTH { continue; }
+        frames.push((i, x, depth));
+        max_depth = max_depth.max(depth);
+        let mut child_x = x;
+        for &c in &tree.children[i] {
+            work.push((c, child_x, depth + 1));
+            child_x += tree.weight[c] as f64 * scale;
+        }
+    }
+
+    let height = (max_depth + 1) as f64 * FRAME_HEIGHT + 2.0 * FRAME_HEIGHT;
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<?xml version="1.0" standalone="no"?>"#);
+    let _ = writeln!(out, r#"<svg version="1.1" width="{}" height="{}" xmlns="http://www.w3.org/2000/svg" font-family="Verdana" font-size="11">"#, WIDTH, height);
+    let _ = writeln!(out, r#"<text x="{}" y="{}" text-anchor="middle" font-size="14">{}</text>"#,
+                     WIDTH / 2.0, FRAME_HEIGHT, escape(&frame_name(cg, tree.nodes[0])));
+    for (i, x, depth) in frames {
+        let name = frame_name(cg, tree.nodes[i]);
+        let w = tree.weight[i] as f64 * scale;
+        // Root at the bottom, like flamegraph.pl.
+        let y = height - (depth + 1) as f64 * FRAME_HEIGHT;
+        // Warm colors, stable for a given name.
+        let hash = name.bytes().fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+        let (r, g, b) = (205 + hash % 50, 100 + (hash >> 8) % 130, (hash >> 16) % 55);
+        let _ = writeln!(out, r#"<g><title>{} ({}, {:.2}%)</title><rect x="{:.2}" y="{:.2}" width="{:.2}" height="{}" fill="rgb({},{},{})" rx="2"/>"#,
+                         escape(&name), tree.weight[i], tree.weight[i] as f64 * 100.0 / total,
+                         x, y, w, FRAME_HEIGHT - 1.0, r, g, b);
+        // Roughly 7px per character.
+        let chars = ((w - 6.0) / 7.0) as usize;
+        if chars >= 3 {
+            let label : String = if name.chars().count() > chars {
+                name.chars().take(chars - 2).collect::<String>() + ".."
+            } else {
+                name
+            };
+            let _ = writeln!(out, r#"<text x="{:.2}" y="{:.2}">{}</text>"#, x + 3.0, y + FRAME_HEIGHT - 4.0, escape(&label));
+        }
+        let _ = writeln!(out, "</g>");
+    }
+    let _ = writeln!(out, "</svg>");
+    out
+}
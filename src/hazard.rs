@@ -1,8 +1,10 @@
 use crate::callgraph::{Callgraph, PropertySet};
 use json;
-use petgraph::graph::NodeIndex;
+use memchr::memchr;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use regex::Regex;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, Error, ErrorKind};
 use std::io::prelude::*;
@@ -11,7 +13,9 @@ use std::fmt;
 #[derive(Debug)]
 pub enum LoadError {
     IOError(Error),
-    FormatError(u32, String)
+    FormatError(u32, String),
+    BadSnapshot(String),
+    Aborted,
 }
 
 impl std::error::Error for LoadError {}
@@ -20,7 +24,9 @@ impl fmt::Display for LoadError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             LoadError::IOError(e) => write!(f, "I/O error: {}", e),
-            LoadError::FormatError(lineno, s) => write!(f, "Format error on line {}: {}", lineno, s)
+            LoadError::FormatError(lineno, s) => write!(f, "Format error on line {}: {}", lineno, s),
+            LoadError::BadSnapshot(s) => write!(f, "Bad snapshot: {}", s),
+            LoadError::Aborted => write!(f, "Load aborted"),
         }
     }
 }
@@ -32,6 +38,27 @@ impl From<Error> for LoadError {
 }
 
 fn parse_proptable(text : &str, lineno : u32, cg : &mut Callgraph) -> Result<(), LoadError> {
+    cg.property_names.extend(parse_properties(text, lineno)?);
+    cg.property_aliases.extend(parse_property_aliases(text, lineno)?);
+    Ok(())
+}
+
+// The optional "Aliases" table next to "Properties", of other names for
+// properties: {"suppressed": "GC_SUPPRESSED"}.
+pub fn parse_property_aliases(text : &str, lineno : u32) -> Result<HashMap<String, String>, LoadError> {
+    let json = json::parse(text).map_err(|_| LoadError::FormatError(lineno, "Bad JSON info".to_string()))?;
+    let mut aliases = HashMap::new();
+    for (alias, name) in json["Aliases"].entries() {
+        match name.as_str() {
+            Some(name) => aliases.insert(alias.to_string(), name.to_string()),
+            None => return Err(LoadError::FormatError(lineno, format!("Bad alias for property '{}'", alias))),
+        };
+    }
+    Ok(aliases)
+}
+
+pub fn parse_properties(text : &str, lineno : u32) -> Result<HashMap<u32, String>, LoadError> {
+    let mut properties = HashMap::new();
     // There must be a more idiomatic way of doing this.
     let json = match json::parse(text) {
         Err(_) => return Err(LoadError::FormatError(lineno, "Bad JSON info".to_string())),
@@ -45,16 +72,224 @@ fn parse_proptable(text : &str, lineno : u32, cg : &mut Callgraph) -> Result<(),
         match k.parse::<u32>() {
             Err(_) => return Err(LoadError::FormatError(lineno, "Bad property bit number".to_string())),
             Ok(num) => {
-                cg.property_names.insert(num, name.to_string());
+                properties.insert(num, name.to_string());
             }
         }
     };
-    Ok(())
+    Ok(properties)
+}
+
+pub struct LoadOptions {
+    // Stop after this many lines (0 for no limit).
+    pub line_limit : u32,
+
+    // Skip malformed lines instead of failing the whole load.
+    pub lenient : bool,
+
+    // Record the unmangled names from `=` lines. Without them only mangled
+    // names can be resolved or displayed, but the graph is much smaller.
+    pub demangle : bool,
+
+    // JSON file with a "Properties" table, overriding any in the graph file.
+    pub property_file : Option<String>,
+
+    // Functions with a name (mangled or not) matching this are dropped as
+    // they are read, along with their calls, eg /^std::|^__gnu/. They keep
+    // their ids, as nameless placeholders.
+    pub node_filter : Option<Regex>,
+
+    // Instead of losing the calls through dropped functions, connect their
+    // callers directly to their callees.
+    pub bypass_filtered : bool,
+
+    // Avoid list (see Callgraph::avoid_list) to resolve once loaded, as the
+    // named set @avoid.
+    pub avoid_file : Option<String>,
+
+    // Don't print a summary of what was loaded, eg when embedded in a
+    // program whose stdout isn't ours to write to.
+    pub quiet : bool,
+}
+
+// A node filter pattern, with or without the /slashes/ used elsewhere for
+// regexes.
+pub fn node_filter(pattern : &str) -> Result<Regex, String> {
+    let pattern = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')).unwrap_or(pattern);
+    Regex::new(pattern).map_err(|e| format!("invalid exclusion pattern: {}", e))
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            line_limit: 0,
+            lenient: false,
+            demangle: true,
+            property_file: None,
+            node_filter: None,
+            bypass_filtered: false,
+            avoid_file: None,
+            quiet: false,
+        }
+    }
+}
+
+// Called periodically during loading with (lines read, bytes read, total
+// bytes). Returning false aborts the load.
+pub type ProgressCallback<'a> = &'a mut dyn FnMut(u32, u64, u64) -> bool;
+
+const PROGRESS_INTERVAL : u32 = 100_000;
+
+// The loader works on raw bytes with hand-rolled field splitting and number
+// parsing; read_line, split_whitespace, and str::parse dominated load time on
+// full-size graphs.
+
+pub fn parse_u32(s : &[u8]) -> Option<u32> {
+    if s.is_empty() || s.len() > 10 {
+        return None;
+    }
+    let mut n : u64 = 0;
+    for &c in s {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        n = n * 10 + (c - b'0') as u64;
+    }
+    if n > u32::MAX as u64 { None } else { Some(n as u32) }
+}
+
+pub fn parse_id(s : &[u8]) -> Result<u32, String> {
+    parse_u32(s).ok_or_else(|| format!("malformed function id '{}'", String::from_utf8_lossy(s)))
 }
 
-pub fn load_graph(filename : &str, line_limit : u32) -> Result<Callgraph, LoadError> {
+pub fn to_str(s : &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(s).map_err(|_| "invalid UTF-8".to_string())
+}
+
+pub fn split_token(s : &[u8]) -> (&[u8], &[u8]) {
+    match memchr(b' ', s) {
+        Some(pos) => (&s[..pos], &s[pos+1..]),
+        None => (s, b"")
+    }
+}
+
+// Space-separated fields, ignoring runs of spaces.
+struct Fields<'a> {
+    rest : &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn next(&mut self) -> Result<&'a [u8], String> {
+        while let Some((b' ', tail)) = self.rest.split_first() {
+            self.rest = tail;
+        }
+        if self.rest.is_empty() {
+            return Err("missing function id".to_string());
+        }
+        let (token, rest) = split_token(self.rest);
+        self.rest = rest;
+        Ok(token)
+    }
+}
+
+// src, dst, properties, and the call site location (eg "jsgc.cpp:123") if given.
+pub type LocatedEdge<'a> = (u32, u32, PropertySet, Option<&'a [u8]>);
+
+// D/R lines: "D [all:any | /bits] [SUPPRESS_GC] src dst [location]"
+pub fn parse_edge(text : &[u8]) -> Result<LocatedEdge<'_>, String> {
+    let mut fields = Fields { rest: text.get(2..).unwrap_or(b"") };
+    let mut src = fields.next()?;
+    let mut dst = fields.next()?;
+    let mut limit = PropertySet { all: 0, any: 0 };
+    if let Some(colon) = memchr(b':', src) {
+        let malformed = || format!("malformed 'all:any' {}", String::from_utf8_lossy(src));
+        let all = parse_u32(&src[0..colon]).ok_or_else(malformed)?;
+        let any = parse_u32(&src[colon+1..]).ok_or_else(malformed)?;
+        limit = PropertySet { all, any };
+        src = dst;
+        dst = fields.next()?;
+    } else if let Some(bits) = src.strip_prefix(b"/") {
+        let bits = parse_u32(bits).ok_or_else(|| format!("malformed limit {}", String::from_utf8_lossy(src)))?;
+        limit = PropertySet { all: bits, any: bits };
+        src = dst;
+        dst = fields.next()?;
+    };
+    if src == b"SUPPRESS_GC" {
+        src = dst;
+        dst = fields.next()?;
+        limit = PropertySet { all: 1, any: 1 };
+    };
+    let location = match fields.rest.trim_ascii() {
+        b"" => None,
+        location => Some(location),
+    };
+    Ok((parse_id(src)?, parse_id(dst)?, limit, location))
+}
+
+// I lines: "I [/bits] src VARIABLE name..."
+pub fn parse_indirect(text : &[u8]) -> Result<(u32, String, PropertySet), String> {
+    let (mut src, mut rest) = split_token(text.get(2..).unwrap_or(b""));
+    let mut limit = 0;
+    if let Some(bits) = src.strip_prefix(b"/") {
+        limit = parse_u32(bits).ok_or_else(|| format!("malformed limit {}", String::from_utf8_lossy(src)))?;
+        let (s, r) = split_token(rest);
+        src = s;
+        rest = r;
+    }
+    Ok((parse_id(src)?, to_str(rest)?.to_string(), PropertySet { all: limit, any: limit }))
+}
+
+const MAX_PLACEHOLDER_GAP : usize = 1 << 20;
+
+// Calls may only refer to functions declared earlier in the file. In lenient
+// mode, undeclared ones get placeholder nodes instead, which are named if
+// their declarations turn up later.
+fn declared(cg : &mut Callgraph, id : u32, lenient : bool, placeholders : &mut HashSet<u32>) -> Result<NodeIndex, String> {
+    if id == 0 {
+        return Err("function ids start at 1".to_string());
+    }
+    if id as usize >= cg.graph.node_count() {
+        // A wildly out of range id is more likely garbage than a forward
+        // reference.
+        if !lenient || id as usize - cg.graph.node_count() > MAX_PLACEHOLDER_GAP {
+            return Err(format!("undeclared function #{}", id));
+        }
+        while id as usize >= cg.graph.node_count() {
+            let next = cg.graph.node_count() as u32;
+            cg.add_function(&format!("<undeclared #{}>", next));
+            placeholders.insert(next);
+        }
+    }
+    Ok(NodeIndex::new(id as usize))
+}
+
+// A repeat of a call with the same properties and no known location adds
+// nothing but one to the count of the first.
+fn add_call(
+    cg : &mut Callgraph,
+    calls : &mut HashMap<(NodeIndex, NodeIndex, PropertySet), EdgeIndex>,
+    src : NodeIndex,
+    dst : NodeIndex,
+    limit : PropertySet
+) {
+    match calls.entry((src, dst, limit)) {
+        Entry::Occupied(entry) => cg.count_call(*entry.get()),
+        Entry::Vacant(entry) => { entry.insert(cg.add_edge(src, dst, limit)); },
+    }
+}
+
+pub fn load_graph_with_options(
+    filename : &str,
+    options : &LoadOptions,
+    mut progress : Option<ProgressCallback>
+) -> Result<Callgraph, LoadError>
+{
+    macro_rules! report {
+        ($($arg:tt)*) => { if !options.quiet { println!($($arg)*); } }
+    }
+
     let mut cg = Callgraph::new();
     let file = File::open(filename)?;
+    let total_bytes = file.metadata()?.len();
     let mut reader = BufReader::new(file);
 
     fn error(message : &str) -> Result<Callgraph, Error> {
@@ -62,101 +297,224 @@ pub fn load_graph(filename : &str, line_limit : u32) -> Result<Callgraph, LoadEr
     }
 
     let mut indirects = Vec::<(u32, String, PropertySet)>::new();
+    let mut virtuals = Vec::<(u32, String, PropertySet)>::new();
+    let mut placeholders = HashSet::new();
+    // Calls seen so far with no location, to spot repeats; see add_call.
+    let mut calls = HashMap::new();
+
+    // Functions dropped by the node filter, and with bypass_filtered, the
+    // calls into and out of them.
+    let mut filtered = HashSet::<u32>::new();
+    let mut filtered_calls = HashMap::<u32, Vec<(u32, PropertySet)>>::new();
+    let mut filtered_callers = Vec::<(u32, u32, PropertySet)>::new();
+    let rejected = |cg : &Callgraph, idx : NodeIndex| -> bool {
+        options.node_filter.as_ref().is_some_and(|re| {
+            re.is_match(&cg.graph[idx]) || cg.unmangled_names(idx).iter().any(|name| re.is_match(name))
+        })
+    };
 
     let mut lineno = 0;
-    let mut line = String::with_capacity(4000);
+    let mut bytes_read : u64 = 0;
+    let mut skipped = 0;
+    let mut line = Vec::<u8>::with_capacity(4000);
     loop {
         line.clear();
-        match reader.read_line(&mut line)? {
+        match reader.read_until(b'\n', &mut line)? {
             0 => break,
-            _ => (),
+            n => bytes_read += n as u64,
         };
         lineno += 1;
 
-        match line.chars().next() {
-            Some('#') => {
-                let space = match line.find(' ') {
-                    Some(pos) => pos,
-                    None => return Err(LoadError::FormatError(lineno, line)),
+        if let Some(callback) = progress.as_mut() {
+            if lineno % PROGRESS_INTERVAL == 0 && !callback(lineno, bytes_read, total_bytes) {
+                return Err(LoadError::Aborted);
+            }
+        }
+
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let parsed : Result<(), String> = match text.first() {
+            Some(b'#') => {
+                let (function, func) = split_token(&text[1..]);
+                let declared = match parse_u32(function) {
+                    Some(num) if num as usize == cg.graph.node_count() => {
+                        to_str(func).map(|func| cg.add_function(func))
+                    },
+                    Some(num) if placeholders.remove(&num) => {
+                        to_str(func).map(|func| {
+                            let idx = NodeIndex::new(num as usize);
+                            cg.rename_function(idx, func);
+                            idx
+                        })
+                    },
+                    Some(num) => Err(format!("function #{} declared out of order", num)),
+                    None => Err(String::from_utf8_lossy(function).into_owned()),
                 };
-                let function : &str = &line[1..space];
-                match function.parse::<u32>() {
-                    Ok(num) => {
-                        let func = &line[(space+1)..line.len()-1];
-                        let index = cg.add_function(func);
-                        assert!(num as usize == index.index());
+                declared.map(|idx| {
+                    if rejected(&cg, idx) && cg.graph.neighbors_undirected(idx).next().is_none() {
+                        cg.filter_out(idx);
+                        filtered.insert(idx.index() as u32);
+                    }
+                })
+            },
+            Some(b'D')|Some(b'R') => parse_edge(text).and_then(|(src, dst, limit, location)| {
+                if filtered.contains(&src) || filtered.contains(&dst) {
+                    if options.bypass_filtered {
+                        if filtered.contains(&src) {
+                            filtered_calls.entry(src).or_default().push((dst, limit));
+                        } else {
+                            filtered_callers.push((src, dst, limit));
+                        }
+                    }
+                    return Ok(());
+                }
+                let src = declared(&mut cg, src, options.lenient, &mut placeholders)?;
+                let dst = declared(&mut cg, dst, options.lenient, &mut placeholders)?;
+                match location {
+                    Some(location) => {
+                        let edge = cg.add_edge(src, dst, limit);
+                        cg.call_sites.insert(edge, to_str(location)?.to_string());
                     },
-                    Err(_) => return Err(LoadError::FormatError(lineno, function.to_owned())),
+                    None => add_call(&mut cg, &mut calls, src, dst, limit),
                 }
+                Ok(())
+            }),
+            Some(b'=') => { // Unmangled name (one of them)
+                let (id, name) = split_token(text.get(2..).unwrap_or(b""));
+                // May come before the function's own line; checked below.
+                parse_id(id).and_then(|id| {
+                    if id == 0 {
+                        return Err("function ids start at 1".to_string());
+                    }
+                    if filtered.contains(&id) {
+                        return Ok(());
+                    }
+                    // Same bound as for calls to undeclared functions, since
+                    // the name table grows to fit.
+                    if (id as usize).saturating_sub(cg.graph.node_count()) > MAX_PLACEHOLDER_GAP {
+                        return Err(format!("name for undeclared function #{}", id));
+                    }
+                    let idx = NodeIndex::new(id as usize);
+                    let name = to_str(name)?;
+                    // Filtering on an unmangled name has to happen before
+                    // the function's first call, which it normally does.
+                    if options.node_filter.as_ref().is_some_and(|re| re.is_match(name)) && idx.index() < cg.graph.node_count()
+                        && cg.graph.neighbors_undirected(idx).next().is_none()
+                    {
+                        cg.filter_out(idx);
+                        filtered.insert(id);
+                        return Ok(());
+                    }
+                    if options.demangle {
+                        cg.add_unmangled_name(idx, name);
+                    }
+                    Ok(())
+                })
             },
-            Some('D')|Some('R') => {
-                let mut iter = (&line[2..]).split_whitespace();
-                let mut src = iter.next().expect("missing src function id");
-                let mut dst = iter.next().expect("missing dst function id");
-                let mut limit = PropertySet { all: 0, any: 0 };
-                if let Some(colon) = src.find(':') {
-                    let all : u32 = src[0..colon].parse().unwrap_or_else(|_| panic!("malformed 'all:any' {} on line {}", src, lineno));
-                    let any : u32 = src[colon+1..].parse().unwrap_or_else(|_| panic!("malformed 'all:any' {} on line {}", src, lineno));
-                    limit = PropertySet { all, any };
-                    src = dst;
-                    dst = iter.next().expect("missing dst function id");
-                } else if &src[0..1] == "/" {
-                    let bits : u32 = src[1..].parse().unwrap_or_else(|_| panic!("malformed limit {} on line {}", src, lineno));
-                    limit = PropertySet { all: bits, any: bits };
-                    src = dst;
-                    dst = iter.next().expect("missing dst function id");
-                };
-                if src == "SUPPRESS_GC" {
-                    src = dst;
-                    dst = iter.next().expect("missing dst function id");
-                    limit = PropertySet { all: 1, any: 1 };
-                };
-
-                let src : u32 = src.parse().unwrap_or_else(|_| panic!("malformed function id on line {}", lineno));
-                let dst : u32 = dst.parse().expect("malformed function id");
-                let src = NodeIndex::new(src as usize);
-                let dst = NodeIndex::new(dst as usize);
-                cg.add_edge(src, dst, limit);
+            Some(b'F') => Ok(()), // Field call
+            Some(b'I') => { // Indirect call
+                // Have to defer generating a node for the indirect function
+                // pointer, because otherwise it would change the numbering.
+                parse_indirect(text).and_then(|indirect| {
+                    if filtered.contains(&indirect.0) {
+                        return Ok(());
+                    }
+                    declared(&mut cg, indirect.0, options.lenient, &mut placeholders)?;
+                    indirects.push(indirect);
+                    Ok(())
+                })
             },
-            Some('=') => { // Unmangled name (one of them)
-                let wtf = &line[2..];
-                let space = match wtf.find(' ') {
-                    Some(pos) => pos + 2,
-                    None => return Err(LoadError::FormatError(lineno, line)),
-                };
-                let id : usize = line[2..space].parse().unwrap_or_else(|_| panic!("malformed function id on line {}", lineno));
-                cg.add_unmangled_name(id, &line[space+1..line.len()-1]);
+            Some(b'T') => Ok(()), // Tag
+            Some(b'V') => { // Virtual method call, "V <caller> CLASS Foo FIELD bar"
+                parse_indirect(text).and_then(|call| {
+                    if filtered.contains(&call.0) {
+                        return Ok(());
+                    }
+                    declared(&mut cg, call.0, options.lenient, &mut placeholders)?;
+                    virtuals.push(call);
+                    Ok(())
+                })
             },
-            Some('F') => {}, // Field call
-            Some('I') => { // Indirect call
-                let mut len = 2;
-                let mut iter = (&line[2..]).split_whitespace();
-                let mut src = iter.next().expect("missing src function id");
-                len += src.len();
-                let mut limit = 0;
-                if &src[0..1] == "/" {
-                    limit = src[1..].parse().unwrap_or_else(|_| panic!("malformed limit {} on line {}", src, lineno));
-                    src = iter.next().expect("missing src function id");
-                    len += 1 + src.len();
+            Some(b'!') => {
+                match to_str(text.get(2..).unwrap_or(b"")) {
+                    Ok(json) => {
+                        parse_proptable(json, lineno, &mut cg)?;
+                        Ok(())
+                    },
+                    Err(e) => Err(e),
                 }
-                let dst = &line[1+len..line.len()-1];
-                let src : u32 = src.parse().unwrap_or_else(|_| panic!("malformed function id on line {}", lineno));
-                // Have to defer generating a node for the indirect function
-                // pointer, because otherwise it would change the numbering.
-                indirects.push((src, dst.to_string(), PropertySet { all: limit, any: limit }));
             },
-            Some('T') => {}, // Tag
-            Some('V') => {}, // virtual method
-            Some('!') => parse_proptable(&line[2..], lineno, &mut cg)?,
-            Some(_) => return Err(LoadError::FormatError(lineno, "Unhandled leading character".to_string())),
-            None => {}
+            Some(_) => Err("Unhandled leading character".to_string()),
+            None => Ok(())
+        };
+
+        if let Err(message) = parsed {
+            if !options.lenient {
+                return Err(LoadError::FormatError(lineno, message));
+            }
+            skipped += 1;
         }
 
-        if line_limit > 0 && lineno > line_limit { break; }
+        if options.line_limit > 0 && lineno > options.line_limit { break; }
     };
 
+    // Names for functions that never turned up. This has to be settled before
+    // the function pointer nodes are added, or they would inherit them.
+    if cg.alt_names.len() > cg.graph.node_count() {
+        let last = cg.alt_names.len() as u32 - 1;
+        if !options.lenient {
+            return Err(LoadError::FormatError(lineno, format!("unmangled name for undeclared function #{}", last)));
+        }
+        declared(&mut cg, last, true, &mut placeholders).map_err(|e| LoadError::FormatError(lineno, e))?;
+    }
+
+    if skipped > 0 {
+        report!("skipped {} malformed lines", skipped);
+    }
+
+    if !filtered.is_empty() {
+        let mut bypasses = 0;
+        for (src, dst, limit) in filtered_callers {
+            // Everything the dropped function leads to through other dropped
+            // functions, with the properties of the calls on the way.
+            let mut seen = HashSet::from([dst]);
+            let mut work = vec![(dst, limit)];
+            while let Some((func, props)) = work.pop() {
+                for &(callee, callee_props) in filtered_calls.get(&func).map_or(&[][..], |calls| calls.as_slice()) {
+                    let props = PropertySet { all: props.all | callee_props.all, any: props.any | callee_props.any };
+                    if filtered.contains(&callee) {
+                        if seen.insert(callee) {
+                            work.push((callee, props));
+                        }
+                    } else if callee != src {
+                        add_call(&mut cg, &mut calls, NodeIndex::new(src as usize), NodeIndex::new(callee as usize), props);
+                        bypasses += 1;
+                    }
+                }
+            }
+        }
+        report!("filtered out {} functions{}", filtered.len(),
+                 if options.bypass_filtered { format!(", adding {} calls around them", bypasses) } else { String::new() });
+    }
+    if !placeholders.is_empty() {
+        report!("{} functions were called but never declared", placeholders.len());
+    }
+
+    if let Some(property_file) = &options.property_file {
+        let text = std::fs::read_to_string(property_file)?;
+        parse_proptable(&text, 0, &mut cg)?;
+    }
+    let mut dangling : Vec<&String> = cg.property_aliases.iter()
+        .filter(|(_, name)| !cg.property_names.values().any(|n| n == *name))
+        .map(|(alias, _)| alias)
+        .collect();
+    dangling.sort();
+    for alias in dangling {
+        report!("property alias '{}' is for unknown property '{}'", alias, cg.property_aliases[alias]);
+    }
+
     let mut seen = HashMap::<(&str,PropertySet),NodeIndex>::new();
-    for (src, dst_name, limit) in &indirects {
+    let mut methods = BTreeSet::new();
+    for (i, (src, dst_name, limit)) in indirects.iter().chain(&virtuals).enumerate() {
         // For now, just leave the "VARIABLE " in the beginning.
         let key = (dst_name.as_ref(), *limit);
         let dst = match seen.entry(key) {
@@ -170,16 +528,144 @@ pub fn load_graph(filename : &str, line_limit : u32) -> Result<Callgraph, LoadEr
             }
         };
         cg.add_edge(NodeIndex::new(*src as usize), dst, *limit);
+        if i >= indirects.len() {
+            methods.insert(dst);
+        }
+    }
+    report!("{} indirects, {} distinct", indirects.len(), seen.len() - methods.len());
+    if !virtuals.is_empty() {
+        let methods : Vec<NodeIndex> = methods.into_iter().collect();
+        let dispatches = cg.resolve_virtual_calls(&methods);
+        report!("{} virtual calls to {} methods, with {} possible implementations", virtuals.len(), methods.len(), dispatches);
     }
-    println!("{} indirects, {} distinct", indirects.len(), seen.len());
 
-    let roots = &cg.roots();
-    println!("found {} roots", roots.len());
+    let roots = cg.roots();
+    report!("found {} roots", roots.len());
 
-    let sinks = &cg.sinks();
-    println!("found {} sinks", sinks.len());
+    let sinks = cg.sinks();
+    report!("found {} sinks", sinks.len());
 
-    println!("Final lineno = {}", lineno);
+    if let Some(avoid_file) = &options.avoid_file {
+        let list = cg.avoid_list(&std::fs::read_to_string(avoid_file)?);
+        let mut funcs : Vec<NodeIndex> = list.iter().flat_map(|(_, found)| found.iter().cloned()).collect();
+        funcs.sort();
+        funcs.dedup();
+        let unresolved = list.iter().filter(|(_, found)| found.is_empty()).count();
+        report!("@avoid has {} functions from {}{}", funcs.len(), avoid_file,
+                 if unresolved > 0 { format!(" ({} patterns matched nothing)", unresolved) } else { String::new() });
+        cg.define_set("avoid", funcs);
+    }
+
+    report!("Final lineno = {}", lineno);
+
+    if let Some(callback) = progress.as_mut() {
+        callback(lineno, bytes_read, total_bytes);
+    }
 
     Ok(cg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_text(name : &str, text : &str, lenient : bool) -> Result<Callgraph, LoadError> {
+        let path = std::env::temp_dir().join(format!("hazard-{}-{}.txt", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let options = LoadOptions { lenient, quiet: true, ..Default::default() };
+        let result = load_graph_with_options(path.to_str().unwrap(), &options, None);
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    fn unmangled(cg : &Callgraph, id : usize) -> Vec<String> {
+        cg.unmangled_names(NodeIndex::new(id)).to_vec()
+    }
+
+    #[test]
+    fn names_before_declarations() {
+        let cg = load_text("names-first", "= 2 b()\n= 1 a()\n#1 a\n#2 b\nD 1 2\n", false).unwrap();
+        assert_eq!(unmangled(&cg, 1), vec!["a()"]);
+        assert_eq!(unmangled(&cg, 2), vec!["b()"]);
+    }
+
+    #[test]
+    fn names_interleaved_with_indirect_calls() {
+        // The function pointer node is added after everything else, and must
+        // not pick up a name meant for a declared function.
+        let text = "#1 a\nI 1 VARIABLE fp\n= 1 a()\n#2 b\nD 2 1\n= 2 b()\n";
+        let cg = load_text("indirect", text, false).unwrap();
+        assert_eq!(unmangled(&cg, 1), vec!["a()"]);
+        assert_eq!(unmangled(&cg, 2), vec!["b()"]);
+        let fp = cg.node_ids().find(|&idx| cg.graph[idx].starts_with("VARIABLE ")).unwrap();
+        assert!(cg.unmangled_names(fp).is_empty());
+    }
+
+    #[test]
+    fn calls_and_names_before_declarations_when_lenient() {
+        let cg = load_text("lenient", "D 1 2\n= 2 b()\n#1 a\n#2 b\n= 1 a()\n", true).unwrap();
+        assert_eq!(cg.graph[NodeIndex::new(2)], "b");
+        assert_eq!(unmangled(&cg, 2), vec!["b()"]);
+        assert_eq!(cg.edge_ids().count(), 1);
+    }
+
+    #[test]
+    fn calls_to_undeclared_functions() {
+        let error_line = |text : &str, lenient : bool| match load_text("dangling", text, lenient) {
+            Err(LoadError::FormatError(line, _)) => Some(line),
+            _ => None,
+        };
+        assert_eq!(error_line("#1 a\n#2 b\nD 1 3\n", false), Some(3));
+        assert_eq!(error_line("#1 a\nD 0 1\n", false), Some(2));
+        assert_eq!(error_line("#1 a\nI 2 VARIABLE fp\n", false), Some(2));
+
+        // Placeholders fill in for the missing functions when lenient, and
+        // keep a placeholder name if they are never declared.
+        let cg = load_text("dangling-lenient", "#1 a\nD 1 3\n", true).unwrap();
+        assert_eq!(cg.graph.node_count(), 4);
+        assert_eq!(cg.graph[NodeIndex::new(3)], "<undeclared #3>");
+        assert_eq!(cg.callees(NodeIndex::new(1)), vec![NodeIndex::new(3)]);
+
+        // A wildly out of range id is skipped instead.
+        let cg = load_text("dangling-huge", "#1 a\nD 1 4000000000\n", true).unwrap();
+        assert_eq!(cg.graph.node_count(), 2);
+        assert_eq!(cg.edge_ids().count(), 0);
+    }
+
+    #[test]
+    fn name_for_undeclared_function() {
+        assert!(load_text("undeclared", "#1 a\n= 2 b()\n", false).is_err());
+    }
+
+    #[test]
+    fn huge_name_id_is_rejected() {
+        let text = "#1 a\n= 1 a()\n= 400000000 b()\n";
+        match load_text("huge-strict", text, false) {
+            Err(LoadError::FormatError(3, _)) => {},
+            other => panic!("expected an error on line 3, got {:?}", other.map(|cg| cg.graph.node_count())),
+        }
+        // Skipped when lenient, without growing the name table to fit.
+        let cg = load_text("huge-lenient", text, true).unwrap();
+        assert!(cg.alt_names.len() <= cg.graph.node_count());
+        assert_eq!(unmangled(&cg, 1), vec!["a()"]);
+    }
+
+    #[test]
+    fn repeated_calls_are_counted() {
+        let text = "#1 a\n#2 b\n#3 c\nD 1 2\nD 1 3\nD 1 2\nD /1 1 2\nD 1 2 a.cpp:10\nD 1 2\nD 1 3\n";
+        let cg = load_text("repeats", text, false).unwrap();
+        let calls = |src : usize, dst : usize| -> Vec<(PropertySet, u32, bool)> {
+            let mut calls : Vec<_> = cg.edge_ids()
+                .filter(|&edge| cg.graph.edge_endpoints(edge) == Some((NodeIndex::new(src), NodeIndex::new(dst))))
+                .map(|edge| (cg.graph[edge], cg.call_count(edge), cg.call_sites.contains_key(&edge)))
+                .collect();
+            calls.sort();
+            calls
+        };
+        let plain = PropertySet { all: 0, any: 0 };
+        let suppressed = PropertySet { all: 1, any: 1 };
+        // Calls with a location are kept apart, so that each keeps its own.
+        assert_eq!(calls(1, 2), vec![(plain, 1, true), (plain, 3, false), (suppressed, 1, false)]);
+        assert_eq!(calls(1, 3), vec![(plain, 2, false)]);
+    }
+}
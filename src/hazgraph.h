@@ -0,0 +1,58 @@
+/* C interface to the callgraph traversal engine. Keep in sync with capi.rs.
+ *
+ * The library also contains the hazgraph Python extension module, so link
+ * against libpython2.7 as well (or load it with lazy symbol binding). */
+
+#ifndef HAZGRAPH_H
+#define HAZGRAPH_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct cg_graph cg_graph;
+
+/* A list of function ids. Free with cg_free_ids. */
+typedef struct {
+    uint32_t* ids;
+    size_t len;
+} cg_ids;
+
+/* Description of the last failure on this thread, or NULL. Owned by the
+ * library. Internal errors (bugs) are reported the same way, rather than
+ * aborting the process. */
+const char* cg_last_error(void);
+
+/* Returns NULL on failure. Prints nothing. */
+cg_graph* cg_load(const char* filename);
+void cg_free(cg_graph* cg);
+
+/* Functions have ids from 1 to cg_function_count(cg). Id 0 is not a
+ * function. */
+uint32_t cg_function_count(const cg_graph* cg);
+
+/* Same query syntax as the cgtraverse resolve command. */
+cg_ids cg_resolve(const cg_graph* cg, const char* query);
+cg_ids cg_callees(const cg_graph* cg, uint32_t id);
+cg_ids cg_callers(const cg_graph* cg, uint32_t id);
+
+/* Returns 1 and sets *out to the functions along the route (src first, dst
+ * last), 0 if there is no route, or -1 on error. avoid may be NULL if
+ * avoid_len is 0. */
+int cg_route(const cg_graph* cg, uint32_t src, uint32_t dst,
+             const uint32_t* avoid, size_t avoid_len, cg_ids* out);
+
+/* Mangled name, or NULL on error. Free with cg_free_string. */
+char* cg_name(const cg_graph* cg, uint32_t id);
+
+void cg_free_ids(cg_ids ids);
+void cg_free_string(char* s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HAZGRAPH_H */
@@ -0,0 +1,186 @@
+# Type stubs for the hazgraph extension module. Keep in sync with the
+# py_class! definitions (and install_defaults calls) in lib.rs; the
+# stubs_match_methods test there checks the names and parameters.
+
+from typing import Any, Callable, Dict, Iterator, List, Optional, Tuple, Union
+
+EdgeAttrs = Dict[str, int]
+Props = Union[int, List[str]]
+
+class HazGraph:
+    def __init__(
+        self,
+        filename: str,
+        line_limit: int = ...,
+        lenient: bool = ...,
+        demangle: bool = ...,
+        property_file: Optional[str] = ...,
+        progress: Optional[Callable[[int, int, int], Optional[bool]]] = ...,
+        exclude: Optional[str] = ...,
+        bypass_excluded: bool = ...,
+        avoid_file: Optional[str] = ...,
+    ) -> None: ...
+    @staticmethod
+    def empty() -> HazGraph: ...
+    @staticmethod
+    def load(filename: str) -> HazGraph: ...
+    @staticmethod
+    def snapshot_fingerprint(filename: str) -> str: ...
+    @staticmethod
+    def open_shared(filename: str) -> SharedHazGraph: ...
+    @staticmethod
+    def make_store(filename: str, out: str) -> Tuple[int, int]: ...
+    def save(self, filename: str) -> None: ...
+    def fingerprint(self) -> str: ...
+    def memory_report(self) -> List[Tuple[str, int]]: ...
+    def complete(self, prefix: str, limit: int) -> List[str]: ...
+    def add_function(self, name: str) -> int: ...
+    def add_unmangled_name(self, func: int, name: str) -> None: ...
+    def add_edge(self, src: int, dst: int, props: Props = ...) -> None: ...
+    def resolve(self, query: str) -> List[int]: ...
+    def explain_resolve(self, pattern: str) -> List[Tuple[int, str]]: ...
+    def query(self, expr: str) -> List[int]: ...
+    def define_goal_set(self, name: str, funcs: Union[str, List[int]]) -> List[int]: ...
+    def load_goal_sets(self, filename: str) -> Dict[str, List[int]]: ...
+    def load_entry_points(self, filename: str) -> Dict[Optional[str], List[Any]]: ...
+    def classify_roots(self) -> Dict[Optional[str], List[int]]: ...
+    def apply_rules(self, rules: str) -> Dict[str, List[int]]: ...
+    def search(self, pattern: str, regex: bool = ..., limit: Optional[int] = ...) -> List[int]: ...
+    def filter(self, ids: List[int], pattern: str, regex: bool = ..., negate: bool = ...) -> List[int]: ...
+    def callees(self, func: int) -> List[int]: ...
+    def callers(self, func: int) -> List[int]: ...
+    def callee_edges(self, func: int) -> List[Edge]: ...
+    def caller_edges(self, func: int) -> List[Edge]: ...
+    def grouped_callee_edges(self, func: int) -> List[Tuple[int, List[Edge]]]: ...
+    def route(self, src: int, goal: Union[int, List[int], str], avoid: List[int] = ..., avoid_props: Props = ..., matching: str = ..., clean: Props = ..., virtual_mode: Optional[str] = ..., options: Optional[Dict[str, Any]] = ...) -> List[int]: ...
+    def route_matrix(self, sources: Union[str, List[int]], goals: Union[str, List[int]], avoid: List[int] = ..., avoid_props: Props = ..., matching: str = ...) -> List[List[Optional[int]]]: ...
+    def alternative_routes(self, src: Union[int, str, List[int]], goal: Union[int, str, List[int]], avoid: List[int] = ..., avoid_props: Props = ..., matching: str = ..., count: int = ...) -> List[List[int]]: ...
+    def route_blockers(self, src: int, avoid: List[int] = ..., avoid_props: Props = ..., matching: str = ...) -> Dict[str, Any]: ...
+    def reach_diff(self, src: int, avoid_a: List[int], avoid_b: List[int], avoid_props_a: Props = ..., avoid_props_b: Props = ...) -> Dict[str, Any]: ...
+    def combined_props(self, route: List[int]) -> Tuple[int, int]: ...
+    def annotate_gc(self, sink_ids: List[int]) -> None: ...
+    def build_reach_index(self) -> None: ...
+    def can_gc(self, func: int) -> bool: ...
+    def gc_route(self, func: int) -> Optional[List[int]]: ...
+    def cycle_route(self, func: int, avoid: List[int] = ..., avoid_props: Props = ...) -> Optional[List[int]]: ...
+    def diff_routes(self, a: List[int], b: List[int]) -> List[Union[Tuple[str, List[int]], Tuple[str, List[int], List[int]]]]: ...
+    def gc_cut(self, entries: List[int], goal: Optional[List[int]] = ...) -> List[int]: ...
+    def collapse_route(self, route: List[int]) -> List[Union[int, Tuple[int, List[int]]]]: ...
+    def reachable(
+        self,
+        func: int,
+        direction: str = ...,
+        max_depth: Optional[int] = ...,
+        avoid: List[int] = ...,
+        avoid_props: Props = ...,
+    ) -> List[int]: ...
+    def components(self) -> List[Tuple[int, List[int]]]: ...
+    def ring(
+        self,
+        func: int,
+        hops: int,
+        direction: str = ...,
+        avoid: List[int] = ...,
+        avoid_props: Props = ...,
+    ) -> List[int]: ...
+    def namespace_matrix(self, exclude: List[int] = ...) -> Dict[str, Any]: ...
+    def expand(
+        self,
+        func: int,
+        depth: int,
+        direction: str = ...,
+        avoid: List[int] = ...,
+        avoid_props: Props = ...,
+    ) -> List[Tuple[int, int]]: ...
+    def impact(self, func: int) -> Dict[str, Any]: ...
+    def iter_reachable(
+        self,
+        func: int,
+        direction: str = ...,
+        max_depth: Optional[int] = ...,
+        avoid: List[int] = ...,
+        avoid_props: Props = ...,
+    ) -> HazGraphWalk: ...
+    def bfs(
+        self,
+        start: Union[int, List[int], str],
+        direction: str,
+        callback: Callable[[int, int, EdgeAttrs, int], Optional[str]],
+    ) -> List[int]: ...
+    def subgraph_between(self, sources: List[int], targets: List[int]) -> HazGraph: ...
+    def retain(self, pattern: Optional[str], negate: bool = ..., drop_props: Union[int, List[str]] = ...) -> HazGraph: ...
+    def trim_leaves(self, keep: Union[int, str, List[int]] = ...) -> HazGraph: ...
+    def only_reachable_via(self, props: Props) -> List[int]: ...
+    def sample_subgraph(self, keep_fraction: float, must_keep: Union[int, str, List[int]] = ...) -> HazGraph: ...
+    def save_text(self, filename: str) -> None: ...
+    def split_properties(self, props: int) -> Tuple[List[str], int]: ...
+    def display_name(self, func: int) -> str: ...
+    def describe(self, func: int, brevity: str = ...) -> str: ...
+    def describe_edge(self, edge: int, brevity: str = ...) -> str: ...
+    def describe_calls(self, edges: List[int], brevity: str = ...) -> str: ...
+    def set_name_format(self, template: Optional[str] = ...) -> str: ...
+    def names(self, func: int) -> List[str]: ...
+    def scope(self, func: int) -> Optional[str]: ...
+    def node_kind(self, func: int) -> str: ...
+    def indirects(self, pattern: Optional[str] = ...) -> List[Tuple[int, int]]: ...
+    def edges(self, subset: Optional[List[int]] = ...) -> List[Tuple[int, int, EdgeAttrs]]: ...
+    def edges_dataframe(self) -> Dict[str, List[int]]: ...
+    def nodes_dataframe(self) -> Dict[str, List[Any]]: ...
+    def diff(self, other: HazGraph) -> Dict[str, List[Any]]: ...
+    def link(self, other: HazGraph, rules: Optional[str] = ...) -> Tuple[HazGraph, Dict[str, int]]: ...
+    def to_networkx(self, subset: Optional[List[int]] = ...) -> Any: ...
+    def load_call_sites(self, filename: str) -> int: ...
+    def load_type_info(self, filename: str) -> int: ...
+    def set_virtual_mode(self, mode: Optional[str] = ...) -> str: ...
+    def set_seed(self, seed: Optional[int] = ...) -> None: ...
+    def seed(self) -> Optional[int]: ...
+    def set_legacy_roots(self, legacy: bool) -> None: ...
+    def load_annotations(self, filename: str) -> None: ...
+    def tag(self, func: int, tag: str) -> None: ...
+    def untag(self, func: int, tag: str) -> None: ...
+    def add_note(self, func: int, note: str) -> None: ...
+    def unannotate(self, func: int) -> None: ...
+    def annotated(self) -> List[int]: ...
+    def annotations_file(self) -> Optional[str]: ...
+    def annotations(self, func: int) -> Dict[str, List[str]]: ...
+    def __len__(self) -> int: ...
+    def __contains__(self, item: Union[int, str]) -> bool: ...
+    def __getitem__(self, func: int) -> Dict[str, Any]: ...
+    def __iter__(self) -> HazGraphIterator: ...
+
+class SharedHazGraph:
+    def __len__(self) -> int: ...
+    def __iter__(self) -> HazGraphIterator: ...
+    def name(self, func: int) -> str: ...
+    def describe_props(self, props: int) -> str: ...
+    def resolve(self, pattern: str, limit: int = ...) -> List[int]: ...
+    def names(self, func: int) -> List[str]: ...
+    def callees(self, func: int) -> List[Tuple[int, int]]: ...
+    def callers(self, func: int) -> List[Tuple[int, int]]: ...
+    def reachable(self, func: int, direction: str = ..., avoid_props: Props = ...) -> List[int]: ...
+    def route(self, src: int, goal: Union[int, List[int], str], avoid: List[int] = ..., avoid_props: Props = ..., matching: str = ...) -> List[int]: ...
+
+class Edge:
+    id: int
+    source: int
+    target: int
+    all: int
+    any: int
+    all_names: List[str]
+    any_names: List[str]
+    description: str
+    count: int
+    location: Optional[str]
+    def __repr__(self) -> str: ...
+
+class HazGraphIterator:
+    def __iter__(self) -> HazGraphIterator: ...
+    def __next__(self) -> int: ...
+    def next(self) -> int: ...
+
+class HazGraphWalk:
+    def __iter__(self) -> HazGraphWalk: ...
+    def __next__(self) -> int: ...
+    def next(self) -> int: ...
+
+def generate_stubs(filename: str) -> None: ...
@@ -1,21 +1,45 @@
+// The HazGraph constructor takes its load options as keyword arguments.
+#![allow(clippy::too_many_arguments)]
+
 mod hazard;
-use hazard::load_graph;
+use hazard::{load_graph_with_options, node_filter, LoadError, LoadOptions};
 
+mod annotations;
 mod callgraph;
-use callgraph::Callgraph;
+mod csr;
+mod reach;
+mod trie;
+mod query;
+mod rules;
+mod signature;
+mod namefmt;
+use callgraph::{AvoidConfig, Callgraph, DescriptionBrevity, Matcher, Direction, PropertyMatch, PropertySet, ReachableWalk, RouteDiff, RouteOptions, RouteStep, VirtualMode};
 
-#[macro_use]
-extern crate lazy_static;
+mod link;
+use link::{link, LinkRules};
+
+mod diff;
+use diff::diff_graphs;
+
+mod snapshot;
+use snapshot::{save_snapshot, load_snapshot, snapshot_fingerprint};
+
+mod capi;
+
+mod diskstore;
+use diskstore::DiskStore;
 
 #[macro_use]
 extern crate cpython;
 
-use cpython::{PyResult, PyErr};
+use cpython::{PyResult, PyErr, PyObject, PyDict, PyList, PyString, PyType, PyClone, Python, PythonObject, ObjectProtocol, ToPyObject, NoArgs};
+use cpython::_detail::ffi;
 use cpython::exc;
-use petgraph::graph::NodeIndex;
+use petgraph::graph::{NodeIndex, EdgeIndex};
 use std::cell;
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::iter::FromIterator;
+use std::sync::{Arc, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 // impl cpython::ToPyObject for NodeIndex {
 //     type ObjectType = PyInt;
@@ -24,70 +48,1871 @@ use std::iter::FromIterator;
 //     }
 // }
 
+fn load_error(py : Python, e : LoadError) -> PyErr {
+    match e {
+        LoadError::IOError(_) => PyErr::new::<exc::IOError, _>(py, e.to_string()),
+        LoadError::FormatError(..) | LoadError::BadSnapshot(_) => PyErr::new::<exc::ValueError, _>(py, e.to_string()),
+        LoadError::Aborted => PyErr::new::<exc::KeyboardInterrupt, _>(py, e.to_string()),
+    }
+}
+
+fn node_arg(py : Python, cg : &Callgraph, func : usize) -> PyResult<NodeIndex> {
+    cg.resolve_id(func).ok_or_else(|| PyErr::new::<exc::IndexError, _>(py, format!("invalid node id {}", func)))
+}
+
+fn node_args(py : Python, cg : &Callgraph, funcs : &[usize]) -> PyResult<Vec<NodeIndex>> {
+    funcs.iter().map(|&x| node_arg(py, cg, x)).collect()
+}
+
+fn direction_arg(py : Python, direction : &str) -> PyResult<Direction> {
+    match direction {
+        "callees" => Ok(Direction::Callees),
+        "callers" => Ok(Direction::Callers),
+        _ => Err(PyErr::new::<exc::ValueError, _>(py, "direction must be 'callees' or 'callers'"))
+    }
+}
+
+const ROUTE_OPTIONS : [&str; 9] = ["avoid", "avoid_props", "matching", "clean", "virtual_mode", "max_depth", "prefer_direct", "budget", "frequent"];
+
+fn virtual_mode_arg(py : Python, mode : &str) -> PyResult<VirtualMode> {
+    VirtualMode::parse(mode).ok_or_else(|| PyErr::new::<exc::ValueError, _>(py, "virtual mode must be 'conservative', 'refined' or 'ignore'"))
+}
+
+fn brevity_arg(py : Python, brevity : &str) -> PyResult<DescriptionBrevity> {
+    match brevity {
+        "brief" => Ok(DescriptionBrevity::_Brief),
+        "normal" => Ok(DescriptionBrevity::Normal),
+        "verbose" => Ok(DescriptionBrevity::Verbose),
+        _ => Err(PyErr::new::<exc::ValueError, _>(py, "brevity must be 'brief', 'normal' or 'verbose'"))
+    }
+}
+
+fn matching_arg(py : Python, matching : &str) -> PyResult<PropertyMatch> {
+    match matching {
+        "all" => Ok(PropertyMatch::All),
+        "any" => Ok(PropertyMatch::Any),
+        _ => Err(PyErr::new::<exc::ValueError, _>(py, "matching must be 'all' or 'any'"))
+    }
+}
+
+fn property_names_arg(py : Python, cg : &Callgraph, names : &[String]) -> PyResult<u32> {
+    let mut props = 0;
+    for name in names {
+        match cg.resolve_property(name) {
+            Some(bit) => props |= bit,
+            None => return Err(PyErr::new::<exc::ValueError, _>(py, format!("unknown property '{}'", name)))
+        }
+    }
+    Ok(props)
+}
+
+// Accept either a single node id or a list of them.
+// An id, a list of ids, or a pattern such as "@GC".
+fn ids_arg(py : Python, cg : &Callgraph, obj : &PyObject) -> PyResult<Vec<NodeIndex>> {
+    if let Ok(func) = obj.extract::<usize>(py) {
+        Ok(vec![node_arg(py, cg, func)?])
+    } else if let Ok(pattern) = obj.extract::<String>(py) {
+        cg.resolve(&pattern).ok_or_else(|| PyErr::new::<exc::KeyError, _>(py, format!("nothing matches '{}'", pattern)))
+    } else {
+        node_args(py, cg, &obj.extract::<Vec<usize>>(py)?)
+    }
+}
+
+// Accept either a property bitmask or a list of property names.
+fn props_arg(py : Python, cg : &Callgraph, obj : &PyObject) -> PyResult<u32> {
+    if let Ok(bits) = obj.extract::<u32>(py) {
+        Ok(bits)
+    } else {
+        property_names_arg(py, cg, &obj.extract::<Vec<String>>(py)?)
+    }
+}
+
+fn edge_attrs(py : Python, cg : &Callgraph, edge : EdgeIndex) -> PyResult<PyDict> {
+    let attrs = PyDict::new(py);
+    attrs.set_item(py, "all", cg.graph[edge].all)?;
+    attrs.set_item(py, "any", cg.graph[edge].any)?;
+    if let Some(location) = cg.call_sites.get(&edge) {
+        attrs.set_item(py, "location", location)?;
+    }
+    attrs.set_item(py, "count", cg.call_count(edge))?;
+    Ok(attrs)
+}
+
+// Edges as (src, dst, attrs) triples, restricted to edges with both endpoints
+// in `subset` if given.
+fn edge_triples(py : Python, cg : &Callgraph, subset : &Option<Vec<usize>>) -> PyResult<Vec<(usize, usize, PyDict)>> {
+    let subset : Option<HashSet<usize>> = subset.as_ref().map(|v| v.iter().cloned().collect());
+    let mut result = vec![];
+    for edge in cg.edge_ids() {
+        let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+        if let Some(nodes) = &subset {
+            if !nodes.contains(&src.index()) || !nodes.contains(&dst.index()) {
+                continue;
+            }
+        }
+        result.push((src.index(), dst.index(), edge_attrs(py, cg, edge)?));
+    }
+    Ok(result)
+}
+
 py_class!(class HazGraph |py| {
-    data callgraph: cell::RefCell<Callgraph>;
+    data callgraph: Arc<RwLock<Callgraph>>;
+
+    def __new__(
+        _cls,
+        filename: &str,
+        line_limit: u32,
+        lenient: bool,
+        demangle: bool,
+        property_file: Option<String>,
+        progress: Option<PyObject>,
+        exclude: Option<String>,
+        bypass_excluded: bool,
+        avoid_file: Option<String>
+    ) -> PyResult<HazGraph> {
+        let node_filter = match exclude {
+            Some(pattern) => Some(node_filter(&pattern).map_err(|e| PyErr::new::<exc::ValueError, _>(py, e))?),
+            None => None,
+        };
+        let options = LoadOptions { line_limit, lenient, demangle, property_file, node_filter, bypass_filtered: bypass_excluded, avoid_file, quiet: false };
+        let loaded = match progress {
+            // Loading without a callback can take a while, so let other
+            // Python threads run in the meantime.
+            None => py.allow_threads(|| load_graph_with_options(filename, &options, None)),
+            Some(progress) => {
+                // An exception from the callback aborts the load and is
+                // reraised in place of the generic abort error.
+                let mut raised = None;
+                let mut callback = |lines : u32, bytes : u64, total : u64| {
+                    match progress.call(py, (lines, bytes, total), None) {
+                        // Returning False stops the load; None carries on.
+                        Ok(result) => result == py.None() || result.is_true(py).unwrap_or(true),
+                        Err(e) => {
+                            raised = Some(e);
+                            false
+                        }
+                    }
+                };
+                let loaded = load_graph_with_options(filename, &options, Some(&mut callback));
+                if let Some(e) = raised {
+                    return Err(e);
+                }
+                loaded
+            }
+        };
+        let callgraph = loaded.map_err(|e| load_error(py, e))?;
+        HazGraph::create_instance(py, Arc::new(RwLock::new(callgraph)))
+    }
+
+    @staticmethod def empty() -> PyResult<HazGraph> {
+        HazGraph::create_instance(py, Arc::new(RwLock::new(Callgraph::new())))
+    }
+
+    def add_function(&self, name: &str) -> PyResult<usize> {
+        let mut cg = self.write(py);
+        Ok(cg.add_function(name).index())
+    }
+
+    def add_unmangled_name(&self, func: usize, name: &str) -> PyResult<PyObject> {
+        let mut cg = self.write(py);
+        let idx = node_arg(py, &cg, func)?;
+        cg.add_unmangled_name(idx, name);
+        Ok(py.None())
+    }
+
+    def add_edge(&self, src: usize, dst: usize, props: PyObject) -> PyResult<PyObject> {
+        let mut cg = self.write(py);
+        let src = node_arg(py, &cg, src)?;
+        let dst = node_arg(py, &cg, dst)?;
+        let props = props_arg(py, &cg, &props)?;
+        cg.add_edge(src, dst, PropertySet { all: props, any: props });
+        Ok(py.None())
+    }
+
+    def save(&self, filename: &str) -> PyResult<PyObject> {
+        let cg = self.read(py);
+        save_snapshot(&cg, filename).map_err(|e| load_error(py, e))?;
+        Ok(py.None())
+    }
+
+    @staticmethod def load(filename: &str) -> PyResult<HazGraph> {
+        let callgraph = load_snapshot(filename).map_err(|e| load_error(py, e))?;
+        HazGraph::create_instance(py, Arc::new(RwLock::new(callgraph)))
+    }
+
+    // Without loading it, to check whether a cached snapshot is of the graph
+    // wanted.
+    @staticmethod def snapshot_fingerprint(filename: &str) -> PyResult<String> {
+        let fingerprint = snapshot_fingerprint(filename).map_err(|e| load_error(py, e))?;
+        Ok(format!("{:016x}", fingerprint))
+    }
+
+    // Hash of the functions, calls and properties, as 16 hex digits. Equal
+    // for identical graphs, including function ids.
+    def fingerprint(&self) -> PyResult<String> {
+        let cg = self.read(py);
+        let cg : &Callgraph = &cg;
+        Ok(format!("{:016x}", py.allow_threads(|| cg.fingerprint())))
+    }
+
+    // Approximate bytes used by each part of the graph, as the REPL's "mem"
+    // command shows them.
+    def memory_report(&self) -> PyResult<Vec<(String, usize)>> {
+        let cg = self.read(py);
+        Ok(cg.memory_report().into_iter().map(|(what, bytes)| (what.to_string(), bytes)).collect())
+    }
+
+    // Up to `limit` function names (stems or qualified names) starting with
+    // `prefix`, for completion.
+    def complete(&self, prefix: &str, limit: usize) -> PyResult<Vec<String>> {
+        let cg = self.read(py);
+        Ok(cg.complete(prefix, limit))
+    }
 
-    def __new__(_cls, filename: &str) -> PyResult<HazGraph> {
-        let callgraph = load_graph(filename, 0);
-        let callgraph = callgraph.unwrap();
-        HazGraph::create_instance(py, cell::RefCell::new(callgraph))
+    // A read-only graph store (see make_store) mapped into memory rather than
+    // loaded, so any number of worker processes can query one copy of it.
+    // A snapshot (see save) is converted to a store the first time, kept
+    // next to it as <filename>.store, and that is opened instead.
+    @staticmethod def open_shared(filename: &str) -> PyResult<SharedHazGraph> {
+        let store_file = if diskstore::is_store(filename) {
+            filename.to_string()
+        } else if snapshot::is_snapshot(filename) {
+            py.allow_threads(|| snapshot_store(filename)).map_err(|e| load_error(py, e))?
+        } else {
+            return Err(PyErr::new::<exc::ValueError, _>(py, format!("{} is not a graph store or snapshot; convert it with make_store", filename)));
+        };
+        let store = py.allow_threads(|| DiskStore::open(&store_file)).map_err(|e| load_error(py, e))?;
+        SharedHazGraph::create_instance(py, Arc::new(store))
+    }
+
+    // Convert callgraph.txt, or a snapshot, into a store for open_shared.
+    // callgraph.txt is converted without loading it. Returns (functions,
+    // calls).
+    @staticmethod def make_store(filename: &str, out: &str) -> PyResult<(usize, usize)> {
+        let result = if snapshot::is_snapshot(filename) {
+            py.allow_threads(|| snapshot::build_store(filename, out))
+        } else {
+            py.allow_threads(|| diskstore::build(filename, out))
+        };
+        result.map_err(|e| load_error(py, e))
     }
 
     def resolve(&self, query: &str) -> PyResult<Vec<usize>> {
-        let cg = self.callgraph(py).borrow();
-        if &query[0..1] == "#" {
-            match query[1..].parse::<usize>() {
+        let cg = self.read(py);
+        if let Some(id) = query.strip_prefix('#') {
+            match id.parse::<usize>() {
                 Ok(n) => {
-                    Ok(vec![n])
+                    Ok(vec![node_arg(py, &cg, n)?.index()])
                 },
                 Err(_) =>
                     Err(PyErr::new::<exc::ValueError, _>(py, "invalid node id"))
             }
         } else {
-            match cg.resolve(query) {
+            // Regex and substring matches scan every name, so let other
+            // Python threads run in the meantime.
+            let cg : &Callgraph = &cg;
+            match py.allow_threads(|| cg.resolve(query)) {
                 None => Ok(vec![]),
                 Some(matches) => Ok(matches.iter().map(|&x| x.index()).collect())
             }
         }
     }
 
+    // (id, rule) for each function resolve(pattern) matches, eg
+    // "substring of unmangled name #2".
+    def explain_resolve(&self, pattern: &str) -> PyResult<Vec<(usize, String)>> {
+        let cg = self.read(py);
+        let cg : &Callgraph = &cg;
+        Ok(py.allow_threads(|| cg.explain_resolve(pattern)).unwrap_or_default().into_iter().map(|(idx, rule)| (idx.index(), rule)).collect())
+    }
+
+    def query(&self, expr: &str) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let cg : &Callgraph = &cg;
+        match py.allow_threads(|| cg.query(expr)) {
+            Ok(matches) => Ok(matches.iter().map(|&x| x.index()).collect()),
+            Err(e) => Err(PyErr::new::<exc::ValueError, _>(py, e)),
+        }
+    }
+
+    // `funcs` is a query (see query.rs) or a list of ids. The set is then
+    // usable as "@name" anywhere a pattern is, eg as a route goal.
+    def define_goal_set(&self, name: &str, funcs: PyObject) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let defined = if let Ok(spec) = funcs.extract::<String>(py) {
+            cg.define_goal_set(name, &spec)
+        } else {
+            let ids = node_args(py, &cg, &funcs.extract::<Vec<usize>>(py)?)?;
+            cg.define_goal_set(name, &ids.iter().map(|idx| format!("#{}", idx.index())).collect::<Vec<_>>().join(" | "))
+        };
+        match defined {
+            Ok(funcs) => Ok(funcs.iter().map(|idx| idx.index()).collect()),
+            Err(e) => Err(PyErr::new::<exc::ValueError, _>(py, e)),
+        }
+    }
+
+    def load_goal_sets(&self, filename: &str) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let defined = match cg.load_goal_sets(filename) {
+            Ok(defined) => defined,
+            Err(e) => return Err(PyErr::new::<exc::IOError, _>(py, e)),
+        };
+        let result = PyDict::new(py);
+        for (name, funcs) in defined {
+            let ids : Vec<usize> = funcs.iter().map(|idx| idx.index()).collect();
+            result.set_item(py, name, ids)?;
+        }
+        Ok(result)
+    }
+
+    // Entry points by kind from a file of [kind] sections, each also defined
+    // as @<kind>-entrypoints. Patterns that match nothing are listed under
+    // None.
+    def load_entry_points(&self, filename: &str) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let entry_points = cg.load_entry_points(filename).map_err(|e| PyErr::new::<exc::IOError, _>(py, e))?;
+        let result = PyDict::new(py);
+        for (kind, funcs) in entry_points.kinds {
+            result.set_item(py, kind, funcs.iter().map(|idx| idx.index()).collect::<Vec<_>>())?;
+        }
+        result.set_item(py, py.None(), entry_points.unmatched)?;
+        Ok(result)
+    }
+
+    // The roots by kind of entry point, with those of no known kind under
+    // None.
+    def classify_roots(&self) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let classes = cg.classify_roots();
+        let result = PyDict::new(py);
+        for (kind, roots) in classes.kinds {
+            result.set_item(py, kind, roots.iter().map(|idx| idx.index()).collect::<Vec<_>>())?;
+        }
+        result.set_item(py, py.None(), classes.unclassified.iter().map(|idx| idx.index()).collect::<Vec<_>>())?;
+        Ok(result)
+    }
+
+    def apply_rules(&self, rules: &str) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let cg : &Callgraph = &cg;
+        let derived = match py.allow_threads(|| cg.apply_rules(rules)) {
+            Ok(derived) => derived,
+            Err(e) => return Err(PyErr::new::<exc::ValueError, _>(py, e)),
+        };
+        let result = PyDict::new(py);
+        for (name, funcs) in derived {
+            let ids : Vec<usize> = funcs.iter().map(|idx| idx.index()).collect();
+            result.set_item(py, name, ids)?;
+        }
+        Ok(result)
+    }
+
+    def search(&self, pattern: &str, regex: bool, limit: Option<usize>) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let cg : &Callgraph = &cg;
+        let pattern = if regex { format!("/{}/", pattern) } else { pattern.to_string() };
+        let mut matches : Vec<usize> = match py.allow_threads(|| cg.resolve(&pattern)) {
+            None => vec![],
+            Some(matches) => matches.iter().map(|&x| x.index()).collect()
+        };
+        if let Some(limit) = limit {
+            matches.truncate(limit);
+        }
+        Ok(matches)
+    }
+
+    // Keep the functions with a name containing `pattern` (or matching it, as
+    // a regex), or without, if `negate`. "#tag:NAME" instead matches the
+    // functions tagged NAME.
+    def filter(&self, ids: Vec<usize>, pattern: &str, regex: bool, negate: bool) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let matcher = if regex { Matcher::regex(pattern) } else { Matcher::new(pattern) };
+        let matcher = match matcher {
+            Some(m) => m,
+            None => return Err(PyErr::new::<exc::ValueError, _>(py, "invalid regex"))
+        };
+        let mut result = vec![];
+        for func in ids {
+            if matcher.is_match(&cg, node_arg(py, &cg, func)?) != negate {
+                result.push(func);
+            }
+        }
+        Ok(result)
+    }
+
     def callees(&self, func: usize) -> PyResult<Vec<usize>> {
-        let cg = self.callgraph(py).borrow();
-        let callees = cg.callees(NodeIndex::new(func));
+        let cg = self.read(py);
+        let callees = cg.callees(node_arg(py, &cg, func)?);
         Ok(callees.iter().map(|&x| x.index()).collect())
     }
 
     def callers(&self, func: usize) -> PyResult<Vec<usize>> {
-        let cg = self.callgraph(py).borrow();
-        let callers = cg.callers(NodeIndex::new(func));
+        let cg = self.read(py);
+        let callers = cg.callers(node_arg(py, &cg, func)?);
         Ok(callers.iter().map(|&x| x.index()).collect())
     }
 
-    def route(&self, src: usize, goal: Vec<usize>, avoid: Vec<usize>, avoid_props: u32) -> PyResult<Vec<usize>> {
-        let cg = self.callgraph(py).borrow();
-        let src = NodeIndex::new(src);
-        let goal : Vec<NodeIndex> = goal.iter().map(|&x| NodeIndex::new(x)).collect();
-        let goal = HashSet::from_iter(goal);
-        let avoid : Vec<NodeIndex> = avoid.iter().map(|&x| NodeIndex::new(x)).collect();
-        let avoid = HashSet::from_iter(avoid);
+    // The calls out of (or into) a function as Edge objects.
+    def callee_edges(&self, func: usize) -> PyResult<Vec<Edge>> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        cg.callee_edges(idx).into_iter().map(|edge| make_edge(py, &cg, edge)).collect()
+    }
 
-        match cg.any_route(src, &goal, &avoid, avoid_props) {
-            None => Ok(vec![]),
-            Some(route) => Ok(route.iter().map(|&x| x.index()).collect())
+    def caller_edges(&self, func: usize) -> PyResult<Vec<Edge>> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        cg.caller_edges(idx).into_iter().map(|edge| make_edge(py, &cg, edge)).collect()
+    }
+
+    // callee_edges grouped by callee, as (callee, edges) in the order each
+    // callee is first called, so a function called from several places is
+    // listed once.
+    def grouped_callee_edges(&self, func: usize) -> PyResult<Vec<(usize, Vec<Edge>)>> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        cg.grouped_callee_edges(idx).into_iter().map(|(callee, edges)| {
+            let edges = edges.into_iter().map(|edge| make_edge(py, &cg, edge)).collect::<PyResult<Vec<Edge>>>()?;
+            Ok((callee.index(), edges))
+        }).collect()
+    }
+
+    // `matching` is "all" to skip calls only when every call site has one of
+    // `avoid_props`, or "any" to skip them when any call site does. Calls
+    // with any of `clean` at any call site are never used. `virtual_mode`
+    // applies to this route only; see set_virtual_mode.
+    // With `options`, a dict of any of the keyword arguments (which it
+    // overrides) plus max_depth (the most calls to allow), prefer_direct (the
+    // cost of an indirect or virtual call, where a direct one costs 1) and
+    // budget (how many functions to visit before raising RuntimeError), the
+    // cheapest route by those costs rather than the shortest. A true
+    // `frequent` option instead prefers calls repeated most often in the
+    // input, as the REPL's "route ... frequent" does, and cannot be combined
+    // with those three or with `clean`.
+    def route(&self, src: usize, goal: PyObject, avoid: Vec<usize>, avoid_props: PyObject, matching: &str, clean: PyObject, virtual_mode: Option<String>, options: Option<PyDict>) -> PyResult<Vec<usize>> {
+        if let Some(options) = &options {
+            for (key, _) in options.items(py) {
+                let key = key.extract::<String>(py)?;
+                if !ROUTE_OPTIONS.contains(&key.as_str()) {
+                    return Err(PyErr::new::<exc::ValueError, _>(py, format!("unknown route option '{}'", key)));
+                }
+            }
         }
+        // Present and not None.
+        let option = |name : &str| options.as_ref().and_then(|o| o.get_item(py, name)).filter(|value| *value != py.None());
+        let virtual_mode = match option("virtual_mode") {
+            Some(mode) => Some(mode.extract::<String>(py)?),
+            None => virtual_mode,
+        };
+        let cg = self.read(py);
+        let cg : &Callgraph = &cg;
+        let mode = match virtual_mode {
+            Some(name) => virtual_mode_arg(py, &name)?,
+            None => cg.virtual_mode(),
+        };
+        let src = node_arg(py, cg, src)?;
+        let goal = HashSet::from_iter(ids_arg(py, cg, &goal)?);
+        let avoid = match option("avoid") {
+            Some(avoid) => avoid.extract::<Vec<usize>>(py)?,
+            None => avoid,
+        };
+        let avoid = HashSet::from_iter(node_args(py, cg, &avoid)?);
+        let avoid_props = props_arg(py, cg, &option("avoid_props").unwrap_or(avoid_props))?;
+        let matching = match option("matching") {
+            Some(matching) => matching_arg(py, &matching.extract::<String>(py)?)?,
+            None => matching_arg(py, matching)?,
+        };
+        let clean = props_arg(py, cg, &option("clean").unwrap_or(clean))?;
+
+        let frequent = option("frequent").map(|f| f.is_true(py)).transpose()?.unwrap_or(false);
+
+        let route = if options.is_none() {
+            py.allow_threads(|| cg.clean_route(&[src], &goal, &avoid, avoid_props, matching, clean, mode))
+        } else if frequent {
+            if clean != 0 || ["max_depth", "prefer_direct", "budget"].iter().any(|name| option(name).is_some()) {
+                return Err(PyErr::new::<exc::ValueError, _>(py, "frequent cannot be combined with clean, max_depth, prefer_direct or budget"));
+            }
+            // Each call costs the most repeated call's count minus its own,
+            // plus one.
+            let most = cg.call_counts.values().cloned().max().unwrap_or(1);
+            py.allow_threads(|| cg.weighted_route(&[src], &goal, &avoid, avoid_props, matching, mode, |edge| (most - cg.call_count(edge) + 1) as u64))
+        } else {
+            let options = RouteOptions {
+                avoid: AvoidConfig { functions: avoid, props: avoid_props },
+                matching,
+                clean,
+                max_depth: option("max_depth").map(|n| n.extract::<usize>(py)).transpose()?,
+                indirect_cost: option("prefer_direct").map(|n| n.extract::<u64>(py)).transpose()?.unwrap_or(1),
+                budget: option("budget").map(|n| n.extract::<usize>(py)).transpose()?,
+                virtual_mode: Some(mode),
+            };
+            match py.allow_threads(|| cg.route_with_options(&[src], &goal, &options)) {
+                Ok(route) => route,
+                Err(visited) => return Err(PyErr::new::<exc::RuntimeError, _>(py, format!("gave up after visiting {} functions", visited))),
+            }
+        };
+        Ok(route.map_or(vec![], |route| route.iter().map(|&x| x.index()).collect()))
+    }
+
+    // The shortest route and up to `count` alternatives, as edge ids.
+    def alternative_routes(&self, src: PyObject, goal: PyObject, avoid: Vec<usize>, avoid_props: PyObject, matching: &str, count: usize) -> PyResult<Vec<Vec<usize>>> {
+        let cg = self.read(py);
+        let src = ids_arg(py, &cg, &src)?;
+        let goal = HashSet::from_iter(ids_arg(py, &cg, &goal)?);
+        let avoid = HashSet::from_iter(node_args(py, &cg, &avoid)?);
+        let avoid_props = props_arg(py, &cg, &avoid_props)?;
+        let matching = matching_arg(py, matching)?;
+        let cg : &Callgraph = &cg;
+        let routes = py.allow_threads(|| cg.alternative_routes(&src, &goal, &avoid, avoid_props, matching, count));
+        Ok(routes.into_iter().map(|route| route.iter().map(|x| x.index()).collect()).collect())
+    }
+
+    // Shortest route length from each of `sources` to each of `goals`, as a
+    // list of rows with None where there is no route.
+    def route_matrix(&self, sources: PyObject, goals: PyObject, avoid: Vec<usize>, avoid_props: PyObject, matching: &str) -> PyResult<Vec<Vec<Option<u32>>>> {
+        let cg = self.read(py);
+        let sources = ids_arg(py, &cg, &sources)?;
+        let goals = ids_arg(py, &cg, &goals)?;
+        let avoid = HashSet::from_iter(node_args(py, &cg, &avoid)?);
+        let avoid_props = props_arg(py, &cg, &avoid_props)?;
+        let matching = matching_arg(py, matching)?;
+        let cg : &Callgraph = &cg;
+        Ok(py.allow_threads(|| cg.route_matrix(&sources, &goals, &avoid, avoid_props, matching)))
+    }
+
+    // Why route() from `src` found nothing: how far the search got, and how
+    // many calls each avoided function and property bit cut it off at, most
+    // first.
+    def route_blockers(&self, src: usize, avoid: Vec<usize>, avoid_props: PyObject, matching: &str) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let src = node_arg(py, &cg, src)?;
+        let avoid = HashSet::from_iter(node_args(py, &cg, &avoid)?);
+        let avoid_props = props_arg(py, &cg, &avoid_props)?;
+        let matching = matching_arg(py, matching)?;
+        let cg : &Callgraph = &cg;
+        let blockers = py.allow_threads(|| cg.route_blockers(&[src], &avoid, avoid_props, matching, cg.virtual_mode()));
+        let result = PyDict::new(py);
+        result.set_item(py, "reached", blockers.reached)?;
+        result.set_item(py, "functions", blockers.functions.iter().map(|&(idx, count)| (idx.index(), count)).collect::<Vec<_>>())?;
+        result.set_item(py, "properties", blockers.properties)?;
+        Ok(result)
+    }
+
+    // What reaching out from src gains and loses when switching from
+    // avoiding (avoid_a, avoid_props_a) to (avoid_b, avoid_props_b).
+    def reach_diff(&self, src: usize, avoid_a: Vec<usize>, avoid_b: Vec<usize>, avoid_props_a: PyObject, avoid_props_b: PyObject) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let src = node_arg(py, &cg, src)?;
+        let a = AvoidConfig { functions: HashSet::from_iter(node_args(py, &cg, &avoid_a)?), props: props_arg(py, &cg, &avoid_props_a)? };
+        let b = AvoidConfig { functions: HashSet::from_iter(node_args(py, &cg, &avoid_b)?), props: props_arg(py, &cg, &avoid_props_b)? };
+        let cg : &Callgraph = &cg;
+        let diff = py.allow_threads(|| cg.reach_diff(&[src], &a, &b));
+        let result = PyDict::new(py);
+        result.set_item(py, "reached_a", diff.reached_a)?;
+        result.set_item(py, "reached_b", diff.reached_b)?;
+        result.set_item(py, "lost", diff.lost.iter().map(|idx| idx.index()).collect::<Vec<_>>())?;
+        result.set_item(py, "gained", diff.gained.iter().map(|idx| idx.index()).collect::<Vec<_>>())?;
+        Ok(result)
+    }
+
+    // (all, any) for a route: the properties every call on it has at every
+    // call site, and those that some call might have.
+    def combined_props(&self, route: Vec<usize>) -> PyResult<(u32, u32)> {
+        let cg = self.read(py);
+        if let Some(&bad) = route.iter().find(|&&e| e >= cg.graph.edge_count()) {
+            return Err(PyErr::new::<exc::IndexError, _>(py, format!("invalid edge id {}", bad)));
+        }
+        let route : Vec<EdgeIndex> = route.into_iter().map(EdgeIndex::new).collect();
+        let props = cg.combined_props(&route);
+        Ok((props.all, props.any))
+    }
+
+    // The edges of a route with each run of calls inside one recursion cycle
+    // replaced by (number of functions in the cycle, [edges]).
+    def collapse_route(&self, route: Vec<usize>) -> PyResult<Vec<PyObject>> {
+        let cg = self.read(py);
+        if let Some(&bad) = route.iter().find(|&&e| e >= cg.graph.edge_count()) {
+            return Err(PyErr::new::<exc::IndexError, _>(py, format!("invalid edge id {}", bad)));
+        }
+        let route : Vec<EdgeIndex> = route.into_iter().map(EdgeIndex::new).collect();
+        let cg : &Callgraph = &cg;
+        let steps = py.allow_threads(|| cg.collapse_route(&route));
+        Ok(steps.into_iter().map(|step| match step {
+            RouteStep::Call(edge) => edge.index().to_py_object(py).into_object(),
+            RouteStep::Cycle { functions, calls } => {
+                let calls : Vec<usize> = calls.iter().map(|e| e.index()).collect();
+                (functions, calls).to_py_object(py).into_object()
+            },
+        }).collect())
+    }
+
+    // Two routes (edge ids) lined up: a list of ("same", [ids]) and
+    // ("diverged", [ids on a], [ids on b]).
+    def diff_routes(&self, a: Vec<usize>, b: Vec<usize>) -> PyResult<Vec<PyObject>> {
+        let cg = self.read(py);
+        if let Some(&bad) = a.iter().chain(&b).find(|&&e| e >= cg.graph.edge_count()) {
+            return Err(PyErr::new::<exc::IndexError, _>(py, format!("invalid edge id {}", bad)));
+        }
+        let a : Vec<EdgeIndex> = a.into_iter().map(EdgeIndex::new).collect();
+        let b : Vec<EdgeIndex> = b.into_iter().map(EdgeIndex::new).collect();
+        let ids = |nodes : Vec<NodeIndex>| -> Vec<usize> { nodes.iter().map(|x| x.index()).collect() };
+        Ok(cg.diff_routes(&a, &b).into_iter().map(|segment| match segment {
+            RouteDiff::Same(nodes) => ("same", ids(nodes)).to_py_object(py).into_object(),
+            RouteDiff::Diverged { first, second } => ("diverged", ids(first), ids(second)).to_py_object(py).into_object(),
+        }).collect())
+    }
+
+    // Edges that would need to be GC-suppressed for none of `entries` to reach
+    // `goal` (by default, the functions passed to annotate_gc), as few as
+    // possible.
+    def gc_cut(&self, entries: Vec<usize>, goal: Option<Vec<usize>>) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let entries = node_args(py, &cg, &entries)?;
+        let goal = match goal {
+            Some(goal) => HashSet::from_iter(node_args(py, &cg, &goal)?),
+            None => cg.gc_functions.clone(),
+        };
+        let cg : &Callgraph = &cg;
+        match py.allow_threads(|| cg.gc_cut(&entries, &goal, &|| false)) {
+            Ok(cut) => Ok(cut.iter().map(|&x| x.index()).collect()),
+            Err(e) => Err(PyErr::new::<exc::ValueError, _>(py, e)),
+        }
+    }
+
+    // Edges of a shortest route from `func` back to itself, or None if it is
+    // not part of a cycle.
+    def cycle_route(&self, func: usize, avoid: Vec<usize>, avoid_props: PyObject) -> PyResult<Option<Vec<usize>>> {
+        let cg = self.read(py);
+        let origin = node_arg(py, &cg, func)?;
+        let avoid = HashSet::from_iter(node_args(py, &cg, &avoid)?);
+        let avoid_props = props_arg(py, &cg, &avoid_props)?;
+        let cg : &Callgraph = &cg;
+        let route = py.allow_threads(|| cg.cycle_route(origin, &avoid, avoid_props));
+        Ok(route.map(|edges| edges.iter().map(|&x| x.index()).collect()))
+    }
+
+    // Mark functions as GCing directly, for can_gc and gc_route.
+    def annotate_gc(&self, sink_ids: Vec<usize>) -> PyResult<PyObject> {
+        let mut cg = self.write(py);
+        let sinks = node_args(py, &cg, &sink_ids)?;
+        cg.annotate_gc(&sinks);
+        Ok(py.None())
+    }
+
+    def build_reach_index(&self) -> PyResult<PyObject> {
+        let mut cg = self.write(py);
+        let cg : &mut Callgraph = &mut cg;
+        py.allow_threads(|| cg.build_reach_index());
+        Ok(py.None())
+    }
+
+    def can_gc(&self, func: usize) -> PyResult<bool> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        let cg : &Callgraph = &cg;
+        Ok(py.allow_threads(|| cg.can_gc(idx)))
+    }
+
+    // Edges of a route to some GC function, or None if it cannot GC.
+    def gc_route(&self, func: usize) -> PyResult<Option<Vec<usize>>> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        let cg : &Callgraph = &cg;
+        let route = py.allow_threads(|| cg.gc_route(idx));
+        Ok(route.map(|edges| edges.iter().map(|&x| x.index()).collect()))
+    }
+
+    def reachable(&self, func: usize, direction: &str, max_depth: Option<usize>, avoid: Vec<usize>, avoid_props: PyObject) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let origin = node_arg(py, &cg, func)?;
+        let direction = direction_arg(py, direction)?;
+        let avoid = HashSet::from_iter(node_args(py, &cg, &avoid)?);
+        let avoid_props = props_arg(py, &cg, &avoid_props)?;
+        let cg : &Callgraph = &cg;
+        let reached = py.allow_threads(|| cg.reachable(&[origin], direction, max_depth, &avoid, avoid_props));
+        Ok(reached.iter().map(|&x| x.index()).collect())
+    }
+
+    // (representative, functions) for each weakly connected component,
+    // largest first.
+    def components(&self) -> PyResult<Vec<(usize, Vec<usize>)>> {
+        let cg = self.read(py);
+        let cg : &Callgraph = &cg;
+        let components = py.allow_threads(|| cg.components());
+        Ok(components.into_iter().map(|c| (c.representative.index(), c.functions.iter().map(|x| x.index()).collect())).collect())
+    }
+
+    // Functions and calls by top-level namespace: "namespaces" is a list of
+    // (namespace, functions), largest first, and "calls" of (caller index,
+    // callee index, calls) into that.
+    def namespace_matrix(&self, exclude: Vec<usize>) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let exclude = HashSet::from_iter(node_args(py, &cg, &exclude)?);
+        let cg : &Callgraph = &cg;
+        let matrix = py.allow_threads(|| cg.namespace_matrix(&exclude));
+        let result = PyDict::new(py);
+        result.set_item(py, "namespaces", matrix.namespaces)?;
+        result.set_item(py, "calls", matrix.calls.into_iter().map(|((i, j), count)| (i, j, count)).collect::<Vec<_>>())?;
+        Ok(result)
+    }
+
+    // Functions exactly `hops` calls away from `func`.
+    def ring(&self, func: usize, hops: usize, direction: &str, avoid: Vec<usize>, avoid_props: PyObject) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let origin = node_arg(py, &cg, func)?;
+        let direction = direction_arg(py, direction)?;
+        let avoid = HashSet::from_iter(node_args(py, &cg, &avoid)?);
+        let avoid_props = props_arg(py, &cg, &avoid_props)?;
+        Ok(cg.ring(origin, direction, hops, &avoid, avoid_props).iter().map(|&x| x.index()).collect())
+    }
+
+    // (id, distance) for everything within `depth` calls of `func`, each
+    // after the function it was first reached from.
+    def expand(&self, func: usize, depth: usize, direction: &str, avoid: Vec<usize>, avoid_props: PyObject) -> PyResult<Vec<(usize, usize)>> {
+        let cg = self.read(py);
+        let origin = node_arg(py, &cg, func)?;
+        let direction = direction_arg(py, direction)?;
+        let avoid = HashSet::from_iter(node_args(py, &cg, &avoid)?);
+        let avoid_props = props_arg(py, &cg, &avoid_props)?;
+        Ok(cg.expand(origin, direction, depth, &avoid, avoid_props).iter().map(|&(idx, distance)| (idx.index(), distance)).collect())
+    }
+
+    // Transitive callers of `func`, their counts by top-level namespace, and
+    // the roots that reach it.
+    def impact(&self, func: usize) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        let cg : &Callgraph = &cg;
+        let impact = py.allow_threads(|| cg.impact(idx));
+        let result = PyDict::new(py);
+        result.set_item(py, "callers", impact.callers.iter().map(|x| x.index()).collect::<Vec<_>>())?;
+        result.set_item(py, "namespaces", impact.namespaces)?;
+        result.set_item(py, "roots", impact.roots.iter().map(|x| x.index()).collect::<Vec<_>>())?;
+        Ok(result)
+    }
+
+    // Like reachable, but returns an iterator producing functions as they are
+    // found, so a huge result can be consumed (or abandoned) incrementally.
+    def iter_reachable(&self, func: usize, direction: &str, max_depth: Option<usize>, avoid: Vec<usize>, avoid_props: PyObject) -> PyResult<HazGraphWalk> {
+        let cg = self.read(py);
+        let origin = node_arg(py, &cg, func)?;
+        let direction = direction_arg(py, direction)?;
+        let avoid = HashSet::from_iter(node_args(py, &cg, &avoid)?);
+        let avoid_props = props_arg(py, &cg, &avoid_props)?;
+        let walk = ReachableWalk::new(&[origin], direction, max_depth, avoid, avoid_props);
+        HazGraphWalk::create_instance(py, self.callgraph(py).clone(), cell::RefCell::new(walk))
+    }
+
+    // Call `callback(src, dst, attrs, depth)` for each function reached. The
+    // callback may return "skip" to avoid expanding below that function,
+    // "ignore" to act as if that call weren't there, or "stop" to end the
+    // traversal. Returns the visited ids in order. As Callgraph::bfs, but the
+    // graph is only locked while looking up each function's calls, so the
+    // callback may modify it; later steps see the changes.
+    def bfs(&self, start: PyObject, direction: &str, callback: PyObject) -> PyResult<Vec<usize>> {
+        let (origins, direction) = {
+            let cg = self.read(py);
+            (ids_arg(py, &cg, &start)?, direction_arg(py, direction)?)
+        };
+        let mut visited = vec![];
+        let mut seen : HashSet<NodeIndex> = origins.iter().cloned().collect();
+        let mut work : VecDeque<(NodeIndex, usize)> = origins.iter().map(|&idx| (idx, 0)).collect();
+        while let Some((src, depth)) = work.pop_front() {
+            let calls : Vec<(NodeIndex, PropertySet)> = {
+                let cg = self.read(py);
+                cg.neighbor_edges(src, direction).map(|(dst, _, props)| (dst, props)).collect()
+            };
+            for (dst, props) in calls {
+                if seen.contains(&dst) { continue; }
+                visited.push(dst.index());
+                let attrs = PyDict::new(py);
+                attrs.set_item(py, "all", props.all)?;
+                attrs.set_item(py, "any", props.any)?;
+                let ret = callback.call(py, (src.index(), dst.index(), attrs, depth + 1), None)?;
+                match ret.extract::<String>(py).as_ref().map(|s| s.as_str()) {
+                    Ok("stop") => return Ok(visited),
+                    Ok("skip") => (),
+                    Ok("ignore") => {
+                        visited.pop();
+                        continue;
+                    },
+                    _ => work.push_back((dst, depth + 1)),
+                }
+                seen.insert(dst);
+            }
+        }
+        Ok(visited)
+    }
+
+    def subgraph_between(&self, sources: Vec<usize>, targets: Vec<usize>) -> PyResult<HazGraph> {
+        let cg = self.read(py);
+        let sources = node_args(py, &cg, &sources)?;
+        let targets = node_args(py, &cg, &targets)?;
+        let cg : &Callgraph = &cg;
+        let sub = py.allow_threads(|| cg.subgraph_between(&sources, &targets));
+        HazGraph::create_instance(py, Arc::new(RwLock::new(sub)))
+    }
+
+    // Copy with only the functions matching `pattern` (or with negate, only
+    // the ones that don't), minus any calls with one of `drop_props`.
+    def retain(&self, pattern: Option<String>, negate: bool, drop_props: PyObject) -> PyResult<HazGraph> {
+        let cg = self.read(py);
+        let drop_props = props_arg(py, &cg, &drop_props)?;
+        let matcher = match &pattern {
+            Some(pattern) => Some(Matcher::new(pattern).ok_or_else(|| PyErr::new::<exc::ValueError, _>(py, format!("invalid pattern '{}'", pattern)))?),
+            None => None,
+        };
+        let cg : &Callgraph = &cg;
+        let sub = py.allow_threads(|| {
+            let sub = match &matcher {
+                Some(matcher) => cg.retain(|idx| matcher.is_match(cg, idx) != negate),
+                None => cg.retain(|_| true),
+            };
+            if drop_props == 0 { sub } else { sub.retain_calls(|edge| sub.graph[edge].any & drop_props == 0) }
+        });
+        HazGraph::create_instance(py, Arc::new(RwLock::new(sub)))
+    }
+
+    // Copy with leaf functions repeatedly removed, except for `keep` and the
+    // GC functions.
+    def trim_leaves(&self, keep: PyObject) -> PyResult<HazGraph> {
+        let cg = self.read(py);
+        let keep : HashSet<NodeIndex> = ids_arg(py, &cg, &keep)?.into_iter().collect();
+        let cg : &Callgraph = &cg;
+        let sub = py.allow_threads(|| cg.trim_leaves(&keep));
+        HazGraph::create_instance(py, Arc::new(RwLock::new(sub)))
+    }
+
+    // Functions the roots can only reach through a call with one of `props`
+    // at every call site, eg those only called while GC is suppressed.
+    def only_reachable_via(&self, props: PyObject) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let props = props_arg(py, &cg, &props)?;
+        let cg : &Callgraph = &cg;
+        Ok(py.allow_threads(|| cg.only_reachable_via(props)).into_iter().map(|idx| idx.index()).collect())
+    }
+
+    // A copy with about `keep_fraction` of the functions, always including
+    // `must_keep` and a shortest route between each two of them, for drawing.
+    def sample_subgraph(&self, keep_fraction: f64, must_keep: PyObject) -> PyResult<HazGraph> {
+        let cg = self.read(py);
+        let must_keep = ids_arg(py, &cg, &must_keep)?;
+        let cg : &Callgraph = &cg;
+        let sub = py.allow_threads(|| cg.sample_subgraph(keep_fraction, &must_keep));
+        HazGraph::create_instance(py, Arc::new(RwLock::new(sub)))
+    }
+
+    // Write the graph in the callgraph.txt format.
+    def save_text(&self, filename: &str) -> PyResult<PyObject> {
+        let cg = self.read(py);
+        let result = std::fs::File::create(filename).and_then(|file| {
+            let mut out = std::io::BufWriter::new(file);
+            cg.write_text(&mut out)?;
+            std::io::Write::flush(&mut out)
+        });
+        result.map_err(|e| load_error(py, LoadError::IOError(e)))?;
+        Ok(py.None())
+    }
+
+    // The names of the properties in `props`, and a mask of any bits with no
+    // name.
+    def split_properties(&self, props: u32) -> PyResult<(Vec<String>, u32)> {
+        let cg = self.read(py);
+        let (known, unknown) = cg.split_property_set(props);
+        Ok((known.into_iter().map(String::from).collect(), unknown))
+    }
+
+    // The function's one-line description, following the name format.
+    def display_name(&self, func: usize) -> PyResult<String> {
+        let cg = self.read(py);
+        Ok(cg.name(node_arg(py, &cg, func)?, DescriptionBrevity::Normal))
+    }
+
+    // The function as the REPL shows it: "brief" is the mangled name,
+    // "verbose" adds every unmangled name and any annotations on their own
+    // lines.
+    def describe(&self, func: usize, brevity: &str) -> PyResult<String> {
+        let cg = self.read(py);
+        Ok(cg.name(node_arg(py, &cg, func)?, brevity_arg(py, brevity)?))
+    }
+
+    // A call as the REPL shows it in routes: the callee, then the call's
+    // properties, location and count.
+    def describe_edge(&self, edge: usize, brevity: &str) -> PyResult<String> {
+        let cg = self.read(py);
+        if edge >= cg.graph.edge_count() {
+            return Err(PyErr::new::<exc::IndexError, _>(py, format!("invalid edge id {}", edge)));
+        }
+        Ok(cg.describe_edge(EdgeIndex::new(edge), brevity_arg(py, brevity)?))
+    }
+
+    // Several calls between the same two functions (eg a group from
+    // grouped_callee_edges) as one line: the callee once, then each call's
+    // properties and location.
+    def describe_calls(&self, edges: Vec<usize>, brevity: &str) -> PyResult<String> {
+        let cg = self.read(py);
+        let mut calls = vec![];
+        for edge in edges {
+            if edge >= cg.graph.edge_count() {
+                return Err(PyErr::new::<exc::IndexError, _>(py, format!("invalid edge id {}", edge)));
+            }
+            calls.push(EdgeIndex::new(edge));
+        }
+        let endpoints = match calls.first() {
+            Some(&edge) => cg.graph.edge_endpoints(edge),
+            None => return Err(PyErr::new::<exc::ValueError, _>(py, "no calls to describe")),
+        };
+        if calls.iter().any(|&edge| cg.graph.edge_endpoints(edge) != endpoints) {
+            return Err(PyErr::new::<exc::ValueError, _>(py, "calls must all be between the same two functions"));
+        }
+        Ok(cg.describe_calls(&calls, brevity_arg(py, brevity)?))
+    }
+
+    // Template for display_name and descriptions (see namefmt.rs), or None
+    // for the default.
+    def set_name_format(&self, template: Option<String>) -> PyResult<String> {
+        let cg = self.read(py);
+        cg.set_name_format(template.as_deref()).map_err(|e| PyErr::new::<exc::ValueError, _>(py, e))?;
+        Ok(cg.name_format())
     }
 
     def names(&self, func: usize) -> PyResult<Vec<String>> {
-        let cg = self.callgraph(py).borrow();
-        let names = cg.names(NodeIndex::new(func));
+        let cg = self.read(py);
+        let names = cg.names(node_arg(py, &cg, func)?);
         Ok(names.iter().map(|&x| x.to_string()).collect())
     }
 
+    // The class or namespace of a function, from its unmangled name.
+    def scope(&self, func: usize) -> PyResult<Option<String>> {
+        let cg = self.read(py);
+        Ok(cg.scope(node_arg(py, &cg, func)?).map(|s| s.to_string()))
+    }
+
+    // "direct", "indirect" (a function pointer variable) or "virtual".
+    def node_kind(&self, func: usize) -> PyResult<&'static str> {
+        let cg = self.read(py);
+        Ok(cg.node_kind(node_arg(py, &cg, func)?).name())
+    }
+
+    // (id, number of callers) for each indirect call target matching
+    // `pattern`, most called first.
+    def indirects(&self, pattern: Option<String>) -> PyResult<Vec<(usize, usize)>> {
+        let cg = self.read(py);
+        let matcher = match &pattern {
+            Some(pattern) => Some(Matcher::new(pattern).ok_or_else(|| PyErr::new::<exc::ValueError, _>(py, format!("invalid pattern '{}'", pattern)))?),
+            None => None,
+        };
+        Ok(cg.indirect_targets(matcher.as_ref()).into_iter().map(|(idx, callers)| (idx.index(), callers)).collect())
+    }
+
+    // Call site locations from lines of "src dst file:line".
+    def load_call_sites(&self, filename: &str) -> PyResult<usize> {
+        let mut cg = self.write(py);
+        cg.load_call_sites(filename).map_err(|e| PyErr::new::<exc::IOError, _>(py, e))
+    }
+
+    // Class hierarchy for the "refined" virtual mode, from a JSON file with a
+    // "Subclasses" table.
+    def load_type_info(&self, filename: &str) -> PyResult<usize> {
+        let mut cg = self.write(py);
+        cg.load_type_info(filename).map_err(|e| PyErr::new::<exc::IOError, _>(py, e))
+    }
+
+    // "conservative", "refined" or "ignore": which methods a virtual call may
+    // reach in every later query. Returns the mode in effect.
+    def set_virtual_mode(&self, mode: Option<String>) -> PyResult<String> {
+        // The write lock keeps the switch from landing in the middle of
+        // another thread's query.
+        let cg = self.write(py);
+        if let Some(mode) = mode {
+            cg.set_virtual_mode(virtual_mode_arg(py, &mode)?);
+        }
+        Ok(cg.virtual_mode().name().to_string())
+    }
+
+    // Visit calls in id order, so that ties between equally good answers are
+    // broken the same way across runs and load orders, with `seed` for
+    // anything random. None goes back to the order the calls were loaded in.
+    def set_seed(&self, seed: Option<u64>) -> PyResult<PyObject> {
+        let mut cg = self.write(py);
+        cg.set_seed(seed);
+        Ok(py.None())
+    }
+
+    def seed(&self) -> PyResult<Option<u64>> {
+        Ok(self.read(py).seed())
+    }
+
+    // Pick roots and sinks the way older versions did: whichever function of
+    // a cycle a depth-first walk comes back to first, rather than the lowest
+    // numbered one of each cycle nothing outside it calls.
+    def set_legacy_roots(&self, legacy: bool) -> PyResult<PyObject> {
+        let mut cg = self.write(py);
+        cg.set_legacy_roots(legacy);
+        Ok(py.None())
+    }
+
+    def load_annotations(&self, filename: &str) -> PyResult<PyObject> {
+        let cg = self.read(py);
+        cg.load_annotations(std::path::Path::new(filename))
+            .map_err(|e| PyErr::new::<exc::IOError, _>(py, e.to_string()))?;
+        Ok(py.None())
+    }
+
+    def tag(&self, func: usize, tag: &str) -> PyResult<PyObject> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        cg.annotations_mut().add_tag(&cg.graph[idx], tag)
+            .map_err(|e| PyErr::new::<exc::IOError, _>(py, e.to_string()))?;
+        Ok(py.None())
+    }
+
+    def untag(&self, func: usize, tag: &str) -> PyResult<PyObject> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        cg.annotations_mut().remove_tag(&cg.graph[idx], tag)
+            .map_err(|e| PyErr::new::<exc::IOError, _>(py, e.to_string()))?;
+        Ok(py.None())
+    }
+
+    def add_note(&self, func: usize, note: &str) -> PyResult<PyObject> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        cg.annotations_mut().add_note(&cg.graph[idx], note)
+            .map_err(|e| PyErr::new::<exc::IOError, _>(py, e.to_string()))?;
+        Ok(py.None())
+    }
+
+    // Remove every tag and note from the function.
+    def unannotate(&self, func: usize) -> PyResult<PyObject> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        cg.annotations_mut().clear(&cg.graph[idx])
+            .map_err(|e| PyErr::new::<exc::IOError, _>(py, e.to_string()))?;
+        Ok(py.None())
+    }
+
+    // The functions with any annotations, by id.
+    def annotated(&self) -> PyResult<Vec<usize>> {
+        let cg = self.read(py);
+        let annotations = cg.annotations();
+        let names : HashSet<&str> = annotations.names().collect();
+        Ok(cg.node_ids().filter(|&idx| names.contains(cg.graph[idx].as_str())).map(|idx| idx.index()).collect())
+    }
+
+    // The file annotations are saved to, if any.
+    def annotations_file(&self) -> PyResult<Option<String>> {
+        let cg = self.read(py);
+        let path = cg.annotations().path().map(|path| path.display().to_string());
+        Ok(path)
+    }
+
+    def annotations(&self, func: usize) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let idx = node_arg(py, &cg, func)?;
+        let annotation = cg.annotations().get(&cg.graph[idx]).cloned().unwrap_or_default();
+        let result = PyDict::new(py);
+        result.set_item(py, "tags", annotation.tags)?;
+        result.set_item(py, "notes", annotation.notes)?;
+        Ok(result)
+    }
+
+    def __len__(&self) -> PyResult<usize> {
+        let cg = self.read(py);
+        Ok(cg.node_ids().count())
+    }
+
+    def __contains__(&self, item: PyObject) -> PyResult<bool> {
+        let cg = self.read(py);
+        if let Ok(func) = item.extract::<usize>(py) {
+            Ok(cg.resolve_id(func).is_some())
+        } else if let Ok(name) = item.extract::<String>(py) {
+            Ok(cg.lookup_name(&name).is_some())
+        } else {
+            Ok(false)
+        }
+    }
+
+    def __getitem__(&self, func: usize) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let idx = match cg.resolve_id(func) {
+            Some(idx) => idx,
+            None => return Err(PyErr::new::<exc::IndexError, _>(py, "invalid node id")),
+        };
+        let info = PyDict::new(py);
+        info.set_item(py, "id", func)?;
+        info.set_item(py, "mangled", cg.graph[idx].as_str())?;
+        info.set_item(py, "unmangled", cg.unmangled_names(idx).to_vec())?;
+        Ok(info)
+    }
+
+    def __iter__(&self) -> PyResult<HazGraphIterator> {
+        let cg = self.read(py);
+        let ids : Vec<usize> = cg.node_ids().map(|idx| idx.index()).collect();
+        HazGraphIterator::create_instance(py, cell::RefCell::new(ids.into_iter()))
+    }
+
+    def edges(&self, subset: Option<Vec<usize>>) -> PyResult<Vec<(usize, usize, PyDict)>> {
+        let cg = self.read(py);
+        edge_triples(py, &cg, &subset)
+    }
+
+    // Column-oriented exports, suitable for passing straight to
+    // pandas.DataFrame() without a Python-level loop over every edge.
+    def edges_dataframe(&self) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let (mut src, mut dst, mut all, mut any) = (vec![], vec![], vec![], vec![]);
+        for edge in cg.edge_ids() {
+            let (s, d) = cg.graph.edge_endpoints(edge).unwrap();
+            src.push(s.index());
+            dst.push(d.index());
+            all.push(cg.graph[edge].all);
+            any.push(cg.graph[edge].any);
+        }
+        let columns = PyDict::new(py);
+        columns.set_item(py, "src", src)?;
+        columns.set_item(py, "dst", dst)?;
+        columns.set_item(py, "all", all)?;
+        columns.set_item(py, "any", any)?;
+        Ok(columns)
+    }
+
+    def nodes_dataframe(&self) -> PyResult<PyDict> {
+        let cg = self.read(py);
+        let mut out_degree = vec![0usize; cg.graph.node_count()];
+        let mut in_degree = vec![0usize; cg.graph.node_count()];
+        for edge in cg.edge_ids() {
+            let (s, d) = cg.graph.edge_endpoints(edge).unwrap();
+            out_degree[s.index()] += 1;
+            in_degree[d.index()] += 1;
+        }
+        let (mut ids, mut mangled, mut unmangled, mut callees, mut callers) = (vec![], vec![], vec![], vec![], vec![]);
+        for idx in cg.node_ids() {
+            ids.push(idx.index());
+            mangled.push(cg.graph[idx].as_str());
+            unmangled.push(cg.unmangled_names(idx).first().map(|s| s.as_str()));
+            callees.push(out_degree[idx.index()]);
+            callers.push(in_degree[idx.index()]);
+        }
+        let columns = PyDict::new(py);
+        columns.set_item(py, "id", ids)?;
+        columns.set_item(py, "mangled", mangled)?;
+        columns.set_item(py, "unmangled", unmangled)?;
+        columns.set_item(py, "callees", callees)?;
+        columns.set_item(py, "callers", callers)?;
+        Ok(columns)
+    }
+
+    // Functions and edges added or removed in `other` relative to this graph,
+    // keyed by mangled name.
+    def diff(&self, other: HazGraph) -> PyResult<PyDict> {
+        let old = self.read(py);
+        let new = other.read(py);
+        let (old, new) : (&Callgraph, &Callgraph) = (&old, &new);
+        let diff = py.allow_threads(|| diff_graphs(old, new));
+        let edge_tuples = |edges : Vec<(String, String, PropertySet)>| -> Vec<(String, String, u32, u32)> {
+            edges.into_iter().map(|(src, dst, props)| (src, dst, props.all, props.any)).collect()
+        };
+        let result = PyDict::new(py);
+        result.set_item(py, "added_functions", diff.added_functions)?;
+        result.set_item(py, "removed_functions", diff.removed_functions)?;
+        result.set_item(py, "added_edges", edge_tuples(diff.added_edges))?;
+        result.set_item(py, "removed_edges", edge_tuples(diff.removed_edges))?;
+        Ok(result)
+    }
+
+    // One graph holding both this one and `other`, joined by BRIDGE edges as
+    // described by `rules` (JSON text; see link.rs), and a dict with the id
+    // `other`'s functions start at ("offset") and the number of "bridges".
+    // This graph's functions keep their ids.
+    def link(&self, other: HazGraph, rules: Option<String>) -> PyResult<(HazGraph, PyDict)> {
+        let rules = match rules {
+            Some(text) => LinkRules::parse(&text).map_err(|e| PyErr::new::<exc::ValueError, _>(py, e))?,
+            None => LinkRules::default(),
+        };
+        let first = self.read(py);
+        let second = other.read(py);
+        let (first, second) : (&Callgraph, &Callgraph) = (&first, &second);
+        let (linked, report) = py.allow_threads(|| link(first, second, &rules))
+            .map_err(|e| PyErr::new::<exc::ValueError, _>(py, e))?;
+        let summary = PyDict::new(py);
+        summary.set_item(py, "offset", report.offset)?;
+        summary.set_item(py, "bridges", report.bridges)?;
+        Ok((HazGraph::create_instance(py, Arc::new(RwLock::new(linked)))?, summary))
+    }
+
+    def to_networkx(&self, subset: Option<Vec<usize>>) -> PyResult<PyObject> {
+        let cg = self.read(py);
+        let nodes : Vec<NodeIndex> = match &subset {
+            Some(ids) => node_args(py, &cg, ids)?,
+            None => cg.node_ids().collect(),
+        };
+        let nx = py.import("networkx")?;
+        let graph = nx.call(py, "MultiDiGraph", NoArgs, None)?;
+        for idx in nodes {
+            let attrs = PyDict::new(py);
+            attrs.set_item(py, "mangled", cg.graph[idx].as_str())?;
+            attrs.set_item(py, "unmangled", cg.unmangled_names(idx).to_vec())?;
+            graph.call_method(py, "add_node", (idx.index(),), Some(&attrs))?;
+        }
+        for (src, dst, attrs) in edge_triples(py, &cg, &subset)? {
+            graph.call_method(py, "add_edge", (src, dst), Some(&attrs))?;
+        }
+        Ok(graph)
+    }
+
     // Err(PyErr::new::<exc::TypeError, _>(py, "unimplemented"))
 });
 
+// The graph is shared between Python threads, and queries run with the GIL
+// released. Locks are always acquired with the GIL released too, so that a
+// thread blocked on the lock never prevents the holder from reacquiring the
+// GIL and finishing.
+impl HazGraph {
+    fn read<'a>(&'a self, py : Python<'a>) -> RwLockReadGuard<'a, Callgraph> {
+        let lock = self.callgraph(py);
+        py.allow_threads(|| lock.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn write<'a>(&'a self, py : Python<'a>) -> RwLockWriteGuard<'a, Callgraph> {
+        let lock = self.callgraph(py);
+        py.allow_threads(|| lock.write().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+py_class!(class HazGraphIterator |py| {
+    data ids: cell::RefCell<std::vec::IntoIter<usize>>;
+
+    def __iter__(&self) -> PyResult<HazGraphIterator> {
+        Ok(self.clone_ref(py))
+    }
+
+    def __next__(&self) -> PyResult<Option<usize>> {
+        Ok(self.ids(py).borrow_mut().next())
+    }
+});
+
+fn store_node_arg(py : Python, store : &DiskStore, func : usize) -> PyResult<NodeIndex> {
+    if func > 0 && func <= store.function_count() {
+        Ok(NodeIndex::new(func))
+    } else {
+        Err(PyErr::new::<exc::IndexError, _>(py, format!("invalid node id {}", func)))
+    }
+}
+
+// A function id, a pattern for resolve(), or a list of ids.
+fn store_ids_arg(py : Python, store : &DiskStore, obj : &PyObject) -> PyResult<Vec<NodeIndex>> {
+    if let Ok(func) = obj.extract::<usize>(py) {
+        Ok(vec![store_node_arg(py, store, func)?])
+    } else if let Ok(pattern) = obj.extract::<String>(py) {
+        let found = store_resolve(py, store, &pattern, usize::MAX)?;
+        if found.is_empty() {
+            return Err(PyErr::new::<exc::KeyError, _>(py, format!("nothing matches '{}'", pattern)));
+        }
+        Ok(found)
+    } else {
+        obj.extract::<Vec<usize>>(py)?.into_iter().map(|func| store_node_arg(py, store, func)).collect()
+    }
+}
+
+// DiskStore::resolve, but with an error for an invalid regex rather than
+// matching nothing.
+fn store_resolve(py : Python, store : &DiskStore, pattern : &str, limit : usize) -> PyResult<Vec<NodeIndex>> {
+    if let Some(re) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+        if let Err(e) = regex::Regex::new(re) {
+            return Err(PyErr::new::<exc::ValueError, _>(py, format!("invalid regex /{}/: {}", re, e)));
+        }
+    }
+    py.allow_threads(|| store.resolve(pattern, limit)).map_err(|e| load_error(py, e))
+}
+
+fn store_props_arg(py : Python, store : &DiskStore, obj : &PyObject) -> PyResult<u32> {
+    if let Ok(bits) = obj.extract::<u32>(py) {
+        return Ok(bits);
+    }
+    let mut bits = 0;
+    for name in obj.extract::<Vec<String>>(py)? {
+        bits |= store.resolve_property(&name).ok_or_else(|| PyErr::new::<exc::ValueError, _>(py, format!("unknown property '{}'", name)))?;
+    }
+    Ok(bits)
+}
+
+// Opening a snapshot shared: the store converted from it, made now if there
+// isn't one newer than the snapshot. Written under a temporary name and
+// renamed into place, so other processes opening it at the same time never
+// see half a store.
+fn snapshot_store(filename : &str) -> Result<String, LoadError> {
+    let store_file = format!("{}.store", filename);
+    let modified = |name : &str| std::fs::metadata(name).and_then(|m| m.modified()).ok();
+    let current = match (modified(&store_file), modified(filename)) {
+        (Some(store), Some(snapshot)) => store >= snapshot && diskstore::is_store(&store_file),
+        _ => false,
+    };
+    if !current {
+        let temp = format!("{}.{}.tmp", store_file, std::process::id());
+        let built = snapshot::build_store(filename, &temp).and_then(|_| Ok(std::fs::rename(&temp, &store_file)?));
+        if built.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        built?;
+    }
+    Ok(store_file)
+}
+
+// The queries a graph store can answer, from HazGraph.open_shared. Nothing is
+// loaded: each query reads the pages of the store it needs.
+py_class!(class SharedHazGraph |py| {
+    data store: Arc<DiskStore>;
+
+    def __len__(&self) -> PyResult<usize> {
+        Ok(self.store(py).function_count())
+    }
+
+    def __iter__(&self) -> PyResult<HazGraphIterator> {
+        let ids : Vec<usize> = self.store(py).node_ids().map(|idx| idx.index()).collect();
+        HazGraphIterator::create_instance(py, cell::RefCell::new(ids.into_iter()))
+    }
+
+    // "#N = name", for display.
+    def name(&self, func: usize) -> PyResult<String> {
+        let store = self.store(py);
+        store.name(store_node_arg(py, store, func)?).map_err(|e| load_error(py, e))
+    }
+
+    def describe_props(&self, props: u32) -> PyResult<String> {
+        Ok(self.store(py).describe_props(PropertySet { all: props, any: props }))
+    }
+
+    // As HazGraph.resolve, but returning at most `limit` functions.
+    def resolve(&self, pattern: &str, limit: usize) -> PyResult<Vec<usize>> {
+        let found = store_resolve(py, self.store(py), pattern, limit)?;
+        Ok(found.into_iter().map(|idx| idx.index()).collect())
+    }
+
+    // Mangled name first, then any unmangled ones.
+    def names(&self, func: usize) -> PyResult<Vec<String>> {
+        let store = self.store(py);
+        let names = store.names(store_node_arg(py, store, func)?).map_err(|e| load_error(py, e))?;
+        Ok(names.into_iter().map(String::from).collect())
+    }
+
+    def callees(&self, func: usize) -> PyResult<Vec<(usize, u32)>> {
+        let store = self.store(py);
+        let neighbors = store.neighbors(store_node_arg(py, store, func)?, Direction::Callees).map_err(|e| load_error(py, e))?;
+        Ok(neighbors.into_iter().map(|(idx, props)| (idx.index(), props.any)).collect())
+    }
+
+    def callers(&self, func: usize) -> PyResult<Vec<(usize, u32)>> {
+        let store = self.store(py);
+        let neighbors = store.neighbors(store_node_arg(py, store, func)?, Direction::Callers).map_err(|e| load_error(py, e))?;
+        Ok(neighbors.into_iter().map(|(idx, props)| (idx.index(), props.any)).collect())
+    }
+
+    def reachable(&self, func: usize, direction: &str, avoid_props: PyObject) -> PyResult<Vec<usize>> {
+        let store : &DiskStore = self.store(py);
+        let idx = store_node_arg(py, store, func)?;
+        let direction = match direction {
+            "callees" => Direction::Callees,
+            "callers" => Direction::Callers,
+            _ => return Err(PyErr::new::<exc::ValueError, _>(py, "direction must be 'callees' or 'callers'")),
+        };
+        let avoid_props = store_props_arg(py, store, &avoid_props)?;
+        let reached = py.allow_threads(|| store.reachable(&[idx], direction, avoid_props, &|| false)).map_err(|e| load_error(py, e))?;
+        Ok(reached.into_iter().map(|idx| idx.index()).collect())
+    }
+
+    // The functions along a shortest route from `src` to one of `goal`, or []
+    // if there is none. The arguments are as for HazGraph.route.
+    def route(&self, src: usize, goal: PyObject, avoid: Vec<usize>, avoid_props: PyObject, matching: &str) -> PyResult<Vec<usize>> {
+        let store : &DiskStore = self.store(py);
+        let src = store_node_arg(py, store, src)?;
+        let goal = store_ids_arg(py, store, &goal)?;
+        let avoid = avoid.into_iter().map(|func| store_node_arg(py, store, func)).collect::<PyResult<Vec<_>>>()?;
+        let avoid_props = store_props_arg(py, store, &avoid_props)?;
+        let matching = matching_arg(py, matching)?;
+        let route = py.allow_threads(|| store.route(src, &goal, &avoid, avoid_props, matching, &|| false)).map_err(|e| load_error(py, e))?;
+        Ok(route.unwrap_or_default().into_iter().map(|(idx, _)| idx.index()).collect())
+    }
+});
+
+pub struct EdgeInfo {
+    id : usize,
+    source : usize,
+    target : usize,
+    props : PropertySet,
+    all_names : Vec<String>,
+    any_names : Vec<String>,
+    description : String,
+    count : u32,
+    location : Option<String>,
+}
+
+fn property_list(cg : &Callgraph, bits : u32) -> Vec<String> {
+    cg.describe_property_set(bits).split(',').filter(|s| !s.is_empty()).map(String::from).collect()
+}
+
+fn make_edge(py : Python, cg : &Callgraph, edge : EdgeIndex) -> PyResult<Edge> {
+    let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+    let props = cg.graph[edge];
+    Edge::create_instance(py, EdgeInfo {
+        id: edge.index(),
+        source: src.index(),
+        target: dst.index(),
+        props,
+        all_names: property_list(cg, props.all),
+        any_names: property_list(cg, props.any),
+        description: cg.describe_edge(edge, DescriptionBrevity::Normal),
+        count: cg.call_count(edge),
+        location: cg.call_sites.get(&edge).cloned(),
+    })
+}
+
+// A call, as of when it was looked up. The accessors are turned into
+// read-only properties at import time (see install_properties).
+py_class!(class Edge |py| {
+    data info: EdgeInfo;
+
+    def id(&self) -> PyResult<usize> { Ok(self.info(py).id) }
+    def source(&self) -> PyResult<usize> { Ok(self.info(py).source) }
+    def target(&self) -> PyResult<usize> { Ok(self.info(py).target) }
+    def all(&self) -> PyResult<u32> { Ok(self.info(py).props.all) }
+    def any(&self) -> PyResult<u32> { Ok(self.info(py).props.any) }
+    def all_names(&self) -> PyResult<Vec<String>> { Ok(self.info(py).all_names.clone()) }
+    def any_names(&self) -> PyResult<Vec<String>> { Ok(self.info(py).any_names.clone()) }
+    def description(&self) -> PyResult<String> { Ok(self.info(py).description.clone()) }
+    def count(&self) -> PyResult<u32> { Ok(self.info(py).count) }
+    def location(&self) -> PyResult<Option<String>> { Ok(self.info(py).location.clone()) }
+
+    def __repr__(&self) -> PyResult<PyString> {
+        let info = self.info(py);
+        Ok(PyString::new(py, &format!("<Edge {}: #{} -> {}>", info.id, info.source, info.description)))
+    }
+});
+
+const EDGE_PROPERTIES : &[&str] = &["id", "source", "target", "all", "any", "all_names", "any_names", "description", "count", "location"];
+
+// Lazily produces the results of HazGraph.iter_reachable, taking the graph's
+// lock only for each step.
+py_class!(class HazGraphWalk |py| {
+    data callgraph: Arc<RwLock<Callgraph>>;
+    data walk: cell::RefCell<ReachableWalk>;
+
+    def __iter__(&self) -> PyResult<HazGraphWalk> {
+        Ok(self.clone_ref(py))
+    }
+
+    def __next__(&self) -> PyResult<Option<usize>> {
+        let lock = self.callgraph(py);
+        let cg = py.allow_threads(|| lock.read().unwrap_or_else(|e| e.into_inner()));
+        Ok(self.walk(py).borrow_mut().next(&cg).map(|idx| idx.index()))
+    }
+});
+
+// py_class! methods cannot declare optional parameters, so methods that want
+// Python-style defaults are replaced at import time with a small Python
+// wrapper that fills in any missing arguments before calling the Rust method.
+const WITH_DEFAULTS : &str = "
+def with_defaults(method, params, defaults):
+    def wrapper(self, *args, **kwargs):
+        for name in params[len(args):]:
+            if name not in kwargs and name in defaults:
+                kwargs[name] = defaults[name]
+        return method(self, *args, **kwargs)
+    wrapper.__name__ = method.__name__
+    wrapper.__doc__ = method.__doc__
+    return wrapper
+";
+
+fn install_defaults(
+    py : Python,
+    cls : &PyType,
+    method : &str,
+    params : &[&str],
+    defaults : &[(&str, PyObject)]
+) -> PyResult<()>
+{
+    let globals = PyDict::new(py);
+    globals.set_item(py, "__builtins__", py.import("__builtin__")?)?;
+    py.run(WITH_DEFAULTS, Some(&globals), None)?;
+    let with_defaults = globals.get_item(py, "with_defaults").unwrap();
+
+    // Extension types do not allow setting attributes from Python, so go
+    // through the type's dict directly.
+    let dict = unsafe { PyObject::from_borrowed_ptr(py, (*cls.as_type_ptr()).tp_dict) };
+    let dict = dict.cast_into::<PyDict>(py)?;
+    let original = match dict.get_item(py, method) {
+        Some(m) => m,
+        None => return Err(PyErr::new::<exc::AttributeError, _>(py, method))
+    };
+
+    let default_dict = PyDict::new(py);
+    for (name, value) in defaults {
+        default_dict.set_item(py, *name, value)?;
+    }
+    let wrapped = with_defaults.call(py, (original, params.to_vec(), default_dict), None)?;
+    dict.set_item(py, method, wrapped)?;
+    unsafe { ffi::PyType_Modified(cls.as_type_ptr()) };
+    Ok(())
+}
+
+// py_class! has no properties either, so turn getter methods into them.
+fn install_properties(py : Python, cls : &PyType, names : &[&str]) -> PyResult<()> {
+    let property = py.import("__builtin__")?.get(py, "property")?;
+    let dict = unsafe { PyObject::from_borrowed_ptr(py, (*cls.as_type_ptr()).tp_dict) };
+    let dict = dict.cast_into::<PyDict>(py)?;
+    for name in names {
+        let getter = match dict.get_item(py, *name) {
+            Some(m) => m,
+            None => return Err(PyErr::new::<exc::AttributeError, _>(py, *name))
+        };
+        dict.set_item(py, *name, property.call(py, (getter,), None)?)?;
+    }
+    unsafe { ffi::PyType_Modified(cls.as_type_ptr()) };
+    Ok(())
+}
+
+// Hand-maintained, since py_class! does not record enough about the
+// signatures (or the defaults installed below) to generate them.
+const STUBS : &str = include_str!("hazgraph.pyi");
+
+// Write the .pyi type stubs for this module, eg next to the installed .so.
+fn generate_stubs(py : Python, filename : &str) -> PyResult<PyObject> {
+    std::fs::write(filename, STUBS).map_err(|e| load_error(py, LoadError::IOError(e)))?;
+    Ok(py.None())
+}
+
+// Static types construct instances through the tp_new slot rather than by
+// looking up __new__, so the constructor's defaults are filled in by wrapping
+// the slot itself.
+type NewDefaults = (ffi::newfunc, &'static [&'static str], Vec<(&'static str, PyObject)>);
+static NEW_DEFAULTS : OnceLock<NewDefaults> = OnceLock::new();
+
+unsafe extern "C" fn new_with_defaults(
+    subtype : *mut ffi::PyTypeObject,
+    args : *mut ffi::PyObject,
+    kwds : *mut ffi::PyObject
+) -> *mut ffi::PyObject
+{
+    let py = Python::assume_gil_acquired();
+    let (original, params, defaults) = NEW_DEFAULTS.get().unwrap();
+    let fill = || -> PyResult<PyDict> {
+        let kwargs = if kwds.is_null() {
+            PyDict::new(py)
+        } else {
+            PyObject::from_borrowed_ptr(py, kwds).cast_into::<PyDict>(py)?.copy(py)?
+        };
+        let nargs = ffi::PyTuple_Size(args) as usize;
+        for name in params.iter().skip(nargs) {
+            if let Some((_, value)) = defaults.iter().find(|(param, _)| param == name) {
+                if !kwargs.contains(py, *name)? {
+                    kwargs.set_item(py, *name, value)?;
+                }
+            }
+        }
+        Ok(kwargs)
+    };
+    match fill() {
+        Ok(kwargs) => original(subtype, args, kwargs.as_object().as_ptr()),
+        Err(e) => {
+            e.restore(py);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+fn install_new_defaults(
+    cls : &PyType,
+    params : &'static [&'static str],
+    defaults : Vec<(&'static str, PyObject)>
+)
+{
+    let tp = cls.as_type_ptr();
+    let original = unsafe { (*tp).tp_new }.expect("HazGraph has no tp_new");
+    if NEW_DEFAULTS.set((original, params, defaults)).is_ok() {
+        unsafe {
+            (*tp).tp_new = Some(new_with_defaults);
+            ffi::PyType_Modified(tp);
+        }
+    }
+}
+
 py_module_initializer!(hazgraph, inithazgraph, PyInit_hazgraph, |py, m| {
     m.add(py, "__doc__", "Python wrapper for Callgraph.")?;
     m.add_class::<HazGraph>(py)?;
+    m.add_class::<HazGraphIterator>(py)?;
+    m.add_class::<HazGraphWalk>(py)?;
+    m.add_class::<Edge>(py)?;
+    m.add_class::<SharedHazGraph>(py)?;
+    install_properties(py, &py.get_type::<Edge>(), EDGE_PROPERTIES)?;
+    m.add(py, "generate_stubs", py_fn!(py, generate_stubs(filename: &str)))?;
+
+    let none = py.None();
+    let empty = || PyList::new(py, &[]).into_object();
+    let no = || false.to_py_object(py).into_object();
+    let cls = py.get_type::<HazGraph>();
+    install_new_defaults(&cls, &["filename", "line_limit", "lenient", "demangle", "property_file", "progress", "exclude", "bypass_excluded", "avoid_file"],
+                         vec![("line_limit", 0u32.to_py_object(py).into_object()),
+                              ("lenient", no()),
+                              ("demangle", true.to_py_object(py).into_object()),
+                              ("property_file", none.clone_ref(py)),
+                              ("progress", none.clone_ref(py)),
+                              ("exclude", none.clone_ref(py)),
+                              ("bypass_excluded", no()),
+                              ("avoid_file", none.clone_ref(py))]);
+    install_defaults(py, &cls, "add_edge", &["src", "dst", "props"],
+                     &[("props", 0u32.to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "retain", &["pattern", "negate", "drop_props"],
+                     &[("negate", no()), ("drop_props", 0u32.to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "indirects", &["pattern"], &[("pattern", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "set_name_format", &["template"], &[("template", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "describe", &["func", "brevity"], &[("brevity", "normal".to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "describe_edge", &["edge", "brevity"], &[("brevity", "normal".to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "describe_calls", &["edges", "brevity"], &[("brevity", "normal".to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "set_virtual_mode", &["mode"], &[("mode", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "set_seed", &["seed"], &[("seed", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "edges", &["subset"], &[("subset", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "link", &["other", "rules"], &[("rules", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "to_networkx", &["subset"], &[("subset", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "search", &["pattern", "regex", "limit"],
+                     &[("regex", no()), ("limit", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "filter", &["ids", "pattern", "regex", "negate"],
+                     &[("regex", no()), ("negate", no())])?;
+    install_defaults(py, &cls, "gc_cut", &["entries", "goal"], &[("goal", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "cycle_route", &["func", "avoid", "avoid_props"],
+                     &[("avoid", empty()), ("avoid_props", 0u32.to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "route", &["src", "goal", "avoid", "avoid_props", "matching", "clean", "virtual_mode", "options"],
+                     &[("avoid", empty()),
+                       ("avoid_props", 0u32.to_py_object(py).into_object()),
+                       ("matching", "all".to_py_object(py).into_object()),
+                       ("clean", 0u32.to_py_object(py).into_object()),
+                       ("virtual_mode", none.clone_ref(py)),
+                       ("options", none.clone_ref(py))])?;
+    install_defaults(py, &cls, "route_matrix", &["sources", "goals", "avoid", "avoid_props", "matching"],
+                     &[("avoid", empty()),
+                       ("avoid_props", 0u32.to_py_object(py).into_object()),
+                       ("matching", "all".to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "alternative_routes", &["src", "goal", "avoid", "avoid_props", "matching", "count"],
+                     &[("avoid", empty()),
+                       ("avoid_props", 0u32.to_py_object(py).into_object()),
+                       ("matching", "all".to_py_object(py).into_object()),
+                       ("count", 2u32.to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "route_blockers", &["src", "avoid", "avoid_props", "matching"],
+                     &[("avoid", empty()),
+                       ("avoid_props", 0u32.to_py_object(py).into_object()),
+                       ("matching", "all".to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "reach_diff", &["src", "avoid_a", "avoid_b", "avoid_props_a", "avoid_props_b"],
+                     &[("avoid_props_a", 0u32.to_py_object(py).into_object()),
+                       ("avoid_props_b", 0u32.to_py_object(py).into_object())])?;
+    install_defaults(py, &cls, "reachable", &["func", "direction", "max_depth", "avoid", "avoid_props"],
+                     &[("direction", "callees".to_py_object(py).into_object()),
+                       ("max_depth", none.clone_ref(py)),
+                       ("avoid", empty()),
+                       ("avoid_props", empty())])?;
+    install_defaults(py, &cls, "trim_leaves", &["keep"], &[("keep", empty())])?;
+    install_defaults(py, &cls, "sample_subgraph", &["keep_fraction", "must_keep"], &[("must_keep", empty())])?;
+    install_defaults(py, &cls, "ring", &["func", "hops", "direction", "avoid", "avoid_props"],
+                     &[("direction", "callees".to_py_object(py).into_object()),
+                       ("avoid", empty()),
+                       ("avoid_props", empty())])?;
+    install_defaults(py, &cls, "namespace_matrix", &["exclude"], &[("exclude", empty())])?;
+    install_defaults(py, &cls, "expand", &["func", "depth", "direction", "avoid", "avoid_props"],
+                     &[("direction", "callees".to_py_object(py).into_object()),
+                       ("avoid", empty()),
+                       ("avoid_props", empty())])?;
+    install_defaults(py, &cls, "iter_reachable", &["func", "direction", "max_depth", "avoid", "avoid_props"],
+                     &[("direction", "callees".to_py_object(py).into_object()),
+                       ("max_depth", none.clone_ref(py)),
+                       ("avoid", empty()),
+                       ("avoid_props", empty())])?;
+
+    let shared = py.get_type::<SharedHazGraph>();
+    install_defaults(py, &shared, "resolve", &["pattern", "limit"], &[("limit", 1000usize.to_py_object(py).into_object())])?;
+    install_defaults(py, &shared, "reachable", &["func", "direction", "avoid_props"],
+                     &[("direction", "callees".to_py_object(py).into_object()),
+                       ("avoid_props", 0u32.to_py_object(py).into_object())])?;
+    install_defaults(py, &shared, "route", &["src", "goal", "avoid", "avoid_props", "matching"],
+                     &[("avoid", empty()), ("avoid_props", 0u32.to_py_object(py).into_object()), ("matching", "all".to_py_object(py).into_object())])?;
     Ok(())
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    // Parameter name and whether it has a default, by method, by class. The
+    // module's own functions are under "".
+    type Signatures = BTreeMap<String, BTreeMap<String, Vec<(String, bool)>>>;
+
+    // The text between the parenthesis at `open` and its match.
+    fn parenthesized(text : &str, open : usize) -> &str {
+        let mut depth = 0;
+        for (i, c) in text[open..].char_indices() {
+            match c {
+                '(' | '[' | '<' => depth += 1,
+                ')' | ']' | '>' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return &text[open + 1..open + i];
+                    }
+                },
+                _ => {},
+            }
+        }
+        panic!("unbalanced parameter list at {}", &text[open..]);
+    }
+
+    // Split on the commas outside of any brackets.
+    fn params(list : &str) -> Vec<(String, bool)> {
+        let mut parts = vec![String::new()];
+        let mut depth = 0;
+        for c in list.chars() {
+            match c {
+                '(' | '[' | '<' => depth += 1,
+                ')' | ']' | '>' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(String::new());
+                    continue;
+                },
+                _ => {},
+            }
+            parts.last_mut().unwrap().push(c);
+        }
+        parts.iter()
+            .map(|part| part.trim())
+            .filter(|part| !part.is_empty() && !["self", "&self", "_cls"].contains(part))
+            .map(|part| (part.split(':').next().unwrap().trim().to_string(), part.contains('=')))
+            .collect()
+    }
+
+    // Each "def name(...)" starting a line of `block`.
+    fn defs(block : &str) -> Vec<(String, Vec<(String, bool)>)> {
+        let mut found = vec![];
+        let mut offset = 0;
+        for line in block.split_inclusive('\n') {
+            let trimmed = line.trim_start();
+            let def = trimmed.strip_prefix("@staticmethod ").unwrap_or(trimmed);
+            if let Some(rest) = def.strip_prefix("def ") {
+                let start = offset + (line.len() - rest.len());
+                let open = start + rest.find('(').unwrap();
+                found.push((block[start..open].to_string(), params(parenthesized(block, open))));
+            }
+            offset += line.len();
+        }
+        found
+    }
+
+    fn rust_signatures() -> Signatures {
+        let source = include_str!("lib.rs");
+        let source = &source[..source.find("\n#[cfg(test)]").unwrap()];
+        let mut classes = Signatures::new();
+        for block in source.split("py_class!(class ").skip(1) {
+            let name = block.split_whitespace().next().unwrap().to_string();
+            let body = &block[..block.find("\n});").unwrap()];
+            let mut methods = BTreeMap::new();
+            for (method, params) in defs(body) {
+                // Constructed through __init__, as far as Python can tell.
+                let method = if method == "__new__" { "__init__".to_string() } else { method };
+                methods.insert(method, params);
+            }
+            classes.insert(name, methods);
+        }
+        let functions = classes.entry(String::new()).or_default();
+        for call in source.split("py_fn!(py, ").skip(1) {
+            let open = call.find('(').unwrap();
+            functions.insert(call[..open].to_string(), params(parenthesized(call, open)));
+        }
+
+        // Mark the parameters given defaults at import time.
+        let init = &source[source.find("py_module_initializer!").unwrap()..];
+        let new_defaults = &init[init.find("install_new_defaults(").unwrap()..];
+        let mut installs = vec![("HazGraph", "__init__", &new_defaults[..new_defaults.find("]);").unwrap()])];
+        for call in init.split("install_defaults(py, ").skip(1) {
+            let call = &call[..call.find(")?;").unwrap()];
+            let class = match call.split(',').next().unwrap() {
+                "&cls" => "HazGraph",
+                "&shared" => "SharedHazGraph",
+                other => panic!("unknown class {}", other),
+            };
+            let method = call.split('"').nth(1).unwrap();
+            installs.push((class, method, call));
+        }
+        for (class, method, call) in installs {
+            let params = classes.get_mut(class).and_then(|methods| methods.get_mut(method))
+                .unwrap_or_else(|| panic!("defaults for unknown method {}.{}", class, method));
+            let listed = &call[call.find("&[\"").unwrap()..];
+            let listed : Vec<&str> = listed[..listed.find(']').unwrap()].split('"').skip(1).step_by(2).collect();
+            assert_eq!(listed, params.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+                       "install_defaults parameters for {}.{}", class, method);
+            let defaults = &call[call.find("(\"").unwrap()..];
+            for default in defaults.split("(\"").skip(1) {
+                let name = &default[..default.find('"').unwrap()];
+                let param = params.iter_mut().find(|(param, _)| param == name)
+                    .unwrap_or_else(|| panic!("default for unknown parameter {}.{}({})", class, method, name));
+                param.1 = true;
+            }
+        }
+        classes
+    }
+
+    // Properties, made from getter methods by install_properties, are
+    // "name: type" lines, and are taken to be parameterless methods.
+    fn stub_signatures() -> Signatures {
+        let mut classes = Signatures::new();
+        let mut class = String::new();
+        let mut body = String::new();
+        let mut finish = |class : &str, body : &str| {
+            let mut methods : BTreeMap<String, Vec<(String, bool)>> = defs(body).into_iter().collect();
+            for line in body.lines() {
+                let property = line.strip_prefix("    ").and_then(|line| line.split_once(": ")).map(|(name, _)| name);
+                if let Some(name) = property.filter(|name| name.chars().all(|c| c.is_alphanumeric() || c == '_')) {
+                    methods.insert(name.to_string(), vec![]);
+                }
+            }
+            classes.insert(class.to_string(), methods);
+        };
+        for line in STUBS.lines() {
+            if let Some(name) = line.strip_prefix("class ") {
+                finish(&class, &body);
+                class = name.trim_end_matches(':').to_string();
+                body.clear();
+            } else if line.starts_with("def ") {
+                finish(&class, &body);
+                class.clear();
+                body = line.to_string() + "\n";
+            } else {
+                body += line;
+                body += "\n";
+            }
+        }
+        finish(&class, &body);
+        classes.retain(|_, methods| !methods.is_empty());
+        classes
+    }
+
+    #[test]
+    fn stubs_match_methods() {
+        let mut rust = rust_signatures();
+        let stubs = stub_signatures();
+        // Python 2 iterators also answer to next().
+        for methods in rust.values_mut() {
+            if let Some(params) = methods.get("__next__").cloned() {
+                methods.insert("next".to_string(), params);
+            }
+        }
+        assert_eq!(rust.keys().collect::<Vec<_>>(), stubs.keys().collect::<Vec<_>>());
+        for (class, methods) in &rust {
+            let stubbed = &stubs[class];
+            for (method, params) in methods {
+                match stubbed.get(method) {
+                    Some(stub) => assert_eq!(stub, params, "parameters of {}.{}", class, method),
+                    None => panic!("no stub for {}.{}", class, method),
+                }
+            }
+            for method in stubbed.keys() {
+                assert!(methods.contains_key(method), "stub for missing method {}.{}", class, method);
+            }
+        }
+        assert!(rust["Edge"].keys().filter(|name| !name.starts_with("__")).all(|name| EDGE_PROPERTIES.contains(&name.as_str())));
+    }
+}
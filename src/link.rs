@@ -0,0 +1,196 @@
+use crate::callgraph::{Callgraph, PropertySet};
+use petgraph::graph::NodeIndex;
+use std::collections::HashMap;
+
+// Joining two graphs (say a C++ build and a Rust build, or the browser and the
+// JS engine) into one, so routes can cross between them. The result holds
+// every function of both, the first graph's keeping their ids, plus "bridge"
+// edges wherever the rules say a function in one graph is really (or calls
+// straight through to) a function in the other. Bridge edges carry the BRIDGE
+// property, so they show up in routes and can be avoided like any other.
+//
+// Rules are JSON:
+//
+//   { "match_names": true,
+//     "shims": [["rust_ffi_entry", "_ZN2js8RealImplEv"], ...] }
+//
+// match_names links functions sharing any name, mangled or unmangled (after
+// dropping the ::h<hash> that Rust appends to its demangled names), in both
+// directions. Each shim links its first name to its second, in whichever
+// graph each is found.
+
+pub const BRIDGE_PROPERTY : &str = "BRIDGE";
+
+pub struct LinkRules {
+    pub match_names : bool,
+    pub shims : Vec<(String, String)>,
+}
+
+impl Default for LinkRules {
+    fn default() -> LinkRules {
+        LinkRules { match_names: true, shims: vec![] }
+    }
+}
+
+impl LinkRules {
+    pub fn parse(text : &str) -> Result<LinkRules, String> {
+        let data = json::parse(text).map_err(|e| e.to_string())?;
+        let mut rules = LinkRules::default();
+        if let Some(b) = data["match_names"].as_bool() {
+            rules.match_names = b;
+        }
+        for shim in data["shims"].members() {
+            match (shim[0].as_str(), shim[1].as_str()) {
+                (Some(from), Some(to)) => rules.shims.push((from.to_string(), to.to_string())),
+                _ => return Err(format!("shims must be [from, to] name pairs, got {}", shim.dump())),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+pub struct LinkReport {
+    // Id of the first function copied from the second graph; ids below this
+    // are the first graph's.
+    pub offset : usize,
+    pub bridges : usize,
+}
+
+// "foo::bar::h0123456789abcdef" -> "foo::bar"
+fn strip_rust_hash(name : &str) -> &str {
+    match name.rsplit_once("::h") {
+        Some((base, hash)) if hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => base,
+        _ => name,
+    }
+}
+
+fn name_table(cg : &Callgraph, renumber : &HashMap<NodeIndex, NodeIndex>) -> HashMap<String, Vec<NodeIndex>> {
+    let mut table : HashMap<String, Vec<NodeIndex>> = HashMap::new();
+    for idx in cg.node_ids() {
+        let mut names : Vec<&str> = cg.names(idx).iter().map(|name| strip_rust_hash(name)).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            table.entry(name.to_string()).or_default().push(renumber[&idx]);
+        }
+    }
+    table
+}
+
+// Copy `from` into `into`, translating property bits through `props`.
+fn copy_graph(into : &mut Callgraph, from : &Callgraph, props : &dyn Fn(u32) -> u32) -> HashMap<NodeIndex, NodeIndex> {
+    let mut renumber = HashMap::new();
+    for idx in from.node_ids() {
+        let new_idx = into.add_function(&from.graph[idx]);
+        for name in from.unmangled_names(idx) {
+            into.add_unmangled_name(new_idx, name);
+        }
+        renumber.insert(idx, new_idx);
+    }
+    for edge in from.edge_ids() {
+        let (src, dst) = from.graph.edge_endpoints(edge).unwrap();
+        let limit = from.graph[edge];
+        let new_edge = into.add_edge(renumber[&src], renumber[&dst], PropertySet { all: props(limit.all), any: props(limit.any) });
+        if let Some(location) = from.call_sites.get(&edge) {
+            into.call_sites.insert(new_edge, location.clone());
+        }
+        if let Some(&count) = from.call_counts.get(&edge) {
+            into.call_counts.insert(new_edge, count);
+        }
+    }
+    into.gc_functions.extend(from.gc_functions.iter().filter_map(|idx| renumber.get(idx)));
+    renumber
+}
+
+fn edge_bits(cg : &Callgraph) -> u32 {
+    cg.edge_ids().fold(0, |acc, edge| acc | cg.graph[edge].all | cg.graph[edge].any)
+}
+
+// Bits already spoken for: named ones, and unnamed ones in use on edges (which
+// are copied through unchanged). Bit 1 is GC_SUPPRESSED whether or not the
+// table names it.
+fn take_bit(taken : &mut u32) -> Result<u32, String> {
+    let bit = (0..32).map(|shift| 1u32 << shift).find(|bit| *taken & bit == 0)
+        .ok_or("no property bits left".to_string())?;
+    *taken |= bit;
+    Ok(bit)
+}
+
+pub fn link(first : &Callgraph, second : &Callgraph, rules : &LinkRules) -> Result<(Callgraph, LinkReport), String> {
+    let mut linked = Callgraph::new();
+    linked.property_names = first.property_names.clone();
+    linked.property_aliases = first.property_aliases.clone();
+    for (alias, name) in &second.property_aliases {
+        linked.property_aliases.entry(alias.clone()).or_insert_with(|| name.clone());
+    }
+
+    // The graphs were generated separately, so the same property may have
+    // different bits in each. Match them up by name. Unnamed bits are
+    // assumed to mean the same thing in both.
+    let mut taken = 1 | edge_bits(first) | first.property_names.keys().fold(0, |acc, bit| acc | bit);
+    taken |= second.property_names.keys().fold(edge_bits(second), |acc, bit| acc & !bit);
+    let mut bit_map : HashMap<u32, u32> = HashMap::new();
+    let mut second_props : Vec<(&u32, &String)> = second.property_names.iter().collect();
+    second_props.sort();
+    for (&bit, name) in second_props {
+        let new_bit = match linked.resolve_property(name) {
+            Some(existing) => existing,
+            None => {
+                let fresh = take_bit(&mut taken)?;
+                linked.property_names.insert(fresh, name.clone());
+                fresh
+            },
+        };
+        bit_map.insert(bit, new_bit);
+    }
+    let translate = |bits : u32| -> u32 {
+        (0..32).map(|shift| 1u32 << shift).filter(|bit| bits & bit != 0)
+            .map(|bit| bit_map.get(&bit).cloned().unwrap_or(bit))
+            .fold(0, |acc, bit| acc | bit)
+    };
+    let bridge_bit = match linked.resolve_property(BRIDGE_PROPERTY) {
+        Some(bit) => bit,
+        None => {
+            let bit = take_bit(&mut taken)?;
+            linked.property_names.insert(bit, BRIDGE_PROPERTY.to_string());
+            bit
+        },
+    };
+
+    let first_ids = copy_graph(&mut linked, first, &|bits| bits);
+    let offset = linked.graph.node_count();
+    let second_ids = copy_graph(&mut linked, second, &translate);
+
+    let first_names = name_table(first, &first_ids);
+    let second_names = name_table(second, &second_ids);
+    let mut bridges = vec![];
+    if rules.match_names {
+        for (name, here) in &first_names {
+            for &a in here {
+                for &b in second_names.get(name).into_iter().flatten() {
+                    bridges.push((a, b));
+                    bridges.push((b, a));
+                }
+            }
+        }
+    }
+    for (from, to) in &rules.shims {
+        let lookup = |table : &HashMap<String, Vec<NodeIndex>>, name : &str| table.get(strip_rust_hash(name)).cloned().unwrap_or_default();
+        // Only shims that actually cross between the graphs.
+        for (callers, callees) in [(&first_names, &second_names), (&second_names, &first_names)] {
+            for src in lookup(callers, from) {
+                for dst in lookup(callees, to) {
+                    bridges.push((src, dst));
+                }
+            }
+        }
+    }
+    bridges.sort();
+    bridges.dedup();
+    let bridge = PropertySet { all: bridge_bit, any: bridge_bit };
+    for &(src, dst) in &bridges {
+        linked.add_edge(src, dst, bridge);
+    }
+
+    Ok((linked, LinkReport { offset, bridges: bridges.len() }))
+}
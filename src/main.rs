@@ -1,8 +1,33 @@
 mod hazard;
-use hazard::load_graph;
+use hazard::{load_graph_with_options, LoadError, LoadOptions};
 
+mod annotations;
 mod callgraph;
-use callgraph::{Callgraph, Matcher, DescriptionBrevity};
+mod csr;
+mod reach;
+mod trie;
+mod query;
+mod rules;
+mod signature;
+mod namefmt;
+mod bench;
+mod export;
+mod flamegraph;
+mod plugins;
+mod profile;
+mod coverage;
+mod link;
+mod diskstore;
+mod report;
+mod server;
+mod testgraph;
+mod routecache;
+mod outputlog;
+mod suggest;
+mod draw;
+mod presets;
+mod routehistory;
+use callgraph::{AvoidConfig, Blockers, Callgraph, Matcher, DescriptionBrevity, Direction, EntryPoints, PropertyMatch, PropertySet, ReachableWalk, RouteStep, VirtualMode};
 
 #[macro_use]
 extern crate lazy_static;
@@ -10,10 +35,14 @@ extern crate lazy_static;
 use petgraph::graph::{NodeIndex, EdgeIndex};
 use regex::Regex;
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
-use std::collections::HashSet;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::{Context, Editor, Helper};
+use std::collections::{BTreeMap, HashSet};
 use std::env;
 use std::iter::FromIterator;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 enum CommandResult {
     Ok,
@@ -28,16 +57,110 @@ pub enum ResolveResult {
 }
 
 lazy_static! {
-    static ref ROUTE_RE : Regex = Regex::new(r"^route (?:from )?(.*?) (?:to )?(.*?)(?: avoiding (.*))?$").unwrap();
+    static ref COMPARE_RE : Regex = Regex::new(r"^compare avoid (.*?) ?with (.*?) from (.*?)(?: to (.*?))?$").unwrap();
+    static ref EXPORT_ROUTES_RE : Regex = Regex::new(r"^export routes (\S+) from (.*?) to (.*?)(?: avoiding (.*?))?(?: alternatives (\d+))?$").unwrap();
+    static ref MATRIX_RE : Regex = Regex::new(r"^route (?:from )?(.*?) to (.*?)(?: avoiding (.*?))?(?: matching (all|any))? matrix$").unwrap();
+    static ref ROUTE_RE : Regex = Regex::new(r"^route (?:from )?(.*?) (?:to )?(.*?)(?: avoiding (.*?))?(?: matching (all|any|both))?(?: requiring clean (\[[^\]]*\]))?( hottest| frequent)?( collapsed)?(?: virtual (conservative|refined|ignore))?$").unwrap();
+}
+
+// Tab-completes function names from the callgraph.
+struct CallgraphHelper<'a> {
+    cg : &'a Callgraph,
+}
+
+const MAX_COMPLETIONS : usize = 200;
+
+// Loaded at startup if present in the current directory.
+const PLUGIN_MANIFEST : &str = "cgtraverse-plugins.json";
+
+// Presets (see presets.rs), also loaded at startup if present.
+const CONFIG_FILE : &str = "cgtraverse.conf";
+
+// The exported page's layout is quadratic in the number of functions.
+const MAX_EXPORT_NODES : usize = 2000;
+
+impl<'a> Completer for CallgraphHelper<'a> {
+    type Candidate = String;
+
+    fn complete(&self, line : &str, pos : usize, _ctx : &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |i| i + 1);
+        Ok((start, self.cg.complete(&line[start..pos], MAX_COMPLETIONS)))
+    }
+}
+
+impl<'a> Hinter for CallgraphHelper<'a> {}
+impl<'a> Highlighter for CallgraphHelper<'a> {}
+impl<'a> Helper for CallgraphHelper<'a> {}
+
+// Set by SIGINT, so that long-running output can be cut short without killing
+// the whole session. (While reading input, rustyline handles Ctrl-C itself.)
+static INTERRUPTED : AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_ : libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Print functions as they are produced rather than all at once at the end,
+// stopping early on Ctrl-C. Returns the functions printed.
+fn stream_functions(cg : &Callgraph, funcs : impl Iterator<Item = NodeIndex>, brevity : DescriptionBrevity) -> Vec<NodeIndex> {
+    let mut shown = vec![];
+    for idx in funcs {
+        if interrupted() {
+            println!("interrupted after {} results", shown.len());
+            break;
+        }
+        println!("{}", cg.name(idx, brevity));
+        shown.push(idx);
+    }
+    shown
 }
 
 struct UIContext {
     last_command : String,
     active_function : Option<NodeIndex>,
     active_functions : Option<Vec<NodeIndex>>,
-    avoid_functions : Vec<NodeIndex>,
+    // Functions and edges along the most recently found route.
+    last_route : Option<(Vec<NodeIndex>, Vec<EdgeIndex>)>,
+    // Functions to avoid, kept as the patterns they were given by (with #N
+    // recorded as the mangled name) so that a saved list still means the same
+    // functions in a regenerated graph. Resolved when next needed.
+    avoid_patterns : Vec<String>,
+    avoid_functions : Option<Vec<NodeIndex>>,
     avoid_attributes : u32,
     verbosity : u32,
+    filename : String,
+    plugins : BTreeMap<String, plugins::Plugin>,
+    profile : Option<profile::Profile>,
+    // The last route search, for reuse while the avoid set grows.
+    route_cache : Option<routecache::RouteCache>,
+    // The calls of the last route or cut, and which it was, for annotate
+    // suggest.
+    last_calls : Option<(&'static str, Vec<EdgeIndex>)>,
+    // What recent commands printed, for review and search-output.
+    output_log : outputlog::OutputLog,
+    presets : presets::Presets,
+    // Earlier routes, for route recall/save/diff.
+    route_history : routehistory::RouteHistory,
+}
+
+impl UIContext {
+    fn avoided_functions(&mut self, cg : &Callgraph) -> &[NodeIndex] {
+        let patterns = &self.avoid_patterns;
+        self.avoid_functions.get_or_insert_with(|| {
+            let mut funcs = vec![];
+            for pattern in patterns {
+                match cg.resolve(pattern) {
+                    Some(matches) if !matches.is_empty() => funcs.extend(matches),
+                    _ => println!("avoided function '{}' is not in this graph", pattern),
+                }
+            }
+            funcs
+        })
+    }
 }
 
 fn resolve(cg : &Callgraph, query : &[&str], ctx : &UIContext) -> ResolveResult {
@@ -106,29 +229,36 @@ fn show_neighbors(cg : &Callgraph, neighbors : &[NodeIndex], ctx : &mut UIContex
     }
  }
 
-fn show_edges(cg : &Callgraph, neighbors : &[EdgeIndex], ctx : &mut UIContext) {
+fn show_edges(cg : &Callgraph, groups : &[(NodeIndex, Vec<EdgeIndex>)], ctx : &mut UIContext) {
     // If we have a single result, use that as the new "active function". If
     // there are no results, keep the previous value. If there are multiple
     // results, clear out the active function.
-    match neighbors.len() {
+    match groups.len() {
         0 => (),
-        1 => ctx.active_function = Some(cg.graph.edge_endpoints(neighbors[0]).unwrap().1),
+        1 => ctx.active_function = Some(groups[0].0),
         _ => ctx.active_function = None
     }
-    for e in neighbors {
-        println!("{}", cg.describe_edge(*e, DescriptionBrevity::Normal));
+    for (_, edges) in groups {
+        println!("{}", cg.describe_calls(edges, DescriptionBrevity::Normal));
     }
-    if neighbors.len() > 0 {
-        ctx.active_functions = Some(
-            neighbors.iter().map(|e| cg.graph.edge_endpoints(*e).unwrap().1).collect()
-        );
+    if !groups.is_empty() {
+        ctx.active_functions = Some(groups.iter().map(|&(callee, _)| callee).collect());
     }
  }
 
+fn print_entry_points(entry_points : &EntryPoints) {
+    for (kind, funcs) in &entry_points.kinds {
+        println!("@{}-entrypoints = {} functions", kind, funcs.len());
+    }
+    for pattern in &entry_points.unmatched {
+        println!("no entry points match '{}'", pattern);
+    }
+}
+
 fn show_callees(cg : &Callgraph, query : Option<&str>, ctx : &mut UIContext) {
     if let Some(func) = resolve_single(cg, query, ctx, "function") {
         ctx.active_function = Some(func);
-        show_edges(cg, &cg.callee_edges(func), ctx);
+        show_edges(cg, &cg.grouped_callee_edges(func), ctx);
     }
 }
 
@@ -139,6 +269,15 @@ fn show_callers(cg : &Callgraph, query : Option<&str>, ctx : &mut UIContext) {
     }
 }
 
+// Functions matching any of "<pattern> and <pattern> ...".
+fn resolve_all(cg : &Callgraph, query : &str, ctx : &UIContext, purpose : &str) -> Option<Vec<NodeIndex>> {
+    let mut funcs = vec![];
+    for part in query.split(" and ") {
+        funcs.extend(resolve_multi(cg, part.trim(), ctx, purpose)?);
+    }
+    Some(funcs)
+}
+
 fn parse_command<'a>(pattern : &Regex, input : &'a str, usage : &str) -> Option<Vec<&'a str>> {
     match pattern.captures(input) {
         None => {
@@ -167,47 +306,75 @@ fn resolve_avoid(
     let mut attributes : u32 = 0;
     let mut have_attrs = false;
 
-    // Ugh... allow either "A and B" or "A or B". Maybe the caller should pass
-    // this in. Or maybe I should use " ; ".
     let mut idxes = vec![];
-    for part in query.split(" and ") {
-        for s in part.split(" or ") {
-            let s = s.trim();
-            if s.chars().nth(0) == Some('[') && s.len() >= 2 {
-                let s = &s[1..s.len()-1];
-                for attrname in s.split(",") {
-                    if attrname.len() == 0 {
-                        // Allow eg `avoid only []'
-                    } else if let Some(a) = cg.resolve_property(attrname) {
-                        attributes |= a;
-                    } else {
-                        println!("unknown attribute '{}'", attrname);
-                        return None
-                    }
-                    have_attrs = true;
+    for s in avoid_terms(query) {
+        if let Some(s) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            for attrname in s.split(",") {
+                if attrname.len() == 0 {
+                    // Allow eg `avoid only []'
+                } else if let Some(a) = cg.resolve_property(attrname) {
+                    attributes |= a;
+                } else {
+                    println!("unknown attribute '{}'", attrname);
+                    return None
                 }
-            } else if let Some(v) = resolve_multi(cg, s, ctx, purpose) {
-                idxes.extend(v);
-            } else {
-                println!("unable to resolve {}", s);
-                return None
+                have_attrs = true;
             }
+        } else if let Some(v) = resolve_multi(cg, s, ctx, purpose) {
+            idxes.extend(v);
+        } else {
+            println!("unable to resolve {}", s);
+            return None
         }
     }
 
     Some((idxes, if have_attrs { Some(attributes) } else { None }))
 }
 
-fn print_route(cg : &Callgraph, maybe_route : Option<Vec<EdgeIndex>>) {
+// Ugh... allow either "A and B" or "A or B". Maybe the caller should pass
+// this in. Or maybe I should use " ; ".
+fn avoid_terms(query : &str) -> impl Iterator<Item = &str> {
+    query.split(" and ").flat_map(|part| part.split(" or ")).map(|s| s.trim())
+}
+
+// The function patterns in an avoid specification, for remembering.
+fn avoid_patterns(cg : &Callgraph, query : &str) -> Vec<String> {
+    avoid_terms(query).filter(|s| !s.starts_with('[')).map(|s| {
+        match s.strip_prefix('#').and_then(|n| n.parse::<usize>().ok()).and_then(|n| cg.resolve_id(n)) {
+            Some(idx) => cg.graph[idx].clone(),
+            None => s.to_string(),
+        }
+    }).collect()
+}
+
+// With `collapse`, calls wandering around inside a recursion cycle are
+// summarized rather than listed.
+fn print_route(cg : &Callgraph, maybe_route : Option<Vec<EdgeIndex>>, collapse : bool) {
     if let Some(route) = maybe_route {
+        if route.is_empty() {
+            println!("Already there: the origin is in the goal set");
+            return;
+        }
         println!("length {} route found:", route.len());
         let len = route.len();
         if len > 0 {
             let origin = route[0];
             println!("{}", cg.name(cg.graph.edge_endpoints(origin).unwrap().0, DescriptionBrevity::Normal));
         }
-        for idx in route {
-            println!("{}", cg.describe_edge(idx, DescriptionBrevity::Normal));
+        if collapse {
+            for step in cg.collapse_route(&route) {
+                match step {
+                    RouteStep::Call(idx) => println!("{}", cg.describe_edge(idx, DescriptionBrevity::Normal)),
+                    RouteStep::Cycle { functions, calls } => {
+                        println!("... ({} calls within cycle of {} functions) ...", calls.len(), functions);
+                        println!("{}", cg.describe_edge(*calls.last().unwrap(), DescriptionBrevity::Normal));
+                    },
+                }
+            }
+        } else {
+            for idx in route {
+                println!("{}", cg.describe_edge(idx, DescriptionBrevity::Normal));
+            }
         }
         if len > 10 {
             println!("end length {} route", len);
@@ -217,19 +384,127 @@ fn print_route(cg : &Callgraph, maybe_route : Option<Vec<EdgeIndex>>) {
     }
 }
 
+fn print_blockers(cg : &Callgraph, blockers : &Blockers) {
+    if blockers.functions.is_empty() && blockers.properties.is_empty() {
+        println!("The search reached {} functions without running into anything avoided, so there is no route regardless", blockers.reached);
+        return;
+    }
+    println!("The search reached {} functions and was cut off", blockers.reached);
+    for (bit, count) in blockers.properties.iter().take(10) {
+        println!("{:>10} times by [{}]", count, cg.describe_property_set(*bit));
+    }
+    for (idx, count) in blockers.functions.iter().take(10) {
+        println!("{:>10} times at {}", count, cg.name(*idx, DescriptionBrevity::Normal));
+    }
+    if blockers.functions.len() > 10 {
+        println!("...and at {} more avoided functions", blockers.functions.len() - 10);
+    }
+}
+
+fn explain_clean_route(cg : &Callgraph, found : Option<PropertySet>, unrestricted : Option<Vec<EdgeIndex>>, clean : u32) {
+    let required = cg.describe_property_set(clean);
+    if let Some(props) = found {
+        println!("Clean of [{}]. Every call on it is [{}]; some may be [{}]",
+                 required, cg.describe_property_set(props.all), cg.describe_property_set(props.any));
+        return;
+    }
+    match unrestricted {
+        None => println!("There is no route even without requiring clean [{}]", required),
+        Some(candidate) => {
+            let dirty : Vec<EdgeIndex> = candidate.iter().cloned().filter(|&edge| cg.graph[edge].any & clean != 0).collect();
+            println!("Without requiring clean [{}], the shortest route has length {} and fails at {} calls:",
+                     required, candidate.len(), dirty.len());
+            for edge in dirty {
+                let src = cg.graph.edge_endpoints(edge).unwrap().0;
+                println!("  {} -> {}", cg.name(src, DescriptionBrevity::Normal), cg.describe_edge(edge, DescriptionBrevity::Normal));
+            }
+        },
+    }
+}
+
+fn format_bytes(bytes : usize) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.1}G", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1}M", b as f64 / (1u64 << 20) as f64),
+        b if b >= 1 << 10 => format!("{:.1}K", b as f64 / (1u64 << 10) as f64),
+        b => format!("{}B", b),
+    }
+}
+
 enum Command<'a> {
     Help,
     Quit,
     SetVerbose(u32),
+    SetNameFormat(Option<String>),
+    SetVirtualMode(Option<VirtualMode>),
     DumpGraph,
     DumpStems,
+    Memory,
+    Fingerprint,
+    Bench(Option<String>),
+    BenchSave(String),
+    ExportHtml(String),
+    ExportNamespaces(String),
+    Namespaces,
+    ExportRoutes(Vec<String>),
+    RouteHistory,
+    RecallRoute(String),
+    SaveRoute(String),
+    DiffRoutes(String, String),
+    Flamegraph(String, String, Option<String>),
+    Reachable(Direction, String, Option<String>),
+    Ring(Direction, String, usize),
+    Components(Option<String>),
+    Impact(Option<String>),
+    Cut(String, Option<String>),
+    CompareAvoid(Vec<String>),
+    RouteMatrix(Vec<String>),
+    Review(Option<usize>),
+    SuggestAnnotations(Option<String>),
+    DrawRoute(bool),
+    DrawAround(bool, String, usize),
+    SearchOutput(String),
+    Cycle(Option<String>),
     Resolve(String),
+    ExplainResolve(String),
+    Query(String),
+    OnlyVia(String),
+    Rules(String),
+    GoalSet(String, String),
+    LoadGoalSets(String),
+    LoadEntryPoints(String),
+    ClassifyRoots,
+    Tag(bool, String, String),
+    Note(String, String),
+    Unannotate(String),
+    Annotations(Option<String>),
+    Plugins(Option<String>),
+    Plugin(String),
     Callees(Option<String>),
+    // Callees or callers out to some depth, or just how many at each.
+    Expand(Direction, String, usize, bool),
     Callers(Option<String>),
     Route(Vec<String>),
+    LoadProfile(String),
+    LoadCoverage(String),
+    Report(String, String, Option<String>),
+    Prune(Vec<String>, String),
+    TrimLeaves(Option<String>, String),
+    Sample(f64, Option<String>, String),
+    HotStats(usize),
+    CallStats(usize),
+    Indirects(Option<String>),
+    Names(Option<String>, Option<String>),
     Filter(bool, Matcher<'a>),
     Avoid(bool, String),
     ListAvoids,
+    ListPresets,
+    LoadPresets(String),
+    DefinePreset(String, Vec<String>),
+    ApplyPreset(String),
+    SaveAvoids(String),
+    LoadAvoids(String),
+    AvoidFile(String),
     Invalid(String),
     ResolveId(u32),
     Unknown,
@@ -239,6 +514,7 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
     let last_command = ctx.last_command.clone();
     let line = if line.is_empty() { last_command.as_ref() } else { line };
     let words : Vec<_> = line.split_whitespace().collect();
+    INTERRUPTED.store(false, Ordering::SeqCst);
     let command = match words[0] {
         "help" => Command::Help,
 
@@ -248,7 +524,261 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
 
         "stems" => Command::DumpStems,
 
-        "resolve" => Command::Resolve(words[1].to_string()),
+        "mem" => Command::Memory,
+        "fingerprint" => Command::Fingerprint,
+
+        "review" => match words.get(1).map(|n| n.parse::<usize>()) {
+            None => Command::Review(None),
+            Some(Ok(n)) => Command::Review(Some(n)),
+            Some(Err(_)) => Command::Invalid("Usage: review [n]".to_string()),
+        },
+        "search-output" if words.len() > 1 => Command::SearchOutput(line[words[0].len()..].trim().to_string()),
+        "search-output" => Command::Invalid("Usage: search-output <text|/regex/>".to_string()),
+
+        "bench" => {
+            match (words.get(1), words.get(2)) {
+                (Some(&"save"), Some(file)) => Command::BenchSave(file.to_string()),
+                (Some(&"save"), None) => Command::Invalid("Usage: bench save <file>".to_string()),
+                (baseline, _) => Command::Bench(baseline.map(|s| s.to_string())),
+            }
+        },
+
+        "export" => {
+            match (words.get(1), words.get(2)) {
+                (Some(&"html"), Some(file)) => Command::ExportHtml(file.to_string()),
+                (Some(&"namespaces"), Some(file)) => Command::ExportNamespaces(file.to_string()),
+                (Some(&"routes"), _) => {
+                    match parse_command(&EXPORT_ROUTES_RE, line, "Usage: export routes <file.dot|file.json> from <func> to <func> [avoiding <avoidances>] [alternatives N]") {
+                        Some(args) => Command::ExportRoutes(args.iter().map(|s| s.to_string()).collect()),
+                        None => Command::Invalid("bad export command".to_string()),
+                    }
+                },
+                _ => Command::Invalid("Usage: export html <file> | export routes <file> from <func> to <func> ... | export namespaces <file.csv|file.dot>".to_string()),
+            }
+        },
+
+        "rule" | "rules" if words.len() > 1 => {
+            let arg = line[words[0].len()..].trim();
+            // A filename, or rules given inline.
+            if arg.contains(":-") || arg.ends_with('.') && arg.contains('(') {
+                Command::Rules(arg.to_string())
+            } else {
+                match std::fs::read_to_string(arg) {
+                    Ok(text) => Command::Rules(text),
+                    Err(e) => Command::Invalid(format!("failed to read {}: {}", arg, e)),
+                }
+            }
+        },
+
+        "rules" | "goals" if words.len() == 1 => Command::Rules(String::new()),
+
+        "goals" if words.len() == 2 => Command::LoadGoalSets(words[1].to_string()),
+
+        "entrypoints" if words.len() == 1 => Command::ClassifyRoots,
+        "entrypoints" if words.len() == 2 => Command::LoadEntryPoints(words[1].to_string()),
+        "entrypoints" => Command::Invalid("Usage: entrypoints [<file>]".to_string()),
+
+        "goal" => match line[words[0].len()..].split_once('=') {
+            Some((name, spec)) => Command::GoalSet(name.trim().to_string(), spec.trim().to_string()),
+            None => Command::Invalid("Usage: goal <name> = <query>".to_string()),
+        },
+
+        "draw" => {
+            // "draw ascii ..." for terminals without box-drawing characters.
+            let ascii = words.get(1) == Some(&"ascii");
+            let rest = &words[if ascii { 2 } else { 1 }..];
+            match rest {
+                ["route"] => Command::DrawRoute(ascii),
+                ["around", func] => Command::DrawAround(ascii, func.to_string(), 1),
+                ["around", func, "depth", n] if n.parse::<usize>().is_ok() => Command::DrawAround(ascii, func.to_string(), n.parse().unwrap()),
+                _ => Command::Invalid("Usage: draw [ascii] route | draw [ascii] around <func> [depth N]".to_string()),
+            }
+        },
+
+        "annotate" if words.get(1) == Some(&"suggest") && words.len() <= 3 =>
+            Command::SuggestAnnotations(words.get(2).map(|s| s.to_string())),
+        "annotate" => Command::Invalid("Usage: annotate suggest [file]".to_string()),
+
+        "tag" | "untag" if words.len() > 2 => {
+            Command::Tag(words[0] == "tag", words[1].to_string(), words[2..].join(" "))
+        },
+        "tag" | "untag" => Command::Invalid(format!("Usage: {} <tag> <func>", words[0])),
+
+        "note" if words.len() > 2 => {
+            let text = line[words[0].len()..].trim()[words[1].len()..].trim();
+            Command::Note(words[1].to_string(), text.to_string())
+        },
+        "note" => Command::Invalid("Usage: note <func> <text>".to_string()),
+
+        "unannotate" if words.len() > 1 => Command::Unannotate(words[1..].join(" ")),
+        "unannotate" => Command::Invalid("Usage: unannotate <func>".to_string()),
+
+        "plugins" => Command::Plugins(words.get(1).map(|s| s.to_string())),
+
+        "annotations" => Command::Annotations(words.get(1).map(|s| s.to_string())),
+
+        "query" if words.len() > 1 => Command::Query(line[words[0].len()..].trim().to_string()),
+        "onlyvia" => {
+            match line[words[0].len()..].trim().strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                Some(props) if !props.trim().is_empty() => Command::OnlyVia(props.to_string()),
+                _ => Command::Invalid("Usage: onlyvia [PROP,...]".to_string()),
+            }
+        },
+
+        "flamegraph" => {
+            match words.len() {
+                3 | 4 => Command::Flamegraph(words[1].to_string(), words[2].to_string(), words.get(3).map(|s| s.to_string())),
+                _ => Command::Invalid("Usage: flamegraph <func> <out.svg|out.folded> [samples.folded]".to_string()),
+            }
+        },
+
+        "profile" if words.len() == 2 => Command::LoadProfile(words[1].to_string()),
+        "profile" => Command::Invalid("Usage: profile <perf-script|callgrind|collapsed file>".to_string()),
+
+        "coverage" if words.len() == 2 => Command::LoadCoverage(words[1].to_string()),
+        "coverage" => Command::Invalid("Usage: coverage <lcov tracefile>".to_string()),
+
+        "report" if words.len() == 3 || words.len() == 4 =>
+            Command::Report(words[1].to_string(), words[2].to_string(), words.get(3).map(|s| s.to_string())),
+        "report" => Command::Invalid("Usage: report <hazards.txt> <out.md|out.html> [gcFunctions.txt]".to_string()),
+
+        "prune" if words.len() > 3 && words[words.len() - 2] == "to" => {
+            let conds = words[1..words.len() - 2].join(" ");
+            Command::Prune(conds.split(" and ").map(|c| c.trim().to_string()).collect(), words[words.len() - 1].to_string())
+        },
+        "trim" if words.get(1) == Some(&"leaves") && words.len() > 3 && words[words.len() - 2] == "to" => {
+            let out_file = words[words.len() - 1].to_string();
+            match words[2] {
+                "keeping" if words.len() > 5 => Command::TrimLeaves(Some(words[3..words.len() - 2].join(" ")), out_file),
+                "to" => Command::TrimLeaves(None, out_file),
+                _ => Command::Invalid("Usage: trim leaves [keeping <func> [and <func>...]] to <out.txt>".to_string()),
+            }
+        },
+        "trim" => Command::Invalid("Usage: trim leaves [keeping <func> [and <func>...]] to <out.txt>".to_string()),
+        "sample" if words.len() > 3 && words[words.len() - 2] == "to" => {
+            let out_file = words[words.len() - 1].to_string();
+            match (words[1].parse::<f64>(), words[2]) {
+                (Ok(fraction), _) if !(fraction > 0.0 && fraction <= 1.0) => Command::Invalid("The fraction to keep must be more than 0 and at most 1".to_string()),
+                (Ok(fraction), "keeping") if words.len() > 5 => Command::Sample(fraction, Some(words[3..words.len() - 2].join(" ")), out_file),
+                (Ok(fraction), "to") => Command::Sample(fraction, None, out_file),
+                _ => Command::Invalid("Usage: sample <fraction> [keeping <func> [and <func>...]] to <out.txt>".to_string()),
+            }
+        },
+        "sample" => Command::Invalid("Usage: sample <fraction> [keeping <func> [and <func>...]] to <out.txt>".to_string()),
+        "prune" => Command::Invalid("Usage: prune <pattern|!pattern|[PROP,...]> [and ...] to <out.txt>".to_string()),
+
+        "stats" if words.get(1) == Some(&"hot") => {
+            match words.get(2).map(|n| n.parse::<usize>()) {
+                None => Command::HotStats(20),
+                Some(Ok(n)) => Command::HotStats(n),
+                Some(Err(_)) => Command::Invalid("Usage: stats hot [count]".to_string()),
+            }
+        },
+        "stats" if words.get(1) == Some(&"calls") => {
+            match words.get(2).map(|n| n.parse::<usize>()) {
+                None => Command::CallStats(20),
+                Some(Ok(n)) => Command::CallStats(n),
+                Some(Err(_)) => Command::Invalid("Usage: stats calls [count]".to_string()),
+            }
+        },
+
+        // "names [filter] > file", or to the terminal without the file.
+        "names" => {
+            let args = line[words[0].len()..].trim();
+            let (filter, file) = match args.rsplit_once(" > ").or_else(|| args.strip_prefix("> ").map(|file| ("", file))) {
+                Some((filter, file)) => (filter.trim(), Some(file.trim().to_string())),
+                None => (args, None),
+            };
+            if file.as_deref() == Some("") {
+                Command::Invalid("Usage: names [filter] [> file]".to_string())
+            } else {
+                Command::Names(Some(filter.to_string()).filter(|f| !f.is_empty()), file)
+            }
+        },
+
+        "indirects" => Command::Indirects(words.get(1).map(|s| s.to_string())),
+
+        // The whole rest of the line, since a signature can have spaces.
+        "explain" if words.len() > 2 && words[1] == "resolve" => {
+            Command::ExplainResolve(line[words[0].len()..].trim()["resolve".len()..].trim().trim_matches('"').to_string())
+        },
+        "explain" => Command::Invalid("Usage: explain resolve <pattern>".to_string()),
+        "resolve" if words.len() > 1 => Command::Resolve(line[words[0].len()..].trim().trim_matches('"').to_string()),
+        "resolve" => Command::Invalid("Usage: resolve <pattern>".to_string()),
+
+        "impact" => {
+            Command::Impact(if words.len() > 1 {
+                Some(line[words[0].len() + 1 ..].to_string())
+            } else {
+                None
+            })
+        },
+
+        "cycle" => {
+            Command::Cycle(if words.len() > 1 {
+                Some(line[words[0].len() + 1 ..].to_string())
+            } else {
+                None
+            })
+        },
+
+        "cut" if words.len() > 1 => {
+            let args = line[words[0].len()..].trim();
+            match args.split_once(" to ") {
+                Some((entries, goal)) => Command::Cut(entries.to_string(), Some(goal.to_string())),
+                None => Command::Cut(args.to_string(), None),
+            }
+        },
+        "compare" => {
+            match parse_command(&COMPARE_RE, line, "Usage: compare avoid [<avoidances>] with <avoidances> from <func> [to <goal>]") {
+                Some(args) => Command::CompareAvoid(args.iter().map(|s| s.to_string()).collect()),
+                None => Command::Invalid("bad compare command".to_string()),
+            }
+        },
+
+        "cut" => Command::Invalid("Usage: cut <entry> [and <entry>...] [to <func> [and <func>...]]".to_string()),
+
+        "reachable" => {
+            // An optional "where <set>" keeps only the matching functions.
+            let (words, filter) = match words.iter().position(|w| *w == "where") {
+                Some(i) if i + 1 < words.len() => (&words[..i], Some(words[i + 1..].join(" "))),
+                Some(_) => (&words[..0], None),
+                None => (&words[..], None),
+            };
+            match words.get(1) {
+                Some(&"callers") if words.len() > 2 => Command::Reachable(Direction::Callers, words[2..].join(" "), filter),
+                Some(_) => Command::Reachable(Direction::Callees, words[1..].join(" "), filter),
+                None => Command::Invalid("Usage: reachable [callers] <func> [where <set>]".to_string()),
+            }
+        },
+
+        "namespaces" => Command::Namespaces,
+
+        "components" => Command::Components(Some(line[words[0].len()..].trim().to_string()).filter(|s| !s.is_empty())),
+
+        "ring" => {
+            let hops = words.last().and_then(|n| n.parse::<usize>().ok());
+            let (direction, first) = match words.get(1) {
+                Some(&"callers") => (Direction::Callers, 2),
+                Some(&"callees") => (Direction::Callees, 2),
+                _ => (Direction::Callees, 1),
+            };
+            match hops {
+                Some(hops) if words.len() > first + 1 => Command::Ring(direction, words[first..words.len() - 1].join(" "), hops),
+                _ => Command::Invalid("Usage: ring [callers|callees] <func> <N>".to_string()),
+            }
+        },
+
+        "callee" | "callees" | "caller" | "callers" if words.len() > 3 && words.contains(&"depth") => {
+            let direction = if words[0].starts_with("callee") { Direction::Callees } else { Direction::Callers };
+            let count_only = words.last() == Some(&"count");
+            let words = if count_only { &words[..words.len() - 1] } else { &words[..] };
+            let at = words.iter().rposition(|&w| w == "depth").unwrap();
+            match words.get(at + 1).and_then(|n| n.parse::<usize>().ok()) {
+                Some(depth) if at > 1 && at + 2 == words.len() => Command::Expand(direction, words[1..at].join(" "), depth, count_only),
+                _ => Command::Invalid(format!("Usage: {} <func> depth <N> [count]", words[0])),
+            }
+        },
 
         "callee" | "callees" => {
             Command::Callees(if words.len() > 1 {
@@ -266,19 +796,30 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
             })
         },
 
+        "routes" if words.get(1) == Some(&"history") => Command::RouteHistory,
+        "route" if words.len() == 3 && words[1] == "recall" => Command::RecallRoute(words[2].to_string()),
+        "route" if words.len() == 3 && words[1] == "save" => Command::SaveRoute(words[2].to_string()),
+        "route" if words.len() == 4 && words[1] == "diff" => Command::DiffRoutes(words[2].to_string(), words[3].to_string()),
+        "route" if line.ends_with(" matrix") => {
+            match parse_command(&MATRIX_RE, line, "Usage: route from <func> [and <func>...] to <func> [and <func>...] [avoiding ...] [matching all|any] matrix") {
+                Some(args) => Command::RouteMatrix(args.iter().map(|s| s.to_string()).collect()),
+                None => Command::Invalid("bad route command".to_string()),
+            }
+        },
         "route" => {
             if let Some(args) = parse_command(
                 &ROUTE_RE, line,
-                "Invalid syntax. Usage: route from <func1> to <func2> avoiding <func> and <func> and <func>") {
+                "Invalid syntax. Usage: route from <func1> to <func2> [avoiding <func> and <func> and <func>] [matching all|any|both] [requiring clean [PROP,...]] [hottest|frequent] [collapsed] [virtual conservative|refined|ignore]") {
                     Command::Route(args.iter().map(|s| s.to_string()).collect())
                 } else {
                     Command::Invalid("bad route command".to_string())
                 }
         },
 
+        "filter" if words.len() == 1 => Command::Invalid("Usage: filter [!]<pattern>".to_string()),
         "filter" => {
-            if &words[1][0..1] == "!" {
-                if let Some(filter) = Matcher::new(&words[1][1..]) {
+            if let Some(pattern) = words[1].strip_prefix('!') {
+                if let Some(filter) = Matcher::new(pattern) {
                     Command::Filter(true, filter)
                 } else {
                     Command::Invalid("invalid filter".to_string())
@@ -295,7 +836,13 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
         "avoid" => {
             let mut args = line[words[0].len()..].trim();
 
-            if args.len() > 0 {
+            if words.len() == 3 && words[1] == "save" {
+                Command::SaveAvoids(words[2].to_string())
+            } else if words.len() == 3 && words[1] == "load" {
+                Command::LoadAvoids(words[2].to_string())
+            } else if words.len() == 3 && words[1] == "file" {
+                Command::AvoidFile(words[2].to_string())
+            } else if args.len() > 0 {
                 let joined : String;
                 let only = if words.get(1) == Some(&"only") {
                     joined = words[2..].join(" ");
@@ -309,6 +856,34 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
             }
         },
 
+        "preset" if words.len() == 1 => Command::ListPresets,
+        "preset" if words.len() == 3 && words[1] == "load" => Command::LoadPresets(words[2].to_string()),
+        "preset" if line.contains('=') => {
+            match presets::parse_definition(line.trim()["preset".len()..].trim()) {
+                Ok((name, commands)) => Command::DefinePreset(name, commands),
+                Err(e) => Command::Invalid(e),
+            }
+        },
+        "preset" if words.len() == 2 => Command::ApplyPreset(words[1].to_string()),
+        "preset" => Command::Invalid("Usage: preset [<name> | <name> = <command>; ... | load <file>]".to_string()),
+
+        "set" if words.get(1) == Some(&"namefmt") => {
+            match line.trim()["set".len()..].trim()["namefmt".len()..].trim().trim_matches('"') {
+                "" => Command::SetNameFormat(None),
+                template => Command::SetNameFormat(Some(template.to_string())),
+            }
+        },
+        "set" if words.get(1) == Some(&"virtual") => {
+            match words.get(2) {
+                None => Command::SetVirtualMode(None),
+                Some(name) => match VirtualMode::parse(name) {
+                    Some(mode) => Command::SetVirtualMode(Some(mode)),
+                    None => Command::Invalid("Usage: set virtual [conservative|refined|ignore]".to_string()),
+                },
+            }
+        },
+        "set" => Command::Invalid("Usage: set namefmt [\"<template>\"|default] | set virtual [conservative|refined|ignore]".to_string()),
+
         "verbose" => {
             if let Ok(n) = words[1].parse::<u32>() {
                 Command::SetVerbose(n)
@@ -317,9 +892,11 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
             }
         },
 
+        other if ctx.plugins.contains_key(other) => Command::Plugin(other.to_string()),
+
         other => {
-            if &words[0][0..1] == "#" {
-                match other[1..].parse::<u32>() {
+            if let Some(id) = other.strip_prefix('#') {
+                match id.parse::<u32>() {
                     Ok(n) => Command::ResolveId(n),
                     Err(_) => Command::Invalid("Invalid function id".to_string())
                 }
@@ -341,90 +918,1272 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
         Command::SetVerbose(n) => {
             ctx.verbosity = n
         },
+        Command::SetNameFormat(None) => {
+            println!("namefmt \"{}\"", cg.name_format());
+        },
+        Command::SetNameFormat(Some(template)) => {
+            let template = if template == "default" { None } else { Some(template.as_str()) };
+            if let Err(e) = cg.set_name_format(template) {
+                println!("{}", e);
+            }
+        },
+        Command::SetVirtualMode(None) => {
+            println!("virtual calls are {}, with {} possible implementations ({} allowed by the class hierarchy)",
+                     cg.virtual_mode().name(), cg.dispatch.len(), cg.dispatch.values().filter(|&&allowed| allowed).count());
+        },
+        Command::SetVirtualMode(Some(mode)) => {
+            if mode == VirtualMode::Refined && cg.subclasses.is_empty() {
+                println!("No type info loaded (--type-info), so only methods of the called class itself are allowed");
+            }
+            cg.set_virtual_mode(mode);
+        },
         Command::DumpGraph => {
-            println!("{:?}", cg.graph);
+            if cg.show_synthetic {
+                println!("{:?}", cg.graph);
+            } else {
+                for idx in cg.node_ids() {
+                    println!("#{} {}", idx.index(), cg.graph[idx]);
+                    for edge in cg.callee_edges(idx) {
+                        println!("  -> {}", cg.describe_edge(edge, DescriptionBrevity::Normal));
+                    }
+                }
+            }
         },
         Command::DumpStems => {
             println!("{:?}", cg.stem_table);
         },
-        Command::Resolve(pattern) => {
-            match cg.resolve(pattern.as_ref()) {
-                Some(matches) => {
-                    for idx in &matches {
-                        println!("{}", cg.name(*idx, DescriptionBrevity::Verbose));
-                    }
-                    if matches.len() == 1 {
-                        ctx.active_function = Some(matches[0]);
-                    }
-                    if matches.len() > 0 {
-                        ctx.active_functions = Some(matches);
+        Command::DrawRoute(ascii) => {
+            match &ctx.last_route {
+                Some((nodes, edges)) => print!("{}", draw::route(cg, if ascii { &draw::ASCII } else { &draw::UNICODE }, nodes[0], edges)),
+                None => println!("No route to draw; find one with 'route' first"),
+            }
+        },
+        Command::DrawAround(ascii, func, depth) => {
+            if let Some(idx) = resolve_single(cg, Some(&func), ctx, "function") {
+                print!("{}", draw::around(cg, if ascii { &draw::ASCII } else { &draw::UNICODE }, idx, depth));
+                ctx.active_function = Some(idx);
+            }
+        },
+        Command::SuggestAnnotations(file) => {
+            let (what, calls) = match &ctx.last_calls {
+                Some((_, calls)) if calls.is_empty() => {
+                    println!("The last route has no calls to annotate");
+                    return CommandResult::Nothing;
+                },
+                Some(last) => last,
+                None => {
+                    println!("No route or cut to annotate yet");
+                    return CommandResult::Nothing;
+                },
+            };
+            let stubs = suggest::annotation_stubs(cg, calls);
+            match file {
+                Some(file) => {
+                    let appended = std::fs::OpenOptions::new().create(true).append(true).open(&file)
+                        .and_then(|mut f| std::io::Write::write_all(&mut f, stubs.as_bytes()));
+                    match appended {
+                        Ok(()) => println!("appended suggestions for the last {} to {}", what, file),
+                        Err(e) => println!("failed to write {}: {}", file, e),
                     }
                 },
                 None => {
-                    println!("Unable to resolve '{}'", words[1]);
-                }
+                    // Any one of a route's calls would do; a cut needs all of them.
+                    match *what {
+                        "cut" => println!("// Ignore all of these to cut every route:"),
+                        _ => println!("// Ignoring any one of these would break this route (others may remain):"),
+                    }
+                    print!("{}", stubs);
+                },
             }
         },
-        Command::Callees(opt_pattern) => {
-            if let Some(pattern) = opt_pattern {
-                show_callees(cg, Some(&pattern), ctx);
-            } else {
-                show_callees(cg, None, ctx);
+        Command::Review(None) => ctx.output_log.list(),
+        Command::Review(Some(n)) => ctx.output_log.review(n),
+        Command::SearchOutput(pattern) => {
+            if let Err(e) = ctx.output_log.search(&pattern) {
+                println!("invalid regex: {}", e);
             }
         },
-        Command::Callers(opt_pattern) => {
-            if let Some(pattern) = opt_pattern {
-                show_callers(cg, Some(&pattern), ctx);
-            } else {
-                show_callers(cg, None, ctx);
+        Command::Fingerprint => {
+            println!("{:016x}", cg.fingerprint());
+        },
+        Command::Memory => {
+            let report = cg.memory_report();
+            for (what, bytes) in &report {
+                println!("{:>10} {}", format_bytes(*bytes), what);
             }
+            println!("{:>10} total", format_bytes(report.iter().map(|(_, bytes)| bytes).sum()));
         },
-        Command::Route(args) => {
-            let src = match resolve_multi(cg, &args[1], ctx, "source") {
-                None => return CommandResult::Nothing,
-                Some(res) => res,
+        Command::Bench(baseline_file) => {
+            let baseline = match baseline_file.map(|file| bench::load_baseline(&file)) {
+                Some(Err(e)) => {
+                    println!("failed to read baseline: {}", e);
+                    return CommandResult::Nothing;
+                },
+                Some(Ok(b)) => Some(b),
+                None => None,
             };
-            let dst = match resolve_multi(cg, &args[2], ctx, "destination") {
-                None => return CommandResult::Nothing,
-                Some(res) => HashSet::<NodeIndex>::from_iter(res)
+            bench::print_table(&bench::run(cg, &ctx.filename), baseline.as_ref());
+        },
+        Command::BenchSave(file) => {
+            let results = bench::run(cg, &ctx.filename);
+            bench::print_table(&results, None);
+            match bench::save(&results, &file) {
+                Ok(()) => println!("saved to {}", file),
+                Err(e) => println!("failed to save {}: {}", file, e),
+            }
+        },
+        Command::RouteHistory => ctx.route_history.list(),
+        Command::RecallRoute(key) => {
+            let route = match ctx.route_history.recall(&key) {
+                Some(route) => route,
+                None => {
+                    println!("No route {}; see 'routes history'", key);
+                    return CommandResult::Nothing;
+                },
             };
-            if let Some((avoid_funcs, avoid_attributes)) = resolve_avoid(cg, &args[3], ctx, "avoided function") {
-                let mut avoid = HashSet::from_iter(avoid_funcs);
-                avoid.extend(&ctx.avoid_functions);
-                print_route(cg, cg.any_route_from_one_of(&src, &dst, &avoid,
-                                                         avoid_attributes.unwrap_or(0) | ctx.avoid_attributes));
+            println!("{}", route.query);
+            print_route(cg, Some(route.edges.clone()), false);
+            ctx.active_functions = Some(route.nodes.clone());
+            ctx.last_calls = Some(("route", route.edges.clone()));
+            ctx.last_route = Some((route.nodes, route.edges));
+        },
+        Command::SaveRoute(name) => {
+            if !ctx.route_history.save(&name) {
+                println!("No route to save");
             }
         },
-        Command::Filter(negate, filter) => {
-            let _myformat = "https://example.com/?query={mangled}";
-            if let Some(active) = &mut ctx.active_functions {
-                active.retain(|idx| filter.is_match(cg, *idx) != negate);
-                for idx in active {
-                    println!("{}", cg.name(*idx, DescriptionBrevity::Normal));
-                }
-            } else {
-                println!("No functions are active");
+        Command::DiffRoutes(a, b) => {
+            match (ctx.route_history.get(&a), ctx.route_history.get(&b)) {
+                (Some(a), Some(b)) => routehistory::diff(cg, a, b),
+                (None, _) => println!("No route {}; see 'routes history'", a),
+                (_, None) => println!("No route {}; see 'routes history'", b),
             }
         },
-        Command::ListAvoids => {
-            match ctx.avoid_functions.len() {
-                0 => println!("Avoiding attributes [{}]", cg.describe_property_set(ctx.avoid_attributes)),
+        Command::ExportRoutes(args) => {
+            let format = match args[1].rsplit_once('.').map(|(_, ext)| ext) {
+                Some("dot") | Some("gv") => "dot",
+                Some("json") => "json",
                 _ => {
-                    println!("Avoiding attributes [{}] and functions:", cg.describe_property_set(ctx.avoid_attributes));
-                    for idx in &ctx.avoid_functions {
-                        println!("  {}", cg.name(*idx, DescriptionBrevity::Normal));
+                    println!("don't know how to write {}; use .dot or .json", args[1]);
+                    return CommandResult::Nothing;
+                },
+            };
+            let src = match resolve_multi(cg, &args[2], ctx, "source") {
+                Some(src) => src,
+                None => return CommandResult::Nothing,
+            };
+            let dst : HashSet<NodeIndex> = match resolve_multi(cg, &args[3], ctx, "destination") {
+                Some(dst) => dst.into_iter().collect(),
+                None => return CommandResult::Nothing,
+            };
+            let (avoid_funcs, avoid_attributes) = match resolve_avoid(cg, &args[4], ctx, "avoided function") {
+                Some(avoidances) => avoidances,
+                None => return CommandResult::Nothing,
+            };
+            let mut avoid : HashSet<NodeIndex> = avoid_funcs.into_iter().collect();
+            avoid.extend(ctx.avoided_functions(cg));
+            let avoid_props = avoid_attributes.unwrap_or(0) | ctx.avoid_attributes;
+            let count = args[5].parse::<usize>().unwrap_or(2);
+
+            let mut routes : Vec<export::PaintedRoute> = cg.alternative_routes(&src, &dst, &avoid, avoid_props, PropertyMatch::All, count)
+                .into_iter().enumerate().map(|(i, edges)| export::PaintedRoute {
+                    label: if i == 0 { "chosen".to_string() } else { format!("alternative {}", i) },
+                    edges,
+                }).collect();
+            // What the avoidances ruled out, which is why the others detour.
+            if !avoid.is_empty() || avoid_props != 0 {
+                if let Some(edges) = cg.any_route_from_one_of(&src, &dst, &HashSet::new(), 0, PropertyMatch::All) {
+                    if !routes.iter().any(|route| route.edges == edges) {
+                        routes.push(export::PaintedRoute { label: "without avoidances".to_string(), edges });
                     }
                 }
+            }
+            if routes.is_empty() {
+                println!("No route found");
+                return CommandResult::Nothing;
+            }
+            let text = match format {
+                "dot" => export::routes_dot(cg, &routes, &avoid, avoid_props),
+                _ => export::routes_json(cg, &routes, &avoid, avoid_props).pretty(2),
             };
+            match std::fs::write(&args[1], text) {
+                Ok(()) => {
+                    for route in &routes {
+                        println!("{}: {} calls", route.label, route.edges.len());
+                    }
+                    println!("wrote {} routes to {}", routes.len(), args[1]);
+                },
+                Err(e) => println!("failed to write {}: {}", args[1], e),
+            }
         },
-        Command::Avoid(only, args) => {
-            if let Some((avoid_functions, avoid_attributes)) = resolve_avoid(cg, &args, ctx, "avoidances") {
-                if !avoid_functions.is_empty() && only {
-                    ctx.avoid_functions.clear();
-                }
-                ctx.avoid_functions.extend(avoid_functions);
-                if avoid_attributes.is_some() && only {
-                    ctx.avoid_attributes = 0;
+        Command::Namespaces => {
+            // Avoided functions are left out, to see the structure without
+            // eg the logging or assertion machinery everything calls.
+            let matrix = cg.namespace_matrix(&HashSet::from_iter(ctx.avoided_functions(cg).iter().cloned()));
+            let label = |i : usize| match matrix.namespaces[i].0.as_str() {
+                "" => "(none)",
+                ns => ns,
+            };
+            for (i, (_, functions)) in matrix.namespaces.iter().enumerate().take(20) {
+                println!("{:>8} functions {:>8} internal calls  {}", functions, matrix.calls.get(&(i, i)).unwrap_or(&0), label(i));
+            }
+            if matrix.namespaces.len() > 20 {
+                println!("  ...and {} more namespaces", matrix.namespaces.len() - 20);
+            }
+            let mut between : Vec<(&(usize, usize), &usize)> = matrix.calls.iter().filter(|((i, j), _)| i != j).collect();
+            between.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            println!("most calls between namespaces:");
+            for (&(i, j), count) in between.iter().take(20) {
+                println!("{:>8} {} -> {}", count, label(i), label(j));
+            }
+        },
+        Command::ExportNamespaces(file) => {
+            let matrix = cg.namespace_matrix(&HashSet::from_iter(ctx.avoided_functions(cg).iter().cloned()));
+            let text = match file.rsplit_once('.').map(|(_, ext)| ext) {
+                Some("csv") => export::namespaces_csv(&matrix),
+                Some("dot") | Some("gv") => export::namespaces_dot(&matrix),
+                _ => {
+                    println!("don't know how to write {}; use .csv or .dot", file);
+                    return CommandResult::Nothing;
+                },
+            };
+            match std::fs::write(&file, text) {
+                Ok(()) => println!("wrote {} namespaces to {}", matrix.namespaces.len(), file),
+                Err(e) => println!("failed to write {}: {}", file, e),
+            }
+        },
+        Command::ExportHtml(file) => {
+            // The active functions and the calls between them, or just the
+            // calls along the route if they came from one.
+            let (title, nodes, edges) = match (&ctx.last_route, &ctx.active_functions) {
+                (Some((nodes, edges)), Some(active)) if nodes == active => {
+                    ("route".to_string(), nodes.clone(), edges.clone())
+                },
+                (_, Some(active)) => {
+                    (format!("{} functions", active.len()), active.clone(), export::subgraph_edges(cg, active))
+                },
+                (_, None) => {
+                    println!("No route or active functions to export");
+                    return CommandResult::Nothing;
+                }
+            };
+            if nodes.len() > MAX_EXPORT_NODES {
+                println!("{} functions is too many to lay out; filter them down to at most {}", nodes.len(), MAX_EXPORT_NODES);
+                return CommandResult::Nothing;
+            }
+            match std::fs::write(&file, export::html(cg, &title, &nodes, &edges)) {
+                Ok(()) => println!("wrote {} functions and {} calls to {}", nodes.len(), edges.len(), file),
+                Err(e) => println!("failed to write {}: {}", file, e),
+            }
+        },
+        Command::Flamegraph(query, file, samples_file) => {
+            let samples = match samples_file.map(|f| flamegraph::load_samples(&f)) {
+                Some(Err(e)) => {
+                    println!("failed to read samples: {}", e);
+                    return CommandResult::Nothing;
+                },
+                Some(Ok(samples)) => Some(samples),
+                None => None,
+            };
+            if let Some(func) = resolve_single(cg, Some(&query), ctx, "function") {
+                let tree = flamegraph::build(cg, func, samples.as_ref());
+                // SVG, or collapsed stacks for other tools.
+                let output = if file.ends_with(".svg") {
+                    flamegraph::svg(cg, &tree)
+                } else {
+                    flamegraph::collapsed(cg, &tree)
+                };
+                match std::fs::write(&file, output) {
+                    Ok(()) => println!("wrote {}", file),
+                    Err(e) => println!("failed to write {}: {}", file, e),
+                }
+            }
+        },
+        Command::Resolve(ref pattern) if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') => {
+            // Regex matches can cover much of the graph, so show them as
+            // they're found.
+            match Matcher::new(pattern) {
+                Some(matcher) => {
+                    let matches = stream_functions(cg, cg.node_ids().filter(|&idx| matcher.is_match(cg, idx)),
+                                                   DescriptionBrevity::Verbose);
+                    if matches.len() == 1 {
+                        ctx.active_function = Some(matches[0]);
+                    }
+                    if !matches.is_empty() {
+                        ctx.active_functions = Some(matches);
+                    }
+                },
+                None => println!("invalid regex: {}", pattern),
+            }
+        },
+        Command::Reachable(direction, query, filter) => {
+            // The filter is a named set (eg "uncovered" for @uncovered) or
+            // failing that, any query expression.
+            let keep : Option<HashSet<NodeIndex>> = match filter {
+                None => None,
+                Some(filter) => {
+                    match cg.resolve(&format!("@{}", filter)).map(Ok).unwrap_or_else(|| cg.query(&filter)) {
+                        Ok(funcs) => Some(funcs.into_iter().collect()),
+                        Err(e) => {
+                            println!("invalid filter: {}", e);
+                            return CommandResult::Nothing;
+                        },
+                    }
+                },
+            };
+            if let Some(func) = resolve_single(cg, Some(&query), ctx, "function") {
+                let avoid = HashSet::from_iter(ctx.avoided_functions(cg).iter().cloned());
+                let mut walk = ReachableWalk::new(&[func], direction, None, avoid, ctx.avoid_attributes);
+                let reached = std::iter::from_fn(|| walk.next(cg)).filter(|idx| keep.as_ref().is_none_or(|keep| keep.contains(idx)));
+                let reached = stream_functions(cg, reached, DescriptionBrevity::Normal);
+                println!("{} functions", reached.len());
+                if !reached.is_empty() {
+                    ctx.active_functions = Some(reached);
+                }
+            }
+        },
+        Command::Components(query) => {
+            let components = cg.components();
+            match query {
+                None => {
+                    let isolated = components.iter().filter(|c| c.functions.len() == 1).count();
+                    println!("{} components ({} isolated functions)", components.len(), isolated);
+                    for (i, component) in components.iter().take_while(|c| c.functions.len() > 1).enumerate().take(20) {
+                        println!("{:>4} {:>8} functions around {}", i + 1, component.functions.len(),
+                                 cg.name(component.representative, DescriptionBrevity::Normal));
+                    }
+                    if components.len() - isolated > 20 {
+                        println!("  ...and {} more", components.len() - isolated - 20);
+                    }
+                },
+                // Which piece each function is in, eg to see why there's no
+                // route between them.
+                Some(query) => {
+                    if let Some(funcs) = resolve_all(cg, &query, ctx, "function") {
+                        for idx in funcs {
+                            if let Some((i, component)) = components.iter().enumerate().find(|(_, c)| c.functions.binary_search(&idx).is_ok()) {
+                                println!("{} is in component {} ({} functions, around {})", cg.name(idx, DescriptionBrevity::Normal),
+                                         i + 1, component.functions.len(), cg.name(component.representative, DescriptionBrevity::Normal));
+                            }
+                        }
+                    }
+                },
+            }
+        },
+        Command::Ring(direction, query, hops) => {
+            if let Some(func) = resolve_single(cg, Some(&query), ctx, "function") {
+                let avoid = HashSet::from_iter(ctx.avoided_functions(cg).iter().cloned());
+                let ring = cg.ring(func, direction, hops, &avoid, ctx.avoid_attributes);
+                for idx in &ring {
+                    println!("{}", cg.name(*idx, DescriptionBrevity::Normal));
+                }
+                println!("{} functions {} {} away", ring.len(), hops, if hops == 1 { "call" } else { "calls" });
+                if !ring.is_empty() {
+                    ctx.active_functions = Some(ring);
+                }
+            }
+        },
+        Command::Expand(direction, query, depth, count_only) => {
+            if let Some(func) = resolve_single(cg, Some(&query), ctx, "function") {
+                let avoid = HashSet::from_iter(ctx.avoided_functions(cg).iter().cloned());
+                let tree = cg.expand(func, direction, depth, &avoid, ctx.avoid_attributes);
+                if !count_only {
+                    for &(idx, distance) in &tree {
+                        println!("{:>3} {}{}", distance, "  ".repeat(distance), cg.name(idx, DescriptionBrevity::Normal));
+                    }
+                }
+                let mut counts = vec![0; depth + 1];
+                for &(_, distance) in &tree {
+                    counts[distance] += 1;
+                }
+                for (distance, count) in counts.iter().enumerate().skip(1).filter(|(_, &count)| count > 0) {
+                    println!("depth {}: {} functions", distance, count);
+                }
+                println!("{} {} within {} {}", tree.len() - 1, if direction == Direction::Callees { "callees" } else { "callers" },
+                         depth, if depth == 1 { "call" } else { "calls" });
+                if tree.len() > 1 {
+                    ctx.active_functions = Some(tree.iter().skip(1).map(|&(idx, _)| idx).collect());
+                }
+            }
+        },
+        Command::Impact(query) => {
+            if let Some(func) = resolve_single(cg, query.as_deref(), ctx, "function") {
+                let impact = cg.impact(func);
+                println!("{} transitive callers of {}", impact.callers.len(), cg.name(func, DescriptionBrevity::Normal));
+                for (ns, count) in impact.namespaces.iter().take(20) {
+                    println!("{:>10} {}", count, if ns.is_empty() { "(no namespace)" } else { ns });
+                }
+                if impact.namespaces.len() > 20 {
+                    println!("  ...and {} more namespaces", impact.namespaces.len() - 20);
+                }
+                println!("reachable from {} roots:", impact.roots.len());
+                let roots = stream_functions(cg, impact.roots.into_iter(), DescriptionBrevity::Normal);
+                if !roots.is_empty() {
+                    ctx.active_functions = Some(roots);
+                }
+            }
+        },
+        Command::Cycle(query) => {
+            if let Some(func) = resolve_single(cg, query.as_deref(), ctx, "function") {
+                let avoid = HashSet::from_iter(ctx.avoided_functions(cg).iter().cloned());
+                match cg.cycle_route(func, &avoid, ctx.avoid_attributes) {
+                    Some(route) => print_route(cg, Some(route), false),
+                    None => println!("{} is not part of a cycle", cg.name(func, DescriptionBrevity::Normal)),
+                }
+            }
+        },
+        Command::Cut(entries, goal) => {
+            let entries = match resolve_all(cg, &entries, ctx, "entry point") {
+                Some(entries) => entries,
+                None => return CommandResult::Nothing,
+            };
+            let goal : HashSet<NodeIndex> = match goal {
+                Some(goal) => match resolve_all(cg, &goal, ctx, "GC function") {
+                    Some(goal) => goal.into_iter().collect(),
+                    None => return CommandResult::Nothing,
+                },
+                None if !cg.gc_functions.is_empty() => cg.gc_functions.clone(),
+                None => {
+                    println!("No GC functions known; load --gc-functions or give 'to <func>'");
+                    return CommandResult::Nothing;
+                },
+            };
+            match cg.gc_cut(&entries, &goal, &interrupted) {
+                Ok(cut) if cut.is_empty() => println!("No route from the entry points; nothing to cut"),
+                Ok(cut) => {
+                    println!("suppressing {} calls would cut every route:", cut.len());
+                    for &edge in &cut {
+                        let src = cg.graph.edge_endpoints(edge).unwrap().0;
+                        println!("{} -> {}", cg.name(src, DescriptionBrevity::Normal), cg.describe_edge(edge, DescriptionBrevity::Normal));
+                    }
+                    ctx.last_calls = Some(("cut", cut));
+                },
+                Err(e) => println!("{}", e),
+            }
+        },
+        Command::CompareAvoid(args) => {
+            let origins = match resolve_multi(cg, &args[3], ctx, "source") {
+                Some(origins) => origins,
+                None => return CommandResult::Nothing,
+            };
+            // The current avoidances apply to both.
+            let mut configs = vec![];
+            for spec in &args[1..3] {
+                let (funcs, props) = match resolve_avoid(cg, spec, ctx, "avoided function") {
+                    Some(avoid) => avoid,
+                    None => return CommandResult::Nothing,
+                };
+                let mut config = AvoidConfig { functions: funcs.into_iter().collect(), props: props.unwrap_or(0) | ctx.avoid_attributes };
+                config.functions.extend(ctx.avoided_functions(cg));
+                configs.push(config);
+            }
+            let goal : Option<HashSet<NodeIndex>> = if !args[4].is_empty() {
+                match resolve_multi(cg, &args[4], ctx, "goal") {
+                    Some(goal) => Some(goal.into_iter().collect()),
+                    None => return CommandResult::Nothing,
+                }
+            } else if !cg.gc_functions.is_empty() {
+                Some(cg.gc_functions.clone())
+            } else {
+                None
+            };
+
+            let diff = cg.reach_diff(&origins, &configs[0], &configs[1]);
+            println!("{} functions reachable avoiding '{}', {} avoiding '{}'", diff.reached_a, args[1], diff.reached_b, args[2]);
+            match goal {
+                Some(goal) => {
+                    let lost = diff.lost.iter().filter(|idx| goal.contains(idx)).count();
+                    let gained = diff.gained.iter().filter(|idx| goal.contains(idx)).count();
+                    println!("{} goal functions are no longer reachable, {} newly reachable", lost, gained);
+                    match cg.any_route_from_one_of(&origins, &goal, &configs[1].functions, configs[1].props, PropertyMatch::All) {
+                        Some(route) => {
+                            println!("The goal is still reachable avoiding '{}':", args[2]);
+                            print_route(cg, Some(route), true);
+                        },
+                        None => println!("Every route to the goal is blocked avoiding '{}'", args[2]),
+                    }
+                },
+                None => {
+                    println!("no longer reachable:");
+                    let lost = stream_functions(cg, diff.lost.into_iter(), DescriptionBrevity::Normal);
+                    if !diff.gained.is_empty() {
+                        println!("newly reachable:");
+                        stream_functions(cg, diff.gained.into_iter(), DescriptionBrevity::Normal);
+                    }
+                    if !lost.is_empty() {
+                        ctx.active_functions = Some(lost);
+                    }
+                },
+            }
+        },
+        Command::Query(expr) => {
+            match cg.query(&expr) {
+                Ok(matches) => {
+                    let matches = stream_functions(cg, matches.into_iter(), DescriptionBrevity::Normal);
+                    println!("{} functions", matches.len());
+                    if !matches.is_empty() {
+                        ctx.active_functions = Some(matches);
+                    }
+                },
+                Err(e) => println!("invalid query: {}", e),
+            }
+        },
+        Command::OnlyVia(names) => {
+            let mut props = 0;
+            for name in names.split(',').map(|n| n.trim()) {
+                match cg.resolve_property(name) {
+                    Some(bit) => props |= bit,
+                    None => {
+                        println!("unknown attribute '{}'", name);
+                        return CommandResult::Nothing;
+                    },
+                }
+            }
+            let matches = stream_functions(cg, cg.only_reachable_via(props).into_iter(), DescriptionBrevity::Normal);
+            println!("{} functions are only reachable through [{}] calls", matches.len(), cg.describe_property_set(props));
+            if !matches.is_empty() {
+                ctx.active_functions = Some(matches);
+            }
+        },
+        Command::Rules(ref text) if text.is_empty() => {
+            for name in cg.named_set_names() {
+                println!("@{} ({} functions)", name, cg.resolve(&format!("@{}", name)).unwrap_or_default().len());
+            }
+        },
+        Command::GoalSet(name, spec) => {
+            match cg.define_goal_set(&name, &spec) {
+                Ok(funcs) => println!("@{} = {} functions", name, funcs.len()),
+                Err(e) => println!("invalid goal set: {}", e),
+            }
+        },
+        Command::LoadGoalSets(file) => {
+            match cg.load_goal_sets(&file) {
+                Ok(defined) => {
+                    for (name, funcs) in defined {
+                        println!("@{} = {} functions", name, funcs.len());
+                    }
+                },
+                Err(e) => println!("failed to load goal sets: {}", e),
+            }
+        },
+        Command::LoadEntryPoints(file) => {
+            match cg.load_entry_points(&file) {
+                Ok(entry_points) => print_entry_points(&entry_points),
+                Err(e) => println!("failed to load entry points: {}", e),
+            }
+        },
+        Command::ClassifyRoots => {
+            let classes = cg.classify_roots();
+            if classes.kinds.is_empty() {
+                println!("No entry points loaded (entrypoints <file>)");
+            }
+            for (kind, roots) in &classes.kinds {
+                println!("{:>8} roots in @{}-entrypoints", roots.len(), kind);
+            }
+            println!("{:>8} roots are no known kind of entry point:", classes.unclassified.len());
+            for idx in classes.unclassified.iter().take(20) {
+                println!("  {}", cg.name(*idx, DescriptionBrevity::Normal));
+            }
+            if classes.unclassified.len() > 20 {
+                println!("  ...and {} more (now the active functions)", classes.unclassified.len() - 20);
+            }
+            if !classes.unclassified.is_empty() {
+                ctx.active_functions = Some(classes.unclassified);
+            }
+        },
+        Command::Rules(text) => {
+            match cg.apply_rules(&text) {
+                Ok(derived) => {
+                    for (name, funcs) in derived {
+                        println!("@{} = {} functions", name, funcs.len());
+                    }
+                },
+                Err(e) => println!("invalid rules: {}", e),
+            }
+        },
+        Command::Tag(add, tag, query) => {
+            if let Some(funcs) = resolve_multi(cg, &query, ctx, "function") {
+                let mut annotations = cg.annotations_mut();
+                for idx in &funcs {
+                    let mangled = &cg.graph[*idx];
+                    let result = if add { annotations.add_tag(mangled, &tag) } else { annotations.remove_tag(mangled, &tag) };
+                    if let Err(e) = result {
+                        println!("failed to save annotations: {}", e);
+                        break;
+                    }
+                }
+                drop(annotations);
+                for idx in &funcs {
+                    println!("{}", cg.name(*idx, DescriptionBrevity::Normal));
+                }
+            }
+        },
+        Command::Note(query, text) => {
+            if let Some(idx) = resolve_single(cg, Some(&query), ctx, "function") {
+                if let Err(e) = cg.annotations_mut().add_note(&cg.graph[idx], &text) {
+                    println!("failed to save annotations: {}", e);
+                }
+                println!("{}", cg.name(idx, DescriptionBrevity::Verbose));
+            }
+        },
+        Command::Unannotate(query) => {
+            if let Some(funcs) = resolve_multi(cg, &query, ctx, "function") {
+                let mut annotations = cg.annotations_mut();
+                for idx in &funcs {
+                    if let Err(e) = annotations.clear(&cg.graph[*idx]) {
+                        println!("failed to save annotations: {}", e);
+                        break;
+                    }
+                }
+                drop(annotations);
+                for idx in &funcs {
+                    println!("{}", cg.name(*idx, DescriptionBrevity::Normal));
+                }
+            }
+        },
+        Command::Annotations(Some(file)) => {
+            match cg.load_annotations(std::path::Path::new(&file)) {
+                Ok(()) => println!("using annotations in {}", file),
+                Err(e) => println!("failed to load annotations from {}: {}", file, e),
+            }
+        },
+        Command::Annotations(None) => {
+            let annotated : Vec<NodeIndex> = {
+                let annotations = cg.annotations();
+                if let Some(path) = annotations.path() {
+                    println!("annotations file: {}", path.display());
+                }
+                let names : HashSet<&str> = annotations.names().collect();
+                cg.node_ids().filter(|&idx| names.contains(cg.graph[idx].as_str())).collect()
+            };
+            for idx in &annotated {
+                println!("{}", cg.name(*idx, DescriptionBrevity::Normal));
+            }
+        },
+        Command::Plugins(Some(manifest)) => {
+            match plugins::load_manifest(&manifest) {
+                Ok(loaded) => {
+                    println!("loaded {} plugin commands", loaded.len());
+                    ctx.plugins.extend(loaded);
+                },
+                Err(e) => println!("failed to load plugins from {}: {}", manifest, e),
+            }
+        },
+        Command::Plugins(None) => {
+            for (name, plugin) in &ctx.plugins {
+                println!("{:<16} {}", name, plugin.help);
+            }
+        },
+        Command::Plugin(name) => {
+            match plugins::run(cg, &name, &ctx.plugins[&name], line, &interrupted) {
+                Ok(Some(funcs)) => {
+                    if !funcs.is_empty() {
+                        ctx.active_functions = Some(funcs);
+                    }
+                },
+                Ok(None) => {},
+                Err(e) => println!("{}: {}", name, e),
+            }
+        },
+        Command::LoadProfile(file) => {
+            match profile::Profile::load(cg, &file) {
+                Ok(profile) => {
+                    println!("matched {} of {} frames; {} functions and {} calls have samples",
+                             profile.matched_frames, profile.matched_frames + profile.unmatched_frames,
+                             profile.self_samples.len(), profile.edge_samples.len());
+                    ctx.profile = Some(profile);
+                },
+                Err(e) => println!("failed to load profile {}: {}", file, e),
+            }
+        },
+        Command::LoadCoverage(file) => {
+            match coverage::load(cg, &file) {
+                Ok(coverage) => {
+                    println!("{} functions covered, {} not ({} records matched no function); see @covered and @uncovered",
+                             coverage.covered.len(), coverage.uncovered.len(), coverage.unmatched);
+                    cg.define_set("covered", coverage.covered);
+                    cg.define_set("uncovered", coverage.uncovered);
+                },
+                Err(e) => println!("failed to load coverage {}: {}", file, e),
+            }
+        },
+        Command::Report(hazards_file, out_file, gc_file) => {
+            let hazards = match report::load_hazards(&hazards_file) {
+                Ok(hazards) => hazards,
+                Err(e) => {
+                    println!("failed to load hazards from {}: {}", hazards_file, e);
+                    return CommandResult::Nothing;
+                },
+            };
+            let index = profile::NameIndex::new(cg);
+            // What counts as GCing: gcFunctions.txt if given, else whatever
+            // the graph knows, else the ends of the paths in hazards.txt.
+            let goal : HashSet<NodeIndex> = match gc_file {
+                Some(gc_file) => match report::load_gc_functions(cg, &index, &gc_file) {
+                    Ok(goal) => goal,
+                    Err(e) => {
+                        println!("failed to load GC functions from {}: {}", gc_file, e);
+                        return CommandResult::Nothing;
+                    },
+                },
+                None if !cg.gc_functions.is_empty() => cg.gc_functions.clone(),
+                None => hazards.iter().filter_map(|h| h.recorded_path.last()).filter_map(|name| report::lookup(&index, name)).collect(),
+            };
+            if goal.is_empty() {
+                println!("No GC functions known; pass gcFunctions.txt");
+                return CommandResult::Nothing;
+            }
+            let entries = report::check(cg, &index, &hazards, &goal, &interrupted);
+            if interrupted() {
+                println!("interrupted after {} of {} hazards", entries.len(), hazards.len());
+            }
+            let text = if out_file.ends_with(".html") || out_file.ends_with(".htm") {
+                report::html(cg, &entries)
+            } else {
+                report::markdown(cg, &entries)
+            };
+            match std::fs::write(&out_file, text) {
+                Ok(()) => println!("wrote report on {} hazards to {}", entries.len(), out_file),
+                Err(e) => println!("failed to write {}: {}", out_file, e),
+            }
+        },
+        Command::Prune(conds, out_file) => {
+            // Functions matching a pattern are dropped, or with a leading !,
+            // the ones not matching it. [PROP,...] drops calls with any of
+            // those properties.
+            let mut drop = vec![];
+            let mut keep = vec![];
+            let mut drop_props = 0;
+            for cond in &conds {
+                if let Some(props) = cond.strip_prefix('[').and_then(|c| c.strip_suffix(']')) {
+                    for prop in props.split(',').filter(|p| !p.is_empty()) {
+                        match cg.resolve_property(prop) {
+                            Some(bit) => drop_props |= bit,
+                            None => {
+                                println!("unknown property '{}'", prop);
+                                return CommandResult::Nothing;
+                            },
+                        }
+                    }
+                    continue;
+                }
+                let (list, pattern) = match cond.strip_prefix('!') {
+                    Some(pattern) => (&mut keep, pattern),
+                    None => (&mut drop, cond.as_str()),
+                };
+                match Matcher::new(pattern) {
+                    Some(matcher) => list.push(matcher),
+                    None => {
+                        println!("invalid pattern '{}'", pattern);
+                        return CommandResult::Nothing;
+                    },
+                }
+            }
+            let mut pruned = cg.retain(|idx| {
+                !drop.iter().any(|m| m.is_match(cg, idx)) && keep.iter().all(|m| m.is_match(cg, idx))
+            });
+            if drop_props != 0 {
+                pruned = pruned.retain_calls(|edge| pruned.graph[edge].any & drop_props == 0);
+            }
+            let result = std::fs::File::create(&out_file).and_then(|file| {
+                let mut out = std::io::BufWriter::new(file);
+                pruned.write_text(&mut out)?;
+                std::io::Write::flush(&mut out)
+            });
+            match result {
+                Ok(()) => println!("wrote {} of {} functions and {} of {} calls to {}",
+                                   pruned.node_ids().count(), cg.node_ids().count(),
+                                   pruned.edge_ids().count(), cg.edge_ids().count(), out_file),
+                Err(e) => println!("failed to write {}: {}", out_file, e),
+            }
+        },
+        Command::Sample(fraction, keep, out_file) => {
+            let keep : Vec<NodeIndex> = match keep {
+                Some(keep) => match resolve_all(cg, &keep, ctx, "kept functions") {
+                    Some(funcs) => funcs,
+                    None => return CommandResult::Nothing,
+                },
+                None => vec![],
+            };
+            let sampled = cg.sample_subgraph(fraction, &keep);
+            let result = std::fs::File::create(&out_file).and_then(|file| {
+                let mut out = std::io::BufWriter::new(file);
+                sampled.write_text(&mut out)?;
+                std::io::Write::flush(&mut out)
+            });
+            match result {
+                Ok(()) => println!("wrote {} of {} functions and {} of {} calls to {}",
+                                   sampled.node_ids().count(), cg.node_ids().count(),
+                                   sampled.edge_ids().count(), cg.edge_ids().count(), out_file),
+                Err(e) => println!("failed to write {}: {}", out_file, e),
+            }
+        },
+        Command::TrimLeaves(keep, out_file) => {
+            let keep : HashSet<NodeIndex> = match keep {
+                Some(keep) => match resolve_all(cg, &keep, ctx, "kept functions") {
+                    Some(funcs) => funcs.into_iter().collect(),
+                    None => return CommandResult::Nothing,
+                },
+                None => HashSet::new(),
+            };
+            let trimmed = cg.trim_leaves(&keep);
+            let result = std::fs::File::create(&out_file).and_then(|file| {
+                let mut out = std::io::BufWriter::new(file);
+                trimmed.write_text(&mut out)?;
+                std::io::Write::flush(&mut out)
+            });
+            match result {
+                Ok(()) => println!("wrote {} of {} functions and {} of {} calls to {}",
+                                   trimmed.node_ids().count(), cg.node_ids().count(),
+                                   trimmed.edge_ids().count(), cg.edge_ids().count(), out_file),
+                Err(e) => println!("failed to write {}: {}", out_file, e),
+            }
+        },
+        Command::HotStats(count) => {
+            if let Some(profile) = &ctx.profile {
+                let mut funcs : Vec<(&NodeIndex, &u64)> = profile.self_samples.iter().collect();
+                funcs.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+                println!("hottest functions (self samples):");
+                for (idx, samples) in funcs.iter().take(count) {
+                    println!("{:>10} {}", samples, cg.name(**idx, DescriptionBrevity::Normal));
+                }
+                let mut edges : Vec<(&EdgeIndex, &u64)> = profile.edge_samples.iter().collect();
+                edges.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+                println!("hottest calls:");
+                for (edge, samples) in edges.iter().take(count) {
+                    let src = cg.graph.edge_endpoints(**edge).unwrap().0;
+                    println!("{:>10} {} -> {}", samples, cg.name(src, DescriptionBrevity::Normal),
+                             cg.describe_edge(**edge, DescriptionBrevity::Normal));
+                }
+            } else {
+                println!("No profile loaded");
+            }
+        },
+        Command::CallStats(count) => {
+            let edges = cg.edge_ids().count();
+            let calls : u64 = cg.edge_ids().map(|edge| cg.call_count(edge) as u64).sum();
+            println!("{} calls in the input, {} distinct ({} made more than once)", calls, edges, cg.call_counts.len());
+            let mut repeated : Vec<(&EdgeIndex, &u32)> = cg.call_counts.iter().collect();
+            repeated.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            if !repeated.is_empty() {
+                println!("most frequent calls:");
+            }
+            for (edge, n) in repeated.iter().take(count) {
+                let src = cg.graph.edge_endpoints(**edge).unwrap().0;
+                println!("{:>10} {} -> {}", n, cg.name(src, DescriptionBrevity::Normal),
+                         cg.describe_edge(**edge, DescriptionBrevity::Normal));
+            }
+        },
+        Command::Names(filter, file) => {
+            // One line per function: id, mangled name, then any unmangled
+            // names, separated by tabs.
+            let matcher = match filter.as_deref().map(Matcher::new) {
+                Some(None) => {
+                    println!("invalid filter '{}'", filter.unwrap());
+                    return CommandResult::Nothing;
+                },
+                Some(matcher) => matcher,
+                None => None,
+            };
+            let mut out : Box<dyn std::io::Write> = match &file {
+                Some(file) => match std::fs::File::create(file) {
+                    Ok(f) => Box::new(std::io::BufWriter::new(f)),
+                    Err(e) => {
+                        println!("failed to write {}: {}", file, e);
+                        return CommandResult::Nothing;
+                    },
+                },
+                None => Box::new(std::io::stdout()),
+            };
+            let mut written = 0;
+            let mut result = Ok(());
+            for idx in cg.node_ids().filter(|&idx| matcher.as_ref().is_none_or(|m| m.is_match(cg, idx))) {
+                if interrupted() {
+                    println!("interrupted");
+                    break;
+                }
+                let mut fields = vec![idx.index().to_string(), cg.graph[idx].clone()];
+                fields.extend(cg.unmangled_names(idx).iter().cloned());
+                result = writeln!(out, "{}", fields.join("\t"));
+                if result.is_err() { break; }
+                written += 1;
+            }
+            match result.and_then(|_| out.flush()) {
+                Ok(()) => match file {
+                    Some(file) => println!("wrote {} functions to {}", written, file),
+                    None => println!("{} functions", written),
+                },
+                Err(e) => println!("failed to write names: {}", e),
+            }
+        },
+        Command::Indirects(pattern) => {
+            let filter = match pattern.as_deref().map(Matcher::new) {
+                Some(None) => {
+                    println!("invalid pattern");
+                    return CommandResult::Nothing;
+                },
+                Some(filter) => filter,
+                None => None,
+            };
+            let targets = cg.indirect_targets(filter.as_ref());
+            for (idx, callers) in &targets {
+                println!("{:>8} callers  {} ({})", callers, cg.name(*idx, DescriptionBrevity::Normal), cg.node_kind(*idx).name());
+            }
+            println!("{} indirect call targets", targets.len());
+            if !targets.is_empty() {
+                ctx.active_functions = Some(targets.into_iter().map(|(idx, _)| idx).collect());
+            }
+        },
+        Command::Resolve(pattern) => {
+            match cg.resolve(pattern.as_ref()) {
+                Some(matches) => {
+                    // Overload sets are easier to read a class at a time.
+                    let mut scopes : BTreeMap<Option<&str>, Vec<NodeIndex>> = BTreeMap::new();
+                    for &idx in &matches {
+                        scopes.entry(cg.scope(idx)).or_default().push(idx);
+                    }
+                    for (scope, funcs) in &scopes {
+                        if scopes.len() > 1 {
+                            println!("{}:", scope.unwrap_or("(global)"));
+                        }
+                        for idx in funcs {
+                            println!("{}{}", if scopes.len() > 1 { "  " } else { "" }, cg.name(*idx, DescriptionBrevity::Verbose));
+                        }
+                    }
+                    if matches.len() == 1 {
+                        ctx.active_function = Some(matches[0]);
+                    }
+                    if matches.len() > 0 {
+                        ctx.active_functions = Some(matches);
+                    }
+                },
+                None => {
+                    println!("Unable to resolve '{}'", pattern);
+                }
+            }
+        },
+        Command::ExplainResolve(pattern) => {
+            match cg.explain_resolve(&pattern) {
+                Some(matches) => {
+                    let mut counts : BTreeMap<&str, usize> = BTreeMap::new();
+                    for (idx, rule) in &matches {
+                        println!("{}: {}", rule, cg.name(*idx, DescriptionBrevity::Verbose));
+                        *counts.entry(rule.as_str()).or_default() += 1;
+                    }
+                    // The tally is what to look at when a pattern matches far
+                    // too much.
+                    if matches.len() > 1 {
+                        println!("{} matches: {}", matches.len(), counts.iter().map(|(rule, n)| format!("{} by {}", n, rule)).collect::<Vec<_>>().join(", "));
+                    }
+                },
+                None => println!("Unable to resolve '{}'", pattern),
+            }
+        },
+        Command::Callees(opt_pattern) => {
+            if let Some(pattern) = opt_pattern {
+                show_callees(cg, Some(&pattern), ctx);
+            } else {
+                show_callees(cg, None, ctx);
+            }
+        },
+        Command::Callers(opt_pattern) => {
+            if let Some(pattern) = opt_pattern {
+                show_callers(cg, Some(&pattern), ctx);
+            } else {
+                show_callers(cg, None, ctx);
+            }
+        },
+        Command::RouteMatrix(args) => {
+            let (mut src, mut dst) = match (resolve_all(cg, &args[1], ctx, "source"), resolve_all(cg, &args[2], ctx, "destination")) {
+                (Some(src), Some(dst)) => (src, dst),
+                _ => return CommandResult::Nothing,
+            };
+            let (avoid_funcs, avoid_attributes) = match resolve_avoid(cg, &args[3], ctx, "avoided function") {
+                Some(avoid) => avoid,
+                None => return CommandResult::Nothing,
+            };
+            let mut avoid = HashSet::from_iter(avoid_funcs);
+            avoid.extend(ctx.avoided_functions(cg));
+            let avoid_props = avoid_attributes.unwrap_or(0) | ctx.avoid_attributes;
+            src.sort();
+            src.dedup();
+            dst.sort();
+            dst.dedup();
+            let matching = if args[4] == "any" { PropertyMatch::Any } else { PropertyMatch::All };
+            let matrix = cg.route_matrix(&src, &dst, &avoid, avoid_props, matching);
+
+            // Goals across the top by id, sources down the side by name.
+            println!("goals:");
+            for &idx in &dst {
+                println!("  {}", cg.name(idx, DescriptionBrevity::Normal));
+            }
+            let labels : Vec<String> = src.iter().map(|&idx| cg.name(idx, DescriptionBrevity::Normal)).collect();
+            let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+            let headers : Vec<String> = dst.iter().map(|idx| format!("#{}", idx.index())).collect();
+            let mut line = format!("{:w$}", "", w = label_width);
+            for header in &headers {
+                line += &format!(" {:>w$}", header, w = header.len().max(3));
+            }
+            println!("{}", line);
+            let mut connected = 0;
+            for (label, row) in labels.iter().zip(&matrix) {
+                let mut line = format!("{:w$}", label, w = label_width);
+                for (header, cell) in headers.iter().zip(row) {
+                    let cell = match cell {
+                        Some(len) => {
+                            connected += 1;
+                            len.to_string()
+                        },
+                        None => "-".to_string(),
+                    };
+                    line += &format!(" {:>w$}", cell, w = header.len().max(3));
+                }
+                println!("{}", line);
+            }
+            println!("{} of {} pairs connected", connected, src.len() * dst.len());
+        },
+        Command::Route(args) => {
+            let mode = VirtualMode::parse(&args[8]).unwrap_or_else(|| cg.virtual_mode());
+            let src = match resolve_multi(cg, &args[1], ctx, "source") {
+                None => return CommandResult::Nothing,
+                Some(res) => res,
+            };
+            let dst = match resolve_multi(cg, &args[2], ctx, "destination") {
+                None => return CommandResult::Nothing,
+                Some(res) => HashSet::<NodeIndex>::from_iter(res)
+            };
+            if let Some((avoid_funcs, avoid_attributes)) = resolve_avoid(cg, &args[3], ctx, "avoided function") {
+                let mut avoid = HashSet::from_iter(avoid_funcs);
+                avoid.extend(ctx.avoided_functions(cg));
+                let avoid_props = avoid_attributes.unwrap_or(0) | ctx.avoid_attributes;
+                // Which call sites an avoided property has to hold for to rule
+                // out an edge; with "both", show the route under each.
+                let matchings = match args[4].as_str() {
+                    "any" => vec![PropertyMatch::Any],
+                    "both" => vec![PropertyMatch::All, PropertyMatch::Any],
+                    _ => vec![PropertyMatch::All],
+                };
+                // Calls with any of these at any call site are ruled out, and
+                // if that leaves no route, the route without the requirement
+                // shows where it fails.
+                let clean = match resolve_avoid(cg, &args[5], ctx, "property") {
+                    Some((_, props)) => props.unwrap_or(0),
+                    None => return CommandResult::Nothing,
+                };
+                if clean != 0 && !args[6].is_empty() {
+                    println!("'requiring clean' and '{}' cannot be combined", args[6].trim());
+                    return CommandResult::Nothing;
+                }
+                let mut routes = vec![];
+                for &matching in &matchings {
+                    routes.push(if clean != 0 {
+                        cg.clean_route(&src, &dst, &avoid, avoid_props, matching, clean, mode)
+                    } else if args[6].is_empty() {
+                        let route = routecache::RouteCache::route(&mut ctx.route_cache, cg, &src, &dst, &avoid, avoid_props, matching, mode);
+                        if let (Some(cache), true) = (&ctx.route_cache, ctx.verbosity > 0) {
+                            println!("searched {} of {} reachable functions", cache.explored, cache.reached);
+                        }
+                        route
+                    } else if args[6] == " frequent" {
+                        // The same trade as for hottest, with call counts
+                        // from the input in place of samples.
+                        let most = cg.call_counts.values().cloned().max().unwrap_or(1);
+                        cg.weighted_route(&src, &dst, &avoid, avoid_props, matching, mode, |edge| (most - cg.call_count(edge) + 1) as u64)
+                    } else if let Some(profile) = &ctx.profile {
+                        // Each call costs the hottest call's count minus its own,
+                        // plus one: the sample-heaviest route, except that an
+                        // extra call has to bring more samples than any single
+                        // call has.
+                        let hottest = profile.edge_samples.values().cloned().max().unwrap_or(0);
+                        cg.weighted_route(&src, &dst, &avoid, avoid_props, matching, mode, |edge| hottest - profile.edge_weight(edge) + 1)
+                    } else {
+                        println!("No profile loaded");
+                        return CommandResult::Nothing;
+                    });
+                }
+                ctx.last_route = routes[0].as_ref().map(|edges| {
+                    let start = match edges.first() {
+                        Some(&edge) => cg.graph.edge_endpoints(edge).unwrap().0,
+                        None => *src.iter().find(|idx| dst.contains(idx)).unwrap(),
+                    };
+                    let mut nodes = vec![start];
+                    nodes.extend(edges.iter().map(|&edge| cg.graph.edge_endpoints(edge).unwrap().1));
+                    (nodes, edges.clone())
+                });
+                if let Some((nodes, edges)) = &ctx.last_route {
+                    ctx.active_functions = Some(nodes.clone());
+                    ctx.last_calls = Some(("route", edges.clone()));
+                    let n = ctx.route_history.push(routehistory::SavedRoute { query: args[0].clone(), nodes: nodes.clone(), edges: edges.clone() });
+                    if ctx.verbosity > 0 {
+                        println!("route {}", n);
+                    }
+                }
+                for (matching, route) in matchings.into_iter().zip(routes) {
+                    if avoid_props != 0 {
+                        let props = cg.describe_property_set(avoid_props);
+                        match matching {
+                            PropertyMatch::All => println!("Skipping calls that are [{}] at every call site:", props),
+                            PropertyMatch::Any => println!("Skipping calls that are [{}] at any call site:", props),
+                        }
+                    }
+                    // Calls that only some call sites make with an avoided
+                    // property get through when matching all of them.
+                    let partial = route.as_ref().map_or(0, |edges| edges.iter().filter(|&&edge| cg.graph[edge].any & avoid_props != 0).count());
+                    if clean != 0 {
+                        let unrestricted = route.is_none().then(|| cg.clean_route(&src, &dst, &avoid, avoid_props, matching, 0, mode));
+                        let props = route.as_ref().map(|edges| cg.combined_props(edges));
+                        print_route(cg, route, !args[7].is_empty());
+                        explain_clean_route(cg, props, unrestricted.flatten(), clean);
+                    } else if route.is_none() && (avoid_props != 0 || !avoid.is_empty()) {
+                        print_route(cg, route, !args[7].is_empty());
+                        print_blockers(cg, &cg.route_blockers(&src, &avoid, avoid_props, matching, mode));
+                    } else {
+                        print_route(cg, route, !args[7].is_empty());
+                    }
+                    if partial > 0 {
+                        println!("({} calls on this route are [{}] at only some call sites; 'matching any' would skip them)",
+                                 partial, cg.describe_property_set(avoid_props));
+                    }
+                }
+            }
+        },
+        Command::Filter(negate, filter) => {
+            let _myformat = "https://example.com/?query={mangled}";
+            if let Some(active) = &mut ctx.active_functions {
+                active.retain(|idx| filter.is_match(cg, *idx) != negate);
+                for idx in active {
+                    println!("{}", cg.name(*idx, DescriptionBrevity::Normal));
+                }
+            } else {
+                println!("No functions are active");
+            }
+        },
+        Command::ListPresets => {
+            if ctx.presets.is_empty() {
+                println!("No presets; define them in {} or with 'preset <name> = <commands>'", CONFIG_FILE);
+            }
+            for (name, commands) in &ctx.presets {
+                println!("{} = {}", name, commands.join("; "));
+            }
+        },
+        Command::LoadPresets(file) => {
+            match presets::load(&file) {
+                Ok(loaded) => {
+                    println!("{} presets", loaded.len());
+                    ctx.presets.extend(loaded);
+                },
+                Err(e) => println!("failed to load presets from {}: {}", file, e),
+            }
+        },
+        Command::DefinePreset(name, commands) => {
+            ctx.presets.insert(name, commands);
+        },
+        Command::ApplyPreset(name) => {
+            let commands = match ctx.presets.get(&name) {
+                Some(commands) => commands.clone(),
+                None => {
+                    println!("No preset named '{}'", name);
+                    return CommandResult::Nothing;
+                },
+            };
+            for command in commands {
+                println!("{}: {}", name, command);
+                if let CommandResult::Quit = process_line(&command, cg, ctx) {
+                    return CommandResult::Quit;
+                }
+            }
+        },
+        Command::ListAvoids => {
+            let attributes = cg.describe_property_set(ctx.avoid_attributes);
+            match ctx.avoided_functions(cg).len() {
+                0 => println!("Avoiding attributes [{}]", attributes),
+                _ => {
+                    println!("Avoiding attributes [{}] and functions:", attributes);
+                    for idx in ctx.avoided_functions(cg) {
+                        println!("  {}", cg.name(*idx, DescriptionBrevity::Normal));
+                    }
+                }
+            };
+        },
+        Command::SaveAvoids(file) => {
+            // Noting which graph these were for, since patterns can match
+            // differently in another.
+            let mut lines = vec![format!("! fingerprint {:016x}", cg.fingerprint())];
+            lines.extend(ctx.avoid_patterns.iter().cloned());
+            if ctx.avoid_attributes != 0 {
+                lines.push(format!("[{}]", cg.describe_property_set(ctx.avoid_attributes)));
+            }
+            match std::fs::write(&file, lines.iter().map(|l| format!("{}\n", l)).collect::<String>()) {
+                Ok(()) => println!("saved {} avoidances to {}", lines.len() - 1, file),
+                Err(e) => println!("failed to write {}: {}", file, e),
+            }
+        },
+        Command::LoadAvoids(file) => {
+            let text = match std::fs::read_to_string(&file) {
+                Ok(text) => text,
+                Err(e) => {
+                    println!("failed to read {}: {}", file, e);
+                    return CommandResult::Nothing;
+                },
+            };
+            // Patterns are kept even if they match nothing here, in case the
+            // list is saved again for a graph where they do.
+            for line in text.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+                if let Some(saved) = line.strip_prefix("! fingerprint ") {
+                    let current = format!("{:016x}", cg.fingerprint());
+                    if saved != current {
+                        println!("warning: {} was saved for a different graph ({}, this one is {}), so it may not avoid the same functions", file, saved, current);
+                    }
+                } else if let Some(attrs) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                    for attrname in attrs.split(',').filter(|a| !a.is_empty()) {
+                        match cg.resolve_property(attrname) {
+                            Some(a) => ctx.avoid_attributes |= a,
+                            None => println!("unknown attribute '{}'", attrname),
+                        }
+                    }
+                } else {
+                    ctx.avoid_patterns.push(line.to_string());
+                }
+            }
+            ctx.avoid_functions = None;
+            println!("avoiding {} functions", ctx.avoided_functions(cg).len());
+        },
+        Command::AvoidFile(file) => {
+            let text = match std::fs::read_to_string(&file) {
+                Ok(text) => text,
+                Err(e) => {
+                    println!("failed to read {}: {}", file, e);
+                    return CommandResult::Nothing;
+                },
+            };
+            // Unlike avoid load, only what this graph has is kept, and what
+            // it doesn't is listed, since a curated list going stale is worth
+            // knowing about.
+            let list = cg.avoid_list(&text);
+            let unresolved : Vec<&str> = list.iter().filter(|(_, found)| found.is_empty()).map(|(pattern, _)| pattern.as_str()).collect();
+            ctx.avoid_patterns.extend(list.iter().filter(|(_, found)| !found.is_empty()).map(|(pattern, _)| pattern.clone()));
+            ctx.avoid_functions = None;
+            println!("added {} of {} patterns from {}, now avoiding {} functions",
+                     list.len() - unresolved.len(), list.len(), file, ctx.avoided_functions(cg).len());
+            if !unresolved.is_empty() {
+                println!("{} patterns matched nothing:", unresolved.len());
+                for pattern in unresolved.iter().take(20) {
+                    println!("  {}", pattern);
+                }
+                if unresolved.len() > 20 {
+                    println!("  ...and {} more", unresolved.len() - 20);
+                }
+            }
+        },
+        Command::Avoid(only, args) => {
+            if let Some((avoid_functions, avoid_attributes)) = resolve_avoid(cg, &args, ctx, "avoidances") {
+                if !avoid_functions.is_empty() && only {
+                    ctx.avoid_patterns.clear();
+                }
+                ctx.avoid_patterns.extend(avoid_patterns(cg, &args));
+                ctx.avoid_functions = None;
+                if avoid_attributes.is_some() && only {
+                    ctx.avoid_attributes = 0;
                 }
                 ctx.avoid_attributes |= avoid_attributes.unwrap_or(0);
             } else {
@@ -433,8 +2192,10 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
             }
         },
         Command::ResolveId(n) => {
-            let name = &cg.graph[NodeIndex::from(n)];
-            println!("#{} = {}", n, name);
+            match cg.resolve_id(n as usize) {
+                Some(idx) => println!("#{} = {}", n, cg.graph[idx]),
+                None => println!("No function #{}", n),
+            }
         },
         Command::Invalid(reason) => {
             println!("{}", reason)
@@ -449,14 +2210,246 @@ fn process_line(line : &str, cg : &Callgraph, ctx : &mut UIContext) -> CommandRe
     CommandResult::Ok
 }
 
-fn main() {
-    // `()` can be used when no completer is required
+// Pick one function for a store-backed command, listing the choices if the
+// name is ambiguous.
+fn resolve_in_store(store : &diskstore::DiskStore, pattern : &str) -> Result<Option<NodeIndex>, LoadError> {
+    let matches = store.resolve(pattern, 20)?;
+    match matches.len() {
+        0 => println!("no function matching {}", pattern),
+        1 => return Ok(Some(matches[0])),
+        _ => {
+            println!("multiple matches for {}:", pattern);
+            for idx in matches {
+                println!("  {}", store.name(idx)?);
+            }
+        },
+    }
+    Ok(None)
+}
+
+// "[PROP,PROP]" -> bits
+fn store_props(store : &diskstore::DiskStore, text : &str) -> Option<u32> {
+    let mut bits = 0;
+    for name in text.trim().trim_start_matches('[').trim_end_matches(']').split(',').filter(|s| !s.is_empty()) {
+        match store.resolve_property(name.trim()) {
+            Some(bit) => bits |= bit,
+            None => {
+                println!("unknown attribute '{}'", name);
+                return None;
+            },
+        }
+    }
+    Some(bits)
+}
+
+// Print a function and the properties of the call to it, if any.
+fn print_store_call(store : &diskstore::DiskStore, idx : NodeIndex, props : Option<PropertySet>) -> Result<(), LoadError> {
+    match props.map(|p| store.describe_props(p)).unwrap_or_default().as_str() {
+        "" => println!("{}", store.name(idx)?),
+        desc => println!("{} [{}]", store.name(idx)?, desc),
+    }
+    Ok(())
+}
+
+// One command of store_repl. Errors are from reading the store.
+fn store_command(store : &diskstore::DiskStore, line : &str) -> Result<(), LoadError> {
+    let words : Vec<&str> = line.split_whitespace().collect();
+    match words.first().cloned() {
+        Some("resolve") if words.len() == 2 => {
+            for idx in store.resolve(words[1], 1000)? {
+                println!("{}", store.name(idx)?);
+            }
+        },
+        Some(cmd @ "callees") | Some(cmd @ "callers") if words.len() == 2 => {
+            let direction = if cmd == "callees" { Direction::Callees } else { Direction::Callers };
+            if let Some(idx) = resolve_in_store(store, words[1])? {
+                for (other, props) in store.neighbors(idx, direction)? {
+                    print_store_call(store, other, Some(props))?;
+                }
+            }
+        },
+        Some("reachable") => {
+            let (rest, avoid) = match line.split_once(" avoiding ") {
+                Some((rest, avoid)) => (rest, store_props(store, avoid)),
+                None => (line, Some(0)),
+            };
+            let words : Vec<&str> = rest.split_whitespace().collect();
+            let (direction, func) = match words[1..] {
+                ["callers", func] => (Direction::Callers, func),
+                [func] => (Direction::Callees, func),
+                _ => {
+                    println!("Usage: reachable [callers] <func> [avoiding [PROP,...]]");
+                    return Ok(());
+                },
+            };
+            if let (Some(idx), Some(avoid)) = (resolve_in_store(store, func)?, avoid) {
+                let reached = store.reachable(&[idx], direction, avoid, &interrupted)?;
+                for other in &reached {
+                    println!("{}", store.name(*other)?);
+                }
+                println!("{} functions{}", reached.len(), if interrupted() { " (interrupted)" } else { "" });
+            }
+        },
+        Some("route") => {
+            let args = match ROUTE_RE.captures(line) {
+                Some(args) => args,
+                None => {
+                    println!("Usage: route from <func1> to <func2> [avoiding [PROP,...]]");
+                    return Ok(());
+                },
+            };
+            let src = resolve_in_store(store, &args[1])?;
+            let dst = resolve_in_store(store, &args[2])?;
+            let avoid = store_props(store, args.get(3).map_or("", |m| m.as_str()));
+            if let (Some(src), Some(dst), Some(avoid)) = (src, dst, avoid) {
+                match store.route(src, &[dst], &[], avoid, PropertyMatch::All, &interrupted) {
+                    Ok(Some(route)) => {
+                        println!("length {} route found:", route.len() - 1);
+                        for (idx, props) in route {
+                            print_store_call(store, idx, props)?;
+                        }
+                    },
+                    Ok(None) => println!("No route found"),
+                    Err(LoadError::Aborted) => println!("interrupted"),
+                    Err(e) => return Err(e),
+                }
+            }
+        },
+        Some(other) => println!("Unrecognized command '{}'", other),
+        None => {},
+    }
+    Ok(())
+}
+
+// Cut-down REPL over a disk-backed graph store (see diskstore.rs). Only
+// lookups and traversals are available; convert a smaller graph to use the
+// rest.
+fn store_repl(store : &diskstore::DiskStore) {
+    println!("{} functions (disk-backed; commands: resolve, callees, callers, reachable, route, quit)", store.function_count());
     let mut rl = Editor::<()>::new();
-    if rl.load_history("history.txt").is_err() {
-        println!("No previous history.");
+    let _ = rl.load_history("history.txt");
+    loop {
+        let line = match rl.readline(">> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => { println!("CTRL-C"); continue; },
+            Err(_) => break,
+        };
+        rl.add_history_entry(line.as_str());
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        let line = line.trim();
+        if line == "quit" || line == "exit" {
+            println!("Bye bye");
+            break;
+        }
+        if let Err(e) = store_command(store, line) {
+            println!("{}", e);
+        }
     }
+    let _ = rl.save_history("history.txt");
+}
 
-    let args: Vec<String> = env::args().collect();
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+
+    // generate <out.txt> [name=value...] writes a synthetic graph and exits;
+    // see testgraph.rs for the parameters.
+    if args.get(1).map(|s| s.as_str()) == Some("generate") {
+        let out = match args.get(2) {
+            Some(out) => out,
+            None => {
+                println!("Usage: cgtraverse generate <out.txt> [functions=N] [fanout=F] [distribution=fixed|uniform|powerlaw] [scc=F] [suppressed=F] [indirect=F] [seed=N]");
+                return;
+            },
+        };
+        let mut params = testgraph::Params::default();
+        for setting in &args[3..] {
+            if let Err(e) = params.set(setting) {
+                println!("{}", e);
+                return;
+            }
+        }
+        match testgraph::write_file(&params, out) {
+            Ok((functions, calls)) => println!("wrote {} functions and {} calls to {}", functions, calls, out),
+            Err(e) => println!("failed to write {}: {}", out, e),
+        }
+        return;
+    }
+
+    // --link <graph> [--link-rules <rules.json>] joins a second graph onto the
+    // first; see link.rs.
+    let mut take_option = |flag : &str| -> Option<String> {
+        let i = args.iter().position(|a| a == flag)?;
+        if i + 1 >= args.len() {
+            return None;
+        }
+        args.remove(i);
+        Some(args.remove(i))
+    };
+    let link_file = take_option("--link");
+    let link_rules_file = take_option("--link-rules");
+    let store_file = take_option("--make-store");
+    let serve_address = take_option("--serve");
+    let gc_functions_file = take_option("--gc-functions");
+    let goals_file = take_option("--goals");
+    let entry_points_file = take_option("--entry-points");
+    let call_sites_file = take_option("--call-sites");
+    let type_info_file = take_option("--type-info");
+    // --avoid-file <list> starts out as if with `avoid file <list>`.
+    let avoid_file = take_option("--avoid-file");
+    // --seed N breaks ties by function id rather than load order, and seeds
+    // anything random, for results that can be compared across runs.
+    let seed = take_option("--seed");
+    // --exclude /^std::/ drops matching functions while loading, and
+    // --bypass-excluded keeps the calls that went through them.
+    let exclude = take_option("--exclude");
+    let bypass_excluded = match args.iter().position(|a| a == "--bypass-excluded") {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    };
+    let show_synthetic = match args.iter().position(|a| a == "--show-synthetic") {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    };
+    // Roots (and sinks) as older versions picked them; see compute_roots.
+    let legacy_roots = match args.iter().position(|a| a == "--legacy-roots") {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    };
+    // Index which functions can reach which, so that impossible routes fail
+    // without a search; see build_reach_index.
+    let reach_index = match args.iter().position(|a| a == "--reach-index") {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    };
+    // Routes stick to direct calls, as if starting with "avoid @indirect".
+    let no_indirect = match args.iter().position(|a| a == "--no-indirect") {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    };
+
+    let seed = match seed.map(|n| n.parse::<u64>()) {
+        None => None,
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            println!("--seed should be an integer");
+            return;
+        },
+    };
 
     let (infile, line_limit) = match &args[..] {
         [_] => {
@@ -471,9 +2464,51 @@ fn main() {
         }
     };
 
+    unsafe {
+        libc::signal(libc::SIGINT, on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+
+    // --make-store <out> converts the graph to a disk-backed store and exits.
+    if let Some(store_file) = store_file {
+        match diskstore::build(infile, &store_file) {
+            Ok((functions, calls)) => println!("wrote {} functions and {} calls to {}", functions, calls, store_file),
+            Err(e) => println!("failed to build graph store: {}", e),
+        }
+        return;
+    }
+
+    if diskstore::is_store(infile) {
+        match diskstore::DiskStore::open(infile) {
+            Ok(store) => store_repl(&store),
+            Err(e) => println!("failed to open graph store: {}", e),
+        }
+        return;
+    }
+
+    // Serving JSON-RPC on stdout, so send the loading chatter to stderr.
+    let saved_stdout = if serve_address.as_deref() == Some("-") {
+        unsafe {
+            let saved = libc::dup(1);
+            libc::dup2(2, 1);
+            Some(saved)
+        }
+    } else {
+        None
+    };
+
+    let node_filter = match exclude.as_deref().map(hazard::node_filter) {
+        None => None,
+        Some(Ok(re)) => Some(re),
+        Some(Err(e)) => {
+            println!("{}", e);
+            return;
+        },
+    };
+    let options = LoadOptions { line_limit, node_filter, bypass_filtered: bypass_excluded, ..Default::default() };
+
     println!("loading {:?}", infile);
 
-    let cg = match load_graph(infile, line_limit) {
+    let mut cg = match load_graph_with_options(infile, &options, None) {
         Ok(x) => x,
         Err(e) => {
             println!("failed to load graph: {}", e);
@@ -481,20 +2516,164 @@ fn main() {
         }
     };
 
+    cg.show_synthetic = show_synthetic;
+
+    // Uses the ids of the graph file, so this has to happen before linking.
+    if let Some(call_sites_file) = call_sites_file {
+        match cg.load_call_sites(&call_sites_file) {
+            Ok(found) => println!("{} call sites", found),
+            Err(e) => println!("failed to load call sites: {}", e),
+        }
+    }
+
+    if let Some(type_info_file) = type_info_file {
+        match cg.load_type_info(&type_info_file) {
+            Ok(classes) => println!("{} classes with subclasses", classes),
+            Err(e) => println!("failed to load type info: {}", e),
+        }
+    }
+
+    if let Some(link_file) = link_file {
+        let rules = match link_rules_file.map(|f| std::fs::read_to_string(&f).map_err(|e| e.to_string()).and_then(|text| link::LinkRules::parse(&text))) {
+            None => link::LinkRules::default(),
+            Some(Ok(rules)) => rules,
+            Some(Err(e)) => {
+                println!("failed to load link rules: {}", e);
+                return;
+            },
+        };
+        println!("loading {:?}", link_file);
+        let other = match load_graph_with_options(&link_file, &options, None) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("failed to load graph: {}", e);
+                return;
+            }
+        };
+        match link::link(&cg, &other, &rules) {
+            Ok((linked, report)) => {
+                println!("linked with {} bridge edges; functions from {:?} start at #{}", report.bridges, link_file, report.offset);
+                cg = linked;
+            },
+            Err(e) => {
+                println!("failed to link graphs: {}", e);
+                return;
+            },
+        }
+    }
+
+    if seed.is_some() {
+        cg.set_seed(seed);
+    }
+    if legacy_roots {
+        cg.set_legacy_roots(true);
+    }
+
+    // Annotations live next to the graph, so they carry over when it is
+    // regenerated.
+    let annotations_file = format!("{}.annotations.json", infile);
+    if let Err(e) = cg.load_annotations(std::path::Path::new(&annotations_file)) {
+        println!("failed to load annotations from {}: {}", annotations_file, e);
+    }
+
+    if let Some(gc_file) = gc_functions_file {
+        let index = profile::NameIndex::new(&cg);
+        match report::load_gc_functions(&cg, &index, &gc_file) {
+            Ok(funcs) => {
+                println!("{} GC functions", funcs.len());
+                cg.annotate_gc(&funcs.into_iter().collect::<Vec<_>>());
+            },
+            Err(e) => println!("failed to load GC functions from {}: {}", gc_file, e),
+        }
+    }
+
+    // After the GC functions, so that goal sets can use gc().
+    if let Some(goals_file) = goals_file {
+        match cg.load_goal_sets(&goals_file) {
+            Ok(defined) => println!("{} goal sets", defined.len()),
+            Err(e) => println!("failed to load goal sets: {}", e),
+        }
+    }
+
+    if let Some(entry_points_file) = entry_points_file {
+        match cg.load_entry_points(&entry_points_file) {
+            Ok(entry_points) => print_entry_points(&entry_points),
+            Err(e) => println!("failed to load entry points: {}", e),
+        }
+    }
+
+    // Last, since most changes to the graph throw the index away.
+    if reach_index {
+        cg.build_reach_index();
+    }
+
+    // --serve <address|-> answers editor queries instead of running the REPL.
+    if let Some(address) = serve_address {
+        use std::io::Write;
+        let _ = std::io::stdout().flush();
+        if let Some(saved) = saved_stdout {
+            unsafe { libc::dup2(saved, 1); }
+        }
+        if let Err(e) = server::serve(&cg, &address) {
+            eprintln!("failed to serve on {}: {}", address, e);
+        }
+        return;
+    }
+
+    let mut rl = Editor::<CallgraphHelper>::new();
+    rl.set_helper(Some(CallgraphHelper { cg: &cg }));
+    if rl.load_history("history.txt").is_err() {
+        println!("No previous history.");
+    }
+
     let mut uicontext = UIContext {
         last_command: String::new(),
         active_function: None,
         active_functions: None,
-        avoid_functions: vec![],
+        last_route: None,
+        avoid_patterns: if no_indirect { vec!["@indirect".to_string()] } else { vec![] },
+        avoid_functions: None,
         avoid_attributes: 0,
         verbosity: 0,
+        filename: infile.to_string(),
+        plugins: BTreeMap::new(),
+        profile: None,
+        route_cache: None,
+        last_calls: None,
+        output_log: outputlog::OutputLog::new(),
+        presets: presets::Presets::new(),
+        route_history: routehistory::RouteHistory::new(),
     };
 
+    if std::path::Path::new(PLUGIN_MANIFEST).exists() {
+        match plugins::load_manifest(PLUGIN_MANIFEST) {
+            Ok(loaded) => uicontext.plugins = loaded,
+            Err(e) => println!("failed to load plugins from {}: {}", PLUGIN_MANIFEST, e),
+        }
+    }
+
+    if std::path::Path::new(CONFIG_FILE).exists() {
+        match presets::load(CONFIG_FILE) {
+            Ok(loaded) => uicontext.presets = loaded,
+            Err(e) => println!("failed to load presets from {}: {}", CONFIG_FILE, e),
+        }
+    }
+
+    if let Some(avoid_file) = avoid_file {
+        process_line(&format!("avoid file {}", avoid_file), &cg, &mut uicontext);
+    }
+
     loop {
         let readline = rl.readline(">> ");
         match readline {
             Ok(line) => {
-                match process_line(&line, &cg, &mut uicontext) {
+                let capture = if outputlog::is_review(&line) { None } else { outputlog::Capture::start() };
+                let result = process_line(&line, &cg, &mut uicontext);
+                if let Some(capture) = capture {
+                    let output = capture.finish();
+                    uicontext.output_log.push(&line, output);
+                }
+                match result {
                     CommandResult::Quit => { break; },
                     _ => {
                         rl.add_history_entry(line);
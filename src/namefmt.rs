@@ -0,0 +1,141 @@
+// Templates for how functions are named in listings, since full Gecko
+// signatures can wrap across several terminal lines. A template is text with
+// fields in braces:
+//
+//   #{id} {qualified} {params:short:40}
+//
+// Fields are id, mangled, unmangled (the first unmangled name, or the mangled
+// one), stem, qualified (the name without parameters or return type), scope,
+// params and kind. After the field, `:N` truncates to N characters and
+// `:short` drops namespaces, so `js::gc::GCRuntime::collect(JS::GCReason)`
+// becomes `collect(GCReason)`. `{{` and `}}` are literal braces.
+
+pub const DEFAULT : &str = "#{id} = {unmangled}";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Id,
+    Mangled,
+    Unmangled,
+    Stem,
+    Qualified,
+    Scope,
+    Params,
+    Kind,
+}
+
+enum Piece {
+    Text(String),
+    Field { field : Field, width : Option<usize>, short : bool },
+}
+
+pub struct NameFormat {
+    pub template : String,
+    pieces : Vec<Piece>,
+}
+
+fn field(name : &str) -> Option<Field> {
+    Some(match name {
+        "id" => Field::Id,
+        "mangled" => Field::Mangled,
+        "unmangled" => Field::Unmangled,
+        "stem" => Field::Stem,
+        "qualified" => Field::Qualified,
+        "scope" => Field::Scope,
+        "params" => Field::Params,
+        "kind" => Field::Kind,
+        _ => return None,
+    })
+}
+
+// Remove every `ident::` qualifier.
+fn strip_namespaces(s : &str) -> String {
+    let mut out = String::new();
+    let mut word = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+        } else if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            word.clear();
+        } else {
+            out += &word;
+            word.clear();
+            out.push(c);
+        }
+    }
+    out + &word
+}
+
+fn truncate(s : String, width : usize) -> String {
+    if s.chars().count() <= width {
+        return s;
+    }
+    let mut out : String = s.chars().take(width.saturating_sub(1)).collect();
+    out.push('…');
+    out
+}
+
+impl NameFormat {
+    pub fn parse(template : &str) -> Result<NameFormat, String> {
+        let mut pieces = vec![];
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                text.push(c);
+                rest = &rest[2..];
+                continue;
+            }
+            if c == '}' {
+                return Err("unmatched '}' in name format".to_string());
+            }
+            if c != '{' {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let end = rest.find('}').ok_or("unterminated '{' in name format")?;
+            let mut parts = rest[1..end].split(':');
+            let name = parts.next().unwrap_or("").trim();
+            let field = field(name).ok_or(format!("unknown field '{}' in name format", name))?;
+            let (mut width, mut short) = (None, false);
+            for option in parts {
+                match option.trim() {
+                    "short" => short = true,
+                    n => width = Some(n.parse::<usize>().map_err(|_| format!("unknown option '{}' for {{{}}}", n, name))?),
+                }
+            }
+            if !text.is_empty() {
+                pieces.push(Piece::Text(std::mem::take(&mut text)));
+            }
+            pieces.push(Piece::Field { field, width, short });
+            rest = &rest[end + 1..];
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(NameFormat { template: template.to_string(), pieces })
+    }
+
+    pub fn render(&self, value : impl Fn(Field) -> String) -> String {
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => out += text,
+                Piece::Field { field, width, short } => {
+                    let mut s = value(*field);
+                    if *short {
+                        s = strip_namespaces(&s);
+                    }
+                    if let Some(width) = width {
+                        s = truncate(s, *width);
+                    }
+                    out += &s;
+                },
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,116 @@
+use regex::Regex;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::thread::JoinHandle;
+
+// What the last few commands printed, so a long listing that scrolled away
+// can be looked at again (`review`) or searched (`search-output`) without
+// redoing the work. Output is captured by pointing stdout at a pipe for the
+// duration of the command, with a thread copying everything on to the
+// terminal as it arrives.
+
+const KEEP : usize = 20;
+
+pub struct OutputLog {
+    // (command number, command line, output), oldest first.
+    entries : VecDeque<(usize, String, String)>,
+    commands : usize,
+}
+
+pub struct Capture {
+    terminal : i32,
+    copier : JoinHandle<Vec<u8>>,
+}
+
+impl Capture {
+    pub fn start() -> Option<Capture> {
+        let _ = std::io::stdout().flush();
+        let mut fds = [0; 2];
+        unsafe {
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return None;
+            }
+            let terminal = libc::dup(1);
+            let copy = libc::dup(terminal);
+            libc::dup2(fds[1], 1);
+            libc::close(fds[1]);
+            let mut pipe = File::from_raw_fd(fds[0]);
+            let mut out = File::from_raw_fd(copy);
+            let copier = std::thread::spawn(move || {
+                let mut seen = vec![];
+                let mut buf = [0u8; 8192];
+                while let Ok(n) = pipe.read(&mut buf) {
+                    if n == 0 { break; }
+                    let _ = out.write_all(&buf[..n]);
+                    seen.extend_from_slice(&buf[..n]);
+                }
+                seen
+            });
+            Some(Capture { terminal, copier })
+        }
+    }
+
+    // Put stdout back and return everything written to it meanwhile.
+    pub fn finish(self) -> String {
+        let _ = std::io::stdout().flush();
+        unsafe {
+            libc::dup2(self.terminal, 1);
+            libc::close(self.terminal);
+        }
+        String::from_utf8_lossy(&self.copier.join().unwrap_or_default()).into_owned()
+    }
+}
+
+// Commands that look at the log rather than adding to it.
+pub fn is_review(line : &str) -> bool {
+    matches!(line.split_whitespace().next(), Some("review") | Some("search-output"))
+}
+
+impl OutputLog {
+    pub fn new() -> OutputLog {
+        OutputLog { entries: VecDeque::new(), commands: 0 }
+    }
+
+    pub fn push(&mut self, line : &str, output : String) {
+        self.commands += 1;
+        if self.entries.len() == KEEP {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((self.commands, line.to_string(), output));
+    }
+
+    pub fn list(&self) {
+        for (n, line, output) in &self.entries {
+            println!("{:>4}: {} ({} lines)", n, line, output.lines().count());
+        }
+    }
+
+    pub fn review(&self, n : usize) {
+        match self.entries.iter().find(|(m, _, _)| *m == n) {
+            Some((_, line, output)) => {
+                println!(">> {}", line);
+                print!("{}", output);
+            },
+            None => println!("no output for command {} (the last {} are kept)", n, KEEP),
+        }
+    }
+
+    // Lines matching `pattern`, a substring or a /regex/.
+    pub fn search(&self, pattern : &str) -> Result<(), String> {
+        let re = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(re) => Regex::new(re).map_err(|e| e.to_string())?,
+            None => Regex::new(&regex::escape(pattern)).unwrap(),
+        };
+        let mut found = 0;
+        for (n, line, output) in &self.entries {
+            for text in output.lines().filter(|text| re.is_match(text)) {
+                println!("{:>4} [{}]: {}", n, line, text);
+                found += 1;
+            }
+        }
+        println!("{} matching lines", found);
+        Ok(())
+    }
+}
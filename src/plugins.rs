@@ -0,0 +1,162 @@
+use crate::callgraph::{Callgraph, DescriptionBrevity};
+use petgraph::graph::NodeIndex;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+// Extra REPL commands implemented by external programs, so that projects can
+// add their own without forking. A manifest lists them:
+//
+//   { "commands": { "hazards": { "exec": ["python3", "hazards.py"],
+//                                "help": "list hazards in a function" } } }
+//
+// The program is started for each use of the command and speaks JSON, one
+// message per line. It is first sent
+//
+//   {"type": "invoke", "command": "hazards", "args": ["foo"], "line": "hazards foo"}
+//
+// and may then write any number of
+//
+//   {"type": "print", "text": "..."}
+//   {"type": "call", "id": 1, "method": "callees", "params": [3]}
+//
+// messages, each call being answered on its stdin with
+// {"type": "result", "id": 1, "result": ...} or {"type": "error", "id": 1,
+// "message": "..."}. It finishes by exiting or by sending
+// {"type": "done", "functions": [...]}, where the optional functions become
+// the active set.
+//
+// Methods: resolve(pattern), query(expr), callees(id), callers(id), name(id),
+// names(id), route(src, dst), count().
+
+pub struct Plugin {
+    pub exec : Vec<String>,
+    pub help : String,
+}
+
+pub fn load_manifest(path : &str) -> Result<BTreeMap<String, Plugin>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let manifest = json::parse(&text).map_err(|e| e.to_string())?;
+    let mut plugins = BTreeMap::new();
+    for (name, entry) in manifest["commands"].entries() {
+        let exec : Vec<String> = entry["exec"].members().filter_map(|s| s.as_str()).map(|s| s.to_string()).collect();
+        if exec.is_empty() {
+            return Err(format!("{}: missing exec", name));
+        }
+        let help = entry["help"].as_str().unwrap_or("").to_string();
+        plugins.insert(name.to_string(), Plugin { exec, help });
+    }
+    Ok(plugins)
+}
+
+fn ids(funcs : &[NodeIndex]) -> json::JsonValue {
+    funcs.iter().map(|idx| idx.index()).collect::<Vec<_>>().into()
+}
+
+fn node_param(cg : &Callgraph, params : &json::JsonValue, i : usize) -> Result<NodeIndex, String> {
+    params[i].as_usize().and_then(|n| cg.resolve_id(n)).
+        ok_or(format!("parameter {} must be a function id", i))
+}
+
+fn string_param(params : &json::JsonValue, i : usize) -> Result<&str, String> {
+    params[i].as_str().ok_or(format!("parameter {} must be a string", i))
+}
+
+fn call(cg : &Callgraph, method : &str, params : &json::JsonValue) -> Result<json::JsonValue, String> {
+    Ok(match method {
+        "resolve" => ids(&cg.resolve(string_param(params, 0)?).unwrap_or_default()),
+        "query" => ids(&cg.query(string_param(params, 0)?)?),
+        "callees" => ids(&cg.callees(node_param(cg, params, 0)?)),
+        "callers" => ids(&cg.callers(node_param(cg, params, 0)?)),
+        "name" => cg.name(node_param(cg, params, 0)?, DescriptionBrevity::Normal).into(),
+        "names" => cg.names(node_param(cg, params, 0)?).into(),
+        "route" => {
+            let src = node_param(cg, params, 0)?;
+            let goal : HashSet<NodeIndex> = [node_param(cg, params, 1)?].iter().cloned().collect();
+            match cg.any_route(src, &goal, &HashSet::new(), 0) {
+                Some(route) => {
+                    let mut nodes = vec![src];
+                    nodes.extend(route.iter().map(|&edge| cg.graph.edge_endpoints(edge).unwrap().1));
+                    ids(&nodes)
+                },
+                None => json::JsonValue::Null,
+            }
+        },
+        "count" => cg.node_ids().count().into(),
+        _ => return Err(format!("unknown method {}", method)),
+    })
+}
+
+// Run a plugin command to completion, returning the functions it wants made
+// active (if any). `interrupted` is polled between messages.
+pub fn run(
+    cg : &Callgraph,
+    name : &str,
+    plugin : &Plugin,
+    line : &str,
+    interrupted : &dyn Fn() -> bool
+) -> Result<Option<Vec<NodeIndex>>, String>
+{
+    let mut child = Command::new(&plugin.exec[0])
+        .args(&plugin.exec[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", plugin.exec[0], e))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+
+    let args : Vec<&str> = line.split_whitespace().skip(1).collect();
+    let invoke = json::object!{ "type" => "invoke", "command" => name, "args" => args, "line" => line };
+    let mut result = writeln!(stdin, "{}", invoke.dump()).map_err(|e| e.to_string()).map(|_| None);
+
+    for message in stdout.lines() {
+        if result.is_err() { break; }
+        if interrupted() {
+            result = Err("interrupted".to_string());
+            break;
+        }
+        let message = match message.map_err(|e| e.to_string()).and_then(|m| json::parse(&m).map_err(|e| e.to_string())) {
+            Ok(m) => m,
+            Err(e) => {
+                result = Err(format!("bad message from plugin: {}", e));
+                break;
+            }
+        };
+        match message["type"].as_str() {
+            Some("print") => println!("{}", message["text"].as_str().unwrap_or("")),
+            Some("call") => {
+                let id = message["id"].clone();
+                let reply = match call(cg, message["method"].as_str().unwrap_or(""), &message["params"]) {
+                    Ok(value) => json::object!{ "type" => "result", "id" => id, "result" => value },
+                    Err(e) => json::object!{ "type" => "error", "id" => id, "message" => e },
+                };
+                if let Err(e) = writeln!(stdin, "{}", reply.dump()) {
+                    result = Err(e.to_string());
+                }
+            },
+            Some("done") => {
+                if message["functions"].is_array() {
+                    let funcs : Vec<NodeIndex> = message["functions"].members()
+                        .filter_map(|v| v.as_usize())
+                        .filter(|&n| n < cg.graph.node_count())
+                        .map(NodeIndex::new)
+                        .collect();
+                    result = Ok(Some(funcs));
+                }
+                break;
+            },
+            other => {
+                result = Err(format!("unknown message type {:?}", other));
+            },
+        }
+    }
+
+    drop(stdin);
+    if result.is_err() {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+    result
+}
@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+
+// Named bundles of commands, mostly avoidances, so that a usual triage setup
+// is one command instead of a pasted block. The config file has one per line:
+//
+//   # Ignore the usual suspects.
+//   preset gc-triage = avoid [GC_SUPPRESSED] and /Assert/ and #2
+//   preset quiet = avoid only @indirect; verbose 0
+//
+// Commands are separated by semicolons, and are run in order by
+// `preset gc-triage`. Lines starting with # are comments.
+
+pub type Presets = BTreeMap<String, Vec<String>>;
+
+// "name = command; command", as after `preset` in the config file or REPL.
+pub fn parse_definition(text : &str) -> Result<(String, Vec<String>), String> {
+    let (name, body) = text.split_once('=').ok_or("expected 'preset <name> = <commands>'")?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("invalid preset name '{}'", name));
+    }
+    let commands : Vec<String> = body.split(';').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect();
+    if commands.is_empty() {
+        return Err(format!("preset {} has no commands", name));
+    }
+    if commands.iter().any(|c| c.split_whitespace().next() == Some("preset")) {
+        return Err(format!("preset {} uses another preset", name));
+    }
+    Ok((name.to_string(), commands))
+}
+
+pub fn load(path : &str) -> Result<Presets, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut presets = Presets::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, commands) = match line.strip_prefix("preset ") {
+            Some(rest) => parse_definition(rest),
+            None => Err("expected 'preset <name> = <commands>'".to_string()),
+        }.map_err(|e| format!("line {}: {}", lineno + 1, e))?;
+        presets.insert(name, commands);
+    }
+    Ok(presets)
+}
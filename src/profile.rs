@@ -0,0 +1,180 @@
+use crate::callgraph::{Callgraph, Direction};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::collections::HashMap;
+use std::fs;
+
+// Sample counts from a real profile, attached to the functions and calls of
+// the graph they match. Understands `perf script` output, callgrind files, and
+// collapsed stacks ("a;b;c 123", as produced by stackcollapse-*.pl). pprof's
+// protobuf format is not read directly; convert to collapsed stacks first.
+
+#[derive(Default)]
+pub struct Profile {
+    // Samples with the function at the top of the stack.
+    pub self_samples : HashMap<NodeIndex, u64>,
+    // Samples (or for callgrind, call counts) passing through each call.
+    pub edge_samples : HashMap<EdgeIndex, u64>,
+    pub matched_frames : u64,
+    pub unmatched_frames : u64,
+}
+
+// Lookup from the various spellings of a function's name that profilers (and
+// coverage tools) use.
+pub struct NameIndex {
+    names : HashMap<String, NodeIndex>,
+}
+
+impl NameIndex {
+    pub fn new(cg : &Callgraph) -> NameIndex {
+        let mut names = HashMap::new();
+        for idx in cg.node_ids() {
+            for name in cg.names(idx) {
+                names.entry(name.to_string()).or_insert(idx);
+                if let Some(paren) = name.find('(') {
+                    names.entry(name[..paren].to_string()).or_insert(idx);
+                }
+            }
+        }
+        NameIndex { names }
+    }
+
+    pub fn get(&self, symbol : &str) -> Option<NodeIndex> {
+        let symbol = symbol.trim();
+        self.names.get(symbol).or_else(|| {
+            symbol.find('(').and_then(|paren| self.names.get(&symbol[..paren]))
+        }).cloned()
+    }
+}
+
+fn find_edge(cg : &Callgraph, src : NodeIndex, dst : NodeIndex) -> Option<EdgeIndex> {
+    cg.neighbor_edges(src, Direction::Callees).find(|&(other, _, _)| other == dst).map(|(_, edge, _)| edge)
+}
+
+impl Profile {
+    // `stack` is ordered outermost caller first.
+    fn add_stack(&mut self, cg : &Callgraph, index : &NameIndex, stack : &[&str], count : u64) {
+        let frames : Vec<Option<NodeIndex>> = stack.iter().map(|frame| index.get(frame)).collect();
+        for frame in &frames {
+            if frame.is_some() { self.matched_frames += count; } else { self.unmatched_frames += count; }
+        }
+        if let Some(Some(leaf)) = frames.last() {
+            *self.self_samples.entry(*leaf).or_insert(0) += count;
+        }
+        // Only adjacent frames that are both known; a gap (eg inlining or an
+        // unknown library) breaks the chain.
+        for pair in frames.windows(2) {
+            if let [Some(caller), Some(callee)] = pair {
+                if let Some(edge) = find_edge(cg, *caller, *callee) {
+                    *self.edge_samples.entry(edge).or_insert(0) += count;
+                }
+            }
+        }
+    }
+
+    fn load_collapsed(&mut self, cg : &Callgraph, index : &NameIndex, text : &str) -> Result<(), String> {
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let (stack, count) = line.rsplit_once(' ').ok_or(format!("bad collapsed stack line: {}", line))?;
+            let count = count.trim().parse::<u64>().map_err(|_| format!("bad sample count: {}", line))?;
+            let frames : Vec<&str> = stack.split(';').collect();
+            self.add_stack(cg, index, &frames, count);
+        }
+        Ok(())
+    }
+
+    // perf script: a header line per sample followed by indented frames,
+    // innermost first, as "<addr> <symbol>+0x<offset> (<dso>)".
+    fn load_perf_script(&mut self, cg : &Callgraph, index : &NameIndex, text : &str) {
+        let mut stack : Vec<&str> = vec![];
+        for line in text.lines().chain(std::iter::once("")) {
+            if line.starts_with(|c : char| c.is_whitespace()) && !line.trim().is_empty() {
+                let frame = line.trim();
+                let frame = frame.split_once(' ').map_or(frame, |(_addr, rest)| rest);
+                let frame = frame.rsplit_once(" (").map_or(frame, |(sym, _dso)| sym);
+                let frame = frame.rsplit_once("+0x").map_or(frame, |(sym, _offset)| sym);
+                stack.push(frame);
+            } else if !stack.is_empty() {
+                stack.reverse();
+                self.add_stack(cg, index, &stack, 1);
+                stack.clear();
+            }
+        }
+    }
+
+    // callgrind: costs are listed under fn= lines, and calls made by that
+    // function as cfn= + calls=<count> lines followed by the inclusive cost.
+    // Names may be compressed to "(id) name" on first use and "(id)" after.
+    fn load_callgrind(&mut self, cg : &Callgraph, index : &NameIndex, text : &str) {
+        let mut compressed : HashMap<String, String> = HashMap::new();
+        let mut expand = |spec : &str| -> String {
+            let spec = spec.trim();
+            if spec.starts_with('(') {
+                if let Some((id, name)) = spec.split_once(')') {
+                    let name = name.trim();
+                    if name.is_empty() {
+                        return compressed.get(id).cloned().unwrap_or_default();
+                    }
+                    compressed.insert(id.to_string(), name.to_string());
+                    return name.to_string();
+                }
+            }
+            spec.to_string()
+        };
+
+        let mut current : Option<NodeIndex> = None;
+        let mut callee : Option<NodeIndex> = None;
+        let mut after_calls = false;
+        for line in text.lines() {
+            if let Some(spec) = line.strip_prefix("fn=") {
+                current = index.get(&expand(spec));
+                if current.is_some() { self.matched_frames += 1; } else { self.unmatched_frames += 1; }
+            } else if let Some(spec) = line.strip_prefix("cfn=") {
+                callee = index.get(&expand(spec));
+            } else if let Some(calls) = line.strip_prefix("calls=") {
+                let count = calls.split_whitespace().next().and_then(|c| c.parse::<u64>().ok()).unwrap_or(0);
+                if let (Some(src), Some(dst)) = (current, callee) {
+                    if let Some(edge) = find_edge(cg, src, dst) {
+                        *self.edge_samples.entry(edge).or_insert(0) += count;
+                    }
+                }
+                after_calls = true;
+            } else if line.starts_with(|c : char| c.is_ascii_digit() || c == '+' || c == '-' || c == '*') {
+                // A cost line: position then event counts. The one after
+                // calls= is the inclusive cost of the call, not self cost.
+                if !after_calls {
+                    if let Some(idx) = current {
+                        let cost = line.split_whitespace().nth(1).and_then(|c| c.parse::<u64>().ok()).unwrap_or(0);
+                        *self.self_samples.entry(idx).or_insert(0) += cost;
+                    }
+                }
+                after_calls = false;
+            } else if line.starts_with("cob=") || line.starts_with("cfi=") || line.starts_with("cfl=") {
+                // Callee object/file; doesn't affect matching by name.
+            } else {
+                after_calls = false;
+            }
+        }
+    }
+
+    pub fn load(cg : &Callgraph, filename : &str) -> Result<Profile, String> {
+        let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        let index = NameIndex::new(cg);
+        let mut profile = Profile::default();
+        let is_callgrind = text.starts_with("# callgrind format") || text.lines().any(|l| l.starts_with("fn="));
+        let is_collapsed = text.lines().filter(|l| !l.trim().is_empty()).all(|l| {
+            !l.starts_with(|c : char| c.is_whitespace()) &&
+                l.rsplit_once(' ').is_some_and(|(_, n)| n.trim().parse::<u64>().is_ok())
+        });
+        if is_callgrind {
+            profile.load_callgrind(cg, &index, &text);
+        } else if is_collapsed {
+            profile.load_collapsed(cg, &index, &text)?;
+        } else {
+            profile.load_perf_script(cg, &index, &text);
+        }
+        Ok(profile)
+    }
+
+    pub fn edge_weight(&self, edge : EdgeIndex) -> u64 {
+        self.edge_samples.get(&edge).cloned().unwrap_or(0)
+    }
+}
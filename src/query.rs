@@ -0,0 +1,173 @@
+// Parser for the little set-algebra query language, eg
+//
+//   callers(resolve("Foo")) & reaches(sinks()) - prop(GC_SUPPRESSED)
+//
+// Every expression denotes a set of functions. `&` (intersection) binds
+// tighter than `|` (union) and `-` (difference), which associate left to
+// right. A bare word where a set is expected means resolve("word"), so @name
+// is a set derived by rules, and #N is the function with id N. Evaluation is
+// Callgraph::query.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Call(String, Vec<Expr>),
+    Word(String),
+    Str(String),
+    Num(u32),
+    Id(u32),
+    Union(Box<Expr>, Box<Expr>),
+    Intersect(Box<Expr>, Box<Expr>),
+    Difference(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Num(u32),
+    Id(u32),
+    Punct(char),
+}
+
+fn is_word_char(c : char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == ':' || c == '~' || c == '*' || c == '.' || c == '@'
+}
+
+fn tokenize(text : &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if "()&|-,".contains(c) {
+            tokens.push(Token::Punct(c));
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => s.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some((_, c)) => s.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else if c == '#' || is_word_char(c) {
+            chars.next();
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, c)) = chars.peek() {
+                if !is_word_char(c) { break; }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &text[start..end];
+            tokens.push(if let Some(id) = word.strip_prefix('#') {
+                Token::Id(id.parse().map_err(|_| format!("invalid function id '{}'", word))?)
+            } else if let Ok(n) = word.parse() {
+                Token::Num(n)
+            } else {
+                Token::Word(word.to_string())
+            });
+        } else {
+            return Err(format!("unexpected '{}' at offset {}", c, start));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens : Vec<Token>,
+    pos : usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, c : char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c : char) -> Result<(), String> {
+        if self.eat(c) { Ok(()) } else { Err(format!("expected '{}'", c)) }
+    }
+
+    // expr := conj (('|' | '-') conj)*
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.conj()?;
+        loop {
+            if self.eat('|') {
+                lhs = Expr::Union(Box::new(lhs), Box::new(self.conj()?));
+            } else if self.eat('-') {
+                lhs = Expr::Difference(Box::new(lhs), Box::new(self.conj()?));
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    // conj := term ('&' term)*
+    fn conj(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        while self.eat('&') {
+            lhs = Expr::Intersect(Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    // term := word '(' [expr (',' expr)*] ')' | word | string | number | #id | '(' expr ')'
+    fn term(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Punct('(')) => {
+                let inner = self.expr()?;
+                self.expect(')')?;
+                Ok(inner)
+            },
+            Some(Token::Word(name)) => {
+                if !self.eat('(') {
+                    return Ok(Expr::Word(name));
+                }
+                let mut args = vec![];
+                if !self.eat(')') {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(')') { break; }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            },
+            Some(Token::Str(s)) => Ok(Expr::Str(s)),
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Id(n)) => Ok(Expr::Id(n)),
+            Some(Token::Punct(c)) => Err(format!("unexpected '{}'", c)),
+            None => Err("unexpected end of query".to_string()),
+        }
+    }
+}
+
+pub fn parse(text : &str) -> Result<Expr, String> {
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0 };
+    let expr = parser.expr()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {:?} after end of query", token)),
+    }
+}
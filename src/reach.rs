@@ -0,0 +1,61 @@
+use crate::callgraph::PropertySet;
+use fixedbitset::FixedBitSet;
+use petgraph::algo::tarjan_scc;
+use petgraph::graph::{Graph, NodeIndex};
+use std::mem::{size_of, size_of_val};
+
+// Precomputed reachability: for every strongly connected component, the set
+// of components reachable from it. Edge properties are ignored, so this can
+// only rule routes out -- a route it allows may still be blocked by avoided
+// functions or properties. Needs O(components^2) bits.
+pub struct ReachIndex {
+    component : Vec<u32>,
+    reach : Vec<FixedBitSet>,
+}
+
+impl ReachIndex {
+    pub fn build(graph : &Graph<String, PropertySet>) -> ReachIndex {
+        // tarjan_scc returns components in reverse topological order, so
+        // everything a component calls has been filled in by the time it is
+        // reached.
+        let sccs = tarjan_scc(graph);
+        let mut component = vec![0u32; graph.node_count()];
+        for (i, scc) in sccs.iter().enumerate() {
+            for idx in scc {
+                component[idx.index()] = i as u32;
+            }
+        }
+
+        let mut reach : Vec<FixedBitSet> = Vec::with_capacity(sccs.len());
+        for (i, scc) in sccs.iter().enumerate() {
+            let mut set = FixedBitSet::with_capacity(sccs.len());
+            set.insert(i);
+            for idx in scc {
+                for callee in graph.neighbors(*idx) {
+                    let c = component[callee.index()] as usize;
+                    if c != i && !set.contains(c) {
+                        for (word, other) in set.as_mut_slice().iter_mut().zip(reach[c].as_slice()) {
+                            *word |= *other;
+                        }
+                    }
+                }
+            }
+            reach.push(set);
+        }
+
+        ReachIndex { component, reach }
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.component.capacity() * size_of::<u32>() +
+            self.reach.capacity() * size_of::<FixedBitSet>() +
+            self.reach.iter().map(|set| size_of_val(set.as_slice())).sum::<usize>()
+    }
+
+    // Whether there might be a route from `src` to `dst` (of any length,
+    // including zero).
+    pub fn may_reach(&self, src : NodeIndex, dst : NodeIndex) -> bool {
+        let (s, d) = (self.component[src.index()] as usize, self.component[dst.index()] as usize);
+        self.reach[s].contains(d)
+    }
+}
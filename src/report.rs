@@ -0,0 +1,268 @@
+use crate::callgraph::{Callgraph, DescriptionBrevity};
+use crate::profile::NameIndex;
+use petgraph::graph::NodeIndex;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+
+// Report on the hazards found by the rooting analysis (hazards.txt), checked
+// against the current graph: does the function still make the GC call, can
+// that call still GC, by what route, and where could an annotation (a
+// GC-suppressing guard, or marking a function as not GCing) cut every route
+// at once.
+//
+// The analysis writes names as "mangled$unmangled" and follows each hazard
+// with the call path it found, as
+//
+//   GC Function: <the GC call>
+//       <callee>
+//       ...
+//
+// gcFunctions.txt uses the same "GC Function:" lines to list everything that
+// can GC.
+
+pub struct Hazard {
+    pub function : String,
+    pub variable : String,
+    pub var_type : Option<String>,
+    pub gc_call : String,
+    pub location : Option<String>,
+    // Path from the GC call as recorded by the analysis, if any.
+    pub recorded_path : Vec<String>,
+}
+
+lazy_static! {
+    static ref HAZARD_RE : Regex = Regex::new(
+        r"^Function '(.*?)' has unrooted '(.*?)'(?: of type '(.*?)')? live across GC call '?(.*?)'?(?: at (\S+))?$").unwrap();
+}
+
+// The part to look up: the mangled name if there is one.
+fn lookup_key(name : &str) -> &str {
+    name.split('$').next().unwrap_or(name)
+}
+
+fn display_name(name : &str) -> &str {
+    name.rsplit('$').next().unwrap_or(name)
+}
+
+pub fn lookup(index : &NameIndex, name : &str) -> Option<NodeIndex> {
+    index.get(lookup_key(name)).or_else(|| index.get(display_name(name)))
+}
+
+pub fn load_hazards(filename : &str) -> Result<Vec<Hazard>, String> {
+    let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+    let mut hazards : Vec<Hazard> = vec![];
+    let mut in_path = false;
+    for line in text.lines() {
+        if let Some(cap) = HAZARD_RE.captures(line) {
+            hazards.push(Hazard {
+                function: cap[1].to_string(),
+                variable: cap[2].to_string(),
+                var_type: cap.get(3).map(|m| m.as_str().to_string()),
+                gc_call: cap[4].to_string(),
+                location: cap.get(5).map(|m| m.as_str().to_string()),
+                recorded_path: vec![],
+            });
+            in_path = false;
+        } else if let Some(name) = line.strip_prefix("GC Function: ") {
+            if let Some(hazard) = hazards.last_mut() {
+                hazard.recorded_path = vec![name.trim().to_string()];
+                in_path = true;
+            }
+        } else if in_path && line.starts_with(|c : char| c.is_whitespace()) && !line.trim().is_empty() {
+            hazards.last_mut().unwrap().recorded_path.push(line.trim().to_string());
+        } else {
+            in_path = false;
+        }
+    }
+    Ok(hazards)
+}
+
+pub fn load_gc_functions(cg : &Callgraph, index : &NameIndex, filename : &str) -> Result<HashSet<NodeIndex>, String> {
+    let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+    Ok(text.lines()
+       .filter_map(|line| line.strip_prefix("GC Function: "))
+       .filter_map(|name| lookup(index, name))
+       .filter(|idx| idx.index() < cg.graph.node_count())
+       .collect())
+}
+
+pub enum Status {
+    MissingFunction,
+    MissingGcCall,
+    // The function no longer calls the GC call directly.
+    CallGone,
+    // The GC call can no longer reach anything that GCs.
+    PathGone,
+    // Route from the GC call to a GC function, and the functions on it that
+    // every such route passes through.
+    StillHazard(Vec<NodeIndex>, Vec<NodeIndex>),
+}
+
+pub struct Entry<'a> {
+    pub hazard : &'a Hazard,
+    pub status : Status,
+}
+
+// Everything on `path` that every route from path[0] to `goal` must pass
+// through, ie where annotating a single function would fix the hazard. The
+// GC call itself always qualifies and is not listed.
+fn chokepoints(cg : &Callgraph, path : &[NodeIndex], goal : &HashSet<NodeIndex>, avoid_props : u32) -> Vec<NodeIndex> {
+    if path.len() < 2 {
+        return vec![];
+    }
+    let mut result = vec![];
+    // The last function on the path is the GC itself.
+    for &idx in &path[1..path.len() - 1] {
+        let avoid : HashSet<NodeIndex> = [idx].iter().cloned().collect();
+        if cg.any_route(path[0], goal, &avoid, avoid_props).is_none() {
+            result.push(idx);
+        }
+    }
+    result
+}
+
+pub fn check<'a>(
+    cg : &Callgraph,
+    index : &NameIndex,
+    hazards : &'a [Hazard],
+    goal : &HashSet<NodeIndex>,
+    interrupted : &dyn Fn() -> bool
+) -> Vec<Entry<'a>>
+{
+    let avoid_props = cg.gc_suppressed_bit();
+    let none = HashSet::new();
+    let mut entries = vec![];
+    for hazard in hazards {
+        if interrupted() { break; }
+        let status = match (lookup(index, &hazard.function), lookup(index, &hazard.gc_call)) {
+            (None, _) => Status::MissingFunction,
+            (_, None) => Status::MissingGcCall,
+            (Some(func), Some(call)) => {
+                if !cg.callees(func).contains(&call) {
+                    Status::CallGone
+                } else {
+                    match cg.any_route(call, goal, &none, avoid_props) {
+                        None => Status::PathGone,
+                        Some(route) => {
+                            let mut path = vec![call];
+                            path.extend(route.iter().map(|&edge| cg.graph.edge_endpoints(edge).unwrap().1));
+                            let chokes = chokepoints(cg, &path, goal, avoid_props);
+                            Status::StillHazard(path, chokes)
+                        },
+                    }
+                }
+            },
+        };
+        entries.push(Entry { hazard, status });
+    }
+    entries
+}
+
+fn summary(entries : &[Entry]) -> String {
+    let live = entries.iter().filter(|e| matches!(e.status, Status::StillHazard(..))).count();
+    let fixed = entries.iter().filter(|e| matches!(e.status, Status::CallGone | Status::PathGone)).count();
+    format!("{} hazards: {} still have a GC path, {} no longer do, {} could not be found in the graph.",
+            entries.len(), live, fixed, entries.len() - live - fixed)
+}
+
+fn status_text(entry : &Entry) -> String {
+    let hazard = entry.hazard;
+    match &entry.status {
+        Status::MissingFunction => format!("function {} is not in the graph", display_name(&hazard.function)),
+        Status::MissingGcCall => format!("GC call {} is not in the graph", display_name(&hazard.gc_call)),
+        Status::CallGone => "the function no longer makes the GC call".to_string(),
+        Status::PathGone => "the GC call can no longer GC".to_string(),
+        Status::StillHazard(path, _) => format!("still a hazard (GC after {} calls)", path.len() - 1),
+    }
+}
+
+fn variable_text(hazard : &Hazard) -> String {
+    match &hazard.var_type {
+        Some(t) => format!("{} ({})", hazard.variable, t),
+        None => hazard.variable.clone(),
+    }
+}
+
+pub fn markdown(cg : &Callgraph, entries : &[Entry]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# Hazard report\n\n{}\n", summary(entries));
+    for (i, entry) in entries.iter().enumerate() {
+        let hazard = entry.hazard;
+        let _ = writeln!(out, "## {}. `{}`\n", i + 1, display_name(&hazard.function));
+        let _ = writeln!(out, "- Variable: `{}`", variable_text(hazard));
+        let _ = write!(out, "- GC call: `{}`", display_name(&hazard.gc_call));
+        if let Some(location) = &hazard.location {
+            let _ = write!(out, " at {}", location);
+        }
+        let _ = writeln!(out, "\n- Status: {}", status_text(entry));
+        if let Status::StillHazard(path, chokes) = &entry.status {
+            let _ = writeln!(out, "- Current GC path:");
+            for idx in path {
+                let _ = writeln!(out, "    1. `{}`", cg.name(*idx, DescriptionBrevity::Normal));
+            }
+            if chokes.is_empty() {
+                let _ = writeln!(out, "- Chokepoints: none besides the GC call itself");
+            } else {
+                let _ = writeln!(out, "- Chokepoints (annotating any one of these would remove every GC path):");
+                for idx in chokes {
+                    let _ = writeln!(out, "    - `{}`", cg.name(*idx, DescriptionBrevity::Normal));
+                }
+            }
+        }
+        if !hazard.recorded_path.is_empty() {
+            let _ = writeln!(out, "- Path recorded by the analysis: {}",
+                             hazard.recorded_path.iter().map(|n| format!("`{}`", display_name(n))).collect::<Vec<_>>().join(" → "));
+        }
+        let _ = writeln!(out);
+    }
+    out
+}
+
+fn escape(s : &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn html(cg : &Callgraph, entries : &[Entry]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Hazard report</title>");
+    let _ = writeln!(out, "<style>body {{ font-family: sans-serif; }} code {{ background: #eee; }} .live {{ color: #b00; }} .gone {{ color: #080; }}</style>");
+    let _ = writeln!(out, "</head><body>\n<h1>Hazard report</h1>\n<p>{}</p>", escape(&summary(entries)));
+    for (i, entry) in entries.iter().enumerate() {
+        let hazard = entry.hazard;
+        let class = match entry.status {
+            Status::StillHazard(..) => "live",
+            Status::CallGone | Status::PathGone => "gone",
+            _ => "missing",
+        };
+        let _ = writeln!(out, "<h2>{}. <code>{}</code></h2>\n<ul>", i + 1, escape(display_name(&hazard.function)));
+        let _ = writeln!(out, "<li>Variable: <code>{}</code></li>", escape(&variable_text(hazard)));
+        let _ = writeln!(out, "<li>GC call: <code>{}</code>{}</li>", escape(display_name(&hazard.gc_call)),
+                         hazard.location.as_ref().map(|l| format!(" at {}", escape(l))).unwrap_or_default());
+        let _ = writeln!(out, "<li>Status: <span class=\"{}\">{}</span></li>", class, escape(&status_text(entry)));
+        if let Status::StillHazard(path, chokes) = &entry.status {
+            let _ = writeln!(out, "<li>Current GC path:<ol>");
+            for idx in path {
+                let _ = writeln!(out, "<li><code>{}</code></li>", escape(&cg.name(*idx, DescriptionBrevity::Normal)));
+            }
+            let _ = writeln!(out, "</ol></li>");
+            if chokes.is_empty() {
+                let _ = writeln!(out, "<li>Chokepoints: none besides the GC call itself</li>");
+            } else {
+                let _ = writeln!(out, "<li>Chokepoints (annotating any one of these would remove every GC path):<ul>");
+                for idx in chokes {
+                    let _ = writeln!(out, "<li><code>{}</code></li>", escape(&cg.name(*idx, DescriptionBrevity::Normal)));
+                }
+                let _ = writeln!(out, "</ul></li>");
+            }
+        }
+        if !hazard.recorded_path.is_empty() {
+            let path : Vec<String> = hazard.recorded_path.iter().map(|n| format!("<code>{}</code>", escape(display_name(n)))).collect();
+            let _ = writeln!(out, "<li>Path recorded by the analysis: {}</li>", path.join(" &rarr; "));
+        }
+        let _ = writeln!(out, "</ul>");
+    }
+    let _ = writeln!(out, "</body></html>");
+    out
+}
@@ -0,0 +1,172 @@
+use crate::callgraph::{Callgraph, Direction, PropertyMatch, VirtualMode};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+
+// Shortest-route search that keeps its whole breadth-first tree between
+// queries, for the common loop of finding a route, avoiding something on it,
+// and asking again. When the only change is a larger avoid set, just the part
+// of the tree hanging off the newly avoided functions is searched again, from
+// the intact part around it. Anything else starts over.
+
+const UNREACHED : u32 = u32::MAX;
+const NO_EDGE : u32 = u32::MAX;
+
+pub struct RouteCache {
+    origins : Vec<NodeIndex>,
+    avoid : HashSet<NodeIndex>,
+    avoid_props : u32,
+    matching : PropertyMatch,
+    virtual_mode : VirtualMode,
+    // Distance from the nearest origin and the call that got there.
+    dist : Vec<u32>,
+    pred : Vec<u32>,
+    // Functions (re)visited by the last query, and how many are reachable.
+    pub explored : usize,
+    pub reached : usize,
+}
+
+impl RouteCache {
+    fn search(cg : &Callgraph, origins : &[NodeIndex], avoid : &HashSet<NodeIndex>, avoid_props : u32, matching : PropertyMatch, mode : VirtualMode) -> RouteCache {
+        let n = cg.graph.node_count();
+        let mut cache = RouteCache {
+            origins: origins.to_vec(),
+            avoid: avoid.clone(),
+            avoid_props,
+            matching,
+            virtual_mode: mode,
+            dist: vec![UNREACHED; n],
+            pred: vec![NO_EDGE; n],
+            explored: 0,
+            reached: 0,
+        };
+        let mut work = VecDeque::new();
+        for &origin in origins {
+            if cache.dist[origin.index()] == UNREACHED {
+                cache.dist[origin.index()] = 0;
+                work.push_back(origin);
+            }
+        }
+        while let Some(src) = work.pop_front() {
+            cache.explored += 1;
+            let d = cache.dist[src.index()] + 1;
+            for (dst, edge, props) in cg.neighbor_edges_in(src, Direction::Callees, mode) {
+                if cache.dist[dst.index()] != UNREACHED || avoid.contains(&dst) || matching.blocks(props, avoid_props) { continue; }
+                cache.dist[dst.index()] = d;
+                cache.pred[dst.index()] = edge.index() as u32;
+                work.push_back(dst);
+            }
+        }
+        cache.reached = cache.explored;
+        cache
+    }
+
+    // Take `added` out of the tree, along with everything the tree reached
+    // through them, then search that region again from whatever still
+    // reaches into it. Avoiding more never makes anything closer, so the rest
+    // of the tree stays correct.
+    fn avoid_more(&mut self, cg : &Callgraph, added : &[NodeIndex]) {
+        let mut cut = vec![];
+        let mut work : Vec<NodeIndex> = added.iter().cloned().filter(|idx| self.dist[idx.index()] != UNREACHED).collect();
+        for idx in &work {
+            self.dist[idx.index()] = UNREACHED;
+        }
+        while let Some(node) = work.pop() {
+            cut.push(node);
+            for (dst, edge, _) in cg.neighbor_edges_in(node, Direction::Callees, self.virtual_mode) {
+                if self.pred[dst.index()] == edge.index() as u32 && self.dist[dst.index()] != UNREACHED {
+                    self.dist[dst.index()] = UNREACHED;
+                    work.push(dst);
+                }
+            }
+        }
+        self.avoid.extend(added);
+
+        // Calls are all the same length, but the region is entered at
+        // different depths, so take the nearest first.
+        let mut frontier = BinaryHeap::new();
+        for &node in &cut {
+            self.pred[node.index()] = NO_EDGE;
+            if self.avoid.contains(&node) { continue; }
+            for (src, edge, props) in cg.neighbor_edges_in(node, Direction::Callers, self.virtual_mode) {
+                let d = self.dist[src.index()];
+                if d == UNREACHED || self.matching.blocks(props, self.avoid_props) { continue; }
+                if d + 1 < self.dist[node.index()] {
+                    self.dist[node.index()] = d + 1;
+                    self.pred[node.index()] = edge.index() as u32;
+                }
+            }
+            if self.dist[node.index()] != UNREACHED {
+                frontier.push(Reverse((self.dist[node.index()], node)));
+            }
+        }
+        self.explored = 0;
+        while let Some(Reverse((d, src))) = frontier.pop() {
+            if d > self.dist[src.index()] { continue; }
+            self.explored += 1;
+            for (dst, edge, props) in cg.neighbor_edges_in(src, Direction::Callees, self.virtual_mode) {
+                if self.avoid.contains(&dst) || self.matching.blocks(props, self.avoid_props) { continue; }
+                if d + 1 < self.dist[dst.index()] {
+                    self.dist[dst.index()] = d + 1;
+                    self.pred[dst.index()] = edge.index() as u32;
+                    frontier.push(Reverse((d + 1, dst)));
+                }
+            }
+        }
+        self.reached = self.dist.iter().filter(|&&d| d != UNREACHED).count();
+    }
+
+    // As Callgraph::clean_route with nothing required clean, reusing `cache`
+    // when it can.
+    #[allow(clippy::too_many_arguments)]
+    pub fn route(
+        cache : &mut Option<RouteCache>,
+        cg : &Callgraph,
+        origins : &[NodeIndex],
+        goal : &HashSet<NodeIndex>,
+        avoid : &HashSet<NodeIndex>,
+        avoid_props : u32,
+        matching : PropertyMatch,
+        mode : VirtualMode
+    ) -> Option<Vec<EdgeIndex>>
+    {
+        let mut origins : Vec<NodeIndex> = origins.iter().cloned().filter(|origin| !avoid.contains(origin)).collect();
+        origins.sort();
+        origins.dedup();
+        if origins.is_empty() {
+            return None;
+        }
+        if origins.iter().any(|origin| goal.contains(origin)) {
+            return Some(vec![]);
+        }
+
+        let reusable = cache.as_ref().is_some_and(|c| {
+            c.origins == origins && c.avoid_props == avoid_props && c.matching == matching && c.virtual_mode == mode &&
+                c.dist.len() == cg.graph.node_count() && c.avoid.is_subset(avoid)
+        });
+        let cache = if reusable {
+            let cache = cache.as_mut().unwrap();
+            let added : Vec<NodeIndex> = avoid.difference(&cache.avoid).cloned().collect();
+            if !added.is_empty() {
+                cache.avoid_more(cg, &added);
+            } else {
+                cache.explored = 0;
+            }
+            cache
+        } else {
+            cache.insert(RouteCache::search(cg, &origins, avoid, avoid_props, matching, mode))
+        };
+
+        let mut node = goal.iter().cloned()
+            .filter(|idx| idx.index() < cache.dist.len() && cache.dist[idx.index()] != UNREACHED)
+            .min_by_key(|idx| (cache.dist[idx.index()], *idx))?;
+        let mut route = vec![];
+        while cache.pred[node.index()] != NO_EDGE {
+            let edge = EdgeIndex::new(cache.pred[node.index()] as usize);
+            route.push(edge);
+            node = cg.graph.edge_endpoints(edge).unwrap().0;
+        }
+        route.reverse();
+        Some(route)
+    }
+}
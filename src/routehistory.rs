@@ -0,0 +1,118 @@
+use crate::callgraph::{Callgraph, DescriptionBrevity, RouteDiff};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use std::collections::{BTreeMap, VecDeque};
+
+// Routes found earlier in the session, numbered in the order they were
+// found, so one can be shown again (`route recall 3`), given a name to keep
+// it past the last few (`route save mypath`), or compared with another
+// (`route diff 3 mypath`, see Callgraph::diff_routes) without searching
+// again.
+
+const KEEP : usize = 20;
+
+#[derive(Clone)]
+pub struct SavedRoute {
+    // The command that found it.
+    pub query : String,
+    pub nodes : Vec<NodeIndex>,
+    pub edges : Vec<EdgeIndex>,
+}
+
+pub struct RouteHistory {
+    entries : VecDeque<(usize, SavedRoute)>,
+    found : usize,
+    named : BTreeMap<String, SavedRoute>,
+    // The route last found or recalled, which is what gets saved.
+    current : Option<SavedRoute>,
+}
+
+impl RouteHistory {
+    pub fn new() -> RouteHistory {
+        RouteHistory { entries: VecDeque::new(), found: 0, named: BTreeMap::new(), current: None }
+    }
+
+    pub fn push(&mut self, route : SavedRoute) -> usize {
+        self.found += 1;
+        if self.entries.len() == KEEP {
+            self.entries.pop_front();
+        }
+        self.current = Some(route.clone());
+        self.entries.push_back((self.found, route));
+        self.found
+    }
+
+    pub fn recall(&mut self, key : &str) -> Option<SavedRoute> {
+        self.current = Some(self.get(key)?.clone());
+        self.current.clone()
+    }
+
+    // By number, or by the name it was saved under.
+    pub fn get(&self, key : &str) -> Option<&SavedRoute> {
+        if let Some(route) = self.named.get(key) {
+            return Some(route);
+        }
+        let n = key.parse::<usize>().ok()?;
+        self.entries.iter().find(|(m, _)| *m == n).map(|(_, route)| route)
+    }
+
+    pub fn save(&mut self, name : &str) -> bool {
+        match &self.current {
+            Some(route) => {
+                self.named.insert(name.to_string(), route.clone());
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn list(&self) {
+        if self.entries.is_empty() && self.named.is_empty() {
+            println!("No routes yet");
+        }
+        for (n, route) in &self.entries {
+            println!("{:>4}: {} ({} calls)", n, route.query, route.edges.len());
+        }
+        for (name, route) in &self.named {
+            println!("{:>4}: {} ({} calls)", name, route.query, route.edges.len());
+        }
+    }
+}
+
+// The two routes lined up, shared functions unmarked and the stretches
+// where they differ marked - for the first and + for the second.
+pub fn diff(cg : &Callgraph, a : &SavedRoute, b : &SavedRoute) {
+    let name = |idx : NodeIndex| cg.name(idx, DescriptionBrevity::Normal);
+    let segments = cg.diff_routes(&a.edges, &b.edges);
+    let diverged = segments.iter().filter(|segment| matches!(segment, RouteDiff::Diverged { .. })).count();
+    if diverged == 0 {
+        println!("The routes are the same");
+        return;
+    }
+    println!("- {}", a.query);
+    println!("+ {}", b.query);
+    for (i, segment) in segments.iter().enumerate() {
+        match segment {
+            RouteDiff::Same(nodes) => {
+                for (j, &idx) in nodes.iter().enumerate() {
+                    let mark = if j == 0 && i > 0 {
+                        "    <- meet again"
+                    } else if j + 1 == nodes.len() && i + 1 < segments.len() {
+                        "    <- part"
+                    } else {
+                        ""
+                    };
+                    println!("  {}{}", name(idx), mark);
+                }
+            },
+            RouteDiff::Diverged { first, second } => {
+                for &idx in first {
+                    println!("- {}", name(idx));
+                }
+                for &idx in second {
+                    println!("+ {}", name(idx));
+                }
+            },
+        }
+    }
+    println!("{} and {} calls, diverging {} {}", a.edges.len(), b.edges.len(), diverged, if diverged == 1 { "time" } else { "times" });
+}
@@ -0,0 +1,482 @@
+use crate::callgraph::{Callgraph, Direction};
+use petgraph::graph::NodeIndex;
+use std::collections::{HashMap, HashSet};
+
+// Datalog-style rules deriving sets of functions, eg
+//
+//   canGC(F) :- gc(F).
+//   canGC(F) :- calls(F, G), canGC(G), !suppressed(F, G).
+//
+// Variables start with an uppercase letter (or _). Derived predicates take a
+// single function; the builtins are
+//
+//   calls(F, G)          F directly calls G
+//   suppressed(F, G)     ...with a GC-suppressed call
+//   prop(F, G, "NAME")   ...with a call that may have property NAME
+//   name(F, "pattern")   F is matched by `resolve pattern`
+//   gc(F), root(F), sink(F), func(F)
+//
+// A negated literal (!p(...)) may only use variables bound by the positive
+// literals of its rule, and may not negate a predicate that depends on the
+// rule's own head. Evaluation is bottom-up and semi-naive, a stratum at a time.
+// `%` starts a comment.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Var(String),
+    Const(String),
+}
+
+#[derive(Debug, Clone)]
+struct Atom {
+    pred : String,
+    args : Vec<Term>,
+}
+
+#[derive(Debug, Clone)]
+struct Literal {
+    negated : bool,
+    atom : Atom,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    head : Atom,
+    body : Vec<Literal>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Punct(&'static str),
+}
+
+fn tokenize(text : &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '%' {
+            while let Some(&(_, c)) = chars.peek() {
+                if c == '\n' { break; }
+                chars.next();
+            }
+        } else if text[start..].starts_with(":-") {
+            tokens.push(Token::Punct(":-"));
+            chars.next();
+            chars.next();
+        } else if let Some(p) = ["(", ")", ",", ".", "!"].iter().find(|p| text[start..].starts_with(*p)) {
+            tokens.push(Token::Punct(p));
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, c)) => s.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_alphanumeric() && c != '_' { break; }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Ident(text[start..end].to_string()));
+        } else {
+            return Err(format!("unexpected '{}' at offset {}", c, start));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens : Vec<Token>,
+    pos : usize,
+}
+
+impl Parser {
+    fn eat(&mut self, p : &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some(Token::Punct(q)) if *q == p) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, p : &str) -> Result<(), String> {
+        if self.eat(p) { Ok(()) } else { Err(format!("expected '{}' in rule {}", p, self.pos)) }
+    }
+
+    fn atom(&mut self) -> Result<Atom, String> {
+        let pred = match self.tokens.get(self.pos) {
+            Some(Token::Ident(name)) => name.clone(),
+            other => return Err(format!("expected a predicate name, got {:?}", other)),
+        };
+        self.pos += 1;
+        self.expect("(")?;
+        let mut args = vec![];
+        loop {
+            args.push(match self.tokens.get(self.pos) {
+                Some(Token::Ident(name)) if name.starts_with(|c : char| c.is_uppercase() || c == '_') => Term::Var(name.clone()),
+                Some(Token::Str(s)) => Term::Const(s.clone()),
+                other => return Err(format!("{}: expected a variable or string, got {:?}", pred, other)),
+            });
+            self.pos += 1;
+            if self.eat(")") { break; }
+            self.expect(",")?;
+        }
+        Ok(Atom { pred, args })
+    }
+
+    fn rule(&mut self) -> Result<Rule, String> {
+        let head = self.atom()?;
+        let mut body = vec![];
+        if self.eat(":-") {
+            loop {
+                let negated = self.eat("!");
+                body.push(Literal { negated, atom: self.atom()? });
+                if !self.eat(",") { break; }
+            }
+        }
+        self.expect(".")?;
+        Ok(Rule { head, body })
+    }
+}
+
+fn parse(text : &str) -> Result<Vec<Rule>, String> {
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0 };
+    let mut rules = vec![];
+    while parser.pos < parser.tokens.len() {
+        rules.push(parser.rule()?);
+    }
+    Ok(rules)
+}
+
+// A body literal with its variables replaced by slot numbers and its
+// constants looked up.
+#[derive(Clone)]
+enum Goal {
+    Edge(usize, usize, u32, bool), // props bits, whether they must all be set
+    Set(usize, FunctionSet),
+    Derived(usize, usize),
+}
+
+#[derive(Clone)]
+enum FunctionSet {
+    Gc,
+    Roots,
+    Sinks,
+    All,
+    Resolved(HashSet<NodeIndex>),
+}
+
+struct CompiledRule {
+    head_pred : usize,
+    head_var : usize,
+    // Positive goals, then negated goals.
+    goals : Vec<(bool, Goal)>,
+    nvars : usize,
+}
+
+struct Relations {
+    full : Vec<HashSet<NodeIndex>>,
+    delta : Vec<HashSet<NodeIndex>>,
+}
+
+struct Evaluator<'a> {
+    cg : &'a Callgraph,
+    roots : HashSet<NodeIndex>,
+    sinks : HashSet<NodeIndex>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn in_set(&self, set : &FunctionSet, idx : NodeIndex) -> bool {
+        match set {
+            FunctionSet::Gc => self.cg.gc_functions.contains(&idx),
+            FunctionSet::Roots => self.roots.contains(&idx),
+            FunctionSet::Sinks => self.sinks.contains(&idx),
+            FunctionSet::All => true,
+            FunctionSet::Resolved(set) => set.contains(&idx),
+        }
+    }
+
+    fn members(&self, set : &FunctionSet) -> Vec<NodeIndex> {
+        match set {
+            FunctionSet::Gc => self.cg.gc_functions.iter().cloned().collect(),
+            FunctionSet::Roots => self.roots.iter().cloned().collect(),
+            FunctionSet::Sinks => self.sinks.iter().cloned().collect(),
+            FunctionSet::All => self.cg.node_ids().collect(),
+            FunctionSet::Resolved(set) => set.iter().cloned().collect(),
+        }
+    }
+
+    fn edge_matches(&self, props : u32, bits : u32, all : bool) -> bool {
+        if all { props & bits == bits } else { bits == 0 || props & bits != 0 }
+    }
+
+    // Call `emit` with every binding satisfying goals[i..], where goal
+    // `delta_goal` (if any) only looks at the facts new in this round.
+    fn solve(
+        &self,
+        goals : &[(bool, Goal)],
+        i : usize,
+        delta_goal : Option<usize>,
+        rels : &Relations,
+        binding : &mut Vec<Option<NodeIndex>>,
+        emit : &mut dyn FnMut(&[Option<NodeIndex>])
+    ) {
+        let (negated, goal) = match goals.get(i) {
+            Some(g) => g,
+            None => return emit(binding),
+        };
+        if *negated {
+            if !self.holds(goal, rels, binding) {
+                self.solve(goals, i + 1, delta_goal, rels, binding, emit);
+            }
+            return;
+        }
+
+        let mut try_bind = |slots : &[(usize, NodeIndex)], binding : &mut Vec<Option<NodeIndex>>| {
+            let saved : Vec<Option<NodeIndex>> = slots.iter().map(|&(slot, _)| binding[slot]).collect();
+            let consistent = slots.iter().all(|&(slot, value)| {
+                match binding[slot] {
+                    Some(bound) => bound == value,
+                    None => { binding[slot] = Some(value); true },
+                }
+            });
+            if consistent {
+                self.solve(goals, i + 1, delta_goal, rels, binding, emit);
+            }
+            for (&(slot, _), old) in slots.iter().zip(saved) {
+                binding[slot] = old;
+            }
+        };
+
+        match goal {
+            Goal::Edge(f, g, bits, all) => {
+                match (binding[*f], binding[*g]) {
+                    (Some(src), _) => {
+                        for (dst, _, props) in self.cg.neighbor_edges(src, Direction::Callees).collect::<Vec<_>>() {
+                            let p = if *all { props.all } else { props.any };
+                            if self.edge_matches(p, *bits, *all) { try_bind(&[(*g, dst)], binding); }
+                        }
+                    },
+                    (None, Some(dst)) => {
+                        for (src, _, props) in self.cg.neighbor_edges(dst, Direction::Callers).collect::<Vec<_>>() {
+                            let p = if *all { props.all } else { props.any };
+                            if self.edge_matches(p, *bits, *all) { try_bind(&[(*f, src)], binding); }
+                        }
+                    },
+                    (None, None) => {
+                        for edge in self.cg.edge_ids().collect::<Vec<_>>() {
+                            let props = self.cg.graph[edge];
+                            let p = if *all { props.all } else { props.any };
+                            if !self.edge_matches(p, *bits, *all) { continue; }
+                            let (src, dst) = self.cg.graph.edge_endpoints(edge).unwrap();
+                            try_bind(&[(*f, src), (*g, dst)], binding);
+                        }
+                    },
+                }
+            },
+            Goal::Set(v, set) => match binding[*v] {
+                Some(idx) => if self.in_set(set, idx) { try_bind(&[], binding) },
+                None => for idx in self.members(set) { try_bind(&[(*v, idx)], binding) },
+            },
+            Goal::Derived(p, v) => {
+                let facts = if delta_goal == Some(i) { &rels.delta[*p] } else { &rels.full[*p] };
+                match binding[*v] {
+                    Some(idx) => if facts.contains(&idx) { try_bind(&[], binding) },
+                    None => for idx in facts.iter().cloned().collect::<Vec<_>>() { try_bind(&[(*v, idx)], binding) },
+                }
+            },
+        }
+    }
+
+    // For negated goals, whose variables are all bound.
+    fn holds(&self, goal : &Goal, rels : &Relations, binding : &[Option<NodeIndex>]) -> bool {
+        match goal {
+            Goal::Edge(f, g, bits, all) => {
+                let (src, dst) = (binding[*f].unwrap(), binding[*g].unwrap());
+                self.cg.neighbor_edges(src, Direction::Callees).any(|(other, _, props)| {
+                    other == dst && self.edge_matches(if *all { props.all } else { props.any }, *bits, *all)
+                })
+            },
+            Goal::Set(v, set) => self.in_set(set, binding[*v].unwrap()),
+            Goal::Derived(p, v) => rels.full[*p].contains(&binding[*v].unwrap()),
+        }
+    }
+}
+
+fn compile(cg : &Callgraph, rules : &[Rule], preds : &HashMap<String, usize>) -> Result<Vec<CompiledRule>, String> {
+    let mut compiled = vec![];
+    for rule in rules {
+        let mut vars : HashMap<String, usize> = HashMap::new();
+        let head_var = match rule.head.args.as_slice() {
+            [Term::Var(v)] => { vars.insert(v.clone(), 0); 0 },
+            _ => return Err(format!("{}: derived predicates take a single variable", rule.head.pred)),
+        };
+
+        let mut positive_vars = HashSet::new();
+        let mut goals = vec![];
+        for negated in &[false, true] {
+            for lit in rule.body.iter().filter(|lit| lit.negated == *negated) {
+                let atom = &lit.atom;
+                let mut slot = |term : &Term| -> Result<usize, String> {
+                    match term {
+                        Term::Var(v) => {
+                            let n = vars.len();
+                            let slot = *vars.entry(v.clone()).or_insert(n);
+                            if *negated && !positive_vars.contains(&slot) {
+                                return Err(format!("variable {} in !{} is not bound by a positive literal", v, atom.pred));
+                            }
+                            if !*negated {
+                                positive_vars.insert(slot);
+                            }
+                            Ok(slot)
+                        },
+                        Term::Const(c) => Err(format!("{}: expected a variable, got \"{}\"", atom.pred, c)),
+                    }
+                };
+                let goal = match (atom.pred.as_str(), atom.args.as_slice()) {
+                    ("calls", [f, g]) => Goal::Edge(slot(f)?, slot(g)?, 0, false),
+                    ("suppressed", [f, g]) => Goal::Edge(slot(f)?, slot(g)?, cg.gc_suppressed_bit(), true),
+                    ("prop", [f, g, Term::Const(name)]) => {
+                        let bit = cg.resolve_property(name).ok_or(format!("unknown property {}", name))?;
+                        Goal::Edge(slot(f)?, slot(g)?, bit, false)
+                    },
+                    ("name", [f, Term::Const(pattern)]) => {
+                        Goal::Set(slot(f)?, FunctionSet::Resolved(cg.resolve(pattern).unwrap_or_default().into_iter().collect()))
+                    },
+                    ("gc", [f]) => Goal::Set(slot(f)?, FunctionSet::Gc),
+                    ("root", [f]) => Goal::Set(slot(f)?, FunctionSet::Roots),
+                    ("sink", [f]) => Goal::Set(slot(f)?, FunctionSet::Sinks),
+                    ("func", [f]) => Goal::Set(slot(f)?, FunctionSet::All),
+                    (pred, [f]) => match preds.get(pred) {
+                        Some(&p) => Goal::Derived(p, slot(f)?),
+                        None => return Err(format!("undefined predicate {}", pred)),
+                    },
+                    (pred, args) => return Err(format!("{} does not take {} arguments", pred, args.len())),
+                };
+                goals.push((*negated, goal));
+            }
+        }
+        if !positive_vars.contains(&head_var) {
+            return Err(format!("{}: head variable is not bound by the body", rule.head.pred));
+        }
+        compiled.push(CompiledRule { head_pred: preds[&rule.head.pred], head_var, goals, nvars: vars.len() });
+    }
+    Ok(compiled)
+}
+
+// Put each derived predicate in a stratum after everything it negates.
+fn stratify(rules : &[CompiledRule], npreds : usize) -> Result<Vec<usize>, String> {
+    let mut stratum = vec![0; npreds];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in rules {
+            for (negated, goal) in &rule.goals {
+                if let Goal::Derived(p, _) = goal {
+                    let need = stratum[*p] + if *negated { 1 } else { 0 };
+                    if stratum[rule.head_pred] < need {
+                        if need > npreds {
+                            return Err("rules negate a predicate that depends on itself".to_string());
+                        }
+                        stratum[rule.head_pred] = need;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    Ok(stratum)
+}
+
+// Evaluate a set of rules, returning each derived predicate (in order of
+// first definition) with the functions it holds for.
+pub fn evaluate(cg : &Callgraph, text : &str) -> Result<Vec<(String, Vec<NodeIndex>)>, String> {
+    let rules = parse(text)?;
+    let mut names = vec![];
+    let mut preds = HashMap::new();
+    for rule in &rules {
+        if ["calls", "suppressed", "prop", "name", "gc", "root", "sink", "func"].contains(&rule.head.pred.as_str()) {
+            return Err(format!("cannot redefine builtin {}", rule.head.pred));
+        }
+        if !preds.contains_key(&rule.head.pred) {
+            preds.insert(rule.head.pred.clone(), names.len());
+            names.push(rule.head.pred.clone());
+        }
+    }
+    let compiled = compile(cg, &rules, &preds)?;
+    let stratum = stratify(&compiled, names.len())?;
+
+    let evaluator = Evaluator {
+        cg,
+        roots: cg.query("roots()")?.into_iter().collect(),
+        sinks: cg.query("sinks()")?.into_iter().collect(),
+    };
+    let mut rels = Relations {
+        full: vec![HashSet::new(); names.len()],
+        delta: vec![HashSet::new(); names.len()],
+    };
+
+    for s in 0..=stratum.iter().cloned().max().unwrap_or(0) {
+        let rules : Vec<&CompiledRule> = compiled.iter().filter(|r| stratum[r.head_pred] == s).collect();
+        let mut first = true;
+        loop {
+            let mut new : Vec<HashSet<NodeIndex>> = vec![HashSet::new(); names.len()];
+            for rule in &rules {
+                // After the first round, only bindings using at least one
+                // new fact of this stratum can produce anything new.
+                let delta_goals : Vec<Option<usize>> = if first {
+                    vec![None]
+                } else {
+                    rule.goals.iter().enumerate().filter(|(_, (negated, goal))| {
+                        !negated && matches!(goal, Goal::Derived(p, _) if stratum[*p] == s)
+                    }).map(|(i, _)| Some(i)).collect()
+                };
+                for delta_goal in delta_goals {
+                    // Start from the new facts, so the other goals are
+                    // evaluated with a variable already bound.
+                    let mut goals = rule.goals.clone();
+                    let delta_goal = delta_goal.map(|i| {
+                        let goal = goals.remove(i);
+                        goals.insert(0, goal);
+                        0
+                    });
+                    let mut binding = vec![None; rule.nvars];
+                    let found = &mut new[rule.head_pred];
+                    let full = &rels.full[rule.head_pred];
+                    evaluator.solve(&goals, 0, delta_goal, &rels, &mut binding, &mut |b| {
+                        let idx = b[rule.head_var].unwrap();
+                        if !full.contains(&idx) {
+                            found.insert(idx);
+                        }
+                    });
+                }
+            }
+            first = false;
+            if new.iter().all(|set| set.is_empty()) {
+                break;
+            }
+            for (p, facts) in new.iter().enumerate() {
+                rels.full[p].extend(facts);
+            }
+            rels.delta = new;
+        }
+    }
+
+    Ok(names.into_iter().zip(rels.full).map(|(name, set)| {
+        let mut funcs : Vec<NodeIndex> = set.into_iter().collect();
+        funcs.sort();
+        (name, funcs)
+    }).collect())
+}
@@ -0,0 +1,203 @@
+use crate::callgraph::{AvoidConfig, Callgraph, Direction, RouteOptions};
+use json::JsonValue;
+use petgraph::graph::NodeIndex;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+// JSON-RPC 2.0 server for editor integrations, answering questions about a
+// graph loaded once up front. Requests and responses are one JSON object per
+// line, over TCP (any number of clients) or stdin/stdout. Functions are
+// passed by name, resolved as in the REPL; ambiguous names are an error whose
+// data lists the candidates.
+//
+//   {"jsonrpc": "2.0", "id": 1, "method": "canGC", "params": {"function": "js::Foo"}}
+//
+// Methods, with params by name or position:
+//
+//   resolve(pattern)             matching functions
+//   callees(function)            direct calls out, with call properties and location
+//   callers(function)            direct calls in
+//   route(from, to, avoid=[], max_depth=null, budget=null)
+//                                shortest call chain, or null
+//   canGC(function)              {"canGC": bool, "route": [...] or null}
+//
+// Each function is described as {"id": 12, "name": "<unmangled>", "names": [...]}.
+
+const INVALID_PARAMS : i32 = -32602;
+const METHOD_NOT_FOUND : i32 = -32601;
+const PARSE_ERROR : i32 = -32700;
+const NOT_FOUND : i32 = -32001;
+const AMBIGUOUS : i32 = -32002;
+
+struct RpcError {
+    code : i32,
+    message : String,
+    data : Option<JsonValue>,
+}
+
+fn error(code : i32, message : String) -> RpcError {
+    RpcError { code, message, data: None }
+}
+
+fn describe(cg : &Callgraph, idx : NodeIndex) -> JsonValue {
+    let names = cg.names(idx);
+    json::object!{
+        "id" => idx.index(),
+        "name" => *names.last().unwrap(),
+        "names" => names,
+    }
+}
+
+fn param<'a>(params : &'a JsonValue, position : usize, name : &str) -> &'a JsonValue {
+    if params.is_array() { &params[position] } else { &params[name] }
+}
+
+fn string_param<'a>(params : &'a JsonValue, position : usize, name : &str) -> Result<&'a str, RpcError> {
+    param(params, position, name).as_str().ok_or_else(|| error(INVALID_PARAMS, format!("missing string parameter '{}'", name)))
+}
+
+fn function_param(cg : &Callgraph, params : &JsonValue, position : usize, name : &str) -> Result<NodeIndex, RpcError> {
+    let pattern = string_param(params, position, name)?;
+    let matches = cg.resolve(pattern).unwrap_or_default();
+    match matches.len() {
+        0 => Err(error(NOT_FOUND, format!("no function matching '{}'", pattern))),
+        1 => Ok(matches[0]),
+        _ => Err(RpcError {
+            code: AMBIGUOUS,
+            message: format!("{} functions match '{}'", matches.len(), pattern),
+            data: Some(matches.iter().take(50).map(|&idx| describe(cg, idx)).collect::<Vec<_>>().into()),
+        }),
+    }
+}
+
+fn route_nodes(cg : &Callgraph, origin : NodeIndex, route : &[petgraph::graph::EdgeIndex]) -> JsonValue {
+    let mut nodes = vec![describe(cg, origin)];
+    nodes.extend(route.iter().map(|&edge| describe(cg, cg.graph.edge_endpoints(edge).unwrap().1)));
+    nodes.into()
+}
+
+fn dispatch(cg : &Callgraph, method : &str, params : &JsonValue) -> Result<JsonValue, RpcError> {
+    Ok(match method {
+        "resolve" => {
+            let pattern = string_param(params, 0, "pattern")?;
+            cg.resolve(pattern).unwrap_or_default().iter().map(|&idx| describe(cg, idx)).collect::<Vec<_>>().into()
+        },
+        "callees" | "callers" => {
+            let func = function_param(cg, params, 0, "function")?;
+            let direction = if method == "callees" { Direction::Callees } else { Direction::Callers };
+            cg.neighbor_edges(func, direction).map(|(other, edge, _)| {
+                let mut entry = describe(cg, other);
+                let props = cg.describe_property_set(cg.graph[edge].any);
+                entry["properties"] = props.split(',').filter(|p| !p.is_empty()).collect::<Vec<_>>().into();
+                if let Some(location) = cg.call_sites.get(&edge) {
+                    entry["location"] = location.as_str().into();
+                }
+                entry
+            }).collect::<Vec<_>>().into()
+        },
+        "route" => {
+            let src = function_param(cg, params, 0, "from")?;
+            let dst = function_param(cg, params, 1, "to")?;
+            let avoid_names = param(params, 2, "avoid");
+            let mut avoid = HashSet::new();
+            for i in 0..avoid_names.len() {
+                avoid.insert(function_param(cg, avoid_names, i, "avoid")?);
+            }
+            let goal : HashSet<NodeIndex> = [dst].iter().cloned().collect();
+            let options = RouteOptions {
+                avoid: AvoidConfig { functions: avoid, props: 0 },
+                max_depth: param(params, 3, "max_depth").as_usize(),
+                budget: param(params, 4, "budget").as_usize(),
+                ..RouteOptions::default()
+            };
+            match cg.route_with_options(&[src], &goal, &options) {
+                Ok(Some(route)) => route_nodes(cg, src, &route),
+                Ok(None) => JsonValue::Null,
+                Err(visited) => return Err(error(NOT_FOUND, format!("gave up after visiting {} functions", visited))),
+            }
+        },
+        "canGC" => {
+            let func = function_param(cg, params, 0, "function")?;
+            match cg.gc_route(func) {
+                Some(route) => json::object!{ "canGC" => true, "route" => route_nodes(cg, func, &route) },
+                None => json::object!{ "canGC" => false, "route" => JsonValue::Null },
+            }
+        },
+        _ => return Err(error(METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+    })
+}
+
+fn respond(cg : &Callgraph, line : &str) -> Option<JsonValue> {
+    let request = match json::parse(line) {
+        Ok(request) => request,
+        Err(e) => return Some(json::object!{
+            "jsonrpc" => "2.0",
+            "id" => JsonValue::Null,
+            "error" => json::object!{ "code" => PARSE_ERROR, "message" => e.to_string() },
+        }),
+    };
+    let result = dispatch(cg, request["method"].as_str().unwrap_or(""), &request["params"]);
+    // Requests without an id are notifications and get no reply.
+    if request["id"].is_null() {
+        return None;
+    }
+    let mut response = json::object!{ "jsonrpc" => "2.0", "id" => request["id"].clone() };
+    match result {
+        Ok(value) => response["result"] = value,
+        Err(e) => {
+            let mut err = json::object!{ "code" => e.code, "message" => e.message };
+            if let Some(data) = e.data {
+                err["data"] = data;
+            }
+            response["error"] = err;
+        },
+    }
+    Some(response)
+}
+
+fn handle(cg : &Callgraph, input : impl BufRead, mut output : impl Write) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = respond(cg, &line) {
+            writeln!(output, "{}", response.dump())?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+// Serve on `address` (eg "127.0.0.1:7878"), or on stdin/stdout if it is "-".
+// Runs until killed.
+pub fn serve(cg : &Callgraph, address : &str) -> io::Result<()> {
+    if address == "-" {
+        let stdin = io::stdin();
+        return handle(cg, stdin.lock(), io::stdout());
+    }
+
+    let listener = TcpListener::bind(address)?;
+    eprintln!("serving {} functions on {}", cg.node_ids().count(), listener.local_addr()?);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("accept failed: {}", e);
+                    continue;
+                },
+            };
+            scope.spawn(move || {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                let result = stream.try_clone().and_then(|input| handle(cg, BufReader::new(input), stream));
+                if let Err(e) = result {
+                    eprintln!("{}: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,131 @@
+// Parameter lists of unmangled C++ names, for picking overloads apart. A
+// pattern like
+//
+//   Foo(JSContext
+//   js::Foo(JSContext*, JS::Handle<JSObject*>)
+//
+// matches functions named Foo (or anything::Foo) whose parameters start with
+// the given ones. Each parameter of the pattern need only be a prefix of the
+// real one, with or without its namespaces, so `Handle<JSObject` matches
+// `JS::Handle<JSObject*>`. A closing paren means there are no more
+// parameters. Whitespace is only significant between identifiers.
+
+pub struct Signature<'a> {
+    // Qualified name, eg "js::GC::collect".
+    pub name : &'a str,
+    pub params : Vec<String>,
+}
+
+pub struct SignaturePattern {
+    pub name : String,
+    params : Vec<String>,
+    complete : bool,
+}
+
+fn is_ident(c : u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+fn normalize(s : &str) -> String {
+    let mut out = String::new();
+    let mut space = false;
+    for c in s.trim().chars() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && out.bytes().last().is_some_and(is_ident) && c.is_ascii() && is_ident(c as u8) {
+            out.push(' ');
+        }
+        space = false;
+        out.push(c);
+    }
+    out
+}
+
+// Split on commas that aren't inside template arguments or nested parens.
+fn split_params(s : &str) -> Vec<String> {
+    let mut params = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.bytes().enumerate() {
+        match c {
+            b'<' | b'(' | b'[' => depth += 1,
+            b'>' | b')' | b']' => depth -= 1,
+            b',' if depth == 0 => {
+                params.push(normalize(&s[start..i]));
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    let last = normalize(&s[start..]);
+    if !last.is_empty() || !params.is_empty() {
+        params.push(last);
+    }
+    params
+}
+
+pub fn parse(raw : &str) -> Option<Signature<'_>> {
+    let bytes = raw.as_bytes();
+    let open = (0..bytes.len()).find(|&i| bytes[i] == b'(' && i > 0 && is_ident(bytes[i - 1]))?;
+    let start = bytes[..open].iter().rposition(|&c| !(is_ident(c) || c == b':' || c == b'~')).map_or(0, |i| i + 1);
+    let mut depth = 0;
+    let close = (open..bytes.len()).find(|&i| {
+        match bytes[i] {
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            _ => {},
+        }
+        depth == 0
+    })?;
+    let mut params = split_params(&raw[open + 1..close]);
+    if params.len() == 1 && params[0] == "void" {
+        params.clear();
+    }
+    Some(Signature { name: raw[start..open].trim_start_matches(':'), params })
+}
+
+// None if `pattern` has no parameter list.
+pub fn parse_pattern(pattern : &str) -> Option<SignaturePattern> {
+    let (name, rest) = pattern.split_once('(')?;
+    let (rest, complete) = match rest.trim_end().strip_suffix(')') {
+        Some(rest) => (rest, true),
+        None => (rest, false),
+    };
+    let mut params = split_params(rest);
+    if !complete && params.last().is_some_and(|p| p.is_empty()) {
+        params.pop();
+    }
+    Some(SignaturePattern { name: name.trim().to_string(), params, complete })
+}
+
+// Everything before the last `::`, eg "js::GC" for "js::GC::collect".
+pub fn scope(name : &str) -> Option<&str> {
+    name.rsplit_once("::").map(|(scope, _)| scope)
+}
+
+fn param_matches(param : &str, fragment : &str) -> bool {
+    let mut rest = param.trim_start_matches("const ");
+    loop {
+        if rest.starts_with(fragment) || param.starts_with(fragment) {
+            return true;
+        }
+        match rest.find("::") {
+            Some(pos) => rest = &rest[pos + 2..],
+            None => return false,
+        }
+    }
+}
+
+impl SignaturePattern {
+    pub fn matches(&self, sig : &Signature) -> bool {
+        if !self.name.is_empty() && sig.name != self.name && !sig.name.strip_suffix(self.name.as_str()).is_some_and(|s| s.ends_with("::")) {
+            return false;
+        }
+        if self.params.len() > sig.params.len() || (self.complete && self.params.len() != sig.params.len()) {
+            return false;
+        }
+        self.params.iter().zip(&sig.params).all(|(fragment, param)| param_matches(param, fragment))
+    }
+}
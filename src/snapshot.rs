@@ -0,0 +1,186 @@
+use crate::callgraph::{Callgraph, PropertySet};
+use crate::diskstore;
+use crate::hazard::LoadError;
+use petgraph::graph::NodeIndex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+// Binary snapshot of a loaded Callgraph, so that a graph that took minutes to
+// parse from callgraph.txt can be reloaded in seconds. The header carries the
+// graph's fingerprint, so a cache of snapshots can be checked without
+// loading them, and a damaged or mismatched one is caught on load.
+
+const MAGIC : &[u8; 8] = b"HAZGRAPH";
+const VERSION : u32 = 4;
+
+fn write_u32(out : &mut impl Write, n : u32) -> Result<(), LoadError> {
+    out.write_all(&n.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_str(out : &mut impl Write, s : &str) -> Result<(), LoadError> {
+    write_u32(out, s.len() as u32)?;
+    out.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn write_u64(out : &mut impl Write, n : u64) -> Result<(), LoadError> {
+    out.write_all(&n.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_u64(input : &mut impl Read) -> Result<u64, LoadError> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_u32(input : &mut impl Read) -> Result<u32, LoadError> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_str(input : &mut impl Read) -> Result<String, LoadError> {
+    let len = read_u32(input)? as usize;
+    let mut buf = vec![0u8; len];
+    input.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|_| LoadError::BadSnapshot("invalid UTF-8 in snapshot".to_string()))
+}
+
+pub fn save_snapshot(cg : &Callgraph, filename : &str) -> Result<(), LoadError> {
+    let mut out = BufWriter::new(File::create(filename)?);
+    out.write_all(MAGIC)?;
+    write_u32(&mut out, VERSION)?;
+
+    // Node ids are renumbered densely on load, so record where each one went
+    // in case a synthetic node is somewhere in the middle.
+    let nodes : Vec<NodeIndex> = cg.node_ids().collect();
+    let renumber : HashMap<NodeIndex, u32> = nodes.iter().enumerate().map(|(i, &idx)| (idx, i as u32 + 1)).collect();
+    write_u64(&mut out, cg.fingerprint_renumbered(|idx| renumber[&idx]))?;
+
+    write_u32(&mut out, cg.property_names.len() as u32)?;
+    for (bit, name) in &cg.property_names {
+        write_u32(&mut out, *bit)?;
+        write_str(&mut out, name)?;
+    }
+    write_u32(&mut out, cg.property_aliases.len() as u32)?;
+    for (alias, name) in &cg.property_aliases {
+        write_str(&mut out, alias)?;
+        write_str(&mut out, name)?;
+    }
+
+    write_u32(&mut out, nodes.len() as u32)?;
+    for idx in &nodes {
+        write_str(&mut out, &cg.graph[*idx])?;
+        let alt = cg.unmangled_names(*idx);
+        write_u32(&mut out, alt.len() as u32)?;
+        for name in alt {
+            write_str(&mut out, name)?;
+        }
+    }
+
+    let edges : Vec<_> = cg.edge_ids().collect();
+    write_u32(&mut out, edges.len() as u32)?;
+    for edge in edges {
+        let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+        write_u32(&mut out, renumber[&src])?;
+        write_u32(&mut out, renumber[&dst])?;
+        write_u32(&mut out, cg.graph[edge].all)?;
+        write_u32(&mut out, cg.graph[edge].any)?;
+        write_u32(&mut out, cg.call_count(edge))?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn read_header(input : &mut impl Read) -> Result<u64, LoadError> {
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(LoadError::BadSnapshot("not a callgraph snapshot".to_string()));
+    }
+    let version = read_u32(input)?;
+    if version != VERSION {
+        return Err(LoadError::BadSnapshot(format!("unsupported snapshot version {}", version)));
+    }
+    read_u64(input)
+}
+
+pub fn is_snapshot(filename : &str) -> bool {
+    let mut magic = [0u8; 8];
+    File::open(filename).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && magic == *MAGIC
+}
+
+// Convert a snapshot into a graph store (see diskstore.rs), for opening
+// shared. Call counts are dropped, since stores don't keep them. Returns the
+// number of functions and calls written.
+pub fn build_store(filename : &str, out_filename : &str) -> Result<(usize, usize), LoadError> {
+    let cg = load_snapshot(filename)?;
+    // Snapshots are numbered densely from 1, as stores are.
+    let mut names = vec![vec!["(dummy node zero)".to_string()]];
+    for idx in cg.graph.node_indices().skip(1) {
+        let mut node_names = vec![cg.graph[idx].clone()];
+        node_names.extend(cg.unmangled_names(idx).iter().cloned());
+        names.push(node_names);
+    }
+    let edges = cg.edge_ids().map(|edge| {
+        let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+        (src.index() as u32, dst.index() as u32, cg.graph[edge])
+    }).collect();
+    let properties = diskstore::properties_json(&cg.property_names, &cg.property_aliases);
+    diskstore::write(out_filename, names, edges, &properties)
+}
+
+// The fingerprint of the graph in a snapshot, without loading it.
+pub fn snapshot_fingerprint(filename : &str) -> Result<u64, LoadError> {
+    read_header(&mut BufReader::new(File::open(filename)?))
+}
+
+pub fn load_snapshot(filename : &str) -> Result<Callgraph, LoadError> {
+    let mut input = BufReader::new(File::open(filename)?);
+    let fingerprint = read_header(&mut input)?;
+
+    let mut cg = Callgraph::new();
+
+    for _ in 0..read_u32(&mut input)? {
+        let bit = read_u32(&mut input)?;
+        let name = read_str(&mut input)?;
+        cg.property_names.insert(bit, name);
+    }
+    for _ in 0..read_u32(&mut input)? {
+        let alias = read_str(&mut input)?;
+        let name = read_str(&mut input)?;
+        cg.property_aliases.insert(alias, name);
+    }
+
+    for _ in 0..read_u32(&mut input)? {
+        let idx = cg.add_function(&read_str(&mut input)?);
+        for _ in 0..read_u32(&mut input)? {
+            cg.add_unmangled_name(idx, &read_str(&mut input)?);
+        }
+    }
+
+    let node_count = cg.graph.node_count() as u32;
+    for _ in 0..read_u32(&mut input)? {
+        let src = read_u32(&mut input)?;
+        let dst = read_u32(&mut input)?;
+        let all = read_u32(&mut input)?;
+        let any = read_u32(&mut input)?;
+        let count = read_u32(&mut input)?;
+        if src >= node_count || dst >= node_count {
+            return Err(LoadError::BadSnapshot("edge endpoint out of range".to_string()));
+        }
+        let edge = cg.add_edge(NodeIndex::new(src as usize), NodeIndex::new(dst as usize), PropertySet { all, any });
+        if count > 1 {
+            cg.call_counts.insert(edge, count);
+        }
+    }
+
+    if cg.fingerprint() != fingerprint {
+        return Err(LoadError::BadSnapshot("contents do not match the fingerprint".to_string()));
+    }
+    Ok(cg)
+}
@@ -0,0 +1,54 @@
+use crate::callgraph::{Callgraph, NodeKind};
+use petgraph::graph::EdgeIndex;
+use std::collections::BTreeMap;
+
+// Stubs for the hazard analysis's annotations.js that would break a set of
+// calls (a route, or a cut from `cut`), ready to paste into its ignore
+// lists: ignoreFunctions for functions that should not be considered to GC,
+// ignoreIndirectCalls for function pointer variables, and ignoreCallees for
+// virtual method fields. Each entry says which calls it is for, since the fix
+// might better be suppressing GC in the caller instead.
+
+fn readable(kind : NodeKind, name : &str) -> String {
+    match kind {
+        NodeKind::Direct => name.to_string(),
+        NodeKind::Indirect => name.trim_start_matches("VARIABLE ").to_string(),
+        // "CLASS Foo FIELD bar" is listed as "Foo.bar".
+        NodeKind::Virtual => {
+            let words : Vec<&str> = name.split_whitespace().collect();
+            match (words.iter().position(|&w| w == "CLASS"), words.iter().position(|&w| w == "FIELD")) {
+                (Some(c), Some(f)) if c + 1 < words.len() && f + 1 < words.len() => format!("{}.{}", words[c + 1], words[f + 1]),
+                _ => name.to_string(),
+            }
+        },
+    }
+}
+
+pub fn annotation_stubs(cg : &Callgraph, calls : &[EdgeIndex]) -> String {
+    let mut lists : BTreeMap<&str, BTreeMap<String, Vec<String>>> = BTreeMap::new();
+    for &edge in calls {
+        let (src, dst) = cg.graph.edge_endpoints(edge).unwrap();
+        let kind = cg.node_kind(dst);
+        let list = match kind {
+            NodeKind::Direct => "ignoreFunctions",
+            NodeKind::Indirect => "ignoreIndirectCalls",
+            NodeKind::Virtual => "ignoreCallees",
+        };
+        // annotations.js looks functions up by their readable name.
+        let name = cg.unmangled_names(dst).first().unwrap_or(&cg.graph[dst]).clone();
+        let mut caller = cg.unmangled_names(src).first().unwrap_or(&cg.graph[src]).clone();
+        if let Some(location) = cg.call_sites.get(&edge) {
+            caller = format!("{} at {}", caller, location);
+        }
+        lists.entry(list).or_default().entry(readable(kind, &name)).or_default().push(caller);
+    }
+
+    let mut out = String::new();
+    for (list, entries) in &lists {
+        out += &format!("// {}\n", list);
+        for (name, callers) in entries {
+            out += &format!("    \"{}\" : true, // called from {}\n", name.replace('\\', "\\\\").replace('"', "\\\""), callers.join(", "));
+        }
+    }
+    out
+}
@@ -0,0 +1,250 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Synthetic callgraphs in the native callgraph.txt format, for test fixtures
+// and benchmarks. The same parameters (including the seed) always produce the
+// same file.
+//
+// Functions are numbered in a topological order, and calls normally go from a
+// function to a later one, so the graph is acyclic apart from a `scc_density`
+// fraction of calls pointing backwards, which tie functions together into
+// strongly connected components.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FanOut {
+    // Every function makes exactly `fanout` calls.
+    Fixed,
+    // Between 0 and 2 * `fanout` calls.
+    Uniform,
+    // Most functions make few calls and a few make a great many, as in real
+    // code; the mean is still roughly `fanout`.
+    PowerLaw,
+}
+
+#[derive(Clone, Debug)]
+pub struct Params {
+    pub functions : usize,
+    pub fanout : f64,
+    pub distribution : FanOut,
+    // Fraction of calls that go backwards, creating cycles.
+    pub scc_density : f64,
+    // Fraction of calls that are GC_SUPPRESSED.
+    pub suppressed : f64,
+    // Fraction of functions that also make a call through a function
+    // pointer.
+    pub indirect : f64,
+    pub seed : u64,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            functions: 1000,
+            fanout: 3.0,
+            distribution: FanOut::Uniform,
+            scc_density: 0.01,
+            suppressed: 0.05,
+            indirect: 0.01,
+            seed: 1,
+        }
+    }
+}
+
+impl Params {
+    // Set a parameter from its command line spelling, eg "fanout=4".
+    pub fn set(&mut self, setting : &str) -> Result<(), String> {
+        let (key, value) = setting.split_once('=').ok_or(format!("expected name=value, got '{}'", setting))?;
+        let bad = || format!("bad value for {}: '{}'", key, value);
+        let fraction = || value.parse::<f64>().ok().filter(|f| (0.0..=1.0).contains(f)).ok_or_else(bad);
+        match key {
+            "functions" => self.functions = value.parse().map_err(|_| bad())?,
+            "fanout" => self.fanout = value.parse().ok().filter(|f : &f64| *f >= 0.0).ok_or_else(bad)?,
+            "distribution" => self.distribution = match value {
+                "fixed" => FanOut::Fixed,
+                "uniform" => FanOut::Uniform,
+                "powerlaw" => FanOut::PowerLaw,
+                _ => return Err(bad()),
+            },
+            "scc" => self.scc_density = fraction()?,
+            "suppressed" => self.suppressed = fraction()?,
+            "indirect" => self.indirect = fraction()?,
+            "seed" => self.seed = value.parse().map_err(|_| bad())?,
+            _ => return Err(format!("unknown parameter '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+// xorshift64*; plenty for this, and stable across platforms and versions.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n : usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, p : f64) -> bool {
+        self.unit() < p
+    }
+}
+
+fn fanout(rng : &mut Rng, params : &Params) -> usize {
+    match params.distribution {
+        FanOut::Fixed => params.fanout.round() as usize,
+        FanOut::Uniform => (rng.unit() * (2.0 * params.fanout + 1.0)) as usize,
+        FanOut::PowerLaw => {
+            // Pareto with shape 2, scaled so the mean is about `fanout`.
+            let x = 1.0 / (1.0 - rng.unit()).sqrt();
+            ((x - 1.0) * params.fanout).round() as usize
+        },
+    }
+}
+
+// Function names are only there to be distinctive; the ids are what matter.
+fn mangled(i : usize) -> String {
+    let name = format!("func{}", i);
+    format!("_Z{}{}v", name.len(), name)
+}
+
+fn unmangled(i : usize) -> String {
+    format!("ns{}::Class{}::func{}(int)", i % 7, i % 101, i)
+}
+
+pub fn generate(params : &Params, out : &mut impl Write) -> io::Result<(usize, usize)> {
+    let n = params.functions;
+    let mut rng = Rng(params.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    writeln!(out, "! {{\"Properties\": {{\"1\": \"GC_SUPPRESSED\"}}}}")?;
+    for i in 1..=n {
+        writeln!(out, "#{} {}", i, mangled(i))?;
+        writeln!(out, "= {} {}", i, unmangled(i))?;
+    }
+
+    let mut calls = 0;
+    for src in 1..=n {
+        for _ in 0..fanout(&mut rng, params) {
+            let backwards = rng.chance(params.scc_density);
+            let dst = if backwards {
+                1 + rng.below(src)
+            } else if src < n {
+                src + 1 + rng.below(n - src)
+            } else {
+                // Nothing comes after the last function.
+                continue;
+            };
+            if rng.chance(params.suppressed) {
+                writeln!(out, "D /1 {} {}", src, dst)?;
+            } else {
+                writeln!(out, "D {} {}", src, dst)?;
+            }
+            calls += 1;
+        }
+        if rng.chance(params.indirect) {
+            writeln!(out, "I {} VARIABLE fnptr{}", src, rng.below(10))?;
+            calls += 1;
+        }
+    }
+    Ok((n, calls))
+}
+
+pub fn write_file(params : &Params, filename : &str) -> io::Result<(usize, usize)> {
+    let mut out = BufWriter::new(File::create(filename)?);
+    let counts = generate(params, &mut out)?;
+    out.flush()?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callgraph::Callgraph;
+    use crate::hazard::{load_graph_with_options, LoadOptions};
+    use petgraph::algo::tarjan_scc;
+
+    fn text(params : &Params) -> String {
+        let mut out = vec![];
+        generate(params, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn load(name : &str, params : &Params) -> Callgraph {
+        let path = std::env::temp_dir().join(format!("testgraph-{}-{}.txt", name, std::process::id()));
+        write_file(params, path.to_str().unwrap()).unwrap();
+        let options = LoadOptions { quiet: true, ..Default::default() };
+        let cg = load_graph_with_options(path.to_str().unwrap(), &options, None);
+        let _ = std::fs::remove_file(&path);
+        cg.unwrap()
+    }
+
+    fn largest_cycle(cg : &Callgraph) -> usize {
+        tarjan_scc(&cg.graph).iter().map(|scc| scc.len()).max().unwrap_or(0)
+    }
+
+    #[test]
+    fn same_seed_same_graph() {
+        let params = Params { functions: 200, ..Default::default() };
+        assert_eq!(text(&params), text(&params));
+        assert_ne!(text(&params), text(&Params { seed: 2, ..params.clone() }));
+    }
+
+    #[test]
+    fn loads() {
+        let params = Params { functions: 300, indirect: 0.0, ..Default::default() };
+        let cg = load("loads", &params);
+        assert_eq!(cg.node_ids().count(), 300);
+        assert!(cg.edge_ids().count() > 0);
+        assert_eq!(cg.lookup_name("ns1::Class8::func8(int)"), cg.lookup_name("_Z5func8v"));
+    }
+
+    #[test]
+    fn fixed_fanout() {
+        let params = Params { functions: 50, fanout: 2.0, distribution: FanOut::Fixed, indirect: 0.0, ..Default::default() };
+        let (functions, calls) = generate(&params, &mut io::sink()).unwrap();
+        assert_eq!(functions, 50);
+        // Every function but the last calls two later ones.
+        assert_eq!(calls, 49 * 2);
+    }
+
+    #[test]
+    fn cycles_only_from_scc_density() {
+        let acyclic = load("acyclic", &Params { functions: 300, scc_density: 0.0, ..Default::default() });
+        assert_eq!(largest_cycle(&acyclic), 1);
+        let cyclic = load("cyclic", &Params { functions: 300, scc_density: 0.2, ..Default::default() });
+        assert!(largest_cycle(&cyclic) > 1);
+    }
+
+    #[test]
+    fn suppressed_calls() {
+        let params = Params { functions: 100, suppressed: 1.0, indirect: 0.0, ..Default::default() };
+        let cg = load("suppressed", &params);
+        let suppressed = cg.resolve_property("GC_SUPPRESSED").unwrap();
+        assert!(cg.edge_ids().all(|edge| cg.graph[edge].all & suppressed != 0));
+    }
+
+    #[test]
+    fn set() {
+        let mut params = Params::default();
+        params.set("functions=10").unwrap();
+        params.set("distribution=powerlaw").unwrap();
+        params.set("scc=0.5").unwrap();
+        assert_eq!(params.functions, 10);
+        assert_eq!(params.distribution, FanOut::PowerLaw);
+        assert_eq!(params.scc_density, 0.5);
+        assert!(params.set("scc=2").is_err());
+        assert!(params.set("fanout=-1").is_err());
+        assert!(params.set("distribution=zipf").is_err());
+        assert!(params.set("colour=blue").is_err());
+        assert!(params.set("functions").is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use petgraph::graph::NodeIndex;
+use std::mem::size_of;
+
+// Byte-wise prefix trie from names (stems and qualified Class::method names)
+// to the functions having them, for prefix lookups and tab completion without
+// scanning every name.
+
+#[derive(Default)]
+struct TrieNode {
+    // Sorted by byte.
+    children : Vec<(u8, u32)>,
+    functions : Vec<NodeIndex>,
+}
+
+pub struct Trie {
+    nodes : Vec<TrieNode>,
+}
+
+impl Trie {
+    pub fn new() -> Trie {
+        Trie { nodes: vec![TrieNode::default()] }
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.nodes.capacity() * size_of::<TrieNode>() +
+            self.nodes.iter().map(|node| {
+                node.children.capacity() * size_of::<(u8, u32)>() +
+                    node.functions.capacity() * size_of::<NodeIndex>()
+            }).sum::<usize>()
+    }
+
+    fn child(&self, node : u32, byte : u8) -> Option<u32> {
+        let children = &self.nodes[node as usize].children;
+        children.binary_search_by_key(&byte, |&(b, _)| b).ok().map(|i| children[i].1)
+    }
+
+    fn find(&self, key : &str) -> Option<u32> {
+        key.bytes().try_fold(0, |node, byte| self.child(node, byte))
+    }
+
+    pub fn insert(&mut self, key : &str, idx : NodeIndex) {
+        let mut node = 0;
+        for byte in key.bytes() {
+            node = match self.child(node, byte) {
+                Some(next) => next,
+                None => {
+                    let next = self.nodes.len() as u32;
+                    self.nodes.push(TrieNode::default());
+                    let children = &mut self.nodes[node as usize].children;
+                    let pos = children.partition_point(|&(b, _)| b < byte);
+                    children.insert(pos, (byte, next));
+                    next
+                }
+            };
+        }
+        let functions = &mut self.nodes[node as usize].functions;
+        if !functions.contains(&idx) {
+            functions.push(idx);
+        }
+    }
+
+    // Functions with exactly this name.
+    pub fn get(&self, key : &str) -> Option<&[NodeIndex]> {
+        let functions = &self.nodes[self.find(key)? as usize].functions;
+        if functions.is_empty() { None } else { Some(functions) }
+    }
+
+    // Up to `limit` names starting with `prefix`, in sorted order.
+    pub fn keys_with_prefix(&self, prefix : &str, limit : usize) -> Vec<String> {
+        let mut result = vec![];
+        if let Some(start) = self.find(prefix) {
+            let mut key = prefix.as_bytes().to_vec();
+            self.collect_keys(start, &mut key, limit, &mut result);
+        }
+        result
+    }
+
+    fn collect_keys(&self, node : u32, key : &mut Vec<u8>, limit : usize, result : &mut Vec<String>) {
+        if result.len() >= limit { return; }
+        let node = &self.nodes[node as usize];
+        if !node.functions.is_empty() {
+            result.push(String::from_utf8_lossy(key).into_owned());
+        }
+        for &(byte, child) in &node.children {
+            key.push(byte);
+            self.collect_keys(child, key, limit, result);
+            key.pop();
+        }
+    }
+
+    // All functions having a name starting with `prefix`.
+    pub fn functions_with_prefix(&self, prefix : &str) -> Vec<NodeIndex> {
+        let mut result = vec![];
+        let mut work = match self.find(prefix) {
+            Some(start) => vec![start],
+            None => return result,
+        };
+        while let Some(node) = work.pop() {
+            let node = &self.nodes[node as usize];
+            result.extend_from_slice(&node.functions);
+            work.extend(node.children.iter().map(|&(_, child)| child));
+        }
+        result.sort();
+        result.dedup();
+        result
+    }
+}